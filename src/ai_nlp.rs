@@ -1,64 +1,113 @@
 use anyhow::{anyhow, Result};
-use log::info;
-use reqwest;
-use serde::{Deserialize, Serialize};
-use serde_json;
+use log::{debug, info, trace, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
 
+use crate::database;
+use crate::dockerfile::ContainerBuild;
+use crate::gpu;
 use crate::infrastructure::TerraformConfig;
+use crate::llm::{LlmProvider, Provider, ProviderKind};
+use crate::redact::redact_secrets;
+use crate::repository::RepositoryAnalysis;
 use crate::nlp::{
-    ApplicationType, CloudProvider, DatabaseType, DeploymentRequirements, ScalingRequirements,
+    self, ApplicationType, CloudProvider, DatabaseType, DeploymentRequirements, OsFamily,
+    ScalingRequirements,
 };
 
-const GEMINI_API_URL: &str =
-    "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent";
+/// Gemini 2.5 Flash context window (input tokens)
+const MODEL_INPUT_TOKEN_LIMIT: usize = 1_048_576;
+/// Gemini 2.5 Flash maximum output tokens
+pub const DEFAULT_MAX_OUTPUT_TOKENS: u32 = 65_536;
+/// Room left for the fixed prompt templates when budgeting the user description
+const PROMPT_TEMPLATE_TOKENS: usize = 8_192;
 
-#[derive(Serialize)]
-struct GeminiRequest {
-    contents: Vec<GeminiContent>,
-    generation_config: GeminiGenerationConfig,
+static SETTINGS: OnceLock<AiSettings> = OnceLock::new();
+
+/// Settings applied to every AI call, configured once from the CLI
+#[derive(Debug, Clone)]
+pub struct AiSettings {
+    /// Backend every prompt is sent to (`LLM_PROVIDER`)
+    pub provider: ProviderKind,
+    /// Model name, e.g. `gemini-2.5-flash`
+    pub model: String,
+    pub max_output_tokens: u32,
+    /// Reject responses that are not a single clean JSON object instead of scanning for one
+    pub strict_json: bool,
+    /// Directory every prompt is written to, secrets redacted (`--dump-prompts`)
+    pub dump_prompts: Option<PathBuf>,
+    /// Directory every raw response is written to, secrets redacted (`--dump-responses`)
+    pub dump_responses: Option<PathBuf>,
+    /// Parse requirements with keyword rules only, never calling the AI (`--offline`)
+    pub offline: bool,
 }
 
-#[derive(Serialize)]
-struct GeminiContent {
-    parts: Vec<GeminiPart>,
+impl Default for AiSettings {
+    fn default() -> Self {
+        Self {
+            provider: ProviderKind::default(),
+            model: ProviderKind::default().default_model().to_string(),
+            max_output_tokens: DEFAULT_MAX_OUTPUT_TOKENS,
+            strict_json: false,
+            dump_prompts: None,
+            dump_responses: None,
+            offline: false,
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct GeminiPart {
-    text: String,
+pub fn configure(settings: AiSettings) -> Result<()> {
+    if settings.max_output_tokens == 0 {
+        return Err(anyhow!("--max-output-tokens must be at least 1"));
+    }
+    if settings.max_output_tokens > DEFAULT_MAX_OUTPUT_TOKENS {
+        warn!(
+            "⚠️ --max-output-tokens {} exceeds the model limit of {}",
+            settings.max_output_tokens, DEFAULT_MAX_OUTPUT_TOKENS
+        );
+    }
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow!("AI settings already configured"))
 }
 
-#[derive(Serialize)]
-struct GeminiGenerationConfig {
-    temperature: f32,
-    #[serde(rename = "topK")]
-    top_k: i32,
-    #[serde(rename = "topP")]
-    top_p: f32,
-    #[serde(rename = "maxOutputTokens")]
-    max_output_tokens: i32,
+/// Numbers the dumps of one run so a prompt and its response share a name
+static CALL_SEQUENCE: AtomicU32 = AtomicU32::new(1);
+
+/// Write `contents` to `<dir>/<timestamp>_<call>_<purpose>.<kind>.txt` with secrets redacted.
+/// A failed dump is only logged: it must not fail the AI call.
+fn dump(dir: &Path, prefix: &str, kind: &str, contents: &str) {
+    let path = dir.join(format!("{}.{}.txt", prefix, kind));
+    let written = fs::create_dir_all(dir).and_then(|_| fs::write(&path, redact_secrets(contents)));
+    match written {
+        Ok(()) => debug!("📝 Wrote AI {} to {}", kind, path.display()),
+        Err(e) => warn!("⚠️ Failed to write AI {} to {}: {}", kind, path.display(), e),
+    }
 }
 
-#[derive(Deserialize)]
-struct GeminiResponse {
-    candidates: Vec<GeminiCandidate>,
+fn settings() -> &'static AiSettings {
+    SETTINGS.get_or_init(AiSettings::default)
 }
 
-#[derive(Deserialize)]
-struct GeminiCandidate {
-    content: GeminiResponseContent,
+/// Whether `--offline` is set: nothing but the deploy itself may reach the network
+pub fn offline() -> bool {
+    settings().offline
 }
 
-#[derive(Deserialize)]
-struct GeminiResponseContent {
-    parts: Vec<GeminiResponsePart>,
+/// Model all AI calls go to
+pub fn model() -> &'static str {
+    &settings().model
 }
 
-#[derive(Deserialize)]
-struct GeminiResponsePart {
-    text: String,
+/// The configured backend; fails when its API key is missing
+fn provider() -> Result<Provider> {
+    Provider::new(settings().provider, model(), settings().max_output_tokens)
 }
 
 #[derive(Deserialize)]
@@ -73,10 +122,60 @@ struct ParsedRequirements {
     environment_variables: HashMap<String, String>,
 }
 
+/// Parse deployment requirements, reading the obvious signals with keywords first. The AI
+/// is only called when the description says more than the keywords understand, and its
+/// result is merged over the keyword-derived requirements.
+pub async fn parse_requirements(description: &str) -> Result<DeploymentRequirements> {
+    if settings().offline {
+        return Ok(parse_requirements_offline(description, "--offline"));
+    }
+    if let Some(var) = missing_api_key() {
+        return Ok(parse_requirements_offline(description, &format!("{} is not set", var)));
+    }
+
+    let signals = nlp::extract_keywords(description);
+    if signals.is_complete() {
+        info!("🔎 Requirements read from keywords, skipping the AI call");
+        return Ok(signals.to_requirements());
+    }
+    
+    debug!("Keywords did not cover: {}", signals.unrecognized.join(", "));
+    let requirements = parse_deployment_requirements(description).await?;
+    Ok(signals.merge_under(requirements))
+}
+
+/// Requirements from the keyword rules alone, for air-gapped runs. Anything the keywords
+/// don't understand is ignored, so `reason` is logged along with what was dropped.
+fn parse_requirements_offline(description: &str, reason: &str) -> DeploymentRequirements {
+    warn!("📴 Offline mode ({}): parsing requirements with keyword rules only, results may be less accurate", reason);
+    let signals = nlp::extract_keywords(description);
+    if !signals.is_complete() {
+        warn!("⚠️ Ignored words the keyword rules don't understand: {}", signals.unrecognized.join(", "));
+    }
+    signals.to_requirements()
+}
+
 pub async fn parse_deployment_requirements(description: &str) -> Result<DeploymentRequirements> {
-    info!("🤖 Using Gemini 2.5 Flash to parse deployment requirements...");
+    parse_deployment_requirements_with(&provider()?, description).await
+}
 
-    let prompt = format!(
+/// `parse_deployment_requirements` against a given backend
+async fn parse_deployment_requirements_with<P: LlmProvider>(llm: &P, description: &str) -> Result<DeploymentRequirements> {
+    info!("🤖 Using {} to parse deployment requirements...", model());
+    let prompt = build_requirements_prompt(description);
+    let response_text = call_llm(llm, &prompt, "requirements").await?;
+    let requirements = parse_requirements_response(&response_text, settings().strict_json)?;
+
+    info!("   Application Type: {:?}", requirements.application_type);
+    info!("   Scaling: {:?}", requirements.scaling_requirements);
+    info!("   Databases: {:?}", requirements.database_requirements);
+    Ok(requirements)
+}
+
+/// Prompt asking for the requirements in `description` as JSON
+fn build_requirements_prompt(description: &str) -> String {
+    let (description, _) = fit_to_prompt_budget(description, "");
+    format!(
         r#"Analyze this deployment description and extract structured deployment requirements in JSON format:
 
 Description: "{}"
@@ -87,7 +186,7 @@ Extract the following information and respond with ONLY a JSON object (no markdo
   "application_type": "Flask|Django|FastAPI|NodeJS|React|NextJS|Express|Go|Rust|Ruby|PHP|Static|Unknown",
   "scaling_requirements": "Single|AutoScaling|LoadBalanced|Serverless",
   "database_requirements": ["PostgreSQL", "MySQL", "MongoDB", "Redis", "None"],
-  "cloud_provider": "AWS|GCP|Azure|DigitalOcean",
+  "cloud_provider": "AWS|GCP|Azure|DigitalOcean|Unknown",
   "port_requirements": [80, 443],
   "ssl_required": true,
   "custom_domain": "example.com or null",
@@ -99,27 +198,33 @@ Rules:
 - application_type: infer from keywords (Flask, Django, Node, React, etc.)
 - scaling_requirements: "Single" unless "auto-scaling", "load balanced", or "serverless" mentioned
 - database_requirements: extract database types mentioned, use ["None"] if none
-- cloud_provider: AWS unless GCP/Google/Azure/DigitalOcean specified
+- cloud_provider: Unknown unless AWS/Amazon/GCP/Google/Azure/DigitalOcean is named; the configured default applies then
 - port_requirements: [80, 443] for web apps, [80] for simple apps
 - ssl_required: true for production deployments
 - custom_domain: extract domain if mentioned, otherwise null
 - environment_variables: extract any env vars or configs mentioned"#,
         description
-    );
-
-    let response_text = call_gemini_api(&prompt).await?;
+    )
+}
 
-    // Clean the response to extract JSON
-    let json_text = extract_json_from_response(&response_text)?;
+/// Requirements from the AI's answer to `build_requirements_prompt`. `strict` requires a
+/// single JSON object rather than scanning the text for one.
+fn parse_requirements_response(response_text: &str, strict: bool) -> Result<DeploymentRequirements> {
+    let parsed: ParsedRequirements = if strict {
+        parse_strict_json(response_text, "requirements")?
+    } else {
+        // Clean the response to extract JSON
+        let json_text = extract_json_from_response(response_text)?;
 
-    // Parse the JSON response
-    let parsed: ParsedRequirements = serde_json::from_str(&json_text).map_err(|e| {
-        anyhow!(
-            "Failed to parse Gemini response as JSON: {}. Response: {}",
-            e,
-            json_text
-        )
-    })?;
+        // Parse the JSON response
+        serde_json::from_str(&json_text).map_err(|e| {
+            anyhow!(
+                "Failed to parse AI response as JSON: {}. Response: {}",
+                e,
+                json_text
+            )
+        })?
+    };
 
     // Convert to our internal types
     let application_type = match parsed.application_type.as_str() {
@@ -130,8 +235,8 @@ Rules:
         "React" => Some(ApplicationType::React),
         "NextJS" => Some(ApplicationType::NextJS),
         "Express" => Some(ApplicationType::Express),
-        "Go" => Some(ApplicationType::Unknown),
-        "Rust" => Some(ApplicationType::Unknown),
+        "Go" => Some(ApplicationType::Go),
+        "Rust" => Some(ApplicationType::Rust),
         "Ruby" => Some(ApplicationType::Unknown),
         "PHP" => Some(ApplicationType::Unknown),
         "Static" => Some(ApplicationType::React),
@@ -149,7 +254,8 @@ Rules:
         "GCP" => CloudProvider::GCP,
         "Azure" => CloudProvider::Azure,
         "DigitalOcean" => CloudProvider::DigitalOcean,
-        _ => CloudProvider::AWS,
+        "AWS" => CloudProvider::AWS,
+        _ => CloudProvider::Unknown,
     };
 
     let database_requirements = parsed
@@ -164,10 +270,6 @@ Rules:
         })
         .collect();
 
-    info!("   Application Type: {:?}", application_type);
-    info!("   Scaling: {:?}", scaling_requirements);
-    info!("   Databases: {:?}", database_requirements);
-
     Ok(DeploymentRequirements {
         cloud_provider,
         application_type,
@@ -177,19 +279,98 @@ Rules:
         port_requirements: parsed.port_requirements,
         ssl_required: parsed.ssl_required,
         custom_domain: parsed.custom_domain,
+        os: OsFamily::default(),
+        registry_credentials: HashMap::new(),
+        expects_env_file: false,
+        environment: None,
+        health_check: Default::default(),
+        private_network: false,
+        service_dir: None,
+        deployment_name: None,
+        deploy_non_service: false,
+        region: None,
+        existing_network: None,
+        audit: None,
+        disk_size_gb: None,
+        gpu: false,
+        allow_destroy_stateful: false,
+        git_ref: None,
+        rewrite_localhost: false,
+        secrets_source: None,
+        egress: None,
+        start_script: None,
+        base_image: None,
+        ssh_port: None,
+        authorized_key: None,
     })
 }
 
+/// Extra prompt requirements for `--private` deployments
+const PRIVATE_NETWORK_REQUIREMENTS: &str = r#"
+PRIVATE NETWORK (required):
+- Create a dedicated network with a public subnet and a private subnet
+- Place the instance in the private subnet WITHOUT a public IP (AWS: associate_public_ip_address = false; GCP: no access_config; Azure: no public IP)
+- Give the private subnet outbound-only internet access so the startup script can apt/pip/npm install and git clone: AWS aws_nat_gateway with an aws_eip in the public subnet plus a private route table; GCP google_compute_router with google_compute_router_nat; Azure azurerm_nat_gateway associated with the subnet
+- Expose the app only through a load balancer in the public subnet on port 80, forwarding to the Exposed Ports (AWS: aws_lb + aws_lb_target_group + aws_lb_listener; GCP: external HTTP load balancer; Azure: azurerm_lb), and only allow app traffic from the load balancer
+- Do not open port 22 to the internet (this overrides the SSH rule above)
+- Output the load balancer address as "load_balancer_url" (AWS: aws_lb.<name>.dns_name)
+"#;
+
 pub async fn generate_terraform_with_ai(
     description: &str,
-    cloud_provider: &CloudProvider,
+    requirements: &DeploymentRequirements,
     deployment_type: &str,
-    app_type: &crate::nlp::ApplicationType,
+    analysis: &RepositoryAnalysis,
     repository_url: &str,
+    container: Option<&ContainerBuild>,
 ) -> Result<TerraformConfig> {
-    info!("🤖 Using Gemini 2.5 Flash to generate Terraform configuration...");
+    info!("🤖 Using {} to generate Terraform configuration...", model());
+    let prompt = build_terraform_prompt(description, requirements, deployment_type, analysis, repository_url, container);
+    let response_text = call_llm(&provider()?, &prompt, "terraform").await?;
+    let config = parse_terraform_response(&response_text, settings().strict_json)?;
 
-    let prompt = format!(
+    info!("✅ Successfully generated Terraform config using AI");
+    info!("   Provider: {}", config.provider);
+    info!("   Resources: {}", config.resources.len());
+
+    Ok(config)
+}
+
+/// Prompt asking for a `TerraformConfig` JSON deploying the app as `deployment_type`; container
+/// targets get their image as `container` says and VMs the Python version the app pins
+fn build_terraform_prompt(
+    description: &str,
+    requirements: &DeploymentRequirements,
+    deployment_type: &str,
+    analysis: &RepositoryAnalysis,
+    repository_url: &str,
+    container: Option<&ContainerBuild>,
+) -> String {
+    let cloud_provider = &requirements.cloud_provider;
+    let network_requirements = if requirements.private_network {
+        PRIVATE_NETWORK_REQUIREMENTS
+    } else {
+        ""
+    };
+    let database_requirements = database::prompt_requirements(&requirements.database_requirements, cloud_provider);
+    let container_requirements = container.map(ContainerBuild::prompt_requirements).unwrap_or_default();
+    let gpu_requirements = match deployment_type == "SingleVM" && (requirements.gpu || !analysis.gpu_reasons.is_empty()) {
+        true => gpu::prompt_requirements(cloud_provider),
+        false => String::new(),
+    };
+    let python_requirements = match (&analysis.python_version, container) {
+        (Some(version), None) => format!(
+            "- Python Version: {0}. The startup script MUST install Python {0} rather than the OS's default python3 \
+             (Ubuntu: the deadsnakes PPA; Debian: pyenv) and create the virtualenv with python{0}\n",
+            version
+        ),
+        _ => String::new(),
+    };
+    // The analysis and file contents give way before the user's own words do
+    let context = [python_requirements, network_requirements.to_string(), database_requirements, container_requirements, gpu_requirements].concat();
+    let (description, context) = fit_to_prompt_budget(description, &context);
+
+    format!(
         r#"Generate a Terraform configuration for this deployment:
 
 Description: "{}"
@@ -197,6 +378,7 @@ Cloud Provider: {:?}
 Deployment Type: {}
 Application Type: {:?}
 Repository URL: {}
+Exposed Ports: {:?}
 
 Generate Terraform configuration as JSON with this exact structure:
 
@@ -273,8 +455,8 @@ Requirements:
 - For user_data: use complete application deployment commands
 - For Flask apps: ALWAYS include git clone, dependency installation, and app startup
 - Set up proper ports based on application type (Flask = 5000, Node.js = 3000, etc.)
-- ALWAYS include application ports in firewall rules
-
+- ALWAYS include the Exposed Ports (and 22 for SSH) in firewall rules; if there are none the app is a background worker, so open only 22
+{}
 IMPORTANT:
 - Keep strings simple, avoid nested quotes, use minimal user_data scripts
 - Use modern Terraform syntax: "aws_instance.app_instance.public_ip" not "${{aws_instance.app_instance.public_ip}}"
@@ -349,100 +531,180 @@ FOR FLASK APPLICATIONS: ALWAYS include complete deployment script:
 - Include port 5000 in firewall rules
 
 Respond with ONLY the JSON object, no markdown or explanation."#,
-        description, cloud_provider, deployment_type, app_type, repository_url
+        description, cloud_provider, deployment_type, analysis.app_type, repository_url, analysis.exposed_ports, context
+    )
+}
+
+/// Ask the AI to fix a Terraform config that `terraform plan` rejected, given the plan's error output
+pub async fn repair_terraform_with_ai(
+    config: &TerraformConfig,
+    cloud_provider: &CloudProvider,
+    plan_error: &str,
+) -> Result<TerraformConfig> {
+    info!("🩹 Asking {} to repair the Terraform configuration...", model());
+    let current = serde_json::to_string_pretty(config)?;
+    
+    let prompt = format!(
+        r#"This Terraform configuration for {:?}, written in the JSON structure below, failed `terraform plan`.
+
+Current configuration:
+{}
+
+terraform plan error:
+{}
+
+Fix the specific error reported above and change nothing else. Keep every resource name, the
+startup script (user_data / metadata_startup_script), the variables and the outputs unless the
+error is about them. Use the same JSON structure with "provider", "resources", "variables" and "outputs".
+
+Respond with ONLY the JSON object, no markdown or explanation."#,
+        cloud_provider,
+        current,
+        plan_error.trim()
     );
 
-    let response_text = call_gemini_api(&prompt).await?;
+    let response_text = call_llm(&provider()?, &prompt, "repair").await?;
+    parse_terraform_response(&response_text, settings().strict_json)
+}
+
+/// Characters of a failed deploy's error sent for explanation; the cause is usually near the
+/// start and provider errors repeat themselves after it
+const MAX_EXPLAINED_ERROR_CHARS: usize = 8_000;
+
+/// The API key variable the configured backend is missing, so optional AI steps can be
+/// skipped without one
+pub fn missing_api_key() -> Option<&'static str> {
+    settings()
+        .provider
+        .api_key_var()
+        .filter(|var| !env::var(var).is_ok_and(|key| !key.trim().is_empty()))
+}
+
+/// Ask the AI to explain a failed deploy's error in plain language and suggest a fix
+pub async fn explain_failure(error: &str) -> Result<String> {
+    info!("🤖 Asking {} to explain the failure...", model());
+    call_llm(&provider()?, &explain_failure_prompt(error), "explain").await
+}
+
+/// Prompt for `explain_failure`; secrets are redacted from the error before it leaves the machine
+fn explain_failure_prompt(error: &str) -> String {
+    let error = redact_secrets(error.trim());
+    let error = match error.char_indices().nth(MAX_EXPLAINED_ERROR_CHARS) {
+        Some((end, _)) => format!("{}\n[... truncated]", &error[..end]),
+        None => error,
+    };
+    format!(
+        r#"A deployment tool failed while provisioning cloud infrastructure with Terraform for a user's application. The error output is below.
+
+Error output:
+{}
+
+Explain to someone new to cloud deployments, in plain language and at most three sentences, what went wrong. Then list at most three concrete steps to fix it, most likely fix first. Mention exact settings, permissions or commands where the error names them. Do not guess at causes the error does not support.
+
+Respond in plain text, without markdown headings or code fences."#,
+        error
+    )
+}
+
+/// Config from the AI's answer to `build_terraform_prompt` or a repair prompt. `strict`
+/// requires a single JSON object rather than scanning the text for one.
+fn parse_terraform_response(response_text: &str, strict: bool) -> Result<TerraformConfig> {
+    trace!("🔍 Raw AI response: {}", redact_secrets(response_text));
     
-    // Log the raw response for debugging
-    info!("🔍 Raw Gemini response: {}", response_text);
+    if strict {
+        return parse_strict_json(response_text, "Terraform config");
+    }
     
-    let json_text = extract_json_from_response(&response_text)?;
+    let json_text = extract_json_from_response(response_text)?;
     
-    // Log the extracted JSON for debugging
-    info!("🔍 Extracted JSON: {}", json_text);
+    trace!("🔍 Extracted JSON: {}", redact_secrets(&json_text));
     
     if json_text.is_empty() {
-        return Err(anyhow!("Empty response from Gemini API. Raw response: {}", response_text));
+        return Err(anyhow!("Empty response from the AI. Raw response: {}", redact_secrets(response_text)));
     }
 
-    let config: TerraformConfig = serde_json::from_str(&json_text).map_err(|e| {
+    serde_json::from_str(&json_text).map_err(|e| {
         anyhow!(
             "Failed to parse AI-generated Terraform config: {}. Response: {}",
             e,
             json_text
         )
-    })?;
-
-    info!("✅ Successfully generated Terraform config using AI");
-    info!("   Provider: {}", config.provider);
-    info!("   Resources: {}", config.resources.len());
-
-    Ok(config)
+    })
 }
 
-async fn call_gemini_api(prompt: &str) -> Result<String> {
-    let client = reqwest::Client::new();
-
-    let api_key = env::var("GEMINI_API_KEY")
-        .map_err(|_| anyhow!("GEMINI_API_KEY environment variable not set"))?;
-
-    let request = GeminiRequest {
-        contents: vec![GeminiContent {
-            parts: vec![GeminiPart {
-                text: prompt.to_string(),
-            }],
-        }],
-        generation_config: GeminiGenerationConfig {
-            temperature: 0.1,
-            top_k: 32,
-            top_p: 1.0,
-            max_output_tokens: 100000,
-        },
-    };
+/// Rough token estimate (~4 characters per token)
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
 
-    let url = format!("{}?key={}", GEMINI_API_URL, api_key);
-    
-    info!("🔍 Making API call to: {}", GEMINI_API_URL);
-
-    let response = client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| anyhow!("Failed to call Gemini API: {}", e))?;
-
-    let status = response.status();
-    info!("🔍 Response status: {}", status);
-
-    if !status.is_success() {
-        let error_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(anyhow!("Gemini API error {}: {}", status, error_text));
-    }
-
-    let response_text = response
-        .text()
-        .await
-        .map_err(|e| anyhow!("Failed to read response text: {}", e))?;
+/// Fit a prompt's user-supplied description and its `context` (repository analysis and file
+/// contents) into the model's context window. The context is truncated first; the description
+/// only when it alone exceeds the budget.
+fn fit_to_prompt_budget<'a>(description: &'a str, context: &'a str) -> (&'a str, &'a str) {
+    let budget = MODEL_INPUT_TOKEN_LIMIT - PROMPT_TEMPLATE_TOKENS;
+    let description_tokens = estimate_tokens(description);
+    if description_tokens + estimate_tokens(context) <= budget {
+        return (description, context);
+    }
     
-    info!("🔍 Raw response body: {}", response_text);
+    if description_tokens > budget {
+        warn!(
+            "⚠️ Description is ~{} tokens, truncating it to ~{} and leaving out the repository context to fit the model context window",
+            description_tokens,
+            budget
+        );
+        return (truncate_to_tokens(description, budget), "");
+    }
+    let context_budget = budget - description_tokens;
+    warn!(
+        "⚠️ Repository context is ~{} tokens, truncating it to ~{} to fit the model context window",
+        estimate_tokens(context),
+        context_budget
+    );
+    (description, truncate_to_tokens(context, context_budget))
+}
 
-    let gemini_response: GeminiResponse = serde_json::from_str(&response_text)
-        .map_err(|e| anyhow!("Failed to parse Gemini response as JSON: {}. Response: {}", e, response_text))?;
+fn truncate_to_tokens(text: &str, tokens: usize) -> &str {
+    match text.char_indices().nth(tokens * 4) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
 
-    if gemini_response.candidates.is_empty() {
-        return Err(anyhow!("No candidates in Gemini response. Full response: {}", response_text));
+/// Send `prompt` to the model; `purpose` names the call in prompt and response dumps
+async fn call_llm<P: LlmProvider>(llm: &P, prompt: &str, purpose: &str) -> Result<String> {
+    let estimated_tokens = estimate_tokens(prompt);
+    debug!(
+        "📏 Estimated prompt size: ~{} tokens (limit {}), max output tokens {}",
+        estimated_tokens,
+        MODEL_INPUT_TOKEN_LIMIT,
+        settings().max_output_tokens
+    );
+    if estimated_tokens > MODEL_INPUT_TOKEN_LIMIT {
+        warn!(
+            "⚠️ Prompt is ~{} tokens, above the model limit of {}; the request will likely be rejected",
+            estimated_tokens, MODEL_INPUT_TOKEN_LIMIT
+        );
     }
 
-    if gemini_response.candidates[0].content.parts.is_empty() {
-        return Err(anyhow!("No parts in Gemini response. Full response: {}", response_text));
+    let dump_prefix = format!(
+        "{}_{:03}_{}",
+        chrono::Utc::now().format("%Y%m%d_%H%M%S"),
+        CALL_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        purpose
+    );
+    if let Some(dir) = &settings().dump_prompts {
+        dump(dir, &dump_prefix, "prompt", prompt);
     }
 
-    Ok(gemini_response.candidates[0].content.parts[0].text.clone())
+    let completion = llm.complete(prompt).await;
+    if let Some(dir) = &settings().dump_responses {
+        match &completion {
+            Ok(text) => dump(dir, &dump_prefix, "response", text),
+            Err(e) => dump(dir, &dump_prefix, "response", &e.to_string()),
+        }
+    }
+    completion
 }
 
 fn extract_json_from_response(response: &str) -> Result<String> {
@@ -477,13 +739,87 @@ fn extract_json_from_response(response: &str) -> Result<String> {
     }
 
     // If no clear JSON boundaries found, return error with details
-    Err(anyhow!("No valid JSON object found in response: '{}'", cleaned))
+    Err(anyhow!("No valid JSON object found in response: '{}'", redact_secrets(cleaned)))
+}
+
+/// Parse a response that must be exactly one JSON object, optionally in a single fenced block.
+/// Errors name the offending field path instead of falling back to heuristics.
+fn parse_strict_json<T: DeserializeOwned>(response: &str, what: &str) -> Result<T> {
+    let json_text = extract_strict_json(response)?;
+    let deserializer = &mut serde_json::Deserializer::from_str(json_text);
+    
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        if path == "." {
+            anyhow!("Invalid AI {}: {}", what, e.inner())
+        } else {
+            anyhow!("Invalid AI {} at `{}`: {}", what, path, e.inner())
+        }
+    })
+}
+
+/// Strip a single optional ```/```json fence and require the remainder to be one JSON object
+fn extract_strict_json(response: &str) -> Result<&str> {
+    let response = response.trim();
+    
+    let body = match response.strip_prefix("```") {
+        Some(fenced) => {
+            let (info, rest) = fenced
+                .split_once('\n')
+                .ok_or_else(|| anyhow!("Strict JSON: unterminated code fence"))?;
+            if !matches!(info.trim(), "" | "json") {
+                return Err(anyhow!("Strict JSON: unexpected code fence language '{}'", info.trim()));
+            }
+            let body = rest
+                .trim_end()
+                .strip_suffix("```")
+                .ok_or_else(|| anyhow!("Strict JSON: text after the closing code fence"))?;
+            if body.contains("```") {
+                return Err(anyhow!("Strict JSON: expected a single fenced block"));
+            }
+            body.trim()
+        }
+        None => response,
+    };
+    
+    if !body.starts_with('{') {
+        return Err(anyhow!("Strict JSON: response must be a single JSON object, got: '{}'", redact_secrets(body)));
+    }
+    
+    // Reject trailing text or a second object, reporting where the problem is
+    serde_json::from_str::<serde::de::IgnoredAny>(body)
+        .map_err(|e| anyhow!("Strict JSON: {}", e))?;
+    
+    Ok(body)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_dump_redacts_secrets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("prompts");
+        dump(&dir, "20260101_120000_001_requirements", "prompt", "Deploy with API_KEY=abc123");
+
+        let written = fs::read_to_string(dir.join("20260101_120000_001_requirements.prompt.txt")).unwrap();
+        assert_eq!(written, "Deploy with API_KEY=***");
+    }
+
+    #[test]
+    fn test_explain_failure_prompt() {
+        let prompt = explain_failure_prompt("Error: creating EC2 Instance: UnauthorizedOperation\nAWS_SECRET_ACCESS_KEY=wJalrXUtnFEMI");
+        assert!(prompt.contains("UnauthorizedOperation"));
+        assert!(prompt.contains("AWS_SECRET_ACCESS_KEY=***"));
+        assert!(!prompt.contains("wJalrXUtnFEMI"));
+
+        let long_error = "x".repeat(MAX_EXPLAINED_ERROR_CHARS + 100);
+        let prompt = explain_failure_prompt(&long_error);
+        assert!(prompt.contains("[... truncated]"));
+        assert!(!prompt.contains(&long_error));
+    }
+
     #[test]
     fn test_extract_json_from_response() {
         let response_with_markdown = "```json\n{\"test\": \"value\"}\n```";
@@ -499,6 +835,34 @@ mod tests {
         assert_eq!(result, "{\"test\": \"value\"}");
     }
 
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn test_fit_to_prompt_budget() {
+        let short = "Deploy this Flask app on AWS";
+        assert_eq!(fit_to_prompt_budget(short, ""), (short, ""));
+
+        let context = "- Dockerfile: FROM python:3.11-slim\n";
+        assert_eq!(fit_to_prompt_budget(short, context), (short, context));
+
+        // A huge Dockerfile or analysis is cut, the description kept whole
+        let budget_chars = (MODEL_INPUT_TOKEN_LIMIT - PROMPT_TEMPLATE_TOKENS) * 4;
+        let long_context = "y".repeat(budget_chars);
+        let (description, context) = fit_to_prompt_budget(short, &long_context);
+        assert_eq!(description, short);
+        assert_eq!(context.len(), budget_chars - estimate_tokens(short) * 4);
+
+        // Only a description over the whole budget is cut
+        let long = "x".repeat(budget_chars + 100);
+        let (description, context) = fit_to_prompt_budget(&long, &long_context);
+        assert_eq!((description.len(), context), (budget_chars, ""));
+    }
+
     #[tokio::test]
     async fn test_parse_deployment_requirements_structure() {
         // Test basic structure without calling API
@@ -518,4 +882,168 @@ mod tests {
         assert_eq!(parsed.scaling_requirements, "Single");
         assert_eq!(parsed.database_requirements, vec!["PostgreSQL"]);
     }
+
+    /// A recorded requirements answer: prose around a fenced object, as Gemini often replies
+    const REQUIREMENTS_RESPONSE: &str = r#"Here are the extracted requirements:
+
+```json
+{
+  "application_type": "Static",
+  "scaling_requirements": "LoadBalanced",
+  "database_requirements": ["PostgreSQL", "Cassandra", "Redis"],
+  "cloud_provider": "Azure",
+  "port_requirements": [443],
+  "ssl_required": true,
+  "custom_domain": "shop.example.com",
+  "environment_variables": {"NODE_ENV": "production"}
+}
+```
+
+Let me know if you need anything else."#;
+
+    /// A recorded Terraform answer for a GCP VM
+    const TERRAFORM_RESPONSE: &str = r#"```json
+{
+  "provider": "google",
+  "resources": [
+    {"resource_type": "google_compute_instance", "name": "app", "config": {"machine_type": "e2-micro", "tags": ["web"]}},
+    {"resource_type": "google_compute_firewall", "name": "web", "config": {"network": "default", "allow": [{"protocol": "tcp", "ports": ["80"]}]}}
+  ],
+  "variables": {"project_id": {"type": "string"}},
+  "outputs": {"instance_ip": {"value": "google_compute_instance.app.network_interface.0.access_config.0.nat_ip"}}
+}
+```"#;
+
+    #[test]
+    fn test_parse_requirements_response_fixture() {
+        let requirements = parse_requirements_response(REQUIREMENTS_RESPONSE, false).unwrap();
+        assert_eq!(requirements.application_type, Some(ApplicationType::React), "static sites build like React");
+        assert_eq!(requirements.scaling_requirements, ScalingRequirements::LoadBalanced);
+        assert_eq!(requirements.database_requirements, vec![DatabaseType::PostgreSQL, DatabaseType::Redis]);
+        assert_eq!(requirements.cloud_provider, CloudProvider::Azure);
+        assert_eq!(requirements.custom_domain.as_deref(), Some("shop.example.com"));
+        assert_eq!(requirements.environment_variables["NODE_ENV"], "production");
+
+        assert!(parse_requirements_response(REQUIREMENTS_RESPONSE, true).is_err(), "strict mode rejects the prose");
+        let err = parse_requirements_response(r#"{"application_type": "Flask"}"#, false).unwrap_err().to_string();
+        assert!(err.contains("Failed to parse AI response as JSON"), "{}", err);
+        assert!(build_requirements_prompt("Flask on GCP").contains("Description: \"Flask on GCP\""));
+    }
+
+    #[test]
+    fn test_terraform_prompt_and_response_fixture() {
+        let mut requirements = DeploymentRequirements {
+            cloud_provider: CloudProvider::GCP,
+            database_requirements: vec![DatabaseType::PostgreSQL],
+            ..Default::default()
+        };
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("requirements.txt"), "flask\n").unwrap();
+        fs::write(temp_dir.path().join("app.py"), "from flask import Flask\napp = Flask(__name__)\n").unwrap();
+        fs::write(temp_dir.path().join("runtime.txt"), "python-3.9.18\n").unwrap();
+        let mut analysis = crate::repository::analyze_repository(temp_dir.path()).unwrap();
+        assert_eq!(analysis.app_type, ApplicationType::Flask);
+        analysis.exposed_ports = vec![5000];
+        let prompt = build_terraform_prompt("Flask API", &requirements, "SingleVM", &analysis, "https://github.com/org/app", None);
+        assert!(prompt.contains("Cloud Provider: GCP\nDeployment Type: SingleVM"));
+        assert!(prompt.contains("Exposed Ports: [5000]"));
+        assert!(prompt.contains("- Python Version: 3.9. The startup script MUST install Python 3.9"));
+        assert!(!prompt.contains("PRIVATE NETWORK"));
+        requirements.private_network = true;
+        let prompt = build_terraform_prompt("Flask API", &requirements, "SingleVM", &analysis, "https://github.com/org/app", None);
+        assert!(prompt.contains("PRIVATE NETWORK (required)"));
+        assert!(!prompt.contains("CONTAINER IMAGE"));
+        let prompt = build_terraform_prompt("Flask API", &requirements, "Kubernetes", &analysis, "https://github.com/org/app", Some(&ContainerBuild::BaseImage("python:3.11-slim".to_string())));
+        assert!(prompt.contains("Run the app in the official python:3.11-slim image"));
+        assert!(!prompt.contains("Python Version"), "the image carries the Python version");
+        let dockerfile = ContainerBuild::Dockerfile("FROM python:3.11-slim\nCOPY . .\n".to_string());
+        let prompt = build_terraform_prompt("Flask API", &requirements, "Kubernetes", &analysis, "https://github.com/org/app", Some(&dockerfile));
+        assert!(prompt.contains("write this Dockerfile at the root of the clone (the build context)"));
+        assert!(prompt.contains("```\nFROM python:3.11-slim\nCOPY . .\n```"));
+
+        for strict in [false, true] {
+            let config = parse_terraform_response(TERRAFORM_RESPONSE, strict).unwrap();
+            assert_eq!(config.provider, "google");
+            assert_eq!(config.resources.len(), 2);
+            assert_eq!(config.resources[1].config["allow"][0]["ports"][0], "80");
+        }
+        assert!(parse_terraform_response("I cannot help with that.", false).is_err());
+    }
+
+    /// Answers every prompt with `response`, recording the prompts it was sent
+    struct MockProvider {
+        response: String,
+        prompts: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl LlmProvider for MockProvider {
+        async fn complete(&self, prompt: &str) -> Result<String> {
+            self.prompts.lock().unwrap().push(prompt.to_string());
+            Ok(self.response.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_provider_parses_requirements() {
+        let mock = MockProvider {
+            response: r#"```json
+{"application_type": "Flask", "scaling_requirements": "Single", "database_requirements": ["None"],
+ "cloud_provider": "GCP", "port_requirements": [80], "ssl_required": false, "custom_domain": null,
+ "environment_variables": {}}
+```"#
+                .to_string(),
+            prompts: Default::default(),
+        };
+        let requirements = parse_deployment_requirements_with(&mock, "Deploy my Flask app on GCP").await.unwrap();
+        assert_eq!(requirements.cloud_provider, CloudProvider::GCP);
+        assert_eq!(requirements.application_type, Some(ApplicationType::Flask));
+
+        let prompts = mock.prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].contains("Description: \"Deploy my Flask app on GCP\""), "the shared prompt is sent");
+    }
+
+    #[test]
+    fn test_offline_requirements() {
+        let requirements = parse_requirements_offline("Load balanced Django app on Azure with MongoDB, set DEBUG off", "--offline");
+        assert_eq!(requirements.cloud_provider, CloudProvider::Azure);
+        assert_eq!(requirements.scaling_requirements, ScalingRequirements::LoadBalanced);
+        assert_eq!(requirements.database_requirements, vec![DatabaseType::MongoDB]);
+        assert_eq!(requirements.application_type, Some(ApplicationType::Django));
+
+        let requirements = parse_requirements_offline("serverless api", "GEMINI_API_KEY is not set");
+        assert_eq!(requirements.scaling_requirements, ScalingRequirements::Serverless);
+        assert_eq!(requirements.cloud_provider, CloudProvider::Unknown, "left to --cloud-provider and the configured default");
+    }
+
+    #[test]
+    fn test_extract_strict_json() {
+        assert_eq!(extract_strict_json("{\"a\": 1}").unwrap(), "{\"a\": 1}");
+        assert_eq!(extract_strict_json("```json\n{\"a\": 1}\n```").unwrap(), "{\"a\": 1}");
+        assert_eq!(extract_strict_json("```\n{\"a\": 1}\n```\n").unwrap(), "{\"a\": 1}");
+
+        // Everything the lenient extractor tolerates is rejected
+        assert!(extract_strict_json("Here is the JSON: {\"a\": 1}").is_err());
+        assert!(extract_strict_json("{\"a\": 1} that you requested.").is_err());
+        assert!(extract_strict_json("{\"a\": 1}\n{\"b\": 2}").is_err());
+        assert!(extract_strict_json("```json\n{\"a\": 1}\n```\n```json\n{}\n```").is_err());
+        assert!(extract_strict_json("```yaml\na: 1\n```").is_err());
+        assert!(extract_strict_json("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_parse_strict_json_reports_field_path() {
+        let response = r#"{"provider": "aws", "resources": [{"resource_type": "aws_instance", "name": 7, "config": {}}], "variables": {}, "outputs": {}}"#;
+        let err = parse_strict_json::<TerraformConfig>(response, "Terraform config")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`resources[0].name`"), "{}", err);
+
+        let config: TerraformConfig = parse_strict_json(
+            r#"{"provider": "aws", "resources": [], "variables": {}, "outputs": {}}"#,
+            "Terraform config",
+        )
+        .unwrap();
+        assert_eq!(config.provider, "aws");
+    }
 }
@@ -0,0 +1,388 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tokio::process::Command;
+use which::which;
+
+use crate::http;
+use crate::repository::PackageManager;
+
+const OSV_QUERY_URL: &str = "https://api.osv.dev/v1/query";
+
+/// What `--audit` does with its findings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditMode {
+    /// Warn and deploy anyway
+    Warn,
+    /// Refuse to deploy on high or critical findings (`--audit-block`)
+    Block,
+}
+
+/// Severity of a known vulnerability, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The advisory has no severity rating
+    Unknown,
+    Low,
+    Moderate,
+    High,
+    Critical,
+}
+
+impl Severity {
+    fn parse(severity: &str) -> Self {
+        match severity.to_lowercase().as_str() {
+            "low" => Severity::Low,
+            "moderate" | "medium" => Severity::Moderate,
+            "high" => Severity::High,
+            "critical" => Severity::Critical,
+            _ => Severity::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Unknown => "UNKNOWN",
+            Severity::Low => "LOW",
+            Severity::Moderate => "MODERATE",
+            Severity::High => "HIGH",
+            Severity::Critical => "CRITICAL",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One known vulnerability in a dependency
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub package: String,
+    /// Installed version, or the vulnerable range when npm reports one
+    pub version: Option<String>,
+    /// Advisory ID, e.g. `GHSA-jf85-cpcp-j695`
+    pub id: String,
+    pub severity: Severity,
+    pub summary: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.severity, self.package)?;
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+        write!(f, ": {} {}", self.id, self.summary)
+    }
+}
+
+/// Check the app's dependencies against known vulnerabilities: `npm audit` or `yarn audit`
+/// for Node, the OSV database for pinned Python requirements. Logs a summary of the findings,
+/// and with `AuditMode::Block` fails when any is high or critical, or when the audit itself
+/// fails. In `AuditMode::Warn` a failed audit is only a warning.
+pub async fn audit_dependencies(app_root: &Path, package_manager: &PackageManager, mode: AuditMode) -> Result<Vec<Finding>> {
+    info!("🛡️ Auditing dependencies for known vulnerabilities...");
+    let audited = match package_manager {
+        PackageManager::Npm => npm_audit(app_root).await,
+        PackageManager::Yarn => yarn_audit(app_root).await,
+        PackageManager::Pip => osv_audit(app_root).await,
+        other => {
+            warn!("⚠️ --audit does not support {:?} projects yet; skipping", other);
+            return Ok(Vec::new());
+        }
+    };
+    let mut findings = match (audited, mode) {
+        (Ok(findings), _) => findings,
+        (Err(e), AuditMode::Warn) => {
+            warn!("⚠️ The dependency audit failed, deploying without it: {}", e);
+            return Ok(Vec::new());
+        }
+        (Err(e), AuditMode::Block) => return Err(anyhow!("The dependency audit failed, so --audit-block cannot pass: {}", e)),
+    };
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.package.cmp(&b.package)));
+
+    if findings.is_empty() {
+        info!("✅ No known vulnerabilities in the dependencies");
+        return Ok(findings);
+    }
+    warn!("⚠️ {} known vulnerabilit(ies) in the dependencies:", findings.len());
+    for finding in &findings {
+        warn!("   {}", finding);
+    }
+
+    let severe = findings.iter().filter(|finding| finding.severity >= Severity::High).count();
+    if mode == AuditMode::Block && severe > 0 {
+        return Err(anyhow!(
+            "🚫 {} high or critical vulnerabilit(ies) in the dependencies; upgrade them or deploy without --audit-block",
+            severe
+        ));
+    }
+    Ok(findings)
+}
+
+/// `npm audit` against the lockfile; the dependencies need not be installed
+async fn npm_audit(app_root: &Path) -> Result<Vec<Finding>> {
+    if !app_root.join("package-lock.json").is_file() {
+        warn!("⚠️ npm audit needs a package-lock.json; skipping the audit");
+        return Ok(Vec::new());
+    }
+    if which("npm").is_err() {
+        warn!("⚠️ npm is not installed; skipping the audit");
+        return Ok(Vec::new());
+    }
+    let output = Command::new("npm")
+        .args(["audit", "--json", "--package-lock-only"])
+        .current_dir(app_root)
+        .output()
+        .await?;
+    // npm audit exits non-zero when it finds anything, so the report is read either way
+    let report: Value = serde_json::from_slice(&output.stdout).map_err(|_| {
+        anyhow!("npm audit failed: {}", String::from_utf8_lossy(&output.stderr).trim())
+    })?;
+    Ok(parse_npm_audit(&report))
+}
+
+/// `yarn audit` (Yarn 1) against `yarn.lock`
+async fn yarn_audit(app_root: &Path) -> Result<Vec<Finding>> {
+    if !app_root.join("yarn.lock").is_file() {
+        warn!("⚠️ yarn audit needs a yarn.lock; skipping the audit");
+        return Ok(Vec::new());
+    }
+    if which("yarn").is_err() {
+        warn!("⚠️ yarn is not installed; skipping the audit");
+        return Ok(Vec::new());
+    }
+    let output = Command::new("yarn")
+        .args(["audit", "--json", "--groups", "dependencies"])
+        .current_dir(app_root)
+        .output()
+        .await?;
+    // Like npm, yarn audit exits non-zero when it finds anything
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.lines().any(|line| line.contains("\"auditSummary\"")) {
+        return Err(anyhow!("yarn audit failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(parse_yarn_audit(&stdout))
+}
+
+/// Findings from `yarn audit --json`: one JSON object per line, an `auditAdvisory` for each
+/// path to a vulnerable package, so advisories repeat
+fn parse_yarn_audit(output: &str) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+    for line in output.lines() {
+        let Ok(event) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if event.get("type").and_then(Value::as_str) != Some("auditAdvisory") {
+            continue;
+        }
+        let Some(advisory) = event.pointer("/data/advisory") else {
+            continue;
+        };
+        let text = |key: &str| advisory.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+        let id = advisory
+            .get("github_advisory_id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| text("url").rsplit('/').next().unwrap_or("advisory").to_string());
+        let finding = Finding {
+            package: text("module_name"),
+            version: advisory.pointer("/findings/0/version").and_then(Value::as_str).map(str::to_string),
+            id,
+            severity: Severity::parse(&text("severity")),
+            summary: text("title"),
+        };
+        if !findings.iter().any(|seen| seen.package == finding.package && seen.id == finding.id) {
+            findings.push(finding);
+        }
+    }
+    findings
+}
+
+/// Findings from `npm audit --json` (report version 2). Vulnerabilities only inherited from
+/// another package name it instead of an advisory.
+fn parse_npm_audit(report: &Value) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let Some(vulnerabilities) = report.get("vulnerabilities").and_then(Value::as_object) else {
+        return findings;
+    };
+    for (package, vulnerability) in vulnerabilities {
+        let range = vulnerability.get("range").and_then(Value::as_str).map(str::to_string);
+        let via = vulnerability.get("via").and_then(Value::as_array).cloned().unwrap_or_default();
+        let (advisories, inherited): (Vec<&Value>, Vec<&Value>) = via.iter().partition(|via| via.is_object());
+        for advisory in advisories {
+            let url = advisory.get("url").and_then(Value::as_str).unwrap_or_default();
+            findings.push(Finding {
+                package: package.clone(),
+                version: advisory.get("range").and_then(Value::as_str).map(str::to_string).or_else(|| range.clone()),
+                id: url.rsplit('/').next().filter(|id| !id.is_empty()).unwrap_or("advisory").to_string(),
+                severity: Severity::parse(advisory.get("severity").and_then(Value::as_str).unwrap_or_default()),
+                summary: advisory.get("title").and_then(Value::as_str).unwrap_or_default().to_string(),
+            });
+        }
+        if findings.iter().all(|finding| &finding.package != package) && !inherited.is_empty() {
+            let names: Vec<&str> = inherited.iter().filter_map(|via| via.as_str()).collect();
+            findings.push(Finding {
+                package: package.clone(),
+                version: range,
+                id: "transitive".to_string(),
+                severity: Severity::parse(vulnerability.get("severity").and_then(Value::as_str).unwrap_or_default()),
+                summary: format!("depends on vulnerable {}", names.join(", ")),
+            });
+        }
+    }
+    findings
+}
+
+/// Query OSV for each pinned (`name==version`) requirement; ranges can't be matched to a
+/// single installed version and are skipped
+async fn osv_audit(app_root: &Path) -> Result<Vec<Finding>> {
+    let content = fs::read_to_string(app_root.join("requirements.txt")).unwrap_or_default();
+    let pinned = parse_pinned_requirements(&content);
+    if pinned.is_empty() {
+        warn!("⚠️ No pinned (name==version) requirements to audit");
+        return Ok(Vec::new());
+    }
+
+    let mut findings = Vec::new();
+    for (package, version) in &pinned {
+        let query = json!({"package": {"name": package, "ecosystem": "PyPI"}, "version": version});
+        let response = http::client().post(OSV_QUERY_URL).json(&query).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("OSV query for {} failed: {}", package, response.status()));
+        }
+        let body: Value = response.json().await?;
+        findings.extend(parse_osv_vulns(package, version, &body));
+    }
+    Ok(findings)
+}
+
+/// `(name, version)` of the requirements pinned with `==`, without extras or markers
+fn parse_pinned_requirements(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .map(|line| line.split(';').next().unwrap_or_default().trim())
+        .filter_map(|line| {
+            let (name, version) = line.split_once("==")?;
+            let name = name.split('[').next().unwrap_or_default().trim();
+            let version = version.trim();
+            (!name.is_empty() && !version.is_empty() && !version.contains('*'))
+                .then(|| (name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// Findings from an OSV `/v1/query` response. The severity comes from the GitHub advisory
+/// rating OSV carries in `database_specific`.
+fn parse_osv_vulns(package: &str, version: &str, response: &Value) -> Vec<Finding> {
+    response
+        .get("vulns")
+        .and_then(Value::as_array)
+        .map(|vulns| {
+            vulns
+                .iter()
+                .map(|vuln| Finding {
+                    package: package.to_string(),
+                    version: Some(version.to_string()),
+                    id: vuln.get("id").and_then(Value::as_str).unwrap_or_default().to_string(),
+                    severity: Severity::parse(
+                        vuln.pointer("/database_specific/severity").and_then(Value::as_str).unwrap_or_default(),
+                    ),
+                    summary: vuln.get("summary").and_then(Value::as_str).unwrap_or_default().to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_npm_audit() {
+        let report = json!({
+            "auditReportVersion": 2,
+            "vulnerabilities": {
+                "lodash": {
+                    "severity": "high",
+                    "range": "<4.17.21",
+                    "via": [{
+                        "title": "Command Injection in lodash",
+                        "url": "https://github.com/advisories/GHSA-35jh-r3h4-6jhm",
+                        "severity": "high",
+                        "range": "<4.17.21"
+                    }]
+                },
+                "express-utils": {"severity": "moderate", "range": "1.0.0 - 1.2.0", "via": ["lodash"]}
+            }
+        });
+        let findings = parse_npm_audit(&report);
+        assert_eq!(findings.len(), 2);
+        let lodash = findings.iter().find(|finding| finding.package == "lodash").unwrap();
+        assert_eq!(lodash.to_string(), "[HIGH] lodash <4.17.21: GHSA-35jh-r3h4-6jhm Command Injection in lodash");
+        let inherited = findings.iter().find(|finding| finding.package == "express-utils").unwrap();
+        assert_eq!(inherited.severity, Severity::Moderate);
+        assert_eq!(inherited.summary, "depends on vulnerable lodash");
+    }
+
+    #[test]
+    fn test_parse_yarn_audit() {
+        let advisory = |path: &str| {
+            json!({"type": "auditAdvisory", "data": {"resolution": {"path": path}, "advisory": {
+                "module_name": "minimist",
+                "severity": "critical",
+                "title": "Prototype Pollution in minimist",
+                "url": "https://github.com/advisories/GHSA-xvch-5gv4-984h",
+                "github_advisory_id": "GHSA-xvch-5gv4-984h",
+                "findings": [{"version": "1.2.5"}]
+            }}})
+        };
+        let output = [
+            advisory("mkdirp>minimist").to_string(),
+            advisory("optimist>minimist").to_string(),
+            "warning package.json: No license field".to_string(),
+            json!({"type": "auditSummary", "data": {"vulnerabilities": {"critical": 1}}}).to_string(),
+        ]
+        .join("\n");
+        let findings = parse_yarn_audit(&output);
+        assert_eq!(findings.len(), 1, "one finding per advisory, not per path");
+        assert_eq!(
+            findings[0].to_string(),
+            "[CRITICAL] minimist 1.2.5: GHSA-xvch-5gv4-984h Prototype Pollution in minimist"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_warn_mode_survives_a_failed_audit() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("requirements.txt"), "flask==0.12.2\n").unwrap();
+        // Offline the OSV query fails; online flask 0.12.2 has a high finding. Either way warn
+        // mode deploys and block mode does not.
+        assert!(audit_dependencies(temp_dir.path(), &PackageManager::Pip, AuditMode::Warn).await.is_ok());
+        assert!(audit_dependencies(temp_dir.path(), &PackageManager::Pip, AuditMode::Block).await.is_err());
+    }
+
+    #[test]
+    fn test_osv_findings_for_pinned_requirements() {
+        let requirements = "flask==0.12.2\nrequests[socks] == 2.19.0 ; python_version >= '3'\ngunicorn>=20\n# django==1.0\n";
+        assert_eq!(
+            parse_pinned_requirements(requirements),
+            vec![("flask".to_string(), "0.12.2".to_string()), ("requests".to_string(), "2.19.0".to_string())]
+        );
+
+        let response = json!({"vulns": [
+            {"id": "GHSA-562c-5r94-xh97", "summary": "Flask denial of service", "database_specific": {"severity": "HIGH"}},
+            {"id": "PYSEC-2019-179", "summary": "Flask session issue"}
+        ]});
+        let findings = parse_osv_vulns("flask", "0.12.2", &response);
+        assert_eq!(findings[0].severity, Severity::High);
+        assert_eq!(findings[1].severity, Severity::Unknown);
+        assert!(Severity::Critical > Severity::High && Severity::Moderate < Severity::High);
+        assert!(parse_osv_vulns("flask", "2.3.3", &json!({})).is_empty());
+    }
+}
@@ -0,0 +1,892 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::credentials::RegistryCredentials;
+use crate::dockerfile;
+use crate::env_file::REMOTE_ENV_FILE;
+use crate::infrastructure::TerraformConfig;
+use crate::nlp::{ApplicationType, CloudProvider, DeploymentRequirements, OsFamily};
+use crate::repository::{self, is_build_time_env_var, RepositoryAnalysis, StaticServer};
+use crate::settings;
+use crate::ssh;
+
+/// Directory on the instance the repository is cloned into
+const APP_ROOT: &str = "/opt/app";
+
+/// Process groups of the running app, one per start command, so a redeploy can stop them
+const PID_FILE: &str = "/var/run/autodeployment-app.pids";
+
+/// Where the registry logins are written on the instance, as a Docker config; only root can
+/// read it
+pub const REMOTE_DOCKER_CONFIG: &str = "/etc/autodeployment/docker-config.json";
+
+/// Render the startup script that installs, builds and starts the application on a VM.
+///
+/// Build-time variables (e.g. `REACT_APP_*`) are exported before the build commands so they
+/// are baked into the bundle; runtime variables are exported just before the app starts.
+pub fn render_startup_script(
+    analysis: &RepositoryAnalysis,
+    repo_url: &str,
+    requirements: &DeploymentRequirements,
+) -> String {
+    let os = requirements.os;
+    // Sorted so the rendered script is stable between runs
+    let (build_env, runtime_env): (BTreeMap<_, _>, BTreeMap<_, _>) = requirements
+        .environment_variables
+        .iter()
+        .partition(|(name, _)| is_build_time_env_var(name));
+    let registry_logins: BTreeMap<_, _> = requirements.registry_credentials.iter().collect();
+
+    let mut script = String::from("#!/bin/bash\nset -e\n\n");
+    if let Some(port) = requirements.ssh_port {
+        // The firewall only opens this port, so sshd moves before anything slow runs
+        script.push_str(&ssh::sshd_port_script(port));
+    }
+
+    // System packages
+    let mut packages = system_packages(&analysis.app_type, os);
+    let proxied_port = proxied_port(analysis, requirements);
+    if proxied_port.is_some() {
+        packages.push("nginx");
+    }
+    if !registry_logins.is_empty() {
+        packages.push(docker_package(os));
+    }
+    if requirements.secrets_source.as_ref().is_some_and(|source| source.cloud_provider == CloudProvider::AWS) {
+        // For installing the AWS CLI on images without it
+        packages.extend(["curl", "unzip"]);
+    }
+    script.push_str(&install_packages(os, &packages));
+    let mut python = "python3".to_string();
+    if let (true, Some(version)) = (is_python(&analysis.app_type), &analysis.python_version) {
+        let (install, interpreter) = install_python(os, version);
+        script.push_str(&install);
+        python = interpreter;
+    }
+    if analysis.app_type == ApplicationType::Rust {
+        script.push_str(INSTALL_RUST);
+    }
+    script.push('\n');
+
+    // Private images referenced by compose files or manifests need a registry login to pull.
+    // Like the env file, the logins arrive over SSH after boot rather than in world-readable
+    // instance metadata.
+    if !registry_logins.is_empty() {
+        script.push_str("systemctl enable --now docker\n");
+        script.push_str(&wait_for_file(REMOTE_DOCKER_CONFIG));
+        script.push_str(&format!(
+            "mkdir -p /root/.docker\ninstall -m 600 {} /root/.docker/config.json\nexport DOCKER_CONFIG=/root/.docker\n\n",
+            REMOTE_DOCKER_CONFIG
+        ));
+    }
+
+    // Fetch the source and move into the directory the app runs from; a previous deploy's
+    // app is stopped and its checkout replaced so the script can be re-run on an existing host
+    script.push_str(&stop_previous_app());
+    script.push_str(&format!("rm -rf {}\n", APP_ROOT));
+    script.push_str(&format!("git clone {} {}\n", shell_quote(repo_url), APP_ROOT));
+    if let Some(commit) = &analysis.commit {
+        // The commit that was analyzed, even if the branch has moved on since
+        script.push_str(&format!("git -C {} checkout -q {}\n", APP_ROOT, commit));
+    }
+    if repository::recurse_submodules() {
+        // A missing submodule should not stop the rest of the app from starting
+        script.push_str(&format!(
+            "git -C {} submodule update --init --recursive || echo 'Warning: submodule update failed' >&2\n",
+            APP_ROOT
+        ));
+    }
+    script.push_str(&format!("cd {}\n\n", shell_quote(&app_dir(analysis))));
+
+    // Apps bound to localhost are unreachable from outside the instance
+    script.push_str("find . -name '*.py' -exec sed -i 's/127\\.0\\.0\\.1/0.0.0.0/g' {} \\;\n");
+    script.push_str("find . -name '*.py' -exec sed -i 's/localhost/0.0.0.0/g' {} \\;\n");
+    for ext in ["html", "js", "ts"] {
+        script.push_str(&format!(
+            "find . -name '*.{}' -exec sed -i 's/http:\\/\\/localhost:5000//g' {{}} \\;\n",
+            ext
+        ));
+    }
+    script.push('\n');
+
+    if is_python(&analysis.app_type) {
+        // Debian images refuse system-wide pip installs, so use a virtualenv
+        script.push_str(&format!("{} -m venv .venv\n", python));
+        script.push_str(". .venv/bin/activate\n\n");
+    }
+
+    if requirements.expects_env_file {
+        // Secrets arrive over SSH after boot rather than in world-readable instance metadata
+        script.push_str(&wait_for_file(REMOTE_ENV_FILE));
+        script.push_str(&format!("set -a\n. {}\nset +a\n\n", REMOTE_ENV_FILE));
+    }
+
+    if let Some(source) = &requirements.secrets_source {
+        // Fetched with the instance's role, so they never appear in user_data
+        script.push_str(&source.fetch_script());
+    }
+
+    push_exports(&mut script, &build_env);
+    for cmd in &analysis.build_commands {
+        script.push_str(&format!("{}\n", cmd));
+    }
+    script.push('\n');
+
+    push_exports(&mut script, &runtime_env);
+    if let (Some(commit), false) = (&analysis.commit, requirements.environment_variables.contains_key("GIT_COMMIT")) {
+        script.push_str(&format!("export GIT_COMMIT={}\n", commit));
+    }
+    // The app binds to its internal port; a reverse proxy (if any) owns the exposed ones
+    if let (Some(port), false) = (analysis.internal_port, requirements.environment_variables.contains_key("PORT")) {
+        script.push_str(&format!("export PORT={}\n", port));
+    }
+    // Each command leads its own process group, so stopping it also stops what it spawned
+    // (e.g. node under `npm start`)
+    for cmd in &analysis.start_commands {
+        script.push_str(&format!("setsid nohup {} > /var/log/app.log 2>&1 < /dev/null &\n", cmd));
+        script.push_str(&format!("echo $! >> {}\n", PID_FILE));
+    }
+    if let Some(port) = proxied_port {
+        let static_location = analysis
+            .static_serving
+            .as_ref()
+            .filter(|serving| serving.served_by == StaticServer::Proxy)
+            .map(|serving| serving.nginx_location(&app_dir(analysis)));
+        script.push_str(&nginx_proxy_script(port, static_location.as_deref()));
+    }
+
+    script
+}
+
+/// `analysis` with its build and start commands replaced by building the repository's
+/// Dockerfile and running it with `--gpus all`, for a containerized app on a GPU instance.
+/// The container shares the host network, so it listens where the app would.
+pub fn gpu_container(analysis: &RepositoryAnalysis, requirements: &DeploymentRequirements) -> RepositoryAnalysis {
+    let mut names: Vec<&String> = requirements
+        .environment_variables
+        .keys()
+        .filter(|name| !is_build_time_env_var(name))
+        .collect();
+    names.sort();
+    let mut flags: Vec<String> = names.iter().map(|name| format!("-e {} ", name)).collect();
+    if analysis.commit.is_some() && !requirements.environment_variables.contains_key("GIT_COMMIT") {
+        flags.push("-e GIT_COMMIT ".to_string());
+    }
+    if analysis.internal_port.is_some() && !requirements.environment_variables.contains_key("PORT") {
+        flags.push("-e PORT ".to_string());
+    }
+    if requirements.expects_env_file {
+        flags.push(format!("--env-file {} ", REMOTE_ENV_FILE));
+    }
+    RepositoryAnalysis {
+        build_commands: vec![
+            install_container_toolkit(requirements.os),
+            dockerfile::build_script(dockerfile::IMAGE_NAME, settings::get().build_cache_image.as_deref()),
+        ],
+        start_commands: vec![format!("docker run --rm --gpus all --network host {}{}", flags.concat(), dockerfile::IMAGE_NAME)],
+        ..analysis.clone()
+    }
+}
+
+/// Commands installing the NVIDIA Container Toolkit, which `docker run --gpus` needs to reach
+/// the driver of the GPU image, and Docker itself where the image has none
+fn install_container_toolkit(os: OsFamily) -> String {
+    let install = match os {
+        OsFamily::Ubuntu | OsFamily::Debian => {
+            "command -v docker >/dev/null || apt-get install -y docker.io\n\
+             curl -fsSL https://nvidia.github.io/libnvidia-container/gpgkey | gpg --dearmor --yes -o /usr/share/keyrings/nvidia-container-toolkit-keyring.gpg\n\
+             curl -fsSL https://nvidia.github.io/libnvidia-container/stable/deb/nvidia-container-toolkit.list \
+             | sed 's#deb https://#deb [signed-by=/usr/share/keyrings/nvidia-container-toolkit-keyring.gpg] https://#g' \
+             > /etc/apt/sources.list.d/nvidia-container-toolkit.list\n\
+             apt-get update -y\n\
+             apt-get install -y nvidia-container-toolkit"
+        }
+        OsFamily::AmazonLinux => {
+            "command -v docker >/dev/null || dnf install -y docker\n\
+             curl -fsSL https://nvidia.github.io/libnvidia-container/stable/rpm/nvidia-container-toolkit.repo > /etc/yum.repos.d/nvidia-container-toolkit.repo\n\
+             dnf install -y nvidia-container-toolkit"
+        }
+    };
+    format!("{}\nnvidia-ctk runtime configure --runtime=docker\nsystemctl enable docker\nsystemctl restart docker", install)
+}
+
+/// Port nginx forwards port 80 to, when the app listens behind the exposed ports rather than
+/// on one of them (the repository ships a reverse proxy config)
+fn proxied_port(analysis: &RepositoryAnalysis, requirements: &DeploymentRequirements) -> Option<u16> {
+    let internal_port = analysis.internal_port?;
+    if analysis.exposed_ports.contains(&internal_port) || !analysis.exposed_ports.contains(&80) {
+        return None;
+    }
+    // A PORT chosen by the user moves the app, so the proxy follows it
+    let port = requirements.environment_variables.get("PORT").and_then(|port| port.parse().ok());
+    Some(port.unwrap_or(internal_port))
+}
+
+/// Commands configuring nginx to forward port 80 to the app on `port`, serving the static
+/// files at `static_location` itself. The repository's own proxy config is written for its
+/// authors' hosts, so a plain forwarding site replaces the distribution's default one.
+fn nginx_proxy_script(port: u16, static_location: Option<&str>) -> String {
+    let static_location: String = static_location
+        .map(|location| location.lines().map(|line| format!("    {}\n", line)).collect())
+        .unwrap_or_default();
+    format!(
+        "\ncat > /etc/nginx/conf.d/app.conf <<'NGINX'\n\
+         server {{\n\
+         \x20   listen 80 default_server;\n\
+         {}\
+         \x20   location / {{\n\
+         \x20       proxy_pass http://127.0.0.1:{};\n\
+         \x20       proxy_set_header Host $host;\n\
+         \x20       proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;\n\
+         \x20       proxy_set_header X-Forwarded-Proto $scheme;\n\
+         \x20   }}\n\
+         }}\n\
+         NGINX\n\
+         rm -f /etc/nginx/sites-enabled/default\n\
+         systemctl enable nginx\n\
+         systemctl restart nginx\n",
+        static_location,
+        port
+    )
+}
+
+/// Point instance resources at the base image of the chosen OS so the image matches the
+/// package manager used by the startup script.
+pub fn apply_base_image(config: &mut TerraformConfig, os: OsFamily) -> Result<()> {
+    for resource in &mut config.resources {
+        match resource.resource_type.as_str() {
+            "aws_instance" => {
+                // Public SSM parameters resolve to the latest image in whichever region is used
+                let ami = format!("resolve:ssm:{}", aws_image_parameter(os));
+                resource.config.insert("ami".to_string(), serde_json::Value::String(ami));
+            }
+            "google_compute_instance" => {
+                let image = gcp_image(os)
+                    .ok_or_else(|| anyhow!("{:?} images are not available on GCP", os))?;
+                let boot_disk = resource
+                    .config
+                    .entry("boot_disk".to_string())
+                    .or_insert_with(|| serde_json::json!({}));
+                if !boot_disk.is_object() {
+                    *boot_disk = serde_json::json!({});
+                }
+                let params = boot_disk
+                    .as_object_mut()
+                    .unwrap()
+                    .entry("initialize_params")
+                    .or_insert_with(|| serde_json::json!({}));
+                if !params.is_object() {
+                    *params = serde_json::json!({});
+                }
+                params
+                    .as_object_mut()
+                    .unwrap()
+                    .insert("image".to_string(), serde_json::Value::String(image.to_string()));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Replace the AI-generated startup script on instance resources with the rendered one.
+pub fn apply_startup_script(config: &mut TerraformConfig, script: &str) {
+    for resource in &mut config.resources {
+        let key = match resource.resource_type.as_str() {
+            "aws_instance" => "user_data",
+            "google_compute_instance" => "metadata_startup_script",
+            _ => continue,
+        };
+        resource.config.insert(key.to_string(), serde_json::Value::String(script.to_string()));
+    }
+}
+
+/// Carry the rendered startup script over to a config the AI regenerated from `previous`
+pub fn preserve_startup_script(previous: &TerraformConfig, regenerated: &mut TerraformConfig) {
+    let script = previous.resources.iter().find_map(|resource| {
+        let key = match resource.resource_type.as_str() {
+            "aws_instance" => "user_data",
+            "google_compute_instance" => "metadata_startup_script",
+            _ => return None,
+        };
+        resource.config.get(key)?.as_str()
+    });
+    if let Some(script) = script {
+        apply_startup_script(regenerated, script);
+    }
+}
+
+/// Startup script of the first VM in the config, as the instance will run it: `user_data`
+/// or `user_data_base64` on AWS, `metadata_startup_script` or the `startup-script` metadata
+/// key on GCP, `custom_data` on Azure. Base64 attributes are decoded.
+pub fn startup_script(config: &TerraformConfig) -> Option<String> {
+    config.resources.iter().find_map(|resource| {
+        let attribute = |key: &str| resource.config.get(key).and_then(|value| value.as_str());
+        let (script, encoded) = match resource.resource_type.as_str() {
+            "aws_instance" => match attribute("user_data_base64") {
+                Some(script) => (script, true),
+                None => (attribute("user_data")?, false),
+            },
+            "aws_launch_template" => (attribute("user_data")?, true),
+            "google_compute_instance" | "google_compute_instance_template" => match attribute("metadata_startup_script") {
+                Some(script) => (script, false),
+                None => (resource.config.get("metadata")?.get("startup-script")?.as_str()?, false),
+            },
+            "azurerm_linux_virtual_machine" | "azurerm_virtual_machine" => (attribute("custom_data")?, true),
+            _ => return None,
+        };
+        // A Terraform expression such as base64encode(...) is shown as written
+        let decoded = encoded
+            .then(|| STANDARD.decode(script.trim()).ok().and_then(|bytes| String::from_utf8(bytes).ok()))
+            .flatten();
+        Some(decoded.unwrap_or_else(|| script.to_string()))
+    })
+}
+
+/// Docker config holding `logins`, delivered to `REMOTE_DOCKER_CONFIG`; `None` without any
+pub fn docker_config(logins: &HashMap<String, RegistryCredentials>) -> Option<String> {
+    if logins.is_empty() {
+        return None;
+    }
+    let auths: serde_json::Map<_, _> = logins
+        .iter()
+        .map(|(registry, login)| {
+            // `docker login docker.io` files Docker Hub under its v1 index URL
+            let key = if registry == "docker.io" { "https://index.docker.io/v1/" } else { registry.as_str() };
+            let auth = STANDARD.encode(format!("{}:{}", login.username, login.password));
+            (key.to_string(), serde_json::json!({ "auth": auth }))
+        })
+        .collect();
+    Some(serde_json::json!({ "auths": auths }).to_string())
+}
+
+/// Wait up to 10 minutes for `path` to be uploaded
+/// Stop the process groups a previous run started, waiting up to 10 seconds for each so its
+/// port is free before the new app binds it
+fn stop_previous_app() -> String {
+    format!(
+        "if [ -f {0} ]; then\n  while read -r pid; do\n    kill -TERM -- -\"$pid\" 2>/dev/null || continue\n    for i in $(seq 1 10); do kill -0 -- -\"$pid\" 2>/dev/null || break; sleep 1; done\n    kill -KILL -- -\"$pid\" 2>/dev/null || true\n  done < {0}\n  rm -f {0}\nfi\n",
+        PID_FILE
+    )
+}
+
+fn wait_for_file(path: &str) -> String {
+    format!("for i in $(seq 1 120); do [ -f {0} ] && break; sleep 5; done\n", path)
+}
+
+fn push_exports(script: &mut String, vars: &BTreeMap<&String, &String>) {
+    for (name, value) in vars {
+        script.push_str(&format!("export {}={}\n", name, shell_quote(value)));
+    }
+}
+
+/// Absolute path on the instance the app is built and started from
+pub fn app_dir(analysis: &RepositoryAnalysis) -> String {
+    match &analysis.working_dir {
+        Some(dir) => format!("{}/{}", APP_ROOT, dir.trim_matches('/')),
+        None => APP_ROOT.to_string(),
+    }
+}
+
+/// SSM parameter holding the current x86_64 image id for the OS
+fn aws_image_parameter(os: OsFamily) -> &'static str {
+    match os {
+        OsFamily::Ubuntu => "/aws/service/canonical/ubuntu/server/22.04/stable/current/amd64/hvm/ebs-gp2/ami-id",
+        OsFamily::Debian => "/aws/service/debian/release/12/latest/amd64",
+        OsFamily::AmazonLinux => "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64",
+    }
+}
+
+/// GCP public image family for the OS, if GCP publishes one
+fn gcp_image(os: OsFamily) -> Option<&'static str> {
+    match os {
+        OsFamily::Ubuntu => Some("ubuntu-os-cloud/ubuntu-2204-lts"),
+        OsFamily::Debian => Some("debian-cloud/debian-12"),
+        OsFamily::AmazonLinux => None,
+    }
+}
+
+fn docker_package(os: OsFamily) -> &'static str {
+    match os {
+        OsFamily::Ubuntu | OsFamily::Debian => "docker.io",
+        OsFamily::AmazonLinux => "docker",
+    }
+}
+
+fn install_packages(os: OsFamily, packages: &[&str]) -> String {
+    match os {
+        OsFamily::Ubuntu | OsFamily::Debian => format!(
+            "export DEBIAN_FRONTEND=noninteractive\napt-get update -y\napt-get install -y {}\n",
+            packages.join(" ")
+        ),
+        OsFamily::AmazonLinux => format!("dnf install -y {}\n", packages.join(" ")),
+    }
+}
+
+/// Current stable Rust from rustup: distribution cargo is too old for most crates (Debian 12
+/// ships rustc 1.63). Installed system-wide, as the script may run without a `$HOME`.
+const INSTALL_RUST: &str = "export RUSTUP_HOME=/usr/local/rustup CARGO_HOME=/usr/local/cargo\n\
+     curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y --profile minimal --no-modify-path\n\
+     export PATH=/usr/local/cargo/bin:$PATH\n";
+
+/// Commands installing Python `version` beside the OS's own python3, and the interpreter to
+/// create the virtualenv with: deadsnakes on Ubuntu, the distribution's packages on Amazon
+/// Linux and pyenv on Debian, which has neither
+fn install_python(os: OsFamily, version: &str) -> (String, String) {
+    match os {
+        OsFamily::Ubuntu => (
+            format!(
+                "apt-get install -y software-properties-common\nadd-apt-repository -y ppa:deadsnakes/ppa\n\
+                 apt-get update -y\napt-get install -y python{0} python{0}-venv\n",
+                version
+            ),
+            format!("python{}", version),
+        ),
+        OsFamily::AmazonLinux => (format!("dnf install -y python{0} python{0}-pip\n", version), format!("python{}", version)),
+        OsFamily::Debian => (
+            format!(
+                "apt-get install -y build-essential curl libbz2-dev libffi-dev liblzma-dev libreadline-dev libsqlite3-dev libssl-dev zlib1g-dev\n\
+                 export PYENV_ROOT=/opt/pyenv\n\
+                 [ -d $PYENV_ROOT ] || git clone https://github.com/pyenv/pyenv.git $PYENV_ROOT\n\
+                 $PYENV_ROOT/bin/pyenv install -s {}\n",
+                version
+            ),
+            format!("\"$PYENV_ROOT/versions/$($PYENV_ROOT/bin/pyenv latest {})/bin/python\"", version),
+        ),
+    }
+}
+
+fn system_packages(app_type: &ApplicationType, os: OsFamily) -> Vec<&'static str> {
+    let apt = matches!(os, OsFamily::Ubuntu | OsFamily::Debian);
+    let mut packages = vec!["git"];
+    match app_type {
+        ApplicationType::Flask | ApplicationType::Django | ApplicationType::FastAPI => {
+            // Amazon Linux ships venv with python3
+            packages.extend(["python3", "python3-pip"]);
+            if apt {
+                packages.push("python3-venv");
+            }
+        }
+        ApplicationType::NodeJS
+        | ApplicationType::Express
+        | ApplicationType::React
+        | ApplicationType::NextJS => {
+            packages.extend(["nodejs", "npm"]);
+        }
+        ApplicationType::Rails if apt => packages.extend(["ruby-full", "build-essential"]),
+        ApplicationType::Rails => packages.extend(["ruby", "ruby-devel", "gcc", "make"]),
+        ApplicationType::Spring if apt => packages.extend(["default-jdk", "maven"]),
+        ApplicationType::Spring => packages.extend(["java-17-amazon-corretto-devel", "maven"]),
+        ApplicationType::Go if apt => packages.push("golang-go"),
+        ApplicationType::Go => packages.push("golang"),
+        // The toolchain comes from rustup; crates like openssl-sys link against the system OpenSSL
+        ApplicationType::Rust if apt => packages.extend(["curl", "build-essential", "pkg-config", "libssl-dev"]),
+        ApplicationType::Rust => packages.extend(["curl", "gcc", "openssl-devel"]),
+        ApplicationType::Unknown => {}
+    }
+    packages
+}
+
+fn is_python(app_type: &ApplicationType) -> bool {
+    matches!(
+        app_type,
+        ApplicationType::Flask | ApplicationType::Django | ApplicationType::FastAPI
+    )
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::RegistryCredentials;
+    use crate::infrastructure::TerraformResource;
+    use crate::repository::PackageManager;
+    use std::collections::HashMap;
+
+    fn flask_analysis(working_dir: Option<&str>) -> RepositoryAnalysis {
+        RepositoryAnalysis {
+            app_type: ApplicationType::Flask,
+            dependencies: vec!["Flask".to_string()],
+            build_commands: vec!["pip install -r requirements.txt".to_string()],
+            start_commands: vec!["python app.py".to_string()],
+            environment_variables: vec![],
+            exposed_ports: vec![5000],
+            internal_port: Some(5000),
+            static_files_dir: None,
+            database_migrations: false,
+            requires_build_step: true,
+            docker_config: None,
+            package_manager: PackageManager::Pip,
+            working_dir: working_dir.map(|d| d.to_string()),
+            build_env_vars: vec![],
+            container_images: vec![],
+            framework_version: None,
+            environment_files: Default::default(),
+            static_serving: None,
+            health_endpoint: None,
+            not_a_service: None,
+            build_size_mb: 0,
+            node_package: None,
+            cargo_binary: None,
+            app_module: None,
+            runtime_version: None,
+            python_version: None,
+            commit: None,
+            configured_env: BTreeMap::new(),
+            databases: Vec::new(),
+            gpu_reasons: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_startup_script_uses_working_dir() {
+        let script = render_startup_script(
+            &flask_analysis(Some("backend")),
+            "https://github.com/test/repo",
+            &DeploymentRequirements::default(),
+        );
+
+        assert!(script.contains("git clone 'https://github.com/test/repo' /opt/app"));
+        assert!(script.contains("cd '/opt/app/backend'"));
+
+        // Install and start must happen after changing into the working directory
+        let cd_pos = script.find("cd '/opt/app/backend'").unwrap();
+        assert!(script.find("pip install -r requirements.txt").unwrap() > cd_pos);
+        assert!(script.find("nohup python app.py").unwrap() > cd_pos);
+    }
+
+    #[test]
+    fn test_startup_script_defaults_to_clone_root() {
+        let script = render_startup_script(
+            &flask_analysis(None),
+            "https://github.com/test/repo",
+            &DeploymentRequirements::default(),
+        );
+        assert!(script.contains("cd '/opt/app'\n"));
+    }
+
+    #[test]
+    fn test_startup_script_stops_previous_app() {
+        let script = render_startup_script(&flask_analysis(None), "https://github.com/test/repo", &DeploymentRequirements::default());
+        let stop = script.find("kill -TERM -- -\"$pid\"").unwrap();
+        assert!(stop < script.find("rm -rf /opt/app").unwrap());
+        assert!(script.contains("done < /var/run/autodeployment-app.pids\n  rm -f /var/run/autodeployment-app.pids\n"));
+        let start = script.find("setsid nohup python app.py > /var/log/app.log 2>&1 < /dev/null &\necho $! >> /var/run/autodeployment-app.pids\n").unwrap();
+        assert!(stop < start);
+    }
+
+    #[test]
+    fn test_startup_script_splits_build_and_runtime_env() {
+        let mut analysis = flask_analysis(None);
+        analysis.build_commands = vec!["npm run build".to_string()];
+        analysis.start_commands = vec!["npm start".to_string()];
+
+        let mut requirements = DeploymentRequirements::default();
+        requirements.environment_variables.insert("REACT_APP_API_URL".to_string(), "https://api.example.com".to_string());
+        requirements.environment_variables.insert("SECRET_KEY".to_string(), "it's secret".to_string());
+
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+
+        let build_export = script.find("export REACT_APP_API_URL='https://api.example.com'").unwrap();
+        let runtime_export = script.find("export SECRET_KEY='it'\\''s secret'").unwrap();
+        let build = script.find("npm run build").unwrap();
+        let start = script.find("nohup npm start").unwrap();
+
+        assert!(build_export < build);
+        assert!(build < runtime_export);
+        assert!(runtime_export < start);
+    }
+
+    #[test]
+    fn test_startup_script_exports_internal_port() {
+        let mut analysis = flask_analysis(None);
+        analysis.internal_port = Some(8001);
+        analysis.exposed_ports = vec![80];
+
+        let mut requirements = DeploymentRequirements::default();
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        assert!(script.find("export PORT=8001").unwrap() < script.find("nohup").unwrap());
+        // nginx owns port 80 and forwards it to the app
+        assert!(script.contains("apt-get install -y git python3 python3-pip python3-venv nginx\n"));
+        assert!(script.contains("    listen 80 default_server;\n"));
+        assert!(script.contains("        proxy_pass http://127.0.0.1:8001;\n"));
+        assert!(script.find("systemctl restart nginx").unwrap() > script.find("nohup").unwrap());
+
+        // A PORT chosen by the user wins
+        requirements.environment_variables.insert("PORT".to_string(), "9000".to_string());
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        assert!(script.contains("export PORT='9000'"));
+        assert!(!script.contains("export PORT=8001"));
+        assert!(script.contains("proxy_pass http://127.0.0.1:9000;"));
+
+        // Without a proxy in front the app is reached directly
+        analysis.exposed_ports = vec![8001];
+        assert!(!render_startup_script(&analysis, "https://github.com/test/repo", &requirements).contains("nginx"));
+    }
+
+    #[test]
+    fn test_nginx_serves_proxy_static_files() {
+        let mut analysis = flask_analysis(Some("backend"));
+        analysis.internal_port = Some(8000);
+        analysis.exposed_ports = vec![80];
+        analysis.static_serving = Some(repository::StaticServing {
+            url_path: "/static".to_string(),
+            dir: "staticfiles".to_string(),
+            served_by: StaticServer::Proxy,
+            reason: "Django does not serve static files with DEBUG off".to_string(),
+            collect: true,
+        });
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &DeploymentRequirements::default());
+        assert!(script.contains(
+            "    listen 80 default_server;\n    location /static/ {\n        alias /opt/app/backend/staticfiles/;\n        expires 7d;\n    }\n    location / {\n"
+        ));
+
+        analysis.static_serving.as_mut().unwrap().served_by = StaticServer::App;
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &DeploymentRequirements::default());
+        assert!(!script.contains("alias"));
+    }
+
+    #[test]
+    fn test_gpu_container_runs_with_gpus() {
+        let mut analysis = flask_analysis(None);
+        analysis.docker_config = Some(repository::DockerConfig {
+            dockerfile_path: "Dockerfile".to_string(),
+            exposed_ports: vec![5000],
+            volumes: vec![],
+            start_command: Some(vec!["python".to_string(), "serve.py".to_string()]),
+        });
+        let mut requirements = DeploymentRequirements::default();
+        requirements.environment_variables.insert("MODEL_NAME".to_string(), "llama".to_string());
+        requirements.environment_variables.insert("REACT_APP_TITLE".to_string(), "Demo".to_string());
+
+        let script = render_startup_script(&gpu_container(&analysis, &requirements), "https://github.com/test/repo", &requirements);
+        let toolkit = script.find("apt-get install -y nvidia-container-toolkit\nnvidia-ctk runtime configure --runtime=docker\n").unwrap();
+        let build = script.find("  DOCKER_BUILDKIT=1 docker build --build-arg BUILDKIT_INLINE_CACHE=1 -t app .\n").unwrap();
+        let run = script.find("nohup docker run --rm --gpus all --network host -e MODEL_NAME -e PORT app > /var/log/app.log").unwrap();
+        assert!(toolkit < build && build < run);
+        assert!(!script.contains("pip install -r requirements.txt") && !script.contains("serve.py"));
+
+        requirements.os = OsFamily::AmazonLinux;
+        assert!(render_startup_script(&gpu_container(&analysis, &requirements), "https://github.com/test/repo", &requirements)
+            .contains("dnf install -y nvidia-container-toolkit\n"));
+    }
+
+    #[test]
+    fn test_startup_script_pins_analyzed_commit() {
+        let mut analysis = flask_analysis(None);
+        let requirements = DeploymentRequirements::default();
+        assert!(!render_startup_script(&analysis, "https://github.com/test/repo", &requirements).contains("GIT_COMMIT"));
+        
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        analysis.commit = Some(sha.to_string());
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        let clone = script.find("git clone").unwrap();
+        assert!(script.find(&format!("git -C /opt/app checkout -q {}", sha)).unwrap() > clone);
+        assert!(script.find(&format!("export GIT_COMMIT={}", sha)).unwrap() < script.find("nohup").unwrap());
+    }
+
+    #[test]
+    fn test_startup_script_installs_pinned_python() {
+        let mut analysis = flask_analysis(None);
+        analysis.python_version = Some("3.9".to_string());
+        let mut requirements = DeploymentRequirements::default();
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        let install = script.find("add-apt-repository -y ppa:deadsnakes/ppa").unwrap();
+        assert!(script.contains("apt-get install -y python3.9 python3.9-venv\n"));
+        assert!(script.find("python3.9 -m venv .venv").unwrap() > install);
+
+        requirements.os = OsFamily::AmazonLinux;
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        assert!(script.contains("dnf install -y python3.9 python3.9-pip\n"));
+
+        requirements.os = OsFamily::Debian;
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        assert!(script.contains("$PYENV_ROOT/bin/pyenv install -s 3.9\n"));
+        assert!(script.contains("\"$PYENV_ROOT/versions/$($PYENV_ROOT/bin/pyenv latest 3.9)/bin/python\" -m venv .venv"));
+
+        analysis.python_version = None;
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        assert!(script.contains("python3 -m venv .venv") && !script.contains("pyenv"));
+    }
+
+    #[test]
+    fn test_startup_script_installs_rust_with_rustup() {
+        let analysis = RepositoryAnalysis {
+            app_type: ApplicationType::Rust,
+            build_commands: vec!["cargo build --release".to_string()],
+            ..flask_analysis(None)
+        };
+        let requirements = DeploymentRequirements { os: OsFamily::Debian, ..Default::default() };
+        let script = render_startup_script(&analysis, "https://github.com/test/repo", &requirements);
+        assert!(script.contains("apt-get install -y git curl build-essential pkg-config libssl-dev"), "{}", script);
+        let rustup = script.find("https://sh.rustup.rs | sh -s -- -y --profile minimal").unwrap();
+        assert!(script.find("cargo build --release").unwrap() > rustup);
+        assert!(!script.contains(" cargo "), "the distribution's cargo is too old");
+    }
+
+    #[test]
+    fn test_apply_startup_script() {
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                TerraformResource {
+                    resource_type: "aws_instance".to_string(),
+                    name: "app_instance".to_string(),
+                    config: HashMap::new(),
+                },
+                TerraformResource {
+                    resource_type: "aws_security_group".to_string(),
+                    name: "app_sg".to_string(),
+                    config: HashMap::new(),
+                },
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+
+        apply_startup_script(&mut config, "echo hello");
+
+        assert_eq!(
+            config.resources[0].config.get("user_data"),
+            Some(&serde_json::Value::String("echo hello".to_string()))
+        );
+        assert!(config.resources[1].config.is_empty());
+    }
+
+    #[test]
+    fn test_startup_script_package_manager_follows_os() {
+        let analysis = flask_analysis(None);
+        let url = "https://github.com/test/repo";
+
+        let mut requirements = DeploymentRequirements::default();
+        let ubuntu = render_startup_script(&analysis, url, &requirements);
+        assert!(ubuntu.contains("apt-get install -y git python3 python3-pip python3-venv"));
+        assert!(!ubuntu.contains("dnf"));
+
+        requirements.os = OsFamily::AmazonLinux;
+        let amazon = render_startup_script(&analysis, url, &requirements);
+        assert!(amazon.contains("dnf install -y git python3 python3-pip\n"));
+        assert!(!amazon.contains("apt-get"));
+    }
+
+    #[test]
+    fn test_startup_script_logs_into_private_registries() {
+        let url = "https://github.com/test/repo";
+        let script = render_startup_script(&flask_analysis(None), url, &DeploymentRequirements::default());
+        assert!(!script.contains("docker login"));
+
+        let mut requirements = DeploymentRequirements::default();
+        requirements.registry_credentials.insert(
+            "ghcr.io".to_string(),
+            RegistryCredentials { username: "acme".to_string(), password: "tok'en".to_string() },
+        );
+        let script = render_startup_script(&flask_analysis(None), url, &requirements);
+
+        assert!(script.contains("docker.io"));
+        assert!(!script.contains("tok'en") && !script.contains("docker login"), "the password stays out of user_data");
+        let login = script.find("install -m 600 /etc/autodeployment/docker-config.json /root/.docker/config.json").unwrap();
+        assert!(script.find("[ -f /etc/autodeployment/docker-config.json ] && break").unwrap() < login);
+        assert!(login < script.find("git clone").unwrap());
+
+        let config: serde_json::Value = serde_json::from_str(&docker_config(&requirements.registry_credentials).unwrap()).unwrap();
+        assert_eq!(config["auths"]["ghcr.io"]["auth"], STANDARD.encode("acme:tok'en"));
+        assert!(docker_config(&HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn test_startup_script_waits_for_env_file() {
+        let url = "https://github.com/test/repo";
+        let script = render_startup_script(&flask_analysis(None), url, &DeploymentRequirements::default());
+        assert!(!script.contains(REMOTE_ENV_FILE));
+
+        let requirements = DeploymentRequirements {
+            expects_env_file: true,
+            ..DeploymentRequirements::default()
+        };
+        let script = render_startup_script(&flask_analysis(None), url, &requirements);
+        let source = script.find(". /etc/autodeployment/app.env").unwrap();
+        assert!(source < script.find("pip install").unwrap());
+    }
+
+    #[test]
+    fn test_preserve_startup_script() {
+        let instance = |script: &str| TerraformResource {
+            resource_type: "aws_instance".to_string(),
+            name: "app".to_string(),
+            config: HashMap::from([("user_data".to_string(), serde_json::json!(script))]),
+        };
+        let config = |script: &str| TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![instance(script)],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+
+        let previous = config("#!/bin/bash\nrendered");
+        let mut regenerated = config("echo ai rewrote this");
+        preserve_startup_script(&previous, &mut regenerated);
+        assert_eq!(regenerated.resources[0].config["user_data"], "#!/bin/bash\nrendered");
+    }
+
+    #[test]
+    fn test_extract_startup_script_for_preview() {
+        let config = |resource_type: &str, config: serde_json::Value| TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![TerraformResource {
+                resource_type: resource_type.to_string(),
+                name: "app".to_string(),
+                config: serde_json::from_value(config).unwrap(),
+            }],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let script = "#!/bin/bash\necho hello\n";
+        let encoded = STANDARD.encode(script);
+
+        let plain = config("aws_instance", serde_json::json!({"user_data": script}));
+        assert_eq!(startup_script(&plain).as_deref(), Some(script));
+        let base64 = config("aws_instance", serde_json::json!({"user_data_base64": encoded}));
+        assert_eq!(startup_script(&base64).as_deref(), Some(script));
+        let gcp = config("google_compute_instance", serde_json::json!({"metadata": {"startup-script": script}}));
+        assert_eq!(startup_script(&gcp).as_deref(), Some(script));
+        let expression = config("azurerm_linux_virtual_machine", serde_json::json!({"custom_data": "${base64encode(local.init)}"}));
+        assert_eq!(startup_script(&expression).as_deref(), Some("${base64encode(local.init)}"));
+        assert_eq!(startup_script(&config("aws_s3_bucket", serde_json::json!({}))), None);
+    }
+
+    #[test]
+    fn test_apply_base_image() {
+        let mut config = TerraformConfig {
+            provider: "google".to_string(),
+            resources: vec![TerraformResource {
+                resource_type: "google_compute_instance".to_string(),
+                name: "app".to_string(),
+                config: HashMap::from([(
+                    "boot_disk".to_string(),
+                    serde_json::json!({"initialize_params": {"image": "debian-cloud/debian-11", "size": 20}}),
+                )]),
+            }],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+
+        apply_base_image(&mut config, OsFamily::Ubuntu).unwrap();
+        let params = &config.resources[0].config["boot_disk"]["initialize_params"];
+        assert_eq!(params["image"], "ubuntu-os-cloud/ubuntu-2204-lts");
+        assert_eq!(params["size"], 20);
+
+        // Amazon Linux has no GCP image
+        assert!(apply_base_image(&mut config, OsFamily::AmazonLinux).is_err());
+
+        config.resources[0].resource_type = "aws_instance".to_string();
+        apply_base_image(&mut config, OsFamily::AmazonLinux).unwrap();
+        assert_eq!(
+            config.resources[0].config["ami"],
+            "resolve:ssm:/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+static LIMIT: OnceLock<usize> = OnceLock::new();
+static SEMAPHORE: OnceLock<Arc<Semaphore>> = OnceLock::new();
+
+/// Set the process-wide concurrency limit. Must be called before any bounded work starts.
+/// It bounds the repository scan threads and the parallel localhost rewrites only;
+/// AI calls, Terraform runs and stack or multi-region deploys are not throttled.
+pub fn init(limit: Option<usize>) -> Result<()> {
+    let limit = limit.unwrap_or_else(default_limit);
+    if limit == 0 {
+        return Err(anyhow!("--concurrency must be at least 1"));
+    }
+    LIMIT
+        .set(limit)
+        .map_err(|_| anyhow!("Concurrency limit already initialized"))?;
+    Ok(())
+}
+
+/// Maximum number of operations allowed to run at once
+pub fn limit() -> usize {
+    *LIMIT.get_or_init(default_limit)
+}
+
+/// Wait for a slot in the shared semaphore. The slot is released when the permit is dropped.
+pub async fn acquire() -> OwnedSemaphorePermit {
+    acquire_from(SEMAPHORE.get_or_init(|| Arc::new(Semaphore::new(limit())))).await
+}
+
+async fn acquire_from(semaphore: &Arc<Semaphore>) -> OwnedSemaphorePermit {
+    semaphore
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("concurrency semaphore is never closed")
+}
+
+fn default_limit() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_limit_is_positive() {
+        assert!(default_limit() >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_releases_on_drop() {
+        // A local semaphore, so other tests holding global permits cannot interfere
+        let semaphore = Arc::new(Semaphore::new(3));
+        let permits = acquire_all(&semaphore, 3).await;
+        assert_eq!(semaphore.available_permits(), 0);
+        assert!(semaphore.clone().try_acquire_owned().is_err());
+        drop(permits);
+
+        // All slots are free again
+        assert_eq!(semaphore.available_permits(), 3);
+        let again = acquire_all(&semaphore, 3).await;
+        assert_eq!(again.len(), 3);
+    }
+
+    async fn acquire_all(semaphore: &Arc<Semaphore>, n: usize) -> Vec<OwnedSemaphorePermit> {
+        let mut permits = Vec::new();
+        for _ in 0..n {
+            permits.push(acquire_from(semaphore).await);
+        }
+        permits
+    }
+}
@@ -0,0 +1,117 @@
+use log::LevelFilter;
+use std::future::Future;
+use std::io::Write;
+
+tokio::task_local! {
+    static DEPLOYMENT_ID: String;
+}
+
+/// Generate a short id identifying a single deploy in logs and errors
+pub fn new_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// Run `future` with `id` attached to every log line it emits
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    DEPLOYMENT_ID.scope(id, future).await
+}
+
+/// Run blocking `f` with `id` attached, for work moved onto blocking threads
+pub fn sync_scope<R>(id: Option<String>, f: impl FnOnce() -> R) -> R {
+    match id {
+        Some(id) => DEPLOYMENT_ID.sync_scope(id, f),
+        None => f(),
+    }
+}
+
+/// Id of the deploy running on the current task, if any
+pub fn current() -> Option<String> {
+    DEPLOYMENT_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Modules that log the AI's prompts and responses, silenced below `info` by `--quiet-ai`
+const AI_MODULES: &[&str] = &["llm", "ai_nlp"];
+
+/// Logger filtered by `RUST_LOG` (`info` when unset); each `-v` raises the level, to `debug`
+/// and then `trace`. `quiet_ai` keeps the AI modules at `info` whatever the level.
+fn logger_builder(rust_log: Option<&str>, verbose: u8, quiet_ai: bool) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Info);
+    if let Some(filters) = rust_log {
+        builder.parse_filters(filters);
+    }
+    match verbose {
+        0 => {}
+        1 => {
+            builder.filter_level(LevelFilter::Debug);
+        }
+        _ => {
+            builder.filter_level(LevelFilter::Trace);
+        }
+    }
+    if quiet_ai {
+        for module in AI_MODULES {
+            builder.filter_module(&format!("{}::{}", env!("CARGO_CRATE_NAME"), module), LevelFilter::Info);
+        }
+    }
+    builder
+}
+
+/// Initialise env_logger with the current deployment id prefixed to each message
+pub fn init_logger(verbose: u8, quiet_ai: bool) {
+    logger_builder(std::env::var("RUST_LOG").ok().as_deref(), verbose, quiet_ai)
+        .format(|buf, record| {
+            let prefix = current().map(|id| format!("[{}] ", id)).unwrap_or_default();
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}{}",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.target(),
+                prefix,
+                record.args()
+            )
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_id_is_short_and_unique() {
+        let id = new_id();
+        assert_eq!(id.len(), 8);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, new_id());
+    }
+
+    #[tokio::test]
+    async fn test_scope_sets_current_id() {
+        assert_eq!(current(), None);
+        let inner = scope("abcd1234".to_string(), async { current() }).await;
+        assert_eq!(inner.as_deref(), Some("abcd1234"));
+        assert_eq!(sync_scope(inner, current).as_deref(), Some("abcd1234"));
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn test_logger_levels() {
+        let enabled = |logger: &env_logger::Logger, level: log::Level, module: &str| {
+            let target = format!("{}::{}", env!("CARGO_CRATE_NAME"), module);
+            log::Log::enabled(logger, &log::Metadata::builder().level(level).target(&target).build())
+        };
+        let default = logger_builder(None, 0, false).build();
+        assert!(enabled(&default, log::Level::Info, "deployment"));
+        assert!(!enabled(&default, log::Level::Debug, "llm"));
+
+        let very_verbose = logger_builder(Some("warn"), 2, false).build();
+        assert!(enabled(&very_verbose, log::Level::Trace, "llm"));
+
+        let quiet = logger_builder(Some("debug"), 2, true).build();
+        assert!(enabled(&quiet, log::Level::Trace, "deployment"));
+        assert!(!enabled(&quiet, log::Level::Debug, "ai_nlp"));
+        assert!(enabled(&quiet, log::Level::Info, "llm"));
+    }
+}
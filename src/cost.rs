@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a line of an estimate pays for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CostCategory {
+    Compute,
+    Storage,
+    Database,
+    Networking,
+}
+
+/// One priced line of an estimate, e.g. the instance or a NAT gateway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CostItem {
+    pub category: CostCategory,
+    pub description: String,
+    /// Monthly price in USD
+    pub monthly: f64,
+}
+
+/// Monthly cost of a deployment in USD, per category and per line item
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CostEstimate {
+    pub compute: f64,
+    pub storage: f64,
+    pub database: f64,
+    pub networking: f64,
+    pub total: f64,
+    pub breakdown: Vec<CostItem>,
+}
+
+impl CostEstimate {
+    /// Add a line item, counting it in its category and the total
+    pub fn add(&mut self, category: CostCategory, description: impl Into<String>, monthly: f64) {
+        match category {
+            CostCategory::Compute => self.compute += monthly,
+            CostCategory::Storage => self.storage += monthly,
+            CostCategory::Database => self.database += monthly,
+            CostCategory::Networking => self.networking += monthly,
+        }
+        self.total += monthly;
+        self.breakdown.push(CostItem { category, description: description.into(), monthly });
+    }
+
+    /// Replace line item `index`, keeping the category totals in step
+    pub fn replace(&mut self, index: usize, description: impl Into<String>, monthly: f64) {
+        let mut items = std::mem::take(&mut self.breakdown);
+        items[index].description = description.into();
+        items[index].monthly = monthly;
+        *self = CostEstimate::default();
+        for item in items {
+            self.add(item.category, item.description, item.monthly);
+        }
+    }
+
+    /// The same deployment copied `copies` times: every line item is multiplied
+    pub fn times(&self, copies: usize) -> CostEstimate {
+        let mut scaled = CostEstimate::default();
+        for item in &self.breakdown {
+            let description = if copies > 1 { format!("{} x{}", item.description, copies) } else { item.description.clone() };
+            scaled.add(item.category, description, item.monthly * copies as f64);
+        }
+        scaled
+    }
+}
+
+/// One line per item, then the total
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.breakdown {
+            writeln!(f, "{:<11} {:<44} {:>10}", format!("{:?}", item.category), item.description, format!("${:.2}", item.monthly))?;
+        }
+        write!(f, "{:<56} {:>10}/mo", "Total", format!("${:.2}", self.total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_estimate_categories_and_copies() {
+        let mut estimate = CostEstimate::default();
+        estimate.add(CostCategory::Compute, "t3.micro instance", 8.76);
+        estimate.add(CostCategory::Storage, "10 GB root disk", 0.80);
+        estimate.add(CostCategory::Networking, "NAT gateway", 32.85);
+        assert!((estimate.total - 42.41).abs() < 1e-9);
+        assert_eq!((estimate.compute, estimate.database, estimate.networking), (8.76, 0.0, 32.85));
+        assert_eq!(estimate.breakdown.len(), 3);
+
+        let doubled = estimate.times(2);
+        assert!((doubled.total - 84.82).abs() < 1e-9);
+        assert_eq!(doubled.breakdown[0].description, "t3.micro instance x2");
+        assert_eq!(estimate.times(1), estimate);
+        let printed = estimate.to_string();
+        assert!(printed.contains("Storage     10 GB root disk"));
+        assert!(printed.ends_with("$42.41/mo"));
+    }
+}
@@ -7,12 +7,18 @@ use std::io::{self, Write};
 use log::info;
 
 use crate::nlp::CloudProvider;
+use crate::settings;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CloudCredentials {
     pub aws: Option<AwsCredentials>,
     pub gcp: Option<GcpCredentials>,
     pub azure: Option<AzureCredentials>,
+    #[serde(default)]
+    pub digitalocean: Option<DigitalOceanCredentials>,
+    /// Container registry logins keyed by registry host
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryCredentials>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +36,12 @@ pub struct GcpCredentials {
     pub region: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RegistryCredentials {
+    pub username: String,
+    pub password: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AzureCredentials {
     pub client_id: String,
@@ -38,12 +50,20 @@ pub struct AzureCredentials {
     pub subscription_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigitalOceanCredentials {
+    pub api_token: String, // Personal access token with write scope
+    pub region: Option<String>,
+}
+
 impl CloudCredentials {
     pub fn new() -> Self {
         Self {
             aws: None,
             gcp: None,
             azure: None,
+            digitalocean: None,
+            registries: HashMap::new(),
         }
     }
 
@@ -92,11 +112,27 @@ impl CloudCredentials {
             CloudProvider::AWS => self.aws.is_some(),
             CloudProvider::GCP => self.gcp.is_some(),
             CloudProvider::Azure => self.azure.is_some(),
-            CloudProvider::DigitalOcean => false, // Not implemented yet
+            CloudProvider::DigitalOcean => self.digitalocean.is_some(),
             CloudProvider::Unknown => false,
         }
     }
 
+    /// Region deployments for `provider` go to, falling back to the configured `region`
+    /// setting and then the provider's default
+    pub fn region_for(&self, provider: &CloudProvider) -> String {
+        let region = match provider {
+            CloudProvider::AWS => self.aws.as_ref().and_then(|aws| aws.region.clone()),
+            CloudProvider::GCP => self.gcp.as_ref().and_then(|gcp| gcp.region.clone()),
+            CloudProvider::DigitalOcean => self.digitalocean.as_ref().and_then(|digitalocean| digitalocean.region.clone()),
+            _ => None,
+        };
+        region.or_else(|| settings::get().region.clone()).unwrap_or_else(|| match provider {
+            CloudProvider::GCP => "us-central1".to_string(),
+            CloudProvider::DigitalOcean => "nyc3".to_string(),
+            _ => "us-east-1".to_string(),
+        })
+    }
+
     pub fn get_credentials_for(&self, provider: &CloudProvider) -> Option<HashMap<String, String>> {
         match provider {
             CloudProvider::AWS => {
@@ -144,7 +180,15 @@ impl CloudCredentials {
                     env_vars
                 })
             },
-            CloudProvider::DigitalOcean => None,
+            CloudProvider::DigitalOcean => {
+                self.digitalocean.as_ref().map(|digitalocean| {
+                    // The Terraform provider reads the first, doctl and the API clients the second
+                    let mut env_vars = HashMap::new();
+                    env_vars.insert("DIGITALOCEAN_TOKEN".to_string(), digitalocean.api_token.clone());
+                    env_vars.insert("DIGITALOCEAN_ACCESS_TOKEN".to_string(), digitalocean.api_token.clone());
+                    env_vars
+                })
+            },
             CloudProvider::Unknown => None,
         }
     }
@@ -167,7 +211,7 @@ pub async fn prompt_for_credentials(provider: &CloudProvider) -> Result<()> {
             prompt_azure_credentials(&mut credentials).await?;
         },
         CloudProvider::DigitalOcean => {
-            return Err(anyhow!("DigitalOcean credentials not yet supported"));
+            prompt_digitalocean_credentials(&mut credentials).await?;
         },
         CloudProvider::Unknown => {
             return Err(anyhow!("Unknown cloud provider"));
@@ -180,6 +224,39 @@ pub async fn prompt_for_credentials(provider: &CloudProvider) -> Result<()> {
     Ok(())
 }
 
+/// Prompt for a container registry login and store it for future deploys
+pub fn prompt_for_registry_credentials(registry: &str) -> Result<RegistryCredentials> {
+    let mut credentials = CloudCredentials::load_from_file().unwrap_or_else(|_| CloudCredentials::new());
+    
+    println!("\n🔐 Setting up credentials for registry {}", registry);
+    println!("==========================================");
+    println!("Use an access token rather than your account password where the registry supports it");
+    println!();
+
+    print!("Username: ");
+    io::stdout().flush()?;
+    let mut username = String::new();
+    io::stdin().read_line(&mut username)?;
+    let username = username.trim().to_string();
+
+    print!("Password or token: ");
+    io::stdout().flush()?;
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    let password = password.trim().to_string();
+
+    if username.is_empty() || password.is_empty() {
+        return Err(anyhow!("Registry username and password are required"));
+    }
+
+    let registry_credentials = RegistryCredentials { username, password };
+    credentials.registries.insert(registry.to_string(), registry_credentials.clone());
+    credentials.save_to_file()?;
+    println!("✅ Credentials for {} saved", registry);
+    
+    Ok(registry_credentials)
+}
+
 async fn prompt_aws_credentials(credentials: &mut CloudCredentials) -> Result<()> {
     println!("🔑 AWS Credentials Setup");
     println!("You can find these in AWS Console > IAM > Users > Security credentials");
@@ -325,6 +402,42 @@ async fn prompt_azure_credentials(credentials: &mut CloudCredentials) -> Result<
     Ok(())
 }
 
+async fn prompt_digitalocean_credentials(credentials: &mut CloudCredentials) -> Result<()> {
+    println!("🔑 DigitalOcean Credentials Setup");
+    println!("You need a personal access token with read and write scope.");
+    println!("Get it from: DigitalOcean Control Panel > API > Generate New Token");
+    println!();
+
+    print!("API Token: ");
+    io::stdout().flush()?;
+    let mut api_token = String::new();
+    io::stdin().read_line(&mut api_token)?;
+    let api_token = api_token.trim().to_string();
+
+    print!("Region (default: nyc3): ");
+    io::stdout().flush()?;
+    let mut region = String::new();
+    io::stdin().read_line(&mut region)?;
+    let region = region.trim();
+    let region = if region.is_empty() {
+        "nyc3".to_string()
+    } else {
+        region.to_string()
+    };
+
+    if api_token.is_empty() {
+        return Err(anyhow!("API token is required"));
+    }
+
+    credentials.digitalocean = Some(DigitalOceanCredentials {
+        api_token,
+        region: Some(region),
+    });
+
+    println!("✅ DigitalOcean credentials configured");
+    Ok(())
+}
+
 fn get_config_path() -> Result<PathBuf> {
     let home_dir = dirs::home_dir()
         .ok_or_else(|| anyhow!("Could not find home directory"))?;
@@ -358,8 +471,15 @@ pub fn check_credentials_status() -> Result<()> {
     println!("AWS:   {}", if credentials.aws.is_some() { "✅ Configured" } else { "❌ Not set" });
     println!("GCP:   {}", if credentials.gcp.is_some() { "✅ Configured" } else { "❌ Not set" });
     println!("Azure: {}", if credentials.azure.is_some() { "✅ Configured" } else { "❌ Not set" });
+    println!("DigitalOcean: {}", if credentials.digitalocean.is_some() { "✅ Configured" } else { "❌ Not set" });
+    
+    let mut registries: Vec<&String> = credentials.registries.keys().collect();
+    registries.sort();
+    for registry in registries {
+        println!("Registry {}: ✅ Configured", registry);
+    }
     
-    if credentials.aws.is_none() && credentials.gcp.is_none() && credentials.azure.is_none() {
+    if credentials.aws.is_none() && credentials.gcp.is_none() && credentials.azure.is_none() && credentials.digitalocean.is_none() {
         println!("\n💡 Set up credentials with: cargo run -- credentials <cloud>");
         println!("   Example: cargo run -- credentials aws");
     }
@@ -377,6 +497,35 @@ mod tests {
         assert!(creds.aws.is_none());
         assert!(creds.gcp.is_none());
         assert!(creds.azure.is_none());
+        assert!(creds.digitalocean.is_none());
+        assert!(creds.registries.is_empty());
+    }
+    
+    #[test]
+    fn test_credentials_file_without_registries() {
+        // Files written before registry logins existed still load
+        let creds: CloudCredentials =
+            serde_json::from_str(r#"{"aws": null, "gcp": null, "azure": null}"#).unwrap();
+        assert!(creds.registries.is_empty());
+        assert!(creds.digitalocean.is_none());
+    }
+    
+    #[test]
+    fn test_digitalocean_credentials_export_tokens() {
+        let mut creds = CloudCredentials::new();
+        assert!(!creds.has_credentials_for(&CloudProvider::DigitalOcean));
+        assert!(creds.get_credentials_for(&CloudProvider::DigitalOcean).is_none());
+        
+        creds.digitalocean = Some(DigitalOceanCredentials {
+            api_token: "dop_v1_test".to_string(),
+            region: Some("fra1".to_string()),
+        });
+        
+        assert!(creds.has_credentials_for(&CloudProvider::DigitalOcean));
+        let env_vars = creds.get_credentials_for(&CloudProvider::DigitalOcean).unwrap();
+        assert_eq!(env_vars["DIGITALOCEAN_TOKEN"], "dop_v1_test");
+        assert_eq!(env_vars["DIGITALOCEAN_ACCESS_TOKEN"], "dop_v1_test");
+        assert_eq!(creds.region_for(&CloudProvider::DigitalOcean), "fra1");
     }
     
     #[test]
@@ -0,0 +1,390 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::infrastructure::{TerraformConfig, TerraformResource};
+use crate::nlp::{CloudProvider, DatabaseType};
+
+/// Name of the generated password and of the security group guarding the databases
+const RESOURCE_NAME: &str = "app_db";
+
+/// Databases that need a managed service; SQLite is a file on the instance's own disk
+pub fn managed_databases(databases: &[DatabaseType]) -> Vec<DatabaseType> {
+    let mut managed: Vec<DatabaseType> = Vec::new();
+    for database in databases {
+        if !matches!(database, DatabaseType::None | DatabaseType::SQLite) && !managed.contains(database) {
+            managed.push(database.clone());
+        }
+    }
+    managed
+}
+
+/// Managed databases the repository's dependencies point to that were not asked for; they
+/// cost money, so they are only suggested, never provisioned on detection alone
+pub fn unrequested(requested: &[DatabaseType], detected: &[DatabaseType]) -> Vec<DatabaseType> {
+    managed_databases(detected).into_iter().filter(|database| !requested.contains(database)).collect()
+}
+
+/// Value of `--database` that provisions `database`
+pub fn flag_value(database: &DatabaseType) -> &'static str {
+    match database {
+        DatabaseType::PostgreSQL => "postgresql",
+        DatabaseType::MySQL => "mysql",
+        DatabaseType::MongoDB => "mongodb",
+        DatabaseType::Redis => "redis",
+        DatabaseType::SQLite => "sqlite",
+        DatabaseType::None => "none",
+    }
+}
+
+/// Resource type of the managed service for `database`, if the provider offers one
+fn managed_resource_type(database: &DatabaseType, cloud_provider: &CloudProvider) -> Option<&'static str> {
+    match (cloud_provider, database) {
+        (CloudProvider::AWS, DatabaseType::PostgreSQL | DatabaseType::MySQL) => Some("aws_db_instance"),
+        (CloudProvider::AWS, DatabaseType::MongoDB) => Some("aws_docdb_cluster"),
+        (CloudProvider::AWS, DatabaseType::Redis) => Some("aws_elasticache_cluster"),
+        (CloudProvider::GCP, DatabaseType::PostgreSQL | DatabaseType::MySQL) => Some("google_sql_database_instance"),
+        (CloudProvider::GCP, DatabaseType::Redis) => Some("google_redis_instance"),
+        (CloudProvider::Azure, DatabaseType::PostgreSQL) => Some("azurerm_postgresql_flexible_server"),
+        (CloudProvider::Azure, DatabaseType::MySQL) => Some("azurerm_mysql_flexible_server"),
+        (CloudProvider::Azure, DatabaseType::Redis) => Some("azurerm_redis_cache"),
+        _ => None,
+    }
+}
+
+/// Engine name as it appears in `engine` / `database_version`, to tell PostgreSQL and MySQL
+/// instances of the same resource type apart
+fn engine(database: &DatabaseType) -> &'static str {
+    match database {
+        DatabaseType::PostgreSQL => "postgres",
+        DatabaseType::MySQL => "mysql",
+        DatabaseType::MongoDB => "docdb",
+        _ => "redis",
+    }
+}
+
+/// Port clients connect to the database on
+pub fn port(database: &DatabaseType) -> u16 {
+    match database {
+        DatabaseType::PostgreSQL => 5432,
+        DatabaseType::MySQL => 3306,
+        DatabaseType::MongoDB => 27017,
+        _ => 6379,
+    }
+}
+
+/// Prompt lines asking for a managed service for each database
+pub fn prompt_requirements(databases: &[DatabaseType], cloud_provider: &CloudProvider) -> String {
+    let lines: Vec<String> = managed_databases(databases)
+        .iter()
+        .filter_map(|database| {
+            let resource_type = managed_resource_type(database, cloud_provider)?;
+            Some(format!(
+                "- {:?}: a managed {} (engine {}, smallest size) reachable from the app instance on port {}, not from the internet",
+                database,
+                resource_type,
+                engine(database),
+                port(database)
+            ))
+        })
+        .collect();
+    if lines.is_empty() {
+        return String::new();
+    }
+    format!(
+        "\nDATABASES (required):\n{}\n- Generate passwords with random_password, never hard-code them\n- Output each database's endpoint\n",
+        lines.join("\n")
+    )
+}
+
+/// Description and monthly cost of the smallest managed instance of each database
+pub fn database_costs(databases: &[DatabaseType], cloud_provider: &CloudProvider) -> Vec<(String, f64)> {
+    managed_databases(databases)
+        .iter()
+        .map(|database| {
+            let (size, cost) = match (cloud_provider, database) {
+                (CloudProvider::AWS, DatabaseType::MongoDB) => ("DocumentDB db.t3.medium", 56.94),
+                (CloudProvider::AWS, DatabaseType::Redis) => ("ElastiCache cache.t3.micro", 12.41),
+                (CloudProvider::AWS, _) => ("RDS db.t3.micro + 20 GB gp2", 12.41 + 2.30),
+                (CloudProvider::GCP, DatabaseType::Redis) => ("Memorystore basic, 1 GB", 35.77),
+                (CloudProvider::GCP, _) => ("Cloud SQL db-f1-micro + 10 GB SSD", 7.67 + 1.70),
+                (CloudProvider::Azure, DatabaseType::Redis) => ("Azure Cache Basic C0", 16.06),
+                (CloudProvider::Azure, _) => ("Flexible Server B1ms + 20 GB", 12.41 + 2.30),
+                _ => ("smallest managed instance", 15.0),
+            };
+            (format!("{:?}, {}", database, size), cost)
+        })
+        .collect()
+}
+
+/// Whether the config already has a managed instance of `database`
+fn has_database(config: &TerraformConfig, database: &DatabaseType, resource_type: &str) -> bool {
+    config.resources.iter().any(|resource| {
+        if resource.resource_type != resource_type {
+            return false;
+        }
+        // Without an engine field the resource type alone says which database it is
+        match resource.config.get("engine").or_else(|| resource.config.get("database_version")) {
+            Some(Value::String(value)) => value.to_lowercase().contains(engine(database)),
+            _ => true,
+        }
+    })
+}
+
+/// Add a managed instance of each database the generated configuration is missing, on AWS
+/// and GCP. Returns the resources added; `missing` says which databases still have none.
+pub fn apply(config: &mut TerraformConfig, databases: &[DatabaseType], cloud_provider: &CloudProvider) -> Vec<String> {
+    let mut added = Vec::new();
+    for database in managed_databases(databases) {
+        let Some(resource_type) = managed_resource_type(&database, cloud_provider) else {
+            continue;
+        };
+        if has_database(config, &database, resource_type) {
+            continue;
+        }
+        let resources = match cloud_provider {
+            CloudProvider::AWS => aws_database(config, &database),
+            CloudProvider::GCP => gcp_database(config, &database),
+            _ => continue,
+        };
+        for resource in resources {
+            let address = format!("{}.{}", resource.resource_type, resource.name);
+            if !config.resources.iter().any(|existing| format!("{}.{}", existing.resource_type, existing.name) == address) {
+                added.push(address);
+                config.resources.push(resource);
+            }
+        }
+    }
+    added
+}
+
+/// Whether `name` is the environment variable for a provisioned database's endpoint, e.g.
+/// `APP_DB_POSTGRES_ENDPOINT` for the `app_db_postgres_endpoint` output
+pub fn is_endpoint_variable(name: &str) -> bool {
+    name.starts_with(&format!("{}_", RESOURCE_NAME.to_uppercase())) && name.ends_with("_ENDPOINT")
+}
+
+/// Endpoint variables of the databases added to `config`, and the Terraform expression each
+/// one stands for
+pub fn endpoint_expressions(config: &TerraformConfig) -> HashMap<String, String> {
+    config
+        .outputs
+        .iter()
+        .filter(|(name, _)| is_endpoint_variable(&name.to_uppercase()))
+        .filter_map(|(name, output)| Some((name.to_uppercase(), output.get("value")?.as_str()?.to_string())))
+        .collect()
+}
+
+/// Databases with no managed instance in the config, e.g. MongoDB on GCP
+pub fn missing(config: &TerraformConfig, databases: &[DatabaseType], cloud_provider: &CloudProvider) -> Vec<DatabaseType> {
+    managed_databases(databases)
+        .into_iter()
+        .filter(|database| match managed_resource_type(database, cloud_provider) {
+            Some(resource_type) => !has_database(config, database, resource_type),
+            None => true,
+        })
+        .collect()
+}
+
+fn resource(resource_type: &str, name: &str, config: Value) -> TerraformResource {
+    TerraformResource {
+        resource_type: resource_type.to_string(),
+        name: name.to_string(),
+        config: serde_json::from_value(config).unwrap_or_default(),
+    }
+}
+
+fn password() -> TerraformResource {
+    resource("random_password", RESOURCE_NAME, json!({"length": 24, "special": false}))
+}
+
+fn output(config: &mut TerraformConfig, name: &str, value: String, description: String) {
+    config.outputs.insert(format!("{}_endpoint", name), json!({"value": value, "description": description}));
+}
+
+fn aws_database(config: &mut TerraformConfig, database: &DatabaseType) -> Vec<TerraformResource> {
+    let name = format!("{}_{}", RESOURCE_NAME, engine(database));
+    let password_ref = format!("${{random_password.{}.result}}", RESOURCE_NAME);
+    let group_ref = format!("${{aws_security_group.{}.id}}", RESOURCE_NAME);
+    let mut resources = vec![password()];
+
+    // Reachable from the app's security group only; the default group otherwise
+    let app_group = config
+        .resources
+        .iter()
+        .find(|resource| resource.resource_type == "aws_security_group" && resource.name != RESOURCE_NAME)
+        .map(|resource| format!("${{aws_security_group.{}.id}}", resource.name));
+    let guarded = app_group.is_some();
+    if let Some(app_group) = app_group {
+        let ingress = json!({
+            "from_port": port(database),
+            "to_port": port(database),
+            "protocol": "tcp",
+            "security_groups": [app_group],
+            "description": format!("{:?} from the app", database),
+        });
+        match config
+            .resources
+            .iter_mut()
+            .find(|resource| resource.resource_type == "aws_security_group" && resource.name == RESOURCE_NAME)
+        {
+            Some(group) => {
+                if let Some(Value::Array(rules)) = group.config.get_mut("ingress") {
+                    rules.push(ingress);
+                }
+            }
+            None => resources.push(resource("aws_security_group", RESOURCE_NAME, json!({
+                "name_prefix": "autodeploy-db-",
+                "description": "Database access from the app",
+                "ingress": [ingress],
+            }))),
+        }
+    }
+    let group_ids = if guarded { json!([group_ref]) } else { json!([]) };
+
+    let (resource_type, endpoint) = match database {
+        DatabaseType::MongoDB => {
+            resources.push(resource("aws_docdb_cluster", &name, json!({
+                "cluster_identifier_prefix": "autodeploy-",
+                "engine": "docdb",
+                "master_username": "app",
+                "master_password": password_ref,
+                "skip_final_snapshot": true,
+                "vpc_security_group_ids": group_ids,
+            })));
+            resources.push(resource("aws_docdb_cluster_instance", &name, json!({
+                "identifier_prefix": "autodeploy-",
+                "cluster_identifier": format!("${{aws_docdb_cluster.{}.id}}", name),
+                "instance_class": "db.t3.medium",
+            })));
+            ("aws_docdb_cluster", "endpoint")
+        }
+        DatabaseType::Redis => {
+            resources.push(resource("aws_elasticache_cluster", &name, json!({
+                "cluster_id": format!("autodeploy-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]),
+                "engine": "redis",
+                "node_type": "cache.t3.micro",
+                "num_cache_nodes": 1,
+                "port": port(database),
+                "security_group_ids": group_ids,
+            })));
+            ("aws_elasticache_cluster", "cache_nodes.0.address")
+        }
+        _ => {
+            resources.push(resource("aws_db_instance", &name, json!({
+                "identifier_prefix": "autodeploy-",
+                "engine": engine(database),
+                "instance_class": "db.t3.micro",
+                "allocated_storage": 20,
+                "db_name": "app",
+                "username": "app",
+                "password": password_ref,
+                "publicly_accessible": false,
+                "skip_final_snapshot": true,
+                "vpc_security_group_ids": group_ids,
+            })));
+            ("aws_db_instance", "address")
+        }
+    };
+    output(config, &name, format!("{}.{}.{}", resource_type, name, endpoint), format!("{:?} endpoint", database));
+    resources
+}
+
+fn gcp_database(config: &mut TerraformConfig, database: &DatabaseType) -> Vec<TerraformResource> {
+    let name = format!("{}_{}", RESOURCE_NAME, engine(database));
+    let instance_name = format!("autodeploy-{}-{}", engine(database), &uuid::Uuid::new_v4().simple().to_string()[..8]);
+
+    if *database == DatabaseType::Redis {
+        output(config, &name, format!("google_redis_instance.{}.host", name), "Redis host".to_string());
+        return vec![resource("google_redis_instance", &name, json!({
+            "name": instance_name,
+            "project": "var.project_id",
+            "region": "var.region",
+            "tier": "BASIC",
+            "memory_size_gb": 1,
+        }))];
+    }
+
+    // Cloud SQL is reached over its public IP, open only to the app instance
+    let authorized_networks: Vec<Value> = config
+        .resources
+        .iter()
+        .filter(|resource| resource.resource_type == "google_compute_instance")
+        .map(|resource| {
+            json!({
+                "name": resource.name,
+                "value": format!("${{google_compute_instance.{}.network_interface.0.access_config.0.nat_ip}}", resource.name),
+            })
+        })
+        .collect();
+    let instance_ref = format!("${{google_sql_database_instance.{}.name}}", name);
+    output(config, &name, format!("google_sql_database_instance.{}.public_ip_address", name), format!("{:?} address", database));
+    vec![
+        password(),
+        resource("google_sql_database_instance", &name, json!({
+            "name": instance_name,
+            "project": "var.project_id",
+            "region": "var.region",
+            "database_version": if *database == DatabaseType::MySQL { "MYSQL_8_0" } else { "POSTGRES_15" },
+            "deletion_protection": false,
+            "settings": {
+                "tier": "db-f1-micro",
+                "ip_configuration": {"ipv4_enabled": true, "authorized_networks": authorized_networks},
+            },
+        })),
+        resource("google_sql_database", &name, json!({
+            "name": "app",
+            "project": "var.project_id",
+            "instance": instance_ref,
+        })),
+        resource("google_sql_user", &name, json!({
+            "name": "app",
+            "project": "var.project_id",
+            "instance": instance_ref,
+            "password": format!("${{random_password.{}.result}}", RESOURCE_NAME),
+        })),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(resources: Vec<TerraformResource>) -> TerraformConfig {
+        TerraformConfig {
+            provider: "aws".to_string(),
+            resources,
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_postgres_adds_managed_database() {
+        let databases = vec![DatabaseType::PostgreSQL, DatabaseType::SQLite];
+        let mut aws = config(vec![
+            resource("aws_instance", "app", json!({})),
+            resource("aws_security_group", "app_sg", json!({"ingress": []})),
+        ]);
+        let added = apply(&mut aws, &databases, &CloudProvider::AWS);
+        assert_eq!(added, vec!["random_password.app_db", "aws_security_group.app_db", "aws_db_instance.app_db_postgres"]);
+        let db = aws.resources.iter().find(|r| r.resource_type == "aws_db_instance").unwrap();
+        assert_eq!(db.config["engine"], "postgres");
+        assert_eq!(db.config["vpc_security_group_ids"], json!(["${aws_security_group.app_db.id}"]));
+        assert_eq!(aws.outputs["app_db_postgres_endpoint"]["value"], "aws_db_instance.app_db_postgres.address");
+        assert!(apply(&mut aws, &databases, &CloudProvider::AWS).is_empty(), "an existing instance is kept");
+        assert!(missing(&aws, &databases, &CloudProvider::AWS).is_empty());
+        let costs = database_costs(&databases, &CloudProvider::AWS);
+        assert_eq!(costs.len(), 1, "SQLite costs nothing");
+        assert_eq!(costs[0].0, "PostgreSQL, RDS db.t3.micro + 20 GB gp2");
+        assert!((costs[0].1 - 14.71).abs() < 1e-9);
+
+        let mut gcp = config(vec![resource("google_compute_instance", "app", json!({}))]);
+        let databases = vec![DatabaseType::MySQL, DatabaseType::MongoDB];
+        apply(&mut gcp, &databases, &CloudProvider::GCP);
+        let sql = gcp.resources.iter().find(|r| r.resource_type == "google_sql_database_instance").unwrap();
+        assert_eq!(sql.config["database_version"], "MYSQL_8_0");
+        assert_eq!(missing(&gcp, &databases, &CloudProvider::GCP), vec![DatabaseType::MongoDB]);
+        assert!(!prompt_requirements(&[DatabaseType::PostgreSQL], &CloudProvider::AWS).is_empty());
+        assert_eq!(prompt_requirements(&[DatabaseType::SQLite], &CloudProvider::AWS), "");
+    }
+}
@@ -1,161 +1,2205 @@
 use anyhow::{Result, anyhow};
-use log::{info, warn, error};
+use clap::Args;
+use log::{debug, info, warn, error};
 use regex::Regex;
-use std::io::{self, Write};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
 use std::fs;
-use std::path::Path;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
 use crate::ai_nlp;
-use crate::repository::{clone_repository, analyze_repository, RepositoryAnalysis};
-use crate::infrastructure::{decide_infrastructure, provision_infrastructure, DeploymentResult, InfrastructureDecision};
-use crate::credentials::CloudCredentials;
+use crate::audit::{self, AuditMode};
+use crate::bootstrap;
+use crate::checkpoint::{self, Checkpoint, Phase};
+use crate::concurrency;
+use crate::correlation;
+use crate::cost::CostEstimate;
+use crate::database;
+use crate::disk;
+use crate::gpu;
+use crate::egress::EgressPolicy;
+use crate::detach;
+use crate::dockerfile::{self, DOCKERFILE};
+use crate::dns::{self, DnsConfig};
+use crate::env_file::{EnvFile, REMOTE_ENV_FILE};
+use crate::health::{self, HealthCheck, DEFAULT_HEALTH_INTERVAL, DEFAULT_HEALTH_TIMEOUT};
+use crate::http;
+use crate::feedback::{self, DetectionFeedback};
+use crate::index::{DeploymentState, Index, IndexEntry};
+use crate::interpolation;
+use crate::repository::{
+    self, clone_repository, analyze_repository, framework_eol_warning, private_registries, RepositoryAnalysis,
+    StaticServer, StaticServing,
+};
+use crate::infrastructure::{self, ProviderEstimate};
+use crate::infrastructure::{
+    apply_terraform, decide_infrastructure, generate_deployment_files, provision_infrastructure,
+    summarize_actions, DeploymentResult, InfrastructureDecision, PlanRepair, RegionalEndpoint, TerraformFormat,
+    DEFAULT_MAX_LOG_LINES, DEFAULT_TERRAFORM_TIMEOUT, MAX_AI_REPAIR_ATTEMPTS,
+};
+use crate::network::ExistingNetwork;
+use crate::nlp::{self, ApplicationType, CloudProvider, DatabaseType, DeploymentRequirements, OsFamily, ScalingRequirements};
+use crate::policy::Policy;
+use crate::protection;
+use crate::redact::redact_secrets;
+use crate::safety::{ForceDeploy, SafetyCheck};
+use crate::secrets::SecretsSource;
+use crate::settings;
+use crate::static_hosting::{self, Publisher, StaticHost};
+use crate::credentials::{prompt_for_credentials, prompt_for_registry_credentials, CloudCredentials, RegistryCredentials};
+use crate::ssh::{self, AuthorizedKey, SshTarget};
+use crate::stack::{self, Service, StackMember, StackRecord};
+
+/// Optional deploy settings beyond the description, repository and provider
+#[derive(Debug, Clone, Default, Args)]
+pub struct DeployOptions {
+    #[clap(long, help = "DNS provider for the custom domain record: cloudflare")]
+    pub dns_provider: Option<String>,
+
+    #[clap(long, help = "DNS zone the custom domain record is created in")]
+    pub dns_zone: Option<String>,
+
+    #[clap(long, help = "DNS provider API token (default: CLOUDFLARE_API_TOKEN)")]
+    pub dns_token: Option<String>,
+
+    #[clap(long, help = "Only point the custom domain at the new instance once it passes a health check")]
+    pub health_gated_dns: bool,
+
+    #[clap(long, help = "Path polled when checking the app's health (default: the detected health route, else /)")]
+    pub health_path: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Maximum time to wait for the app to become healthy after the deploy; 0 skips the check (default: 60-600 by deployment type)"
+    )]
+    pub wait: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = DEFAULT_HEALTH_INTERVAL.as_secs(),
+        help = "Initial delay between health polls while waiting; it doubles after each failed poll, up to 30s"
+    )]
+    pub poll_interval: u64,
+
+    #[clap(long, default_value = "30", help = "Seconds between load balancer health checks; raise for slow-starting apps")]
+    pub health_interval: u32,
+
+    #[clap(long, default_value = "200-399", help = "Status codes the load balancer counts as healthy, e.g. 200 or 200-299")]
+    pub health_codes: String,
+
+    #[clap(long, default_value = "ubuntu", help = "VM operating system: ubuntu, debian, amazon-linux")]
+    pub os: OsFamily,
+
+    #[clap(
+        long,
+        default_value = "terraform",
+        help = "Where to deploy: terraform (provision new infra), ssh (existing host), github-pages or netlify (static sites)"
+    )]
+    pub target: DeployTarget,
+
+    #[clap(long, help = "Host to deploy to with --target ssh")]
+    pub host: Option<String>,
+
+    #[clap(long, help = "SSH user (default: the --os image's login user, e.g. ubuntu)")]
+    pub ssh_user: Option<String>,
+
+    #[clap(long, help = "SSH private key (default: ssh agent/config)")]
+    pub ssh_key: Option<PathBuf>,
+
+    #[clap(long, value_name = "PORT", help = "SSH port; new instances get sshd and their firewall moved to it (default: 22)")]
+    pub ssh_port: Option<u16>,
+
+    #[clap(long, value_name = "[USER@]HOST[:PORT]", help = "Bastion host every SSH connection jumps through")]
+    pub bastion: Option<String>,
+
+    #[clap(long, help = "Local env file delivered to the instance over SSH instead of through user_data")]
+    pub env_file: Option<PathBuf>,
+
+    #[clap(long, help = "On a terraform plan failure, send the error to the AI and retry with its fix (up to 3 times)")]
+    pub ai_repair: bool,
+
+    #[clap(
+        long,
+        value_name = "MINUTES",
+        help = "Abort Terraform after this long and destroy what a hung apply created; 0 waits forever (default: 20)"
+    )]
+    pub timeout: Option<u64>,
+
+    #[clap(long, help = "Policy file (JSON) limiting instance size, resource types and required tags")]
+    pub policy: Option<PathBuf>,
+
+    #[clap(long, help = "Abort the deploy on policy violations instead of fixing them")]
+    pub strict: bool,
+
+    #[clap(long, help = "Target environment, e.g. production; uses the repository's .env.<environment> values")]
+    pub environment: Option<String>,
+
+    #[clap(long, help = "Netlify site ID or name for --target netlify (default: NETLIFY_SITE_ID)")]
+    pub netlify_site: Option<String>,
+
+    #[clap(long, help = "When cloud credentials are missing, prompt for them and continue instead of failing")]
+    pub interactive_credentials: bool,
+
+    #[clap(long, help = "Place the instance in a private subnet with a NAT gateway, reachable only through a load balancer")]
+    pub private: bool,
+
+    #[clap(
+        long,
+        value_name = "LINES",
+        default_value_t = DEFAULT_MAX_LOG_LINES,
+        help = "Keep only the last LINES log lines in the deployment result, plus every error line"
+    )]
+    pub prune_logs: usize,
+
+    #[clap(long, help = "Also write every log line to deployment.log in the deployment directory")]
+    pub full_logs: bool,
+
+    #[clap(long, help = "Command run on the instance over SSH, retried until it succeeds, before the deploy counts as done")]
+    pub readiness_cmd: Option<String>,
+
+    #[clap(long, help = "URL or path (e.g. /warmup) requested after the deploy, before it counts as done")]
+    pub warmup_url: Option<String>,
+
+    #[clap(long, default_value = "3", help = "Number of --warmup-url requests")]
+    pub warmup_requests: u32,
+
+    #[clap(long, help = "Name for the deployment (lowercase letters, digits, hyphens); names its directory and tags its resources")]
+    pub name: Option<String>,
+
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "REGIONS",
+        help = "Deploy a full copy of the app to each region (e.g. us-east-1,eu-west-1) behind Route53 latency routing with health-check failover; multiplies the cost"
+    )]
+    pub regions: Vec<String>,
+
+    #[clap(long, help = "On failure, ask the AI for a plain-language explanation and suggested fix (needs the LLM provider's API key)")]
+    pub explain_errors: bool,
+
+    #[clap(long, value_name = "DIR", help = "Deploy the app in this subdirectory of the repository, e.g. services/api")]
+    pub subdir: Option<String>,
+
+    #[clap(long = "ref", value_name = "REF", help = "Branch, tag or commit to deploy instead of the default branch")]
+    pub git_ref: Option<String>,
+
+    #[clap(long, help = "Deploy into this existing VPC instead of creating one (on GCP: the network name)")]
+    pub vpc_id: Option<String>,
+
+    #[clap(long, help = "Deploy into this existing subnet (on GCP: the subnetwork name)")]
+    pub subnet_id: Option<String>,
+
+    #[clap(long, help = "Attach this existing security group instead of creating one (AWS only)")]
+    pub security_group_id: Option<String>,
+
+    #[clap(long, help = "Allow outbound traffic only for HTTP/HTTPS, DNS and the databases' ports instead of everything")]
+    pub restrict_egress: bool,
+
+    #[clap(long, use_value_delimiter = true, value_name = "CIDRS", help = "Destinations the app's outbound ports may reach, e.g. 10.0.0.0/16 (implies --restrict-egress)")]
+    pub egress_cidr: Vec<String>,
+
+    #[clap(long, use_value_delimiter = true, value_name = "PORTS", help = "TCP ports the app may connect out on instead of the databases' (implies --restrict-egress)")]
+    pub egress_ports: Vec<u16>,
+
+    #[clap(long, help = "Check the dependencies for known vulnerabilities (npm audit, OSV for Python) and warn before deploying")]
+    pub audit: bool,
+
+    #[clap(long, help = "Like --audit, but refuse to deploy when a high or critical vulnerability is found")]
+    pub audit_block: bool,
+
+    #[clap(long, value_name = "GB", help = "Root disk size of the instance (default: sized from the app's build)")]
+    pub disk_size: Option<u32>,
+
+    #[clap(long, help = "Leave prevent_destroy off the databases, disks and buckets, so destroy removes them without --allow-destroy-stateful")]
+    pub allow_destroy_stateful: bool,
+
+    #[clap(long, help = "Analyze the repository as committed, without pointing its localhost references at this machine's public IP")]
+    pub no_localhost_rewrite: bool,
+
+    #[clap(long, help = "Run on a GPU instance with the NVIDIA driver (AWS g4dn, GCP with a T4), even when no GPU dependencies are detected")]
+    pub gpu: bool,
+
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "DATABASES",
+        help = "Provision a managed database for each of postgresql, mysql, mongodb, redis, next to any the description names (databases detected in the repository are only suggested)"
+    )]
+    pub database: Vec<DatabaseType>,
+
+    #[clap(
+        long,
+        value_name = "SOURCE",
+        help = "Load environment variables at startup from the secrets named <prefix>*: aws:<prefix> (Secrets Manager) or gcp:<prefix> (Secret Manager)"
+    )]
+    pub secrets_source: Option<SecretsSource>,
+
+    #[clap(long, value_name = "NAME", help = "package.json script to start the app with (default: start:prod, start or serve)")]
+    pub start_script: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "IMAGE",
+        help = "Image container and Kubernetes deployments run the app in (default: the official image matching the detected Python/Node version)"
+    )]
+    pub base_image: Option<String>,
+
+    #[clap(long, help = "Only generate a Dockerfile from the repository analysis and print it for review; nothing is deployed")]
+    pub dockerfile_only: bool,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with = "all",
+        help = "Provision this TerraformConfig JSON as is, skipping the description parsing, repository analysis and AI generation"
+    )]
+    pub terraform_config: Option<PathBuf>,
+}
+
+/// How command results are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow!("Unsupported output format: {}. Use: text, json", s)),
+        }
+    }
+}
+
+/// Print `value` as pretty JSON on stdout, for `--output json`
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Print a failure as `{"error": "..."}` on stdout, so scripts reading `--output json` always
+/// get an object
+pub fn print_json_error(error: &anyhow::Error) {
+    println!("{}", serde_json::json!({ "error": format!("{:#}", error) }));
+}
+
+/// How the application reaches a machine
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DeployTarget {
+    /// Provision new infrastructure with Terraform
+    #[default]
+    Terraform,
+    /// Bootstrap an existing host over SSH, without Terraform
+    Ssh,
+    /// Build a static site locally and push it to the repository's gh-pages branch
+    GithubPages,
+    /// Build a static site locally and upload it with the Netlify CLI
+    Netlify,
+}
+
+impl DeployTarget {
+    /// The static host this target publishes to, if it is one
+    fn static_host(&self) -> Option<StaticHost> {
+        match self {
+            DeployTarget::GithubPages => Some(StaticHost::GithubPages),
+            DeployTarget::Netlify => Some(StaticHost::Netlify),
+            DeployTarget::Terraform | DeployTarget::Ssh => None,
+        }
+    }
+}
+
+impl FromStr for DeployTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "terraform" => Ok(DeployTarget::Terraform),
+            "ssh" => Ok(DeployTarget::Ssh),
+            "github-pages" | "gh-pages" => Ok(DeployTarget::GithubPages),
+            "netlify" => Ok(DeployTarget::Netlify),
+            _ => Err(anyhow!("Unsupported target: {}. Use: terraform, ssh, github-pages, netlify", s)),
+        }
+    }
+}
+
+impl DeployOptions {
+    /// `--audit-block` implies `--audit`
+    fn audit_mode(&self) -> Option<AuditMode> {
+        match (self.audit, self.audit_block) {
+            (_, true) => Some(AuditMode::Block),
+            (true, false) => Some(AuditMode::Warn),
+            (false, false) => None,
+        }
+    }
+
+    /// `--wait`, or the default for the kind of infrastructure deployed
+    fn health_wait(&self, infrastructure_type: &str) -> Duration {
+        self.wait.map(Duration::from_secs).unwrap_or_else(|| health::default_wait(infrastructure_type))
+    }
+
+    /// `--timeout`, where 0 means no limit
+    fn terraform_timeout(&self) -> Option<Duration> {
+        match self.timeout {
+            Some(0) => None,
+            Some(minutes) => Some(Duration::from_secs(minutes * 60)),
+            None => Some(DEFAULT_TERRAFORM_TIMEOUT),
+        }
+    }
+
+    fn validate_wait(&self) -> Result<()> {
+        if self.poll_interval == 0 {
+            return Err(anyhow!("--poll-interval must be at least 1 second"));
+        }
+        match self.wait {
+            Some(0) if self.health_gated_dns => Err(anyhow!("--health-gated-dns needs the health check that --wait 0 skips")),
+            Some(wait) if wait > 0 && self.poll_interval > wait => {
+                Err(anyhow!("--poll-interval {}s is longer than --wait {}s", self.poll_interval, wait))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolve the DNS flags into a usable config, or `None` when no DNS provider is set
+    fn dns_config(&self) -> Result<Option<DnsConfig>> {
+        let provider = match &self.dns_provider {
+            Some(provider) => provider.parse()?,
+            None => return Ok(None),
+        };
+        let zone = self
+            .dns_zone
+            .clone()
+            .ok_or_else(|| anyhow!("--dns-zone is required with --dns-provider"))?;
+        let token = self
+            .dns_token
+            .clone()
+            .or_else(|| std::env::var("CLOUDFLARE_API_TOKEN").ok())
+            .ok_or_else(|| anyhow!("--dns-token or CLOUDFLARE_API_TOKEN is required with --dns-provider"))?;
+        
+        Ok(Some(DnsConfig { provider, zone, token }))
+    }
+
+    fn health_check(&self) -> Result<HealthCheck> {
+        HealthCheck::new(self.health_path.clone(), self.health_interval, &self.health_codes)
+    }
+
+    /// Load the policy file, with `--strict` overriding the file's own setting
+    fn policy(&self) -> Result<Option<Policy>> {
+        let mut policy = match &self.policy {
+            Some(path) => Policy::load(path)?,
+            None if self.strict => return Err(anyhow!("--strict requires --policy")),
+            None => return Ok(None),
+        };
+        policy.strict |= self.strict;
+        Ok(Some(policy))
+    }
+
+    /// Resolve the SSH flags, or `None` when deploying with Terraform
+    fn ssh_target(&self) -> Result<Option<SshTarget>> {
+        if self.target != DeployTarget::Ssh {
+            return Ok(None);
+        }
+        let host = self
+            .host
+            .clone()
+            .ok_or_else(|| anyhow!("--host is required with --target ssh"))?;
+        
+        Ok(Some(self.ssh_target_for(host)))
+    }
+
+    /// Public half of `--ssh-key` for new instances; `--env-file` needs it, since nothing
+    /// else lets the deploy log in to deliver the file
+    fn authorized_key(&self) -> Result<Option<AuthorizedKey>> {
+        let user = self.ssh_user.clone().unwrap_or_else(|| self.os.default_ssh_user().to_string());
+        match (&self.ssh_key, &self.env_file) {
+            (Some(key), _) => AuthorizedKey::from_private_key(key, &user).map(Some),
+            (None, Some(_)) => Err(anyhow!("--env-file is delivered over SSH: pass --ssh-key <private key> so its public key is installed on the instance")),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// SSH connection details for `host` using the SSH flags
+    fn ssh_target_for(&self, host: String) -> SshTarget {
+        SshTarget {
+            host,
+            user: self
+                .ssh_user
+                .clone()
+                .unwrap_or_else(|| self.os.default_ssh_user().to_string()),
+            key: self.ssh_key.clone(),
+            port: self.ssh_port,
+            bastion: self.bastion.clone(),
+        }
+    }
+}
+
+/// Shown in place of a URL for apps that serve no HTTP
+const WORKER_URL: &str = "none (background worker, no web port)";
 
 pub async fn deploy_application(
     description: &str,
     repository: &str,
-    cloud_provider: Option<&str>,
+    cloud_provider: Option<&str>,
+    dry_run: bool,
+    force_deploy: &ForceDeploy,
+    options: &DeployOptions,
+) -> Result<DeploymentResult> {
+    deploy_scoped(description, repository, cloud_provider, dry_run, force_deploy, options, None).await
+}
+
+/// Print an AI explanation of a failed deploy for `--explain-errors`. Skipped without an API
+/// key; when the explanation itself fails, only a warning is logged and the deploy's own
+/// error stays the one reported.
+pub async fn explain_error(error: &anyhow::Error) {
+    if let Some(var) = ai_nlp::missing_api_key() {
+        warn!("⚠️ --explain-errors needs {}, skipping the explanation", var);
+        return;
+    }
+    match ai_nlp::explain_failure(&format!("{:#}", error)).await {
+        Ok(explanation) => {
+            println!("\n🤖 AI-generated advice (may be wrong, check it before acting on it):");
+            for line in explanation.trim().lines() {
+                println!("  {}", line);
+            }
+        }
+        Err(e) => warn!("⚠️ Could not get an explanation of the failure: {}", e),
+    }
+}
+
+/// Deploy every service of a multi-service repository (compose file or monorepo) as its own
+/// deployment, dependencies first. Each service gets the URLs of the services it depends on
+/// as `<NAME>_URL` environment variables. After a failure the remaining services are skipped.
+/// The services are grouped in a stack record indexed under its own id, whose path is
+/// returned with it.
+pub async fn deploy_stack(
+    description: &str,
+    repository: &str,
+    cloud_provider: Option<&str>,
+    dry_run: bool,
+    force_deploy: &ForceDeploy,
+    options: &DeployOptions,
+) -> Result<(StackRecord, PathBuf)> {
+    if options.target != DeployTarget::Terraform {
+        return Err(anyhow!("--all only applies to new infrastructure (--target terraform)"));
+    }
+    if options.subdir.is_some() {
+        return Err(anyhow!("--subdir cannot be combined with --all, which finds each service's directory itself"));
+    }
+    
+    info!("📥 Cloning repository: {}", repository);
+    let services = stack::detect_services(clone_repository(repository, options.git_ref.as_deref()).await?.path())?;
+    if services.len() < 2 {
+        return Err(anyhow!(
+            "Found {} deployable service(s); --all needs a compose file with build contexts or a monorepo with several apps",
+            services.len()
+        ));
+    }
+    let order: Vec<&str> = services.iter().map(|service| service.name.as_str()).collect();
+    info!("🧩 Deploying {} services in order: {}", services.len(), order.join(" -> "));
+    
+    let requirements = resolve_requirements(description, cloud_provider).await?;
+    
+    // The custom domain goes to the one service nothing else depends on, e.g. the frontend
+    let entry_points: Vec<&Service> = services
+        .iter()
+        .filter(|service| !services.iter().any(|other| other.depends_on.contains(&service.name)))
+        .collect();
+    let domain_service = match entry_points.as_slice() {
+        [entry_point] => Some(entry_point.name.clone()),
+        _ => None,
+    };
+    if let (Some(domain), None) = (&requirements.custom_domain, &domain_service) {
+        warn!("⚠️ Custom domain {} ignored: no single entry-point service to point it at", domain);
+    }
+    
+    let mut record = StackRecord {
+        id: correlation::new_id(),
+        repository: repository.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        members: Vec::new(),
+    };
+    let mut urls: HashMap<String, String> = HashMap::new();
+    for service in &services {
+        let mut member = StackMember {
+            service: service.name.clone(),
+            dir: service.dir.clone(),
+            url: None,
+            deployment_id: None,
+            deployment_dir: None,
+            error: None,
+        };
+        if !record.is_complete() {
+            member.error = Some("skipped after an earlier service failed".to_string());
+            record.members.push(member);
+            continue;
+        }
+        
+        let mut service_requirements = requirements.clone();
+        service_requirements.service_dir = Some(service.dir.clone()).filter(|dir| !dir.is_empty());
+        if domain_service.as_ref() != Some(&service.name) {
+            service_requirements.custom_domain = None;
+        }
+        service_requirements.deployment_name = options
+            .name
+            .as_ref()
+            .map(|name| format!("{}-{}", name, service.name.to_lowercase().replace(['_', '.'], "-")));
+        for dependency in services.iter().filter(|other| service.depends_on.contains(&other.name)) {
+            if let Some(url) = urls.get(&dependency.name) {
+                service_requirements.environment_variables.insert(dependency.url_variable(), url.clone());
+            }
+        }
+        
+        info!("🧩 Deploying service {} from {}/", service.name, service.dir);
+        match deploy_scoped(description, repository, cloud_provider, dry_run, force_deploy, options, Some(service_requirements)).await {
+            Ok(result) => {
+                if result.serves_http && !dry_run {
+                    urls.insert(service.name.clone(), result.url.clone());
+                }
+                member.url = Some(result.url);
+                member.deployment_id = result.deployment_id;
+                member.deployment_dir = result.deployment_dir;
+            }
+            Err(e) => {
+                error!("❌ Service {} failed: {:#}", service.name, e);
+                member.error = Some(format!("{:#}", e));
+            }
+        }
+        record.members.push(member);
+    }
+    
+    let path = Index::open()?.save_stack(&record)?;
+    Ok((record, path))
+}
+
+/// Run one deploy under its own deployment id. `resolved` skips parsing the description,
+/// for the services of a stack that share requirements parsed once.
+async fn deploy_scoped(
+    description: &str,
+    repository: &str,
+    cloud_provider: Option<&str>,
+    dry_run: bool,
+    force_deploy: &ForceDeploy,
+    options: &DeployOptions,
+    resolved: Option<DeploymentRequirements>,
+) -> Result<DeploymentResult> {
+    // Every log line and error of this deploy carries the same id; a background deploy
+    // keeps the one `--detach` printed, a resumed one its own
+    let deployment_id = detach::detached_id()
+        .or_else(|| checkpoint::resuming().map(|resume| resume.id.clone()))
+        .unwrap_or_else(correlation::new_id);
+    // Services of a stack are deployed again with the stack, not resumed one by one
+    let resumable = resolved.is_none();
+    let deploy = run_deploy(description, repository, cloud_provider, dry_run, force_deploy, options, resolved);
+    
+    match correlation::scope(deployment_id.clone(), deploy).await {
+        Ok(mut result) => {
+            result.deployment_id = Some(deployment_id);
+            result.prune_logs(options.prune_logs, options.full_logs)?;
+            Ok(result)
+        }
+        Err(e) => {
+            // Nobody sees a background deploy fail, so status has to say why
+            if detach::detached_id().is_some() {
+                let index = Index::open()?;
+                if let Some(mut entry) = index.load(&deployment_id).ok().filter(|entry| entry.error.is_none()) {
+                    entry.error = Some(e.to_string());
+                    if let Err(save_error) = index.save(&entry) {
+                        warn!("⚠️ Failed to record the error for deployment {}: {}", deployment_id, save_error);
+                    }
+                }
+            }
+            if let Some(phase) = checkpoint::resumable(&deployment_id).filter(|_| resumable) {
+                info!("💾 Phases up to {} are checkpointed; retry from the next one with: cargo run -- resume {}", phase, deployment_id);
+            }
+            // Context keeps the original error, so callers can still downcast it
+            Err(e.context(format!("deployment id: {}", deployment_id)))
+        }
+    }
+}
+
+async fn run_deploy(
+    description: &str,
+    repository: &str,
+    cloud_provider: Option<&str>,
+    dry_run: bool,
+    force_deploy: &ForceDeploy,
+    options: &DeployOptions,
+    resolved: Option<DeploymentRequirements>,
+) -> Result<DeploymentResult> {
+    info!("🚀 Starting deployment process...");
+    
+    options.validate_wait()?;
+    if options.ssh_port == Some(0) {
+        return Err(anyhow!("--ssh-port must be between 1 and 65535"));
+    }
+    let dns_config = options.dns_config()?;
+    let ssh_target = options.ssh_target()?;
+    let env_file = options.env_file.as_deref().map(EnvFile::read).transpose()?;
+    let static_host = options.target.static_host();
+    if let (Some(host), Some(_)) = (static_host, &env_file) {
+        return Err(anyhow!("--env-file is not supported with {}: a static site has no server to deliver it to", host.name()));
+    }
+    if options.private {
+        if options.target != DeployTarget::Terraform {
+            return Err(anyhow!("--private only applies to new infrastructure (--target terraform)"));
+        }
+        if env_file.is_some() {
+            return Err(anyhow!("--env-file is not supported with --private: the env file is delivered over SSH to the instance's public IP"));
+        }
+        if options.readiness_cmd.is_some() {
+            return Err(anyhow!("--readiness-cmd is not supported with --private: it runs over SSH on the instance's public IP"));
+        }
+    }
+    if let Some(name) = &options.name {
+        if options.target != DeployTarget::Terraform {
+            return Err(anyhow!("--name only applies to new infrastructure (--target terraform)"));
+        }
+        infrastructure::validate_deployment_name(name)?;
+    }
+    if !options.regions.is_empty() {
+        if options.target != DeployTarget::Terraform {
+            return Err(anyhow!("--regions only applies to new infrastructure (--target terraform)"));
+        }
+        let unsupported = [
+            ("--private", options.private),
+            ("--env-file", env_file.is_some()),
+            ("--readiness-cmd", options.readiness_cmd.is_some()),
+            ("--dns-provider", dns_config.is_some()),
+        ];
+        if let Some((flag, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(anyhow!("{} is not supported with --regions: each region has its own instance and Route53 manages the DNS records", flag));
+        }
+    }
+    if options.disk_size.is_some() && options.target != DeployTarget::Terraform {
+        return Err(anyhow!("--disk-size only applies to new infrastructure (--target terraform)"));
+    }
+    if options.gpu && options.target != DeployTarget::Terraform {
+        return Err(anyhow!("--gpu only applies to new infrastructure (--target terraform)"));
+    }
+    if options.secrets_source.is_some() && options.target != DeployTarget::Terraform {
+        return Err(anyhow!("--secrets-source only applies to new infrastructure (--target terraform): the instance reads the secrets with its own role"));
+    }
+    if options.vpc_id.is_some() || options.subnet_id.is_some() || options.security_group_id.is_some() {
+        if options.target != DeployTarget::Terraform {
+            return Err(anyhow!("--vpc-id, --subnet-id and --security-group-id only apply to new infrastructure (--target terraform)"));
+        }
+        if options.private || !options.regions.is_empty() {
+            return Err(anyhow!("--vpc-id, --subnet-id and --security-group-id are not supported with --private or --regions: those create their own networks"));
+        }
+    }
+    let restricts_egress = options.restrict_egress || !options.egress_cidr.is_empty() || !options.egress_ports.is_empty();
+    if restricts_egress {
+        if options.target != DeployTarget::Terraform {
+            return Err(anyhow!("--restrict-egress, --egress-cidr and --egress-ports only apply to new infrastructure (--target terraform)"));
+        }
+        if options.security_group_id.is_some() {
+            return Err(anyhow!("--restrict-egress is not supported with --security-group-id: the existing group's rules are left alone"));
+        }
+    }
+    if let (Some(host), Some(_)) = (static_host, &options.readiness_cmd) {
+        return Err(anyhow!("--readiness-cmd is not supported with {}: a static site has no server to run it on", host.name()));
+    }
+    let prebuilt = options.terraform_config.as_deref().map(infrastructure::load_terraform_config).transpose()?;
+    if prebuilt.is_some() {
+        if options.target != DeployTarget::Terraform {
+            return Err(anyhow!("--terraform-config only applies to new infrastructure (--target terraform)"));
+        }
+        let generated_only = [
+            ("--regions", !options.regions.is_empty()),
+            ("--private", options.private),
+            ("--secrets-source", options.secrets_source.is_some()),
+            ("--disk-size", options.disk_size.is_some()),
+            ("--gpu", options.gpu),
+            ("--allow-destroy-stateful", options.allow_destroy_stateful),
+            ("--vpc-id", options.vpc_id.is_some() || options.subnet_id.is_some() || options.security_group_id.is_some()),
+            ("--env-file", env_file.is_some()),
+            ("--restrict-egress", restricts_egress),
+            ("--base-image", options.base_image.is_some()),
+            ("--ssh-port", options.ssh_port.is_some()),
+        ];
+        if let Some((flag, _)) = generated_only.iter().find(|(_, set)| *set) {
+            return Err(anyhow!("{} is not supported with --terraform-config: it changes the generated configuration; edit the file instead", flag));
+        }
+    }
+    let subdir = options.subdir.as_deref().map(repository::normalize_subdir).transpose()?;
+    let mut policy = options.policy()?;
+    if let Some(policy) = policy.as_mut().filter(|policy| policy.strict) {
+        if force_deploy.bypasses(SafetyCheck::Policy) {
+            warn!("⚠️ --force-deploy bypasses the strict policy check: violations will be fixed instead of aborting");
+            policy.strict = false;
+        }
+    }
+    if let Some(env_file) = &env_file {
+        // Only the names are logged, never the values
+        info!("🔒 Env file provides: {}", env_file.keys.join(", "));
+    }
+    
+    let mut requirements = match (resolved, &prebuilt) {
+        (Some(requirements), _) => requirements,
+        // A prebuilt config is not described; its resources say which provider it is for
+        (None, Some(config)) => {
+            let config_provider = infrastructure::provider_for_resource_types(config.resources.iter().map(|r| r.resource_type.as_str()))
+                .ok_or_else(|| anyhow!("Could not detect the cloud provider of the --terraform-config resources"))?;
+            if let Some(provider) = cloud_provider.map(parse_cloud_provider).filter(|provider| *provider != config_provider) {
+                return Err(anyhow!("--cloud-provider {:?} does not match the {:?} resources in --terraform-config", provider, config_provider));
+            }
+            DeploymentRequirements { cloud_provider: config_provider, ..Default::default() }
+        }
+        // A resumed deploy has its requirements in the checkpoint
+        (None, None) => match checkpoint::resuming() {
+            Some(resume) => resume.checkpoint.requirements.clone(),
+            None => resolve_requirements(description, cloud_provider).await?,
+        },
+    };
+    requirements.os = options.os;
+    if subdir.is_some() {
+        requirements.service_dir = subdir;
+    }
+    if requirements.deployment_name.is_none() {
+        requirements.deployment_name = options.name.clone();
+    }
+    match checkpoint::resuming() {
+        // The resumed deploy's directory already carries its name
+        Some(resume) => requirements.deployment_name = resume.checkpoint.deployment_name.clone(),
+        None => {
+            if let Some(name) = &requirements.deployment_name {
+                requirements.deployment_name = Some(infrastructure::unique_deployment_name(name)?);
+            }
+        }
+    }
+    requirements.expects_env_file = env_file.is_some();
+    requirements.deploy_non_service = force_deploy.bypasses(SafetyCheck::NotAService);
+    requirements.environment = options.environment.clone();
+    requirements.health_check = options.health_check()?;
+    requirements.private_network = options.private;
+    requirements.audit = options.audit_mode();
+    requirements.start_script = options.start_script.clone();
+    requirements.base_image = options.base_image.clone();
+    if options.target == DeployTarget::Terraform {
+        // An existing host already listens where --ssh-port says
+        requirements.ssh_port = options.ssh_port.filter(|port| *port != 22);
+        requirements.authorized_key = options.authorized_key()?;
+    }
+    if let Some(source) = &options.secrets_source {
+        if source.cloud_provider != requirements.cloud_provider {
+            return Err(anyhow!("--secrets-source {} does not match the {:?} deployment", source, requirements.cloud_provider));
+        }
+        requirements.secrets_source = Some(source.clone());
+    }
+    if let Some(size) = options.disk_size {
+        disk::validate_disk_size(size, &requirements.cloud_provider)?;
+        requirements.disk_size_gb = Some(size);
+    }
+    requirements.gpu = options.gpu;
+    if !options.database.is_empty() {
+        requirements.database_requirements.retain(|database| *database != DatabaseType::None);
+        for database in &options.database {
+            if !requirements.database_requirements.contains(database) {
+                requirements.database_requirements.push(database.clone());
+            }
+        }
+    }
+    requirements.allow_destroy_stateful = options.allow_destroy_stateful;
+    requirements.git_ref = options.git_ref.clone();
+    // The public IP lookup is irrelevant to a dry run and unwanted offline
+    requirements.rewrite_localhost = !(dry_run || options.no_localhost_rewrite || ai_nlp::offline());
+    requirements.existing_network = ExistingNetwork::from_flags(
+        options.vpc_id.clone(),
+        options.subnet_id.clone(),
+        options.security_group_id.clone(),
+        &requirements.cloud_provider,
+    )?;
+    requirements.egress = EgressPolicy::from_flags(
+        options.restrict_egress,
+        options.egress_cidr.clone(),
+        options.egress_ports.clone(),
+        &requirements.database_requirements,
+        &requirements.cloud_provider,
+    )?;
+    if options.private {
+        warn!(
+            "⚠️ --private adds a NAT gateway and a load balancer, about ${:.2}/mo on {:?}",
+            infrastructure::private_network_cost(true, &requirements.cloud_provider),
+            requirements.cloud_provider
+        );
+    }
+
+    if !options.regions.is_empty() {
+        infrastructure::validate_regions(&options.regions, &requirements.cloud_provider)?;
+        if requirements.custom_domain.is_none() {
+            return Err(anyhow!("--regions needs a custom domain in the description: it is the name Route53 routes to the nearest healthy region"));
+        }
+        warn!(
+            "⚠️ --regions provisions a full copy of the app in each of {} regions, multiplying its cost, plus a Route53 health check per region",
+            options.regions.len()
+        );
+    }
+
+    let mut deployment_result = match (&ssh_target, static_host) {
+        (Some(target), _) => deploy_over_ssh(target, repository, &requirements, dry_run, env_file.as_ref()).await?,
+        (None, Some(host)) => {
+            deploy_static_site(host, repository, &requirements, dry_run, options.netlify_site.as_deref()).await?
+        }
+        (None, None) if !options.regions.is_empty() => {
+            deploy_multi_region(
+                description,
+                repository,
+                &requirements,
+                dry_run,
+                force_deploy.is_forced(),
+                policy.as_ref(),
+                options,
+            ).await?
+        }
+        (None, None) => {
+            deploy_with_terraform(
+                description,
+                repository,
+                &requirements,
+                dry_run,
+                force_deploy.is_forced(),
+                policy.as_ref(),
+                options,
+            ).await?
+        }
+    };
+    
+    if dry_run {
+        info!("🧪 Dry run complete - no infrastructure will be provisioned");
+        return Ok(deployment_result);
+    }
+    
+    // New instances wait in their startup script until the env file and registry logins arrive;
+    // the logins are looked up again for the analysis the instance was generated from
+    let docker_config = match (&ssh_target, &deployment_result.deployment_dir) {
+        (None, Some(dir)) => match Checkpoint::load(dir).ok().and_then(|checkpoint| checkpoint.analysis) {
+            Some(analysis) => bootstrap::docker_config(&resolve_registry_credentials(&analysis)?),
+            None => None,
+        },
+        _ => None,
+    };
+    if ssh_target.is_none() && (env_file.is_some() || docker_config.is_some()) {
+        let public_ip = deployment_result
+            .public_ip
+            .clone()
+            .ok_or_else(|| anyhow!("No public IP in deployment outputs, cannot deliver the env file or registry logins"))?;
+        let target = options.ssh_target_for(public_ip);
+        ssh::wait_for_ssh(&target, DEFAULT_HEALTH_TIMEOUT, Duration::from_secs(10)).await?;
+        if let Some(docker_config) = &docker_config {
+            ssh::upload_private_file(&target, docker_config, bootstrap::REMOTE_DOCKER_CONFIG).await?;
+            deployment_result.logs.push(format!("🔒 Registry logins delivered to {}", bootstrap::REMOTE_DOCKER_CONFIG));
+        }
+        if let Some(env_file) = &env_file {
+            ssh::upload_private_file(&target, &env_file.contents, REMOTE_ENV_FILE).await?;
+            deployment_result.logs.push(format!("🔒 Env file delivered to {} ({} variables)", REMOTE_ENV_FILE, env_file.keys.len()));
+        }
+    }
+    
+    // Wait for the app to boot so a slow start is not reported as unreachable
+    let wait = options.health_wait(&deployment_result.infrastructure_type);
+    let health = if deployment_result.serves_http && !wait.is_zero() {
+        let url = health::health_url(&deployment_result.url, requirements.health_check.path());
+        let health = health::wait_until_healthy(&url, wait, Duration::from_secs(options.poll_interval))
+            .await
+            .map_err(|e| e.to_string());
+        match &health {
+            Ok(elapsed) => deployment_result.logs.push(format!("✅ App healthy after {}s", elapsed.as_secs())),
+            Err(e) => deployment_result.logs.push(format!("❌ {}", e)),
+        }
+        Some(health)
+    } else {
+        None
+    };
+    
+    // Point the custom domain at the new instance; with --regions Route53 already does
+    if let Some(domain) = requirements.custom_domain.as_ref().filter(|_| options.regions.is_empty()) {
+        match (&dns_config, &deployment_result.public_ip) {
+            _ if !deployment_result.serves_http => {
+                warn!("⚠️ App is a background worker with no web port, skipping DNS record for {}", domain);
+            }
+            (Some(dns_config), Some(public_ip)) => {
+                let healthy = if options.health_gated_dns {
+                    match &health {
+                        Some(Err(e)) => {
+                            warn!("⚠️ Leaving DNS for {} unchanged: {}", domain, e);
+                            deployment_result.logs.push(format!("❌ Health gate failed, DNS unchanged: {}", e));
+                            false
+                        }
+                        _ => true,
+                    }
+                } else {
+                    true
+                };
+                
+                if healthy {
+                    match dns::upsert_a_record(dns_config, domain, public_ip).await {
+                        Ok(()) => deployment_result.logs.push(format!("🌍 DNS record {} -> {}", domain, public_ip)),
+                        Err(e) => {
+                            warn!("⚠️ Failed to update DNS record for {}: {}", domain, e);
+                            deployment_result.logs.push(format!("⚠️ DNS update failed: {}", e));
+                        }
+                    }
+                }
+            }
+            (Some(_), None) => {
+                warn!("⚠️ No public IP in deployment outputs, skipping DNS record for {}", domain);
+            }
+            (None, _) => {
+                warn!("⚠️ Custom domain {} requested but no --dns-provider configured, skipping DNS record", domain);
+            }
+        }
+    }
+    
+    if let Some(Err(e)) = health {
+        warn!("⚠️ Deployed but not reachable: {}", e);
+        deployment_result.not_ready = Some(e);
+        return Ok(deployment_result);
+    }
+    
+    // The app is up; it is not done until it passes the readiness checks
+    if let Err(e) = verify_readiness(options, &mut deployment_result, ssh_target.as_ref()).await {
+        warn!("⚠️ Deployed but not ready: {}", e);
+        deployment_result.logs.push(format!("❌ Deployed but not ready: {}", e));
+        deployment_result.not_ready = Some(e.to_string());
+        return Ok(deployment_result);
+    }
+    
+    if let Some(dir) = &deployment_result.deployment_dir {
+        checkpoint::record_phase(dir, Phase::Verify);
+    }
+    info!("✅ Deployment completed successfully!");
+    info!("🌐 Application URL: {}", deployment_result.url);
+    
+    Ok(deployment_result)
+}
+
+/// Run the `--readiness-cmd` and `--warmup-url` checks. An error means the app was deployed
+/// but is not ready, not that the deploy failed.
+async fn verify_readiness(
+    options: &DeployOptions,
+    result: &mut DeploymentResult,
+    ssh_target: Option<&SshTarget>,
+) -> Result<()> {
+    if let Some(command) = &options.readiness_cmd {
+        let target = match ssh_target {
+            Some(target) => target.clone(),
+            None => {
+                let public_ip = result
+                    .public_ip
+                    .clone()
+                    .ok_or_else(|| anyhow!("No public IP in deployment outputs, cannot run --readiness-cmd"))?;
+                options.ssh_target_for(public_ip)
+            }
+        };
+        ssh::wait_for_command(&target, command, DEFAULT_HEALTH_TIMEOUT, Duration::from_secs(10)).await?;
+        result.logs.push(format!("✅ Readiness command succeeded: {}", command));
+    }
+    
+    if let Some(warmup_url) = &options.warmup_url {
+        if !result.serves_http {
+            warn!("⚠️ App is a background worker with no web port, skipping --warmup-url");
+            return Ok(());
+        }
+        let url = health::resolve_url(&result.url, warmup_url);
+        let wait = options.health_wait(&result.infrastructure_type);
+        health::warm_up(&url, options.warmup_requests, wait, Duration::from_secs(options.poll_interval)).await?;
+        result.logs.push(format!("🔥 Warmed up {} with {} request(s)", url, options.warmup_requests.max(1)));
+    }
+    
+    Ok(())
+}
+
+/// Provision new infrastructure for the app with Terraform
+async fn deploy_with_terraform(
+    description: &str,
+    repository: &str,
+    requirements: &DeploymentRequirements,
+    dry_run: bool,
+    force_deploy: bool,
+    policy: Option<&Policy>,
+    options: &DeployOptions,
+) -> Result<DeploymentResult> {
+    // Check credentials for non-dry-run deployments
+    if !dry_run || force_deploy {
+        ensure_credentials(&requirements.cloud_provider, options.interactive_credentials).await?;
+    }
+    
+    // Each region of a --regions deploy is its own deployment in the index
+    let index_id = match &requirements.region {
+        Some(region) => format!("{}-{}", correlation::current().unwrap_or_else(correlation::new_id), region),
+        None => correlation::current().unwrap_or_else(correlation::new_id),
+    };
+    
+    // Every phase is checkpointed in the deployment directory; a resumed deploy continues in
+    // its own directory after the last phase that completed
+    let resume = checkpoint::resuming().filter(|resume| resume.id == index_id);
+    let (terraform_dir, mut checkpoint) = match resume {
+        Some(resume) => {
+            info!("⏩ Resuming deployment {} after the {} phase", resume.id, resume.checkpoint.phase);
+            (resume.deployment_dir.clone(), resume.checkpoint.clone())
+        }
+        None => {
+            let terraform_dir = infrastructure::new_deployment_dir(requirements.deployment_name.as_deref())?;
+            let mut checkpoint = Checkpoint::new(requirements);
+            checkpoint.record(&terraform_dir, Phase::Parse);
+            (terraform_dir, checkpoint)
+        }
+    };
+    
+    let index = Index::open()?;
+    let mut entry = IndexEntry::new(&index_id, repository, &requirements.cloud_provider, &terraform_dir);
+    if let Ok(previous) = index.load(&index_id) {
+        if resume.is_some() {
+            entry = previous;
+        } else {
+            // `--detach` recorded the background run before the directory existed
+            entry.created_at = previous.created_at;
+            entry.pid = previous.pid;
+            entry.log_file = previous.log_file;
+        }
+    }
+    entry.region = requirements.region.clone();
+    // So later commands reach the instance the same way
+    entry.ssh_port = options.ssh_port;
+    entry.bastion = options.bastion.clone();
+    if entry.state == DeploymentState::Provisioning {
+        // The apply being resumed was cut off before it finished
+        entry.error = Some("interrupted while provisioning".to_string());
+        index.record(&mut entry, DeploymentState::Failed);
+    }
+    
+    let mut infrastructure_decision = match checkpoint.decision.clone() {
+        Some(decision) => decision,
+        None => {
+            let decision = match &options.terraform_config {
+                Some(path) => {
+                    info!("📥 Using the prebuilt Terraform config in {}", path.display());
+                    infrastructure::prebuilt_decision(infrastructure::load_terraform_config(path)?, &requirements.cloud_provider)
+                }
+                None => decide_with_checkpoint(description, repository, requirements, &mut checkpoint, &terraform_dir).await?,
+            };
+            checkpoint.decision = Some(decision.clone());
+            checkpoint.record(&terraform_dir, Phase::Decide);
+            decision
+        }
+    };
+    entry.deployment_type = Some(format!("{:?}", infrastructure_decision.deployment_type));
+    entry.commit = infrastructure_decision.commit.clone();
+    if let Err(e) = index.save(&entry) {
+        warn!("⚠️ Failed to record deployment {} in the index: {}", index_id, e);
+    }
+    
+    let logs = if checkpoint.completed(Phase::Generate) {
+        vec![format!("⏩ Terraform files already generated in {}", terraform_dir.display())]
+    } else {
+        // Guard against oversized or disallowed resources before anything is written
+        let violations = match policy {
+            Some(policy) => policy.enforce(
+                &mut infrastructure_decision.terraform_config,
+                &infrastructure_decision.instance_type,
+            )?,
+            None => Vec::new(),
+        };
+        
+        // Generate Terraform files (even for dry-run to allow review)
+        info!("📄 Generating Terraform configuration files...");
+        let mut logs = infrastructure::write_deployment_files(&infrastructure_decision, repository, TerraformFormat::Hcl, &terraform_dir)?;
+        logs.extend(violations.iter().map(|v| format!("🛡️ Policy: {}", v)));
+        checkpoint.decision = Some(infrastructure_decision.clone());
+        checkpoint.record(&terraform_dir, Phase::Generate);
+        logs
+    };
+    
+    if dry_run {
+        return Ok(DeploymentResult {
+            url: "dry-run".to_string(),
+            infrastructure_type: format!("{:?}", infrastructure_decision.deployment_type),
+            public_ip: None,
+            logs,
+            deployment_id: None,
+            serves_http: true,
+            deployment_dir: Some(terraform_dir),
+            not_ready: None,
+            outputs: HashMap::new(),
+            cost: Some(infrastructure_decision.estimated_cost.clone()),
+            startup_script: bootstrap::startup_script(&infrastructure_decision.terraform_config)
+                .map(|script| redact_secrets(&script)),
+            commit: infrastructure_decision.commit.clone(),
+        });
+    }
+    
+    if let Some(result) = checkpoint.result.clone().filter(|_| checkpoint.completed(Phase::Provision)) {
+        info!("⏩ Infrastructure already provisioned, continuing with the checks");
+        return Ok(result);
+    }
+    
+    info!("☁️ Provisioning infrastructure...");
+    let mut repair = options.ai_repair.then(|| PlanRepair {
+        config: infrastructure_decision.terraform_config.clone(),
+        repo_url: repository.to_string(),
+        cloud_provider: requirements.cloud_provider.clone(),
+        max_attempts: MAX_AI_REPAIR_ATTEMPTS,
+        policy: policy.cloned(),
+        fallback_instance_type: infrastructure_decision.instance_type.clone(),
+        network: requirements.existing_network.clone(),
+        disk_size_gb: infrastructure_decision.disk_size_gb,
+        secrets_source: requirements.secrets_source.clone(),
+        egress: requirements.egress.clone(),
+        ssh_port: requirements.ssh_port,
+        authorized_key: requirements.authorized_key.clone(),
+    });
+    index.record(&mut entry, DeploymentState::Provisioning);
+    let applied = apply_terraform(
+        &terraform_dir,
+        &requirements.cloud_provider,
+        &format!("{:?}", infrastructure_decision.deployment_type),
+        logs,
+        repair.as_mut(),
+        requirements.region.as_deref(),
+        options.terraform_timeout(),
+    ).await;
+    let applied = applied.map(|mut deployment_result| {
+        deployment_result.cost = Some(infrastructure_decision.estimated_cost.clone());
+        deployment_result.commit = infrastructure_decision.commit.clone();
+        match infrastructure_decision.exposed_ports.first() {
+            // Fix URL if it contains "unknown" 
+            Some(port) => {
+                if let Some(public_ip) = &deployment_result.public_ip {
+                    if deployment_result.url.contains("unknown") {
+                        deployment_result.url = format!("http://{}:{}", public_ip, port);
+                    }
+                }
+                // An instance address answers on the app's port; load balancers and websites on their own
+                if !["load_balancer_url", "website_url"].iter().any(|name| deployment_result.outputs.contains_key(*name)) {
+                    deployment_result.url = health::url_with_port(&deployment_result.url, *port);
+                }
+            }
+            None => {
+                deployment_result.url = WORKER_URL.to_string();
+                deployment_result.serves_http = false;
+            }
+        }
+        deployment_result
+    });
+    index.record_applied(&mut entry, &applied);
+    if let Ok(result) = &applied {
+        checkpoint.result = Some(result.clone());
+        checkpoint.record(&terraform_dir, Phase::Provision);
+    }
+    applied
+}
+
+/// Clone, analyze and decide on the infrastructure, skipping the phases `checkpoint` already
+/// has and checkpointing the others in `terraform_dir`
+async fn decide_with_checkpoint(
+    description: &str,
+    repository: &str,
+    requirements: &DeploymentRequirements,
+    checkpoint: &mut Checkpoint,
+    terraform_dir: &Path,
+) -> Result<InfrastructureDecision> {
+    let analysis = match checkpoint.analysis.clone() {
+        Some(analysis) => analysis,
+        None => {
+            // A resumed deploy clones the commit it cloned before, even if the branch moved on
+            let git_ref = checkpoint.commit.clone().or_else(|| requirements.git_ref.clone());
+            let temp_repo = clone_for_analysis(repository, git_ref.as_deref(), requirements.rewrite_localhost).await?;
+            checkpoint.commit = repository::head_commit(temp_repo.path());
+            checkpoint.record(terraform_dir, Phase::Clone);
+            let analysis = analyze_clone(temp_repo.path(), requirements).await?;
+            checkpoint.analysis = Some(analysis.clone());
+            checkpoint.record(terraform_dir, Phase::Analyze);
+            analysis
+        }
+    };
+    let requirements = complete_requirements(requirements, &analysis)?;
+    require_deploy_key(&requirements)?;
+    
+    info!("🏗️ Determining optimal infrastructure using AI...");
+    let decision = decide_infrastructure(&requirements, &analysis, description, repository).await?;
+    
+    info!("Infrastructure decision: {:?}", decision.deployment_type);
+    info!("Justification: {}", decision.justification);
+    
+    Ok(decision)
+}
+
+/// The startup script waits for the env file and registry logins, which only arrive over SSH;
+/// without a key installed on the instance the deploy could never log in to deliver them
+fn require_deploy_key(requirements: &DeploymentRequirements) -> Result<()> {
+    if (requirements.expects_env_file || !requirements.registry_credentials.is_empty()) && requirements.authorized_key.is_none() {
+        return Err(anyhow!(
+            "The env file and private registry logins are delivered over SSH, but no key would be installed on the instance: deploy with --ssh-key <private key>"
+        ));
+    }
+    Ok(())
+}
+
+/// Deploy a full copy of the app to each `--regions` region, each with its own instance and
+/// Terraform directory, then route the custom domain to them with Route53 latency records.
+/// A region failing its health check drops out of DNS, so users reach the nearest healthy one.
+async fn deploy_multi_region(
+    description: &str,
+    repository: &str,
+    requirements: &DeploymentRequirements,
     dry_run: bool,
     force_deploy: bool,
+    policy: Option<&Policy>,
+    options: &DeployOptions,
 ) -> Result<DeploymentResult> {
-    info!("🚀 Starting deployment process...");
+    let domain = requirements
+        .custom_domain
+        .clone()
+        .ok_or_else(|| anyhow!("--regions needs a custom domain"))?;
     
-    // Parse natural language requirements using AI
-    info!("📝 Parsing deployment requirements from description using AI...");
-    let mut requirements = ai_nlp::parse_deployment_requirements(description).await?;
-    
-    // Use CLI cloud provider if provided, otherwise use LLM-parsed provider
-    if let Some(provider) = cloud_provider {
-        requirements.cloud_provider = match provider.to_lowercase().as_str() {
-            "aws" => crate::nlp::CloudProvider::AWS,
-            "gcp" | "google" => crate::nlp::CloudProvider::GCP,
-            "azure" => crate::nlp::CloudProvider::Azure,
-            "digitalocean" => crate::nlp::CloudProvider::DigitalOcean,
-            _ => {
-                warn!("Unknown cloud provider '{}', defaulting to AWS", provider);
-                crate::nlp::CloudProvider::AWS
-            }
+    let mut logs = Vec::new();
+    let mut endpoints = Vec::new();
+    let mut deployed: Vec<String> = Vec::new();
+    let mut serves_http = true;
+    let mut regional_cost = None;
+    let mut commit = None;
+    for region in &options.regions {
+        info!("🌎 Deploying to {}...", region);
+        let mut regional_requirements = requirements.clone();
+        regional_requirements.region = Some(region.clone());
+        regional_requirements.deployment_name = match &requirements.deployment_name {
+            Some(name) => Some(infrastructure::unique_deployment_name(&format!("{}-{}", name, region))?),
+            None => None,
         };
+        
+        let result = deploy_with_terraform(description, repository, &regional_requirements, dry_run, force_deploy, policy, options)
+            .await
+            .map_err(|e| {
+                if deployed.is_empty() {
+                    anyhow!("Deploy to {} failed: {}", region, e)
+                } else {
+                    anyhow!("Deploy to {} failed: {}. Already deployed and still running: {}", region, e, deployed.join(", "))
+                }
+            })?;
+        logs.extend(result.logs.iter().map(|line| format!("[{}] {}", region, line)));
+        if let Some(dir) = &result.deployment_dir {
+            logs.push(format!("📁 {}: {}", region, dir.display()));
+            deployed.push(format!("{} ({})", region, dir.display()));
+        }
+        serves_http &= result.serves_http;
+        regional_cost = result.cost.clone().or(regional_cost);
+        commit = result.commit.clone().or(commit);
+        
+        if let Some(ip) = &result.public_ip {
+            let port = reqwest::Url::parse(&result.url)
+                .ok()
+                .and_then(|url| url.port_or_known_default())
+                .unwrap_or(80);
+            endpoints.push(RegionalEndpoint { region: region.clone(), ip: ip.clone(), port });
+        }
+    }
+    
+    let cost = regional_cost.map(|cost| infrastructure::multi_region_cost(&cost, options.regions.len()));
+    if dry_run {
+        logs.push("🧪 Route53 latency routing is generated once the regions are deployed".to_string());
+        return Ok(DeploymentResult {
+            url: "dry-run".to_string(),
+            infrastructure_type: format!("MultiRegion({})", options.regions.join(",")),
+            public_ip: None,
+            logs,
+            deployment_id: None,
+            serves_http,
+            deployment_dir: None,
+            not_ready: None,
+            outputs: HashMap::new(),
+            cost,
+            startup_script: None,
+            commit,
+        });
+    }
+    if !serves_http {
+        return Err(anyhow!("App is a background worker with no web port; there is nothing for --regions to route {} to", domain));
     }
-    // If no CLI provider specified, use what the LLM parsed from description
+    if endpoints.len() != options.regions.len() {
+        return Err(anyhow!("No public IP in the deployment outputs of every region, cannot route {} to them", domain));
+    }
+    
+    info!("🌍 Routing {} to the nearest healthy of {} regions...", domain, endpoints.len());
+    let routing_dir = infrastructure::generate_latency_routing_files(
+        &domain,
+        options.dns_zone.as_deref(),
+        &endpoints,
+        requirements.health_check.path(),
+        requirements.deployment_name.as_deref(),
+    )?;
+    let mut result = apply_terraform(
+        &routing_dir,
+        &requirements.cloud_provider,
+        "MultiRegion",
+        logs,
+        None,
+        None,
+        options.terraform_timeout(),
+    ).await?;
+    result.cost = cost;
+    result.commit = commit;
+    result.logs.push(format!(
+        "🌍 {} routed by latency to {}",
+        domain,
+        endpoints.iter().map(|endpoint| endpoint.region.as_str()).collect::<Vec<_>>().join(", ")
+    ));
+    Ok(result)
+}
 
-    // Check credentials for non-dry-run deployments
-    if !dry_run || force_deploy {
-        let credentials = CloudCredentials::load_from_file()
-            .unwrap_or_else(|_| CloudCredentials::new());
-        
-        if !credentials.has_credentials_for(&requirements.cloud_provider) {
-            let provider_str = match requirements.cloud_provider {
-                crate::nlp::CloudProvider::AWS => "aws",
-                crate::nlp::CloudProvider::GCP => "gcp",
-                crate::nlp::CloudProvider::Azure => "azure",
-                crate::nlp::CloudProvider::DigitalOcean => "digitalocean",
-                crate::nlp::CloudProvider::Unknown => "aws", // fallback
-            };
+/// Deploy onto an existing host by running the bootstrap script over SSH, skipping Terraform
+async fn deploy_over_ssh(
+    target: &SshTarget,
+    repository: &str,
+    requirements: &DeploymentRequirements,
+    dry_run: bool,
+    env_file: Option<&EnvFile>,
+) -> Result<DeploymentResult> {
+    let analysis = clone_and_analyze(repository, requirements).await?;
+    let requirements = complete_requirements(requirements, &analysis)?;
+    let script = bootstrap::render_startup_script(&analysis, repository, &requirements);
+    let port = analysis.exposed_ports.first().copied();
+    
+    let mut logs = vec![format!("🔐 Target: {}@{} over SSH", target.user, target.host)];
+    
+    if dry_run {
+        info!("📜 Bootstrap script for {}:\n{}", target.host, script);
+        logs.push("🧪 Dry run: bootstrap script rendered, not executed".to_string());
+        return Ok(DeploymentResult {
+            url: "dry-run".to_string(),
+            infrastructure_type: "SSH".to_string(),
+            public_ip: None,
+            logs,
+            deployment_id: None,
+            serves_http: true,
+            deployment_dir: None,
+            not_ready: None,
+            outputs: HashMap::new(),
+            cost: None,
+            startup_script: None,
+            commit: None,
+        });
+    }
+    
+    if let Some(docker_config) = bootstrap::docker_config(&requirements.registry_credentials) {
+        ssh::upload_private_file(target, &docker_config, bootstrap::REMOTE_DOCKER_CONFIG).await?;
+        logs.push(format!("🔒 Registry logins delivered to {}", bootstrap::REMOTE_DOCKER_CONFIG));
+    }
+    if let Some(env_file) = env_file {
+        ssh::upload_private_file(target, &env_file.contents, REMOTE_ENV_FILE).await?;
+        logs.push(format!("🔒 Env file delivered to {} ({} variables)", REMOTE_ENV_FILE, env_file.keys.len()));
+    }
+    
+    let output = ssh::run_script(target, &script).await?;
+    logs.extend(output.lines().map(|line| line.to_string()));
+    
+    Ok(DeploymentResult {
+        url: match port {
+            Some(port) => format!("http://{}:{}", target.host, port),
+            None => WORKER_URL.to_string(),
+        },
+        infrastructure_type: "SSH".to_string(),
+        public_ip: Some(target.host.clone()),
+        logs,
+        deployment_id: None,
+        serves_http: port.is_some(),
+        deployment_dir: None,
+        not_ready: None,
+        outputs: HashMap::new(),
+        cost: None,
+        startup_script: None,
+        commit: None,
+    })
+}
+
+/// Build a static site locally and publish it to GitHub Pages or Netlify, skipping Terraform
+async fn deploy_static_site(
+    host: StaticHost,
+    repository: &str,
+    requirements: &DeploymentRequirements,
+    dry_run: bool,
+    netlify_site: Option<&str>,
+) -> Result<DeploymentResult> {
+    // A missing token or CLI fails before anything is cloned or built
+    let publisher = if dry_run {
+        None
+    } else {
+        Some(Publisher::resolve(host, repository, netlify_site)?)
+    };
+    
+    info!("📥 Cloning repository: {}", repository);
+    let temp_repo = clone_repository(repository, requirements.git_ref.as_deref()).await?;
+    let analysis = match &requirements.service_dir {
+        Some(dir) => repository::analyze_service(temp_repo.path(), dir)?,
+        None => analyze_repository(temp_repo.path())?,
+    };
+    if !matches!(analysis.app_type, ApplicationType::React | ApplicationType::NextJS | ApplicationType::Unknown) {
+        return Err(anyhow!(
+            "{:?} apps run a server and can't be published to {}; use --target terraform or ssh",
+            analysis.app_type,
+            host.name()
+        ));
+    }
+    let requirements = complete_requirements(requirements, &analysis)?;
+    let app_root = match &analysis.working_dir {
+        Some(dir) => temp_repo.path().join(dir),
+        None => temp_repo.path().to_path_buf(),
+    };
+    
+    let mut logs = vec![format!("🌐 Target: {}", host.name())];
+    let publisher = match publisher {
+        Some(publisher) => publisher,
+        None => {
+            logs.push(format!("🧪 Dry run: would build with {:?} and publish to {}", analysis.build_commands, host.name()));
+            return Ok(DeploymentResult {
+                url: "dry-run".to_string(),
+                infrastructure_type: host.name().to_string(),
+                public_ip: None,
+                logs,
+                deployment_id: None,
+                serves_http: true,
+                deployment_dir: None,
+                not_ready: None,
+                outputs: HashMap::new(),
+                cost: None,
+                startup_script: None,
+                commit: None,
+            });
+        }
+    };
+    
+    static_hosting::build_site(&app_root, &analysis.build_commands, &requirements.environment_variables).await?;
+    let site_dir = static_hosting::find_site_dir(&app_root, analysis.static_files_dir.as_deref())?;
+    logs.push(format!("🔨 Built site in {}", site_dir.strip_prefix(temp_repo.path()).unwrap_or(&site_dir).display()));
+    
+    let url = publisher.publish(&site_dir).await?;
+    logs.push(format!("✅ Published to {}", host.name()));
+    
+    Ok(DeploymentResult {
+        url,
+        infrastructure_type: host.name().to_string(),
+        public_ip: None,
+        logs,
+        deployment_id: None,
+        serves_http: true,
+        deployment_dir: None,
+        not_ready: None,
+        outputs: HashMap::new(),
+        cost: None,
+        startup_script: None,
+        commit: None,
+    })
+}
+
+/// Run the AI and analysis phases and write the Terraform files without applying them.
+pub async fn generate_deployment(
+    description: &str,
+    repository: &str,
+    cloud_provider: Option<&str>,
+    os: OsFamily,
+    format: TerraformFormat,
+    git_ref: Option<String>,
+) -> Result<PathBuf> {
+    info!("🚀 Generating deployment configuration...");
+    
+    let mut requirements = resolve_requirements(description, cloud_provider).await?;
+    requirements.os = os;
+    requirements.git_ref = git_ref;
+    // Generating provisions nothing, so a non-service only gets the warning
+    requirements.deploy_non_service = true;
+    let decision = analyze_and_decide(description, repository, &requirements).await?;
+    
+    info!("📄 Generating Terraform configuration files...");
+    let (terraform_dir, _logs) = generate_deployment_files(&decision, repository, format)?;
+    
+    Ok(terraform_dir)
+}
+
+/// What `plan` prices beyond the description, repository and provider
+#[derive(Debug, Clone, Default, Args)]
+pub struct PlanOptions {
+    #[clap(long, help = "Estimate on AWS, GCP and Azure side by side")]
+    pub compare_providers: bool,
+
+    #[clap(long, help = "Include the NAT gateway and load balancer of a --private deployment")]
+    pub private: bool,
+
+    #[clap(long, use_value_delimiter = true, value_name = "REGIONS", help = "Sum the estimate across the regions of a --regions deployment")]
+    pub regions: Vec<String>,
+
+    #[clap(long, value_name = "GB", help = "Price this root disk size instead of the one derived from the build")]
+    pub disk_size: Option<u32>,
+
+    #[clap(long, help = "Price a GPU instance, as deploy --gpu would use")]
+    pub gpu: bool,
+
+    #[clap(long = "ref", value_name = "REF", help = "Branch, tag or commit to analyze instead of the default branch")]
+    pub git_ref: Option<String>,
+}
+
+/// Estimate the deployment without generating or provisioning anything. Needs no cloud
+/// credentials; the description (if any) is still parsed by the AI.
+pub async fn estimate_deployment(
+    description: Option<&str>,
+    repository: &str,
+    cloud_provider: Option<&str>,
+    options: &PlanOptions,
+) -> Result<Vec<ProviderEstimate>> {
+    let PlanOptions { compare_providers, private: private_network, regions, disk_size, gpu, git_ref } = options.clone();
+    let mut requirements = match description {
+        Some(description) => resolve_requirements(description, cloud_provider).await?,
+        None => DeploymentRequirements {
+            cloud_provider: cloud_provider
+                .or(settings::get().cloud_provider.as_deref())
+                .map(parse_cloud_provider)
+                .unwrap_or(CloudProvider::AWS),
+            ..Default::default()
+        },
+    };
+    
+    requirements.private_network = private_network;
+    requirements.gpu = gpu;
+    if let Some(size) = disk_size {
+        // Compared providers each clamp the size to their own limits
+        if !compare_providers {
+            disk::validate_disk_size(size, &requirements.cloud_provider)?;
+        }
+        requirements.disk_size_gb = Some(size);
+    }
+    if !regions.is_empty() {
+        if compare_providers {
+            return Err(anyhow!("--regions is only supported on AWS and cannot be combined with --compare-providers"));
+        }
+        infrastructure::validate_regions(&regions, &requirements.cloud_provider)?;
+    }
+    
+    info!("📥 Cloning repository: {}", repository);
+    let temp_repo = clone_repository(repository, git_ref.as_deref()).await?;
+    let analysis = analyze_repository(temp_repo.path())?;
+    if let Some(reason) = &analysis.not_a_service {
+        warn!("⚠️ This repository does not look like a service: {}", reason);
+    }
+    
+    let mut estimates = if compare_providers {
+        infrastructure::compare_providers(&requirements, &analysis)
+    } else {
+        let mut estimate = infrastructure::estimate_for_provider(&requirements, &analysis, &requirements.cloud_provider);
+        if !regions.is_empty() {
+            estimate.estimated_cost = infrastructure::multi_region_cost(&estimate.estimated_cost, regions.len());
+            estimate.regions = regions.clone();
+        }
+        vec![estimate]
+    };
+    // The script is the same on every provider; only VMs run one
+    let script = redact_secrets(&bootstrap::render_startup_script(&analysis, repository, &requirements));
+    for estimate in &mut estimates {
+        if matches!(estimate.deployment_type, infrastructure::DeploymentType::SingleVM) {
+            estimate.startup_script = Some(script.clone());
+        }
+    }
+    Ok(estimates)
+}
+
+fn describe_static_serving(serving: &StaticServing) -> String {
+    let server = match serving.served_by {
+        StaticServer::App => "the app",
+        StaticServer::Proxy => "the reverse proxy",
+    };
+    format!("{} from {}/, served by {} ({})", serving.url_path, serving.dir, server, serving.reason)
+}
+
+/// Print estimates as a table, or as a JSON array with `OutputFormat::Json`
+pub fn print_estimates(estimates: &[ProviderEstimate], output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(estimates)?);
+        return Ok(());
+    }
+    
+    println!("{:<10} {:<18} {:<16} {:>12}", "Provider", "Deployment Type", "Instance Type", "Est. Cost");
+    for estimate in estimates {
+        println!(
+            "{:<10} {:<18} {:<16} {:>12}",
+            format!("{:?}", estimate.cloud_provider),
+            format!("{:?}", estimate.deployment_type),
+            estimate.instance_type,
+            format!("${:.2}/mo", estimate.estimated_cost.total)
+        );
+    }
+    if let Some(cheapest) = estimates
+        .iter()
+        .min_by(|a, b| a.estimated_cost.total.total_cmp(&b.estimated_cost.total))
+        .filter(|_| estimates.len() > 1)
+    {
+        println!("💰 Cheapest: {:?}", cheapest.cloud_provider);
+    }
+    if let [estimate] = estimates {
+        println!("\n💰 Cost breakdown:");
+        print_cost_breakdown(&estimate.estimated_cost);
+    }
+    if let Some(estimate) = estimates.first() {
+        if !estimate.regions.is_empty() {
+            println!(
+                "🌎 Summed across {} regions ({}): a full copy in each, plus a Route53 health check per region",
+                estimate.regions.len(),
+                estimate.regions.join(", ")
+            );
+        }
+        if let Some(cost) = gpu::monthly_cost(&estimate.instance_type) {
+            println!("⚠️ GPU instance {}: ${:.2}/mo on its own, dozens of times a CPU micro instance", estimate.instance_type, cost);
+        }
+        if let Some(size) = estimate.disk_size_gb {
+            println!(
+                "💾 Root disk: {} GB, ${:.2}/mo of the estimate",
+                size,
+                disk::storage_cost(size, &estimate.cloud_provider)
+            );
+        }
+        if let Some(serving) = &estimate.static_serving {
+            println!("📁 Static files: {}", describe_static_serving(serving));
+        }
+        if let Some(location) = &estimate.nginx_location {
+            println!("{}", location);
+        }
+        if let Some(script) = &estimate.startup_script {
+            println!("\n📜 Startup script:\n{}", script);
+        }
+    }
+    Ok(())
+}
+
+/// Provision a previously generated Terraform directory.
+pub async fn apply_deployment(terraform_dir: &Path, cloud_provider: Option<&str>) -> Result<DeploymentResult> {
+    let has_main = [TerraformFormat::Hcl, TerraformFormat::Json]
+        .iter()
+        .any(|format| terraform_dir.join(format.main_file()).exists());
+    if !has_main {
+        return Err(anyhow!("No main.tf or main.tf.json found in {}", terraform_dir.display()));
+    }
+    
+    let cloud_provider = match cloud_provider {
+        Some(provider) => parse_cloud_provider(provider),
+        None => detect_cloud_provider(terraform_dir)?,
+    };
+    info!("☁️ Applying {} for {:?}", terraform_dir.display(), cloud_provider);
+    
+    let logs = vec![format!("📁 Applying existing configuration in {}", terraform_dir.display())];
+    apply_terraform(terraform_dir, &cloud_provider, "Terraform", logs, None, None, Some(DEFAULT_TERRAFORM_TIMEOUT)).await
+}
+
+/// Generate the Dockerfile a container deploy would build (`deploy --dockerfile-only`) and save
+/// it in a new deployment directory. Returns the Dockerfile and where it was saved.
+pub async fn generate_dockerfile(repository: &str, options: &DeployOptions) -> Result<(String, PathBuf)> {
+    let requirements = DeploymentRequirements {
+        service_dir: options.subdir.as_deref().map(repository::normalize_subdir).transpose()?,
+        start_script: options.start_script.clone(),
+        git_ref: options.git_ref.clone(),
+        ..Default::default()
+    };
+    let analysis = clone_and_analyze(repository, &requirements).await?;
+    if analysis.docker_config.is_some() {
+        warn!("⚠️ The repository already has a Dockerfile, which deploys use instead of this one");
+    }
+    let image = options
+        .base_image
+        .clone()
+        .or_else(|| analysis.container_base_image())
+        .ok_or_else(|| anyhow!("No official image for {:?} apps; pass the image to build on with --base-image", analysis.app_type))?;
+    let dockerfile = dockerfile::render_dockerfile(&analysis, &image)?;
+    let path = infrastructure::new_deployment_dir(options.name.as_deref())?.join(DOCKERFILE);
+    fs::write(&path, &dockerfile)?;
+    Ok((dockerfile, path))
+}
+
+/// Start the deploy of this command line in the background (`deploy --detach`) and record it in
+/// the index under the id it will deploy with. Its output goes to a log `logs <id>` shows.
+pub fn detach_deploy(repository: &str, cloud_provider: Option<&str>, options: &DeployOptions) -> Result<IndexEntry> {
+    if options.target != DeployTarget::Terraform || !options.regions.is_empty() {
+        return Err(anyhow!("--detach only applies to a single new deployment (--target terraform, without --regions)"));
+    }
+    if options.interactive_credentials {
+        return Err(anyhow!("--detach cannot prompt for credentials; set them up first with: cargo run -- credentials setup"));
+    }
+    let index = Index::open()?;
+    let id = correlation::new_id();
+    let log_file = index.log_path(&id);
+    let cloud_provider = cloud_provider.map(parse_cloud_provider).unwrap_or(CloudProvider::Unknown);
+    // The deploy replaces this with the full entry once its directory is generated
+    let mut entry = IndexEntry::new(&id, repository, &cloud_provider, Path::new(""));
+    entry.pid = Some(detach::spawn_background(&id, &log_file)?);
+    entry.log_file = Some(log_file);
+    index.save(&entry)?;
+    Ok(entry)
+}
+
+/// Print a deployment's background log; `follow` keeps printing until the deploy finishes
+pub fn print_deployment_log(id: &str, follow: bool) -> Result<()> {
+    let index = Index::open()?;
+    let entry = index.load(id)?;
+    let log_file = entry
+        .log_file
+        .ok_or_else(|| anyhow!("Deployment {} did not run in the background; its logs are in the deploy output", id))?;
+    detach::print_log(&log_file, entry.pid.filter(|_| follow))
+}
+
+/// Every deployment in the index, newest first
+pub fn list_deployments() -> Result<Vec<IndexEntry>> {
+    let mut entries = Index::open()?.list()?;
+    // RFC 3339 timestamps in UTC sort chronologically as strings
+    entries.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(entries)
+}
+
+pub fn print_deployments(entries: &[IndexEntry], output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No deployments yet. Deploy with: cargo run -- deploy --description \"...\" --repository <url>");
+        return Ok(());
+    }
+    
+    println!(
+        "{:<18} {:<12} {:<8} {:<18} {:<20} {:<28} Repository",
+        "ID", "State", "Provider", "Deployment Type", "Created", "URL"
+    );
+    for entry in entries {
+        let created = chrono::DateTime::parse_from_rfc3339(&entry.created_at)
+            .map(|created| created.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| entry.created_at.clone());
+        println!(
+            "{:<18} {:<12} {:<8} {:<18} {:<20} {:<28} {}",
+            entry.id,
+            entry.state.to_string(),
+            format!("{:?}", entry.cloud_provider),
+            entry.deployment_type.as_deref().unwrap_or("-"),
+            created,
+            entry.url.as_deref().unwrap_or("-"),
+            entry.repository
+        );
+    }
+    Ok(())
+}
+
+/// Tear down a deployment recorded in the index with `terraform destroy`, then delete its
+/// entry. A failed teardown keeps the entry, with the error, so it can be retried. Resources
+/// protected with `prevent_destroy` are only destroyed with `allow_destroy_stateful`.
+pub async fn destroy_deployment(id: &str, allow_destroy_stateful: bool) -> Result<IndexEntry> {
+    let index = Index::open()?;
+    let mut entry = index.load(id)?;
+    if !entry.state.can_transition_to(DeploymentState::Destroyed) {
+        return Err(anyhow!("Deployment {} is {}; it cannot be destroyed", id, entry.state));
+    }
+    let protected = if entry.deployment_dir.is_dir() { protection::protected_resources(&entry.deployment_dir)? } else { Vec::new() };
+    // Held until the function returns, so protection comes back however the destroy ends
+    let _lifted = if protected.is_empty() {
+        None
+    } else {
+        if !allow_destroy_stateful {
             return Err(anyhow!(
-                "❌ No credentials found for {:?}.\n💡 Set up credentials with: cargo run -- credentials setup {}",
-                requirements.cloud_provider,
-                provider_str
+                "Deployment {} has stateful resources protected with prevent_destroy: {}. Destroying them loses their data; rerun with --allow-destroy-stateful to destroy them too",
+                id,
+                protected.join(", ")
             ));
         }
-        
-        info!("✅ Credentials found for {:?}", requirements.cloud_provider);
+        warn!("⚠️ Destroying protected stateful resources and their data: {}", protected.join(", "));
+        Some(protection::lift_protection(&entry.deployment_dir)?)
+    };
+    info!("💣 Destroying deployment {} ({:?}, {})", id, entry.cloud_provider, entry.repository);
+    if let Err(e) = infrastructure::destroy_terraform(&entry.deployment_dir, &entry.cloud_provider, entry.region.as_deref()).await {
+        entry.error = Some(e.to_string());
+        if let Err(save_error) = index.save(&entry) {
+            warn!("⚠️ Failed to record the error for deployment {}: {}", id, save_error);
+        }
+        return Err(e);
+    }
+    index.remove(id)?;
+    Ok(entry)
+}
+
+/// Whether `id` names a stack (`deploy --all`) rather than a single deployment
+pub fn is_stack(id: &str) -> bool {
+    Index::open().and_then(|index| index.load_stack(id)).is_ok_and(|record| record.is_some())
+}
+
+/// Tear down every service of a stack, dependents before the services they depend on.
+/// Stops at the first failure; the stack then keeps the services that are left, so running
+/// `destroy` again picks up where it stopped.
+pub async fn destroy_stack(id: &str, allow_destroy_stateful: bool) -> Result<Vec<IndexEntry>> {
+    let index = Index::open()?;
+    let mut record = index.load_stack(id)?.ok_or_else(|| anyhow!("No stack {} in the index", id))?;
+    let mut destroyed = Vec::new();
+    while let Some(member) = record.members.last() {
+        // Services that failed before provisioning, or were destroyed on their own, have no entry
+        if let Some(deployment_id) = member.deployment_id.as_deref().filter(|deployment_id| index.load(deployment_id).is_ok()) {
+            info!("💣 Destroying service {} of stack {}", member.service, id);
+            match destroy_deployment(deployment_id, allow_destroy_stateful).await {
+                Ok(entry) => destroyed.push(entry),
+                Err(e) => {
+                    let e = e.context(format!("service {} of stack {}", member.service, id));
+                    index.save_stack(&record)?;
+                    return Err(e);
+                }
+            }
+        }
+        record.members.pop();
+    }
+    index.remove_stack(id)?;
+    Ok(destroyed)
+}
+
+/// Adopt a manually created resource into an indexed deployment's configuration
+pub async fn import_resource(id: &str, address: &str, resource_id: &str) -> Result<IndexEntry> {
+    let entry = Index::open()?.load(id)?;
+    if entry.state == DeploymentState::Provisioning {
+        return Err(anyhow!("Deployment {} is {}; wait for it to finish before importing", id, entry.state));
     }
+    info!("📦 Importing into deployment {} ({:?}, {})", id, entry.cloud_provider, entry.repository);
+    infrastructure::import_terraform(&entry.deployment_dir, &entry.cloud_provider, entry.region.as_deref(), address, resource_id).await?;
+    Ok(entry)
+}
+
+/// Parse the description into requirements, letting an explicit provider override the parsed one
+async fn resolve_requirements(description: &str, cloud_provider: Option<&str>) -> Result<DeploymentRequirements> {
+    // Parse natural language requirements, with the AI for anything keywords don't cover
+    info!("📝 Parsing deployment requirements from description...");
+    let mut requirements = ai_nlp::parse_requirements(description).await?;
+    
+    // Use CLI cloud provider if provided, otherwise the one the description names, then the
+    // configured default, then AWS
+    requirements.cloud_provider = match cloud_provider {
+        Some(provider) => parse_cloud_provider(provider),
+        None => described_or_default_provider(description, &requirements.cloud_provider, settings::get().cloud_provider.as_deref()),
+    };
     
     info!("Requirements parsed: Cloud Provider: {:?}", requirements.cloud_provider);
+    Ok(requirements)
+}
+
+/// Clone and analyze the repository, then decide on the infrastructure for it
+async fn analyze_and_decide(
+    description: &str,
+    repository: &str,
+    requirements: &DeploymentRequirements,
+) -> Result<InfrastructureDecision> {
+    let analysis = clone_and_analyze(repository, requirements).await?;
+    let requirements = complete_requirements(requirements, &analysis)?;
     
-    // Clone and analyze repository
-    info!("📥 Cloning repository: {}", repository);
-    let temp_repo = clone_repository(repository).await?;
+    // Make infrastructure decision
+    info!("🏗️ Determining optimal infrastructure using AI...");
+    let decision = decide_infrastructure(&requirements, &analysis, description, repository).await?;
     
-    // Get public IP first to replace localhost references before analysis
-    info!("🌐 Getting public IP for localhost replacement...");
-    let public_ip = get_public_ip().await.unwrap_or_else(|_| "0.0.0.0".to_string());
+    info!("Infrastructure decision: {:?}", decision.deployment_type);
+    info!("Justification: {}", decision.justification);
     
-    if let Err(e) = replace_localhost_in_repository(temp_repo.path(), &public_ip) {
-        warn!("⚠️ Failed to replace localhost references: {}", e);
-    } else {
-        info!("✅ Successfully updated localhost references in repository files");
+    Ok(decision)
+}
+
+/// Fill in what the requirements need from the analysis: registry logins and the values of
+/// the selected environment's `.env.<environment>` file
+fn complete_requirements(
+    requirements: &DeploymentRequirements,
+    analysis: &RepositoryAnalysis,
+) -> Result<DeploymentRequirements> {
+    if let Some(reason) = &analysis.not_a_service {
+        if !requirements.deploy_non_service {
+            return Err(anyhow!(
+                "🚫 This repository does not look like a service: {}. Nothing would be started on the instance. Use --force-deploy not-a-service to deploy anyway",
+                reason
+            ));
+        }
+        warn!("⚠️ Deploying a repository that does not look like a service: {}", reason);
+    }
+    
+    let mut requirements = requirements.clone();
+    requirements.registry_credentials = resolve_registry_credentials(analysis)?;
+    if requirements.health_check.path.is_none() {
+        requirements.health_check.path = analysis.health_endpoint.clone();
+    }
+    
+    // Values given in the description win over .autodeployment.toml, which wins over .env.<environment>
+    for (name, value) in &analysis.configured_env {
+        requirements
+            .environment_variables
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+    
+    if let Some(environment) = &requirements.environment {
+        match analysis.environment_files.get(environment) {
+            Some(values) => {
+                info!("🌱 Using {} values from .env.{}", values.len(), environment);
+                for (name, value) in values {
+                    // Values given in the description win over the file
+                    requirements
+                        .environment_variables
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+            None => warn!("⚠️ No .env.{} in the repository, deploying without per-environment values", environment),
+        }
+    }
+    
+    // Expanded here, before the values are written into the script; the PORT and GIT_COMMIT
+    // the script exports can be referenced too
+    let mut known = HashMap::new();
+    if let Some(port) = analysis.internal_port {
+        known.insert("PORT".to_string(), port.to_string());
+    }
+    if let Some(commit) = &analysis.commit {
+        known.insert("GIT_COMMIT".to_string(), commit.clone());
+    }
+    let expanded = interpolation::expand(&requirements.environment_variables, &known)?;
+    requirements.environment_variables = expanded.values;
+    // Database endpoints are filled in once Terraform has the databases to point at
+    for name in expanded.unresolved.iter().filter(|name| !database::is_endpoint_variable(name)) {
+        warn!("⚠️ ${} is referenced by an environment variable but set nowhere; it is left as written", name);
+    }
+    
+    Ok(requirements)
+}
+
+/// Clone the repository and analyze its structure, auditing its dependencies with `--audit`
+async fn clone_and_analyze(repository: &str, requirements: &DeploymentRequirements) -> Result<RepositoryAnalysis> {
+    let temp_repo = clone_for_analysis(repository, requirements.git_ref.as_deref(), requirements.rewrite_localhost).await?;
+    analyze_clone(temp_repo.path(), requirements).await
+}
+
+/// Clone the repository at `git_ref` and, with `rewrite_localhost`, point its localhost
+/// references at this machine's public IP
+async fn clone_for_analysis(repository: &str, git_ref: Option<&str>, rewrite_localhost: bool) -> Result<TempDir> {
+    info!("📥 Cloning repository: {}", repository);
+    let temp_repo = clone_repository(repository, git_ref).await?;
+    if !rewrite_localhost {
+        debug!("Leaving localhost references as committed");
+        return Ok(temp_repo);
     }
     
+    match replace_localhost_in_repository(temp_repo.path(), public_ip_or_any).await {
+        Ok(rewrite) => {
+            for file in &rewrite.changed {
+                info!("✏️ Rewrote localhost references in {}", file);
+            }
+            for (file, e) in &rewrite.failed {
+                warn!("⚠️ Localhost references in {} left unchanged: {}", file, e);
+            }
+            info!(
+                "✅ Updated localhost references: {} file(s) changed, {} failed",
+                rewrite.changed.len(),
+                rewrite.failed.len()
+            );
+        }
+        Err(e) => warn!("⚠️ Failed to replace localhost references: {}", e),
+    }
+    Ok(temp_repo)
+}
+
+/// Analyze a clone's structure, auditing its dependencies with `--audit`
+async fn analyze_clone(repo_path: &Path, requirements: &DeploymentRequirements) -> Result<RepositoryAnalysis> {
     info!("🔍 Analyzing repository structure...");
-    let analysis = analyze_repository(temp_repo.path())?;
+    let mut analysis = match requirements.service_dir.as_deref() {
+        Some(dir) => repository::analyze_service(repo_path, dir)?,
+        None => analyze_repository(repo_path)?,
+    };
+    if let Some(script) = &requirements.start_script {
+        repository::select_start_script(&mut analysis, script)?;
+    }
+    if let Some(script) = analysis.start_script() {
+        info!("▶️ Starting with the package.json script {}", script);
+    }
+    if let Some(mode) = requirements.audit {
+        let app_root = repo_path.join(analysis.working_dir.as_deref().unwrap_or_default());
+        audit::audit_dependencies(&app_root, &analysis.package_manager, mode).await?;
+    }
     
     info!("Analysis complete: App Type: {:?}", analysis.app_type);
     info!("Dependencies found: {}", analysis.dependencies.len());
-    info!("Exposed ports: {:?}", analysis.exposed_ports);
-    
+    match analysis.internal_port {
+        Some(port) => info!("Exposed ports: {:?} (app listens on {})", analysis.exposed_ports, port),
+        None => info!("No web port: background worker"),
+    }
     
-    // Make infrastructure decision
-    info!("🏗️ Determining optimal infrastructure using AI...");
-    let infrastructure_decision = decide_infrastructure(&requirements, &analysis, description, repository).await?;
+    Ok(analysis)
+}
+
+/// Fail unless credentials for `provider` are stored. With `interactive` on a terminal the
+/// `credentials setup` prompts run inline instead, and the deploy carries on.
+async fn ensure_credentials(provider: &CloudProvider, interactive: bool) -> Result<()> {
+    let credentials = CloudCredentials::load_from_file()
+        .unwrap_or_else(|_| CloudCredentials::new());
     
-    info!("Infrastructure decision: {:?}", infrastructure_decision.deployment_type);
-    info!("Justification: {}", infrastructure_decision.justification);
+    if credentials.has_credentials_for(provider) {
+        info!("✅ Credentials found for {:?}", provider);
+        return Ok(());
+    }
+    if let (CloudProvider::AWS, Some(endpoint)) = (provider, infrastructure::aws_endpoint()) {
+        info!("🧪 No AWS credentials needed for {}", endpoint);
+        return Ok(());
+    }
     
-    // Generate Terraform files (even for dry-run to allow review)
-    info!("📄 Generating Terraform configuration files...");
-    let work_dir = tempfile::tempdir()?;
-    let file_generation_result = provision_infrastructure(
-        &infrastructure_decision,
-        repository,
-        work_dir.path(),
-        true, // Always generate files for review
-        &requirements.cloud_provider,
-    ).await?;
+    if interactive && io::stdin().is_terminal() {
+        warn!("⚠️ No credentials found for {:?}, setting them up now", provider);
+        prompt_for_credentials(provider).await?;
+        
+        let credentials = CloudCredentials::load_from_file()?;
+        if !credentials.has_credentials_for(provider) {
+            return Err(anyhow!("❌ Credentials for {:?} are still incomplete", provider));
+        }
+        return Ok(());
+    }
     
-    if dry_run {
-        info!("🧪 Dry run complete - no infrastructure will be provisioned");
-        return Ok(DeploymentResult {
-            url: "dry-run".to_string(),
-            infrastructure_type: format!("{:?}", infrastructure_decision.deployment_type),
-            public_ip: None,
-            logs: file_generation_result.logs,
-        });
+    let provider_str = match provider {
+        CloudProvider::AWS => "aws",
+        CloudProvider::GCP => "gcp",
+        CloudProvider::Azure => "azure",
+        CloudProvider::DigitalOcean => "digitalocean",
+        CloudProvider::Unknown => "aws", // fallback
+    };
+    let hint = if interactive {
+        " (--interactive-credentials needs a terminal)"
+    } else {
+        ", or re-run with --interactive-credentials"
+    };
+    Err(anyhow!(
+        "❌ No credentials found for {:?}.\n💡 Set up credentials with: cargo run -- credentials setup {}{}",
+        provider,
+        provider_str,
+        hint
+    ))
+}
+
+/// Find logins for the private registries the app's images come from, prompting for any
+/// missing ones when running interactively
+fn resolve_registry_credentials(analysis: &RepositoryAnalysis) -> Result<HashMap<String, RegistryCredentials>> {
+    let registries = private_registries(analysis);
+    if registries.is_empty() {
+        return Ok(HashMap::new());
     }
+    info!("📦 Images are pulled from private registries: {}", registries.join(", "));
     
-    // Provision infrastructure (sed will handle localhost replacement in startup script)
-    info!("☁️ Provisioning infrastructure...");
-    let work_dir = tempfile::tempdir()?;
-    let mut deployment_result = provision_infrastructure(
-        &infrastructure_decision,
-        repository, // Use original repository - sed will fix localhost in startup script
-        work_dir.path(),
-        false, // Actually deploy  
-        &requirements.cloud_provider,
-    ).await?;
+    let stored = CloudCredentials::load_from_file()
+        .map(|credentials| credentials.registries)
+        .unwrap_or_default();
+    let mut logins = HashMap::new();
     
-    // Fix URL if it contains "unknown" 
-    if let Some(public_ip) = &deployment_result.public_ip {
-        if deployment_result.url.contains("unknown") {
-            deployment_result.url = format!("http://{}:5000", public_ip);
+    for registry in registries {
+        if let Some(login) = stored.get(&registry) {
+            logins.insert(registry, login.clone());
+        } else if io::stdin().is_terminal() {
+            logins.insert(registry.clone(), prompt_for_registry_credentials(&registry)?);
+        } else {
+            warn!(
+                "⚠️ No credentials for registry {}; image pulls may fail. Add them with: cargo run -- credentials registry {}",
+                registry, registry
+            );
         }
     }
     
-    info!("✅ Deployment completed successfully!");
-    info!("🌐 Application URL: {}", deployment_result.url);
+    Ok(logins)
+}
+
+/// `parsed` when the description names a provider, else `configured` (the `cloud_provider`
+/// setting), else AWS. Whether it names one is up to the keywords, since the AI may answer
+/// with a provider the description never mentioned.
+fn described_or_default_provider(description: &str, parsed: &CloudProvider, configured: Option<&str>) -> CloudProvider {
+    match nlp::extract_keywords(description).cloud_provider {
+        Some(named) if *parsed == CloudProvider::Unknown => named,
+        Some(_) => parsed.clone(),
+        None => configured.map(parse_cloud_provider).unwrap_or(CloudProvider::AWS),
+    }
+}
+
+fn parse_cloud_provider(provider: &str) -> CloudProvider {
+    match provider.to_lowercase().as_str() {
+        "aws" => CloudProvider::AWS,
+        "gcp" | "google" => CloudProvider::GCP,
+        "azure" => CloudProvider::Azure,
+        "digitalocean" | "do" => CloudProvider::DigitalOcean,
+        _ => {
+            warn!("Unknown cloud provider '{}', defaulting to AWS", provider);
+            CloudProvider::AWS
+        }
+    }
+}
+
+/// Infer the cloud provider from the resource types in a generated main.tf or main.tf.json
+fn detect_cloud_provider(terraform_dir: &Path) -> Result<CloudProvider> {
+    let json_path = terraform_dir.join(TerraformFormat::Json.main_file());
+    let resource_types: Vec<String> = if json_path.exists() {
+        let main_tf_json: serde_json::Value = serde_json::from_str(&fs::read_to_string(json_path)?)?;
+        main_tf_json["resource"]
+            .as_object()
+            .map(|resources| resources.keys().cloned().collect())
+            .unwrap_or_default()
+    } else {
+        fs::read_to_string(terraform_dir.join(TerraformFormat::Hcl.main_file()))?
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("resource \""))
+            .map(|rest| rest.split('"').next().unwrap_or_default().to_string())
+            .collect()
+    };
     
-    Ok(deployment_result)
+    infrastructure::provider_for_resource_types(resource_types.iter().map(String::as_str))
+        .ok_or_else(|| anyhow!("Could not detect the cloud provider from main.tf, pass --cloud-provider"))
+}
+
+/// Set while chat prints JSON on stdout, so that everything meant for people goes to stderr
+static CHAT_TEXT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for chat output meant for people, on stderr while stdout carries JSON
+macro_rules! chat_println {
+    ($($arg:tt)*) => {
+        if CHAT_TEXT_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
 }
 
-pub async fn interactive_chat(repository: Option<String>) -> Result<()> {
-    println!("🤖 Welcome to AutoDeployment Chat!");
-    println!("Type 'help' for commands, 'quit' to exit.");
+/// With `OutputFormat::Json`, plans and deploy results are printed as JSON on stdout and
+/// everything else (prompts, analysis summaries, help) goes to stderr
+pub async fn interactive_chat(repository: Option<String>, git_ref: Option<String>, output: OutputFormat) -> Result<()> {
+    let json = output == OutputFormat::Json;
+    CHAT_TEXT_TO_STDERR.store(json, Ordering::Relaxed);
+    if !json {
+        chat_println!("🤖 Welcome to AutoDeployment Chat!");
+        chat_println!("Type 'help' for commands, 'quit' to exit.");
+    }
     
     let mut current_repo: Option<(String, TempDir, RepositoryAnalysis)> = None;
+    let mut last_plan: Option<ChatPlan> = None;
     
     // If repository provided, analyze it upfront
     if let Some(repo_url) = repository {
-        println!("📥 Analyzing repository: {}", repo_url);
-        match clone_repository(&repo_url).await {
+        chat_println!("📥 Analyzing repository: {}", repo_url);
+        match clone_repository(&repo_url, git_ref.as_deref()).await {
             Ok(temp_repo) => {
                 match analyze_repository(temp_repo.path()) {
-                    Ok(analysis) => {
-                        println!("✅ Repository analyzed successfully!");
-                        println!("   App Type: {:?}", analysis.app_type);
-                        println!("   Dependencies: {}", analysis.dependencies.len());
-                        println!("   Exposed Ports: {:?}", analysis.exposed_ports);
+                    Ok(mut analysis) => {
+                        chat_println!("✅ Repository analyzed successfully!");
+                        chat_println!("   App Type: {:?}", analysis.app_type);
+                        chat_println!("   Dependencies: {}", analysis.dependencies.len());
+                        chat_println!("   Exposed Ports: {:?}", analysis.exposed_ports);
+                        chat_println!("   Internal Port: {}", describe_internal_port(&analysis));
+                        if settings::get().detection_feedback == Some(true) {
+                            if let Err(e) = confirm_detection(&repo_url, &mut analysis) {
+                                warn!("⚠️ Could not record detection feedback: {}", e);
+                            }
+                        }
                         current_repo = Some((repo_url, temp_repo, analysis));
                     },
                     Err(e) => {
@@ -170,8 +2214,12 @@ pub async fn interactive_chat(repository: Option<String>) -> Result<()> {
     }
     
     loop {
-        print!("\n> ");
-        io::stdout().flush()?;
+        if json {
+            eprint!("\n> ");
+        } else {
+            print!("\n> ");
+            io::stdout().flush()?;
+        }
         
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
@@ -183,25 +2231,38 @@ pub async fn interactive_chat(repository: Option<String>) -> Result<()> {
         
         match input.to_lowercase().as_str() {
             "quit" | "exit" => {
-                println!("👋 Goodbye!");
+                chat_println!("👋 Goodbye!");
                 break;
             },
             "help" => {
                 print_help();
             },
+            "accuracy" => {
+                match feedback::feedback_path().and_then(|path| feedback::summarize(&path)) {
+                    Ok(summary) => chat_println!("\n🎯 Detection accuracy\n{}", summary),
+                    Err(e) => error!("Failed to read detection feedback: {}", e),
+                }
+            },
             "status" => {
                 if let Some((repo_url, _, analysis)) = &current_repo {
                     print_status(repo_url, analysis);
                 } else {
-                    println!("❌ No repository loaded. Use 'load <repo_url>' to load a repository.");
+                    chat_println!("❌ No repository loaded. Use 'load <repo_url>' to load a repository.");
                 }
             },
             _ if input.starts_with("load ") => {
-                let repo_url = input.strip_prefix("load ").unwrap().trim();
-                match load_repository(repo_url).await {
-                    Ok((temp_repo, analysis)) => {
-                        println!("✅ Repository loaded successfully!");
+                let mut args = input.strip_prefix("load ").unwrap().split_whitespace();
+                let repo_url = args.next().unwrap_or_default();
+                match load_repository(repo_url, args.next()).await {
+                    Ok((temp_repo, mut analysis)) => {
+                        chat_println!("✅ Repository loaded successfully!");
+                        if settings::get().detection_feedback == Some(true) {
+                            if let Err(e) = confirm_detection(repo_url, &mut analysis) {
+                                warn!("⚠️ Could not record detection feedback: {}", e);
+                            }
+                        }
                         current_repo = Some((repo_url.to_string(), temp_repo, analysis));
+                        last_plan = None;
                     },
                     Err(e) => {
                         error!("Failed to load repository: {}", e);
@@ -211,42 +2272,99 @@ pub async fn interactive_chat(repository: Option<String>) -> Result<()> {
             _ if input.starts_with("deploy ") => {
                 let description = input.strip_prefix("deploy ").unwrap().trim();
                 if let Some((repo_url, _, analysis)) = &current_repo {
-                    match deploy_with_chat(description, repo_url, analysis).await {
-                        Ok(result) => {
-                            println!("🚀 Deployment successful!");
-                            println!("📍 URL: {}", result.url);
-                            println!("🏗️ Infrastructure: {}", result.infrastructure_type);
-                        },
+                    match deploy_with_chat(description, repo_url, analysis, output).await {
+                        Ok(result) => print_chat_result(&result, output)?,
+                        Err(e) => {
+                            error!("Deployment failed: {}", e);
+                            if json {
+                                print_json_error(&e);
+                            }
+                        }
+                    }
+                } else {
+                    chat_println!("❌ No repository loaded. Use 'load <repo_url>' first.");
+                }
+            },
+            "deploy" => {
+                if let (Some(plan), Some((repo_url, _, analysis))) = (&last_plan, &current_repo) {
+                    if let Some(reason) = &analysis.not_a_service {
+                        warn!("⚠️ This repository does not look like a service: {}", reason);
+                    }
+                    let provisioned = match require_deploy_key(&plan.requirements) {
+                        Ok(()) => provision_with_chat(&plan.decision, &plan.requirements, repo_url, output).await,
+                        Err(e) => Err(e),
+                    };
+                    match provisioned {
+                        Ok(result) => print_chat_result(&result, output)?,
                         Err(e) => {
                             error!("Deployment failed: {}", e);
+                            if json {
+                                print_json_error(&e);
+                            }
                         }
                     }
                 } else {
-                    println!("❌ No repository loaded. Use 'load <repo_url>' first.");
+                    chat_println!("❌ No plan to deploy. Use 'plan <description>' first, or 'deploy <description>'.");
+                }
+            },
+            "edit" => {
+                if let Some(plan) = &mut last_plan {
+                    match edit_plan(plan) {
+                        Ok(()) => print_plan(&plan.decision, output)?,
+                        Err(e) => error!("Editing failed: {}", e),
+                    }
+                } else {
+                    chat_println!("❌ No plan to edit. Use 'plan <description>' first.");
+                }
+            },
+            "replan" => {
+                if let (Some(plan), Some((repo_url, _, analysis))) = (&mut last_plan, &current_repo) {
+                    match decide_infrastructure(&plan.requirements, analysis, &plan.description, repo_url).await {
+                        Ok(decision) => {
+                            plan.decision = decision;
+                            plan.apply_edits();
+                            print_plan(&plan.decision, output)?;
+                        },
+                        Err(e) => {
+                            error!("Planning failed: {}", e);
+                        }
+                    }
+                } else {
+                    chat_println!("❌ No plan to redo. Use 'plan <description>' first.");
                 }
             },
             _ if input.starts_with("plan ") => {
                 let description = input.strip_prefix("plan ").unwrap().trim();
-                if let Some((_repo_url, _, analysis)) = &current_repo {
-                    match plan_deployment(description, analysis).await {
-                        Ok(decision) => {
-                            print_deployment_plan(&decision);
+                if let Some((repo_url, _, analysis)) = &current_repo {
+                    match plan_deployment(description, repo_url, analysis).await {
+                        Ok((requirements, decision)) => {
+                            print_plan(&decision, output)?;
+                            if !json {
+                                chat_println!("✏️ Type 'edit' to change it, then 'deploy' to deploy it.");
+                            }
+                            last_plan = Some(ChatPlan {
+                                description: description.to_string(),
+                                requirements,
+                                decision,
+                                instance_type: None,
+                                port_edits: Vec::new(),
+                            });
                         },
                         Err(e) => {
                             error!("Planning failed: {}", e);
                         }
                     }
                 } else {
-                    println!("❌ No repository loaded. Use 'load <repo_url>' first.");
+                    chat_println!("❌ No repository loaded. Use 'load <repo_url>' first.");
                 }
             },
             _ => {
                 // Treat as a deployment description if repository is loaded
                 if let Some((_repo_url, _, _analysis)) = &current_repo {
-                    println!("🤔 Did you mean to deploy? Use 'deploy {}' to proceed.", input);
-                    println!("    Or use 'plan {}' to see the deployment plan.", input);
+                    chat_println!("🤔 Did you mean to deploy? Use 'deploy {}' to proceed.", input);
+                    chat_println!("    Or use 'plan {}' to see the deployment plan.", input);
                 } else {
-                    println!("❓ Unknown command. Type 'help' for available commands.");
+                    chat_println!("❓ Unknown command. Type 'help' for available commands.");
                 }
             }
         }
@@ -255,18 +2373,19 @@ pub async fn interactive_chat(repository: Option<String>) -> Result<()> {
     Ok(())
 }
 
-async fn load_repository(repo_url: &str) -> Result<(TempDir, RepositoryAnalysis)> {
-    println!("📥 Cloning repository...");
-    let temp_repo = clone_repository(repo_url).await?;
+async fn load_repository(repo_url: &str, git_ref: Option<&str>) -> Result<(TempDir, RepositoryAnalysis)> {
+    chat_println!("📥 Cloning repository...");
+    let temp_repo = clone_repository(repo_url, git_ref).await?;
     
-    println!("🔍 Analyzing repository...");
+    chat_println!("🔍 Analyzing repository...");
     let analysis = analyze_repository(temp_repo.path())?;
     
-    println!("   App Type: {:?}", analysis.app_type);
-    println!("   Package Manager: {:?}", analysis.package_manager);
-    println!("   Dependencies: {}", analysis.dependencies.len());
-    println!("   Build Required: {}", analysis.requires_build_step);
-    println!("   Exposed Ports: {:?}", analysis.exposed_ports);
+    chat_println!("   App Type: {:?}", analysis.app_type);
+    chat_println!("   Package Manager: {:?}", analysis.package_manager);
+    chat_println!("   Dependencies: {}", analysis.dependencies.len());
+    chat_println!("   Build Required: {}", analysis.requires_build_step);
+    chat_println!("   Exposed Ports: {:?}", analysis.exposed_ports);
+    chat_println!("   Internal Port: {}", describe_internal_port(&analysis));
     
     Ok((temp_repo, analysis))
 }
@@ -275,17 +2394,48 @@ async fn deploy_with_chat(
     description: &str,
     repo_url: &str,
     analysis: &RepositoryAnalysis,
+    output: OutputFormat,
 ) -> Result<DeploymentResult> {
-    println!("📝 Parsing deployment requirements using AI...");
-    let requirements = ai_nlp::parse_deployment_requirements(description).await?;
-    
-    println!("🏗️ Planning infrastructure using AI...");
-    let decision = decide_infrastructure(&requirements, analysis, description, "https://github.com/Arvo-AI/hello_world/tree/main").await?;
-    
-    print_deployment_plan(&decision);
+    if let Some(reason) = &analysis.not_a_service {
+        warn!("⚠️ This repository does not look like a service: {}", reason);
+    }
+    let mut requirements = resolve_requirements(description, None).await?;
+    requirements.registry_credentials = resolve_registry_credentials(analysis)?;
+    requirements.health_check.path = analysis.health_endpoint.clone();
+    require_deploy_key(&requirements)?;
     
-    print!("🚀 Proceed with deployment? (y/N): ");
-    io::stdout().flush()?;
+    info!("🏗️ Planning infrastructure using AI...");
+    let decision = decide_infrastructure(&requirements, analysis, description, repo_url).await?;
+    provision_with_chat(&decision, &requirements, repo_url, output).await
+}
+
+/// Show the plan and the actions it authorizes, then provision it once confirmed. With
+/// `OutputFormat::Json` only the actions are shown, on stderr, leaving stdout to the result.
+async fn provision_with_chat(
+    decision: &InfrastructureDecision,
+    requirements: &DeploymentRequirements,
+    repo_url: &str,
+    output: OutputFormat,
+) -> Result<DeploymentResult> {
+    let region = CloudCredentials::load_from_file()
+        .unwrap_or_else(|_| CloudCredentials::new())
+        .region_for(&requirements.cloud_provider);
+    let actions = summarize_actions(decision, &region);
+    if output == OutputFormat::Json {
+        eprintln!("\n✍️ You are authorizing:");
+        for action in actions {
+            eprintln!("  - {}", action);
+        }
+        eprint!("\n🚀 Proceed with deployment? (y/N): ");
+    } else {
+        print_deployment_plan(decision);
+        println!("\n✍️ You are authorizing:");
+        for action in actions {
+            println!("  - {}", action);
+        }
+        print!("\n🚀 Proceed with deployment? (y/N): ");
+        io::stdout().flush()?;
+    }
     
     let mut confirm = String::new();
     io::stdin().read_line(&mut confirm)?;
@@ -294,56 +2444,296 @@ async fn deploy_with_chat(
         return Err(anyhow!("Deployment cancelled by user"));
     }
     
-    println!("☁️ Provisioning infrastructure...");
+    info!("☁️ Provisioning infrastructure...");
     let work_dir = tempfile::tempdir()?;
-    let result = provision_infrastructure(&decision, repo_url, work_dir.path(), false, &requirements.cloud_provider).await?;
+    let result = provision_infrastructure(decision, repo_url, work_dir.path(), false, &requirements.cloud_provider, Some(DEFAULT_TERRAFORM_TIMEOUT)).await?;
     
     Ok(result)
 }
 
-async fn plan_deployment(description: &str, analysis: &RepositoryAnalysis) -> Result<InfrastructureDecision> {
-    let requirements = ai_nlp::parse_deployment_requirements(description).await?;
-    let decision = decide_infrastructure(&requirements, analysis, description, "https://github.com/Arvo-AI/hello_world/tree/main").await?;
-    Ok(decision)
+fn print_chat_result(result: &DeploymentResult, output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        return print_json(result);
+    }
+    println!("🚀 Deployment successful!");
+    println!("📍 URL: {}", result.url);
+    println!("🏗️ Infrastructure: {}", result.infrastructure_type);
+    if let Some(deployment_id) = &result.deployment_id {
+        println!("🆔 Deployment ID: {} (tear down with: destroy {})", deployment_id, deployment_id);
+    }
+    Ok(())
+}
+
+/// Show a chat plan, as the decision's JSON with `OutputFormat::Json`
+fn print_plan(decision: &InfrastructureDecision, output: OutputFormat) -> Result<()> {
+    match output {
+        OutputFormat::Json => print_json(decision),
+        OutputFormat::Text => {
+            print_deployment_plan(decision);
+            Ok(())
+        }
+    }
+}
+
+async fn plan_deployment(
+    description: &str,
+    repo_url: &str,
+    analysis: &RepositoryAnalysis,
+) -> Result<(DeploymentRequirements, InfrastructureDecision)> {
+    let mut requirements = resolve_requirements(description, None).await?;
+    requirements.registry_credentials = resolve_registry_credentials(analysis)?;
+    requirements.health_check.path = analysis.health_endpoint.clone();
+    let decision = decide_infrastructure(&requirements, analysis, description, repo_url).await?;
+    Ok((requirements, decision))
+}
+
+/// The last plan made in chat, kept so `edit` can change it before a bare `deploy`
+struct ChatPlan {
+    description: String,
+    requirements: DeploymentRequirements,
+    decision: InfrastructureDecision,
+    /// Instance type chosen with `edit`, reapplied after `replan`
+    instance_type: Option<String>,
+    /// Ports opened (`true`) or closed with `edit`, in order, reapplied after `replan`
+    port_edits: Vec<(u16, bool)>,
+}
+
+impl ChatPlan {
+    /// Apply the edits made so far to a freshly generated decision
+    fn apply_edits(&mut self) {
+        if let Some(instance_type) = &self.instance_type {
+            self.decision.set_instance_type(instance_type);
+        }
+        for (port, open) in &self.port_edits {
+            if *open {
+                self.decision.open_port(*port);
+            } else {
+                self.decision.close_port(*port);
+            }
+        }
+    }
+}
+
+/// Ask for changes to the plan: instance type, ports to open or close, and scaling.
+/// A blank answer keeps the current value.
+fn edit_plan(plan: &mut ChatPlan) -> Result<()> {
+    let instance_type = ask(&format!("Instance type [{}]: ", plan.decision.instance_type))?;
+    if !instance_type.is_empty() {
+        if plan.decision.set_instance_type(&instance_type).is_empty() {
+            chat_println!("⚠️ The plan has no instance to resize; only the recorded type changed");
+        }
+        plan.instance_type = Some(instance_type);
+    }
+    
+    chat_println!("  Open ports: {:?}", plan.decision.exposed_ports);
+    for open in [true, false] {
+        let question = if open { "Open a port (blank for none): " } else { "Close a port (blank for none): " };
+        while let Some(port) = ask_port(question)? {
+            let changed = if open { plan.decision.open_port(port) } else { plan.decision.close_port(port) };
+            if changed.is_empty() {
+                chat_println!("⚠️ No firewall rule in the plan was changed for port {}", port);
+            } else {
+                chat_println!("🔥 Updated {}", changed.join(", "));
+            }
+            plan.port_edits.push((port, open));
+        }
+    }
+    
+    let current = &plan.requirements.scaling_requirements;
+    loop {
+        let answer = ask(&format!("Scaling [{:?}] (single, auto-scale, load-balanced, serverless): ", current))?;
+        if answer.is_empty() {
+            break;
+        }
+        match answer.parse::<ScalingRequirements>() {
+            Ok(scaling) if scaling == *current => break,
+            Ok(scaling) => {
+                plan.requirements.scaling_requirements = scaling;
+                chat_println!("🔁 Scaling changed; type 'replan' to regenerate the infrastructure for it");
+                break;
+            }
+            Err(e) => chat_println!("❓ {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Ask for a port until the answer is one; blank means none
+fn ask_port(question: &str) -> Result<Option<u16>> {
+    loop {
+        let answer = ask(question)?;
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<u16>() {
+            Ok(port) if port > 0 => return Ok(Some(port)),
+            _ => chat_println!("❓ Not a port: {}", answer),
+        }
+    }
 }
 
 fn print_help() {
-    println!("\n📚 Available Commands:");
-    println!("  help                    - Show this help message");
-    println!("  load <repo_url>         - Load and analyze a repository");
-    println!("  status                  - Show current repository status");
-    println!("  plan <description>      - Plan deployment without executing");
-    println!("  deploy <description>    - Deploy the application");
-    println!("  quit/exit               - Exit the chat");
-    println!("\n💡 Examples:");
-    println!("  load https://github.com/Arvo-AI/hello_world");
-    println!("  plan Deploy this Flask app on AWS");
-    println!("  deploy Deploy with auto-scaling on GCP");
+    chat_println!("\n📚 Available Commands:");
+    chat_println!("  help                    - Show this help message");
+    chat_println!("  load <repo_url> [ref]   - Load and analyze a repository, at a branch, tag or commit");
+    chat_println!("  status                  - Show current repository status");
+    chat_println!("  plan <description>      - Plan deployment without executing");
+    chat_println!("  deploy <description>    - Deploy the application");
+    chat_println!("  edit                    - Change the last plan's instance type, ports or scaling");
+    chat_println!("  replan                  - Regenerate the last plan, keeping its edits");
+    chat_println!("  deploy                  - Deploy the last plan as edited");
+    chat_println!("  accuracy                - Show how often detection was right (detection_feedback setting)");
+    chat_println!("  quit/exit               - Exit the chat");
+    chat_println!("\n💡 Examples:");
+    chat_println!("  load https://github.com/Arvo-AI/hello_world");
+    chat_println!("  plan Deploy this Flask app on AWS");
+    chat_println!("  deploy Deploy with auto-scaling on GCP");
+}
+
+/// Ask whether the detected app type and port are right, apply any correction, and append
+/// both to the local feedback file (the `detection_feedback` setting). Nothing is sent anywhere.
+fn confirm_detection(repo_url: &str, analysis: &mut RepositoryAnalysis) -> Result<()> {
+    let detected = (analysis.app_type.clone(), analysis.internal_port);
+    
+    let mut app_type = analysis.app_type.clone();
+    if !ask_yes(&format!("Is this a {:?} app? [Y/n]: ", app_type))? {
+        let names: Vec<&str> = feedback::APP_TYPES.iter().map(|(name, _)| *name).collect();
+        loop {
+            let answer = ask(&format!("What is it? ({}): ", names.join(", ")))?;
+            if answer.is_empty() {
+                break;
+            }
+            match feedback::parse_app_type(&answer) {
+                Some(actual) => {
+                    app_type = actual;
+                    break;
+                }
+                None => chat_println!("❓ Unknown app type: {}", answer),
+            }
+        }
+    }
+    
+    let mut port = analysis.internal_port;
+    let question = match port {
+        Some(port) => format!("Does it listen on port {}? [Y/n]: ", port),
+        None => "Is it a background worker with no web port? [Y/n]: ".to_string(),
+    };
+    if !ask_yes(&question)? {
+        loop {
+            let answer = ask("Which port does it listen on? (blank for none): ")?;
+            if answer.is_empty() {
+                port = None;
+                break;
+            }
+            match answer.parse::<u16>() {
+                Ok(actual) if actual > 0 => {
+                    port = Some(actual);
+                    break;
+                }
+                _ => chat_println!("❓ Not a port: {}", answer),
+            }
+        }
+    }
+    
+    if (&app_type, port) != (&detected.0, detected.1) {
+        repository::correct_detection(analysis, app_type.clone(), port)?;
+        chat_println!("✏️ Using {:?} on port {}", analysis.app_type, describe_internal_port(analysis));
+    }
+    let path = feedback::feedback_path()?;
+    feedback::record(&path, &DetectionFeedback::new(repo_url, detected, (app_type, port)))?;
+    chat_println!("📝 Feedback saved locally to {}", path.display());
+    Ok(())
+}
+
+fn ask(question: &str) -> Result<String> {
+    if CHAT_TEXT_TO_STDERR.load(Ordering::Relaxed) {
+        eprint!("{}", question);
+    } else {
+        print!("{}", question);
+        io::stdout().flush()?;
+    }
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// A `[Y/n]` question; anything but n/no is yes
+fn ask_yes(question: &str) -> Result<bool> {
+    Ok(!matches!(ask(question)?.to_lowercase().as_str(), "n" | "no"))
+}
+
+fn describe_internal_port(analysis: &RepositoryAnalysis) -> String {
+    match analysis.internal_port {
+        Some(port) => port.to_string(),
+        None => "none (background worker)".to_string(),
+    }
 }
 
 fn print_status(repo_url: &str, analysis: &RepositoryAnalysis) {
-    println!("\n📊 Repository Status:");
-    println!("  URL: {}", repo_url);
-    println!("  App Type: {:?}", analysis.app_type);
-    println!("  Package Manager: {:?}", analysis.package_manager);
-    println!("  Dependencies: {}", analysis.dependencies.len());
-    println!("  Build Required: {}", analysis.requires_build_step);
-    println!("  Exposed Ports: {:?}", analysis.exposed_ports);
-    println!("  Static Files: {:?}", analysis.static_files_dir);
-    println!("  Database Migrations: {}", analysis.database_migrations);
+    chat_println!("\n📊 Repository Status:");
+    chat_println!("  URL: {}", repo_url);
+    chat_println!("  App Type: {:?}", analysis.app_type);
+    chat_println!("  Package Manager: {:?}", analysis.package_manager);
+    chat_println!("  Dependencies: {}", analysis.dependencies.len());
+    chat_println!("  Build Required: {}", analysis.requires_build_step);
+    chat_println!("  Exposed Ports: {:?}", analysis.exposed_ports);
+    chat_println!("  Internal Port: {}", describe_internal_port(analysis));
+    chat_println!("  Static Files: {:?}", analysis.static_files_dir);
+    if let Some(serving) = &analysis.static_serving {
+        chat_println!("  Static Serving: {}", describe_static_serving(serving));
+    }
+    chat_println!("  Database Migrations: {}", analysis.database_migrations);
+    chat_println!("  Working Directory: {}", analysis.working_dir.as_deref().unwrap_or("."));
+    chat_println!("  Framework Version: {}", analysis.framework_version.as_deref().unwrap_or("unknown"));
+    if let Some(version) = &analysis.runtime_version {
+        chat_println!("  Runtime Version: {}", version);
+    }
+    if let Some(reason) = &analysis.not_a_service {
+        chat_println!("  ⚠️ Not a service: {}", reason);
+    }
+    if let Some(warning) = framework_eol_warning(analysis) {
+        chat_println!("  ⚠️ {}", warning);
+    }
     
     if !analysis.environment_variables.is_empty() {
-        println!("  Environment Variables: {:?}", analysis.environment_variables);
+        chat_println!("  Environment Variables: {:?}", analysis.environment_variables);
     }
     
-    println!("\n🛠️ Build Commands:");
+    if !analysis.build_env_vars.is_empty() {
+        chat_println!("  Build-time Variables: {:?}", analysis.build_env_vars);
+    }
+    
+    if !analysis.environment_files.is_empty() {
+        let environments: Vec<&str> = analysis.environment_files.keys().map(String::as_str).collect();
+        chat_println!("  Environments: {} (select with --environment)", environments.join(", "));
+    }
+    
+    if !analysis.container_images.is_empty() {
+        chat_println!("\n📦 Container Images:");
+        for image in &analysis.container_images {
+            let auth = if image.requires_auth() { " (private)" } else { "" };
+            chat_println!("    {} [{}]{}", image.image, image.source, auth);
+        }
+    }
+    
+    chat_println!("\n🛠️ Build Commands:");
     for cmd in &analysis.build_commands {
-        println!("    {}", cmd);
+        chat_println!("    {}", cmd);
     }
     
-    println!("\n▶️ Start Commands:");
+    chat_println!("\n▶️ Start Commands:");
     for cmd in &analysis.start_commands {
-        println!("    {}", cmd);
+        chat_println!("    {}", cmd);
+    }
+    if let Some(script) = analysis.start_script() {
+        chat_println!("  package.json script: {}", script);
+    }
+}
+
+/// Print each line item of `cost`, then the total
+pub fn print_cost_breakdown(cost: &CostEstimate) {
+    for line in cost.to_string().lines() {
+        println!("    {}", line);
     }
 }
 
@@ -351,14 +2741,25 @@ fn print_deployment_plan(decision: &InfrastructureDecision) {
     println!("\n📋 Deployment Plan:");
     println!("  Infrastructure: {:?}", decision.deployment_type);
     println!("  Instance Type: {}", decision.instance_type);
-    println!("  Estimated Cost: ${:.2}/month", decision.estimated_cost);
+    println!("  Estimated Cost: ${:.2}/month", decision.estimated_cost.total);
+    print_cost_breakdown(&decision.estimated_cost);
     println!("  Justification: {}", decision.justification);
+    if let Some(health_check) = &decision.health_check {
+        println!("  Health Check: {}", health_check);
+    }
     
     println!("\n🏗️ Resources to be created:");
     for resource in &decision.terraform_config.resources {
         println!("  - {} ({})", resource.name, resource.resource_type);
     }
     
+    if !decision.required_registries.is_empty() {
+        println!("\n📦 Private Registries (login configured on the instance):");
+        for registry in &decision.required_registries {
+            println!("  - {}", registry);
+        }
+    }
+    
     if !decision.terraform_config.variables.is_empty() {
         println!("\n⚙️ Required Variables:");
         for (var_name, description) in &decision.terraform_config.variables {
@@ -369,84 +2770,336 @@ fn print_deployment_plan(decision: &InfrastructureDecision) {
 
 /// Get the public IP address of the current machine
 async fn get_public_ip() -> Result<String> {
-    let response = reqwest::get("https://api.ipify.org?format=text").await?;
+    let response = http::client().get("https://api.ipify.org?format=text").send().await?;
     let ip = response.text().await?;
     Ok(ip.trim().to_string())
 }
 
-/// Replace localhost references in repository files with the actual public IP
-fn replace_localhost_in_repository(repo_path: &Path, public_ip: &str) -> Result<()> {
-    info!("🔄 Replacing localhost references with {} in repository files", public_ip);
-    
+/// This machine's public IP, or `0.0.0.0` when the lookup fails
+async fn public_ip_or_any() -> String {
+    info!("🌐 Getting public IP for localhost replacement...");
+    get_public_ip().await.unwrap_or_else(|e| {
+        warn!("⚠️ Public IP lookup failed, using 0.0.0.0: {}", e);
+        "0.0.0.0".to_string()
+    })
+}
+
+/// Outcome of rewriting localhost references, with paths relative to the repository root
+#[derive(Debug, Default)]
+struct LocalhostRewrite {
+    changed: Vec<String>,
+    /// Files left untouched after every attempt, with the last error
+    failed: Vec<(String, String)>,
+}
+
+/// Attempts per file before it is reported as failed
+const REWRITE_ATTEMPTS: u32 = 3;
+
+/// Replace localhost references in repository files with the actual public IP.
+///
+/// `public_ip` is only awaited when a file has a reference to point at it, so a repository
+/// without any never triggers the lookup. Each file is replaced atomically, so a failure
+/// leaves it either fully rewritten or untouched, and rewriting an already rewritten
+/// repository changes nothing.
+async fn replace_localhost_in_repository<F: Future<Output = String>>(
+    repo_path: &Path,
+    public_ip: impl FnOnce() -> F,
+) -> Result<LocalhostRewrite> {
     // Common file extensions that might contain localhost references
     let extensions = &[".py", ".js", ".ts", ".html", ".css", ".json", ".yaml", ".yml", ".toml", ".cfg", ".ini"];
     
     // Find all relevant files
-    for entry in WalkDir::new(repo_path)
+    let candidates: Vec<PathBuf> = WalkDir::new(repo_path)
         .follow_links(false)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if extensions.iter().any(|&e| e.trim_start_matches('.') == ext.to_string_lossy()) {
-                    replace_localhost_in_file(path, public_ip)?;
-                }
+        .map(|entry| entry.into_path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| extensions.iter().any(|&e| e.trim_start_matches('.') == ext.to_string_lossy()))
+        })
+        .collect();
+    let public_ip = match candidates.iter().any(|path| needs_public_ip(path)) {
+        true => public_ip().await,
+        // Only bind addresses change, which never use the IP
+        false => "0.0.0.0".to_string(),
+    };
+    info!("🔄 Replacing localhost references with {} in repository files", public_ip);
+    
+    let mut tasks = tokio::task::JoinSet::new();
+    for path in candidates {
+        // Rewrites run in parallel, bounded by --concurrency
+        let permit = concurrency::acquire().await;
+        let public_ip = public_ip.clone();
+        let deployment_id = correlation::current();
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            let result = correlation::sync_scope(deployment_id, || replace_localhost_with_retry(&path, &public_ip));
+            (path, result)
+        });
+    }
+    
+    let mut rewrite = LocalhostRewrite::default();
+    while let Some(joined) = tasks.join_next().await {
+        let (path, result) = joined?;
+        let relative = path.strip_prefix(repo_path).unwrap_or(&path).display().to_string();
+        match result {
+            Ok(true) => rewrite.changed.push(relative),
+            Ok(false) => {}
+            Err(e) => rewrite.failed.push((relative, e.to_string())),
+        }
+    }
+    rewrite.changed.sort();
+    rewrite.failed.sort();
+    
+    Ok(rewrite)
+}
+
+fn replace_localhost_with_retry(file_path: &Path, public_ip: &str) -> Result<bool> {
+    let mut attempt = 1;
+    loop {
+        match replace_localhost_in_file(file_path, public_ip) {
+            Err(e) if attempt < REWRITE_ATTEMPTS => {
+                warn!("⚠️ Rewriting {} failed (attempt {}/{}): {}", file_path.display(), attempt, REWRITE_ATTEMPTS, e);
+                std::thread::sleep(Duration::from_millis(100 * attempt as u64));
+                attempt += 1;
             }
+            result => return result,
         }
     }
+}
+
+/// Replace localhost references in a single file. Returns whether it changed.
+fn replace_localhost_in_file(file_path: &Path, public_ip: &str) -> Result<bool> {
+    // Not UTF-8 text: nothing to rewrite
+    let Ok(content) = fs::read_to_string(file_path) else {
+        return Ok(false);
+    };
     
-    Ok(())
+    let is_python = file_path.extension().is_some_and(|ext| ext == "py");
+    let modified_content = rewrite_localhost(&content, is_python, public_ip);
+    
+    // Only write if content changed
+    if modified_content == content {
+        return Ok(false);
+    }
+    write_atomically(file_path, &modified_content)?;
+    Ok(true)
+}
+
+/// Whether rewriting the file would put the public IP in it
+fn needs_public_ip(file_path: &Path) -> bool {
+    let Ok(content) = fs::read_to_string(file_path) else {
+        return false;
+    };
+    let is_python = file_path.extension().is_some_and(|ext| ext == "py");
+    // Rewritten with two different addresses, the content only differs where the IP goes
+    rewrite_localhost(&content, is_python, "0.0.0.0") != rewrite_localhost(&content, is_python, "127.0.0.2")
 }
 
-/// Replace localhost references in a single file
-fn replace_localhost_in_file(file_path: &Path, public_ip: &str) -> Result<()> {
-    if let Ok(content) = fs::read_to_string(file_path) {
-        let original_content = content.clone();
+/// The rewritten content. Rewriting the result again returns it unchanged.
+fn rewrite_localhost(content: &str, is_python: bool, public_ip: &str) -> String {
+    let mut modified_content = content.to_string();
+    
+    // For Flask specifically, ensure app.run() uses 0.0.0.0 for external access
+    if is_python {
+        // Use regex to replace Flask host parameters more robustly BEFORE general localhost replacement
         
-        let mut modified_content = content.clone();
+        // Replace app.run() with no host specified
+        modified_content = modified_content
+            .replace("app.run()", "app.run(host='0.0.0.0', port=5000)");
         
-        // For Flask specifically, ensure app.run() uses 0.0.0.0 for external access
-        if file_path.extension().map_or(false, |ext| ext == "py") {
-            // Use regex to replace Flask host parameters more robustly BEFORE general localhost replacement
-            
-            // Replace app.run() with no host specified
-            modified_content = modified_content
-                .replace("app.run()", "app.run(host='0.0.0.0', port=5000)");
-            
-            // Replace localhost host parameters (with and without quotes)
-            let localhost_patterns = [
-                (r#"host\s*=\s*"localhost""#, r#"host="0.0.0.0""#),
-                (r#"host\s*=\s*'localhost'"#, r#"host='0.0.0.0'"#),
-                (r#"host\s*=\s*"127\.0\.0\.1""#, r#"host="0.0.0.0""#),
-                (r#"host\s*=\s*'127\.0\.0\.1'"#, r#"host='0.0.0.0'"#),
-            ];
-            
-            for (pattern, replacement) in localhost_patterns {
-                if let Ok(re) = Regex::new(pattern) {
-                    modified_content = re.replace_all(&modified_content, replacement).to_string();
-                }
+        // Replace localhost host parameters (with and without quotes)
+        let localhost_patterns = [
+            (r#"host\s*=\s*"localhost""#, r#"host="0.0.0.0""#),
+            (r#"host\s*=\s*'localhost'"#, r#"host='0.0.0.0'"#),
+            (r#"host\s*=\s*"127\.0\.0\.1""#, r#"host="0.0.0.0""#),
+            (r#"host\s*=\s*'127\.0\.0\.1'"#, r#"host='0.0.0.0'"#),
+        ];
+        
+        for (pattern, replacement) in localhost_patterns {
+            if let Ok(re) = Regex::new(pattern) {
+                modified_content = re.replace_all(&modified_content, replacement).to_string();
             }
-            
-            // Now replace remaining localhost references with public IP (for frontend API calls, etc.)
-            modified_content = modified_content
-                .replace("localhost", public_ip)
-                .replace("127.0.0.1", public_ip);
-        } else {
-            // For non-Python files (HTML, JS, etc.), replace localhost with public IP
-            modified_content = modified_content
-                .replace("localhost", public_ip)
-                .replace("127.0.0.1", public_ip)
-                .replace("0.0.0.0", public_ip);
         }
-        
-        // Only write if content changed
-        if modified_content != original_content {
-            fs::write(file_path, modified_content)?;
+    }
+    
+    // Remaining references become the public IP (for frontend API calls, etc.). Whole
+    // words only, so names like `localhost_url` are left alone; Python keeps its bind
+    // address 0.0.0.0.
+    let mut addresses = vec![r"\blocalhost\b", r"\b127\.0\.0\.1\b"];
+    if !is_python {
+        addresses.push(r"\b0\.0\.0\.0\b");
+    }
+    for pattern in addresses {
+        if let Ok(re) = Regex::new(pattern) {
+            modified_content = re.replace_all(&modified_content, regex::NoExpand(public_ip)).to_string();
         }
     }
     
+    modified_content
+}
+
+/// Write `contents` to a temporary file next to `path`, then rename it over `path`, keeping
+/// its permissions. Readers see the old or the new file, never a partial one.
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let dir = path.parent().ok_or_else(|| anyhow!("{} has no parent directory", path.display()))?;
+    let mut temp_file = tempfile::NamedTempFile::new_in(dir)?;
+    temp_file.write_all(contents.as_bytes())?;
+    temp_file.as_file().set_permissions(fs::metadata(path)?.permissions())?;
+    temp_file
+        .persist(path)
+        .map_err(|e| anyhow!("Failed to replace {}: {}", path.display(), e.error))?;
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_cloud_provider() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        
+        fs::write(
+            temp_dir.path().join("main.tf"),
+            "resource \"google_compute_instance\" \"app\" {\n}\n",
+        ).unwrap();
+        assert_eq!(detect_cloud_provider(temp_dir.path()).unwrap(), CloudProvider::GCP);
+        
+        fs::write(temp_dir.path().join("main.tf"), "# empty\n").unwrap();
+        assert!(detect_cloud_provider(temp_dir.path()).is_err());
+        
+        fs::write(
+            temp_dir.path().join("main.tf.json"),
+            r#"{"resource": {"aws_instance": {"app": {}}}}"#,
+        ).unwrap();
+        assert_eq!(detect_cloud_provider(temp_dir.path()).unwrap(), CloudProvider::AWS);
+    }
+
+    #[test]
+    fn test_parse_cloud_provider() {
+        assert_eq!(parse_cloud_provider("AWS"), CloudProvider::AWS);
+        assert_eq!(parse_cloud_provider("google"), CloudProvider::GCP);
+        assert_eq!(parse_cloud_provider("do"), CloudProvider::DigitalOcean, "the alias credentials setup accepts");
+        assert_eq!(parse_cloud_provider("unknown-cloud"), CloudProvider::AWS);
+
+        // What the AI answered for a description naming no provider does not override the setting
+        assert_eq!(described_or_default_provider("Flask app with Redis", &CloudProvider::AWS, Some("gcp")), CloudProvider::GCP);
+        assert_eq!(described_or_default_provider("Flask app with Redis", &CloudProvider::Unknown, None), CloudProvider::AWS);
+        assert_eq!(described_or_default_provider("Flask app on Azure", &CloudProvider::Azure, Some("gcp")), CloudProvider::Azure);
+        assert_eq!(described_or_default_provider("Flask app on Azure", &CloudProvider::Unknown, Some("gcp")), CloudProvider::Azure);
+    }
+
+    #[test]
+    fn test_ssh_target_from_options() {
+        let mut options = DeployOptions::default();
+        assert!(options.ssh_target().unwrap().is_none());
+        
+        options.target = "ssh".parse().unwrap();
+        assert!(options.ssh_target().is_err(), "--host is required");
+        
+        options.host = Some("203.0.113.10".to_string());
+        let target = options.ssh_target().unwrap().unwrap();
+        assert_eq!(target.host, "203.0.113.10");
+        assert_eq!(target.user, "ubuntu");
+        
+        options.os = "amazon-linux".parse().unwrap();
+        assert_eq!(options.ssh_target().unwrap().unwrap().user, "ec2-user");
+        options.ssh_user = Some("deploy".to_string());
+        assert_eq!(options.ssh_target().unwrap().unwrap().user, "deploy");
+        
+        assert!("ansible".parse::<DeployTarget>().is_err());
+    }
+
+    #[test]
+    fn test_complete_requirements_uses_environment_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("app.py"), "from flask import Flask\n").unwrap();
+        fs::write(temp_dir.path().join(".env.production"), "API_URL=https://api.example.com\nLOG_LEVEL=warn\n").unwrap();
+        let analysis = analyze_repository(temp_dir.path()).unwrap();
+        
+        let mut requirements = DeploymentRequirements::default();
+        requirements.environment_variables.insert("LOG_LEVEL".to_string(), "debug".to_string());
+        
+        // No environment selected: the file is ignored
+        let completed = complete_requirements(&requirements, &analysis).unwrap();
+        assert!(!completed.environment_variables.contains_key("API_URL"));
+        
+        requirements.environment = Some("production".to_string());
+        let completed = complete_requirements(&requirements, &analysis).unwrap();
+        assert_eq!(completed.environment_variables["API_URL"], "https://api.example.com");
+        assert_eq!(completed.environment_variables["LOG_LEVEL"], "debug", "description values win");
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        assert_eq!("json".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert_eq!("Text".parse::<OutputFormat>().unwrap(), OutputFormat::Text);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_rewrite_localhost_is_idempotent() {
+        let python = "app.run()\nAPI = 'http://localhost:5000'\nlocalhost_url = 1\n";
+        let once = rewrite_localhost(python, true, "203.0.113.10");
+        assert_eq!(once, "app.run(host='0.0.0.0', port=5000)\nAPI = 'http://203.0.113.10:5000'\nlocalhost_url = 1\n");
+        assert_eq!(rewrite_localhost(&once, true, "203.0.113.10"), once);
+        
+        let js = "fetch('http://127.0.0.1:3000'); listen('0.0.0.0'); const net = '10.0.0.0/8';";
+        let once = rewrite_localhost(js, false, "203.0.113.10");
+        assert_eq!(once, "fetch('http://203.0.113.10:3000'); listen('203.0.113.10'); const net = '10.0.0.0/8';");
+        assert_eq!(rewrite_localhost(&once, false, "203.0.113.10"), once);
+    }
+    
+    #[tokio::test]
+    async fn test_replace_localhost_reports_changed_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("static")).unwrap();
+        fs::write(temp_dir.path().join("app.py"), "app.run()\n").unwrap();
+        fs::write(temp_dir.path().join("static/main.js"), "fetch('http://localhost:5000')\n").unwrap();
+        fs::write(temp_dir.path().join("README.txt"), "open http://localhost:5000\n").unwrap();
+        fs::write(temp_dir.path().join("config.json"), "{}\n").unwrap();
+        
+        let lookups = std::cell::Cell::new(0);
+        let lookup = || {
+            lookups.set(lookups.get() + 1);
+            async { "203.0.113.10".to_string() }
+        };
+        let rewrite = replace_localhost_in_repository(temp_dir.path(), lookup).await.unwrap();
+        assert_eq!(rewrite.changed, vec!["app.py".to_string(), "static/main.js".to_string()]);
+        assert!(rewrite.failed.is_empty());
+        assert_eq!(fs::read_to_string(temp_dir.path().join("static/main.js")).unwrap(), "fetch('http://203.0.113.10:5000')\n");
+        assert_eq!(lookups.get(), 1);
+        
+        let rerun = replace_localhost_in_repository(temp_dir.path(), lookup).await.unwrap();
+        assert!(rerun.changed.is_empty(), "second run changed {:?}", rerun.changed);
+        assert_eq!(lookups.get(), 1, "no reference left to point at the IP, so no lookup");
+        
+        // A bind address alone is rewritten without the IP
+        let bind_only = tempfile::tempdir().unwrap();
+        fs::write(bind_only.path().join("app.py"), "app.run(host='localhost')\n").unwrap();
+        let rewrite = replace_localhost_in_repository(bind_only.path(), lookup).await.unwrap();
+        assert_eq!(rewrite.changed, vec!["app.py".to_string()]);
+        assert_eq!(lookups.get(), 1);
+    }
+    
+    #[test]
+    fn test_policy_from_options() {
+        let mut options = DeployOptions::default();
+        assert!(options.policy().unwrap().is_none());
+        
+        options.strict = true;
+        assert!(options.policy().is_err(), "--strict needs a policy file");
+        
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("policy.json");
+        fs::write(&path, r#"{"max_vcpus": 4}"#).unwrap();
+        options.policy = Some(path);
+        let policy = options.policy().unwrap().unwrap();
+        assert_eq!(policy.max_vcpus, Some(4));
+        assert!(policy.strict);
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Set on the background process of `deploy --detach` to the deployment id the foreground
+/// command printed, so the deploy runs under that id
+const DETACHED_ID_VAR: &str = "AUTODEPLOY_DETACHED_ID";
+
+/// How often `logs --follow` checks the log for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Id this process deploys under when it is the background half of `deploy --detach`
+pub fn detached_id() -> Option<String> {
+    env::var(DETACHED_ID_VAR).ok().filter(|id| !id.is_empty())
+}
+
+/// Run this command again without `--detach`, on Unix in its own process group so closing the
+/// terminal or Ctrl-C leaves it running, with all output going to `log_file`. Returns its pid.
+pub fn spawn_background(id: &str, log_file: &Path) -> Result<u32> {
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log = File::create(log_file)?;
+    let args: Vec<_> = env::args_os().skip(1).filter(|arg| arg != "--detach").collect();
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(args)
+        .env(DETACHED_ID_VAR, id)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    #[cfg(unix)]
+    command.process_group(0);
+    let child = command
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start the background deploy: {}", e))?;
+    Ok(child.id())
+}
+
+/// Whether the process `pid` is still alive
+pub fn is_running(pid: u32) -> bool {
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Print the log; with `pid`, keep printing what is appended until that process exits
+pub fn print_log(log_file: &Path, pid: Option<u32>) -> Result<()> {
+    let mut file = File::open(log_file).map_err(|e| anyhow!("Failed to open {}: {}", log_file.display(), e))?;
+    let mut stdout = std::io::stdout();
+    let mut buffer = Vec::new();
+    loop {
+        // Read what the process had written before checking whether it is done, so the
+        // last lines are not lost
+        let running = pid.is_some_and(is_running);
+        buffer.clear();
+        file.read_to_end(&mut buffer)?;
+        stdout.write_all(&buffer)?;
+        stdout.flush()?;
+        if !running {
+            return Ok(());
+        }
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background_process_tracking() {
+        assert!(is_running(std::process::id()));
+        let mut finished = Command::new("true").spawn().unwrap();
+        let pid = finished.id();
+        finished.wait().unwrap();
+        assert!(!is_running(pid));
+        
+        let temp_dir = tempfile::tempdir().unwrap();
+        let log_file = temp_dir.path().join("deploy.log");
+        fs::write(&log_file, "🚀 Starting deployment process...\n").unwrap();
+        print_log(&log_file, Some(pid)).unwrap();
+        assert!(print_log(&temp_dir.path().join("missing.log"), None).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::infrastructure::TerraformConfig;
+use crate::nlp::CloudProvider;
+
+/// Room for the OS, packages, Docker images and logs, before the app's own build
+const BASE_DISK_SIZE_GB: u32 = 10;
+
+/// The build is multiplied by this for caches, build intermediates and a redeploy's second copy
+const BUILD_HEADROOM: u64 = 3;
+
+/// Smallest and largest root disk in GB: the image's own size up to the volume type's maximum
+pub fn disk_size_limits(cloud_provider: &CloudProvider) -> (u32, u32) {
+    match cloud_provider {
+        CloudProvider::AWS => (8, 16_384),   // gp3
+        CloudProvider::GCP => (10, 65_536),  // pd-balanced
+        CloudProvider::Azure => (30, 4_095), // managed OS disk
+        _ => (10, 1_024),
+    }
+}
+
+/// Check a `--disk-size` against the provider's limits
+pub fn validate_disk_size(size_gb: u32, cloud_provider: &CloudProvider) -> Result<()> {
+    let (min, max) = disk_size_limits(cloud_provider);
+    if !(min..=max).contains(&size_gb) {
+        return Err(anyhow!(
+            "--disk-size {} is outside the {:?} root disk limits of {}-{} GB",
+            size_gb,
+            cloud_provider,
+            min,
+            max
+        ));
+    }
+    Ok(())
+}
+
+/// Disk for an app whose build takes `build_size_mb`: the base size plus room for the build,
+/// so apps with big dependency trees get proportionally more
+pub fn recommended_disk_size(build_size_mb: u64, cloud_provider: &CloudProvider) -> u32 {
+    let build_gb = (build_size_mb * BUILD_HEADROOM).div_ceil(1024) as u32;
+    let (min, max) = disk_size_limits(cloud_provider);
+    (BASE_DISK_SIZE_GB + build_gb).clamp(min, max)
+}
+
+/// Monthly cost of a root disk of `size_gb`
+pub fn storage_cost(size_gb: u32, cloud_provider: &CloudProvider) -> f64 {
+    let per_gb = match cloud_provider {
+        CloudProvider::AWS => 0.08,    // gp3
+        CloudProvider::GCP => 0.10,    // pd-balanced
+        CloudProvider::Azure => 0.075, // Standard SSD
+        _ => 0.10,
+    };
+    size_gb as f64 * per_gb
+}
+
+/// Set the root disk of every VM in the config to `size_gb`. Returns the sized resources.
+pub fn apply_disk_size(config: &mut TerraformConfig, size_gb: u32) -> Vec<String> {
+    let mut sized = Vec::new();
+    for resource in &mut config.resources {
+        let (disk, path): (&str, &[&str]) = match resource.resource_type.as_str() {
+            "aws_instance" => ("root_block_device", &[]),
+            "google_compute_instance" => ("boot_disk", &["initialize_params"]),
+            "azurerm_linux_virtual_machine" | "azurerm_windows_virtual_machine" => ("os_disk", &[]),
+            _ => continue,
+        };
+        let mut settings = object_entry(resource.config.entry(disk.to_string()).or_insert_with(|| json!({})));
+        for key in path {
+            settings = object_entry(settings.entry(key.to_string()).or_insert_with(|| json!({})));
+        }
+        match resource.resource_type.as_str() {
+            "aws_instance" => {
+                settings.insert("volume_size".to_string(), json!(size_gb));
+                settings.entry("volume_type".to_string()).or_insert_with(|| json!("gp3"));
+            }
+            "google_compute_instance" => {
+                settings.insert("size".to_string(), json!(size_gb));
+            }
+            _ => {
+                settings.insert("disk_size_gb".to_string(), json!(size_gb));
+            }
+        }
+        sized.push(format!("{}.{}", resource.resource_type, resource.name));
+    }
+    sized
+}
+
+/// The object a block holds, replacing anything else; a block given as a list uses its first item
+fn object_entry(value: &mut Value) -> &mut serde_json::Map<String, Value> {
+    if let Value::Array(items) = value {
+        let first = items.drain(..).next().unwrap_or_else(|| json!({}));
+        *value = first;
+    }
+    if !value.is_object() {
+        *value = json!({});
+    }
+    value.as_object_mut().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::TerraformResource;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_disk_size() {
+        assert_eq!(recommended_disk_size(0, &CloudProvider::AWS), 10);
+        assert_eq!(recommended_disk_size(2_000, &CloudProvider::AWS), 16, "a 2 GB build needs 6 GB on top of the base");
+        assert_eq!(recommended_disk_size(0, &CloudProvider::Azure), 30, "Azure OS disks start at 30 GB");
+        assert!(validate_disk_size(8, &CloudProvider::AWS).is_ok());
+        assert!(validate_disk_size(8, &CloudProvider::GCP).is_err());
+        assert!(validate_disk_size(5_000, &CloudProvider::Azure).is_err());
+        assert!((storage_cost(20, &CloudProvider::AWS) - 1.60).abs() < 1e-9);
+
+        let resource = |resource_type: &str, config: Value| TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: "app".to_string(),
+            config: serde_json::from_value(config).unwrap(),
+        };
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                resource("aws_instance", json!({"root_block_device": [{"encrypted": true}]})),
+                resource("google_compute_instance", json!({"boot_disk": {"initialize_params": {"image": "debian-12"}}})),
+                resource("aws_security_group", json!({})),
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let sized = apply_disk_size(&mut config, 24);
+        assert_eq!(sized, vec!["aws_instance.app", "google_compute_instance.app"]);
+        assert_eq!(config.resources[0].config["root_block_device"], json!({"encrypted": true, "volume_size": 24, "volume_type": "gp3"}));
+        assert_eq!(config.resources[1].config["boot_disk"]["initialize_params"], json!({"image": "debian-12", "size": 24}));
+    }
+}
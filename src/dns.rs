@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+
+use crate::http;
+
+const CLOUDFLARE_API_URL: &str = "https://api.cloudflare.com/client/v4";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DnsProvider {
+    Cloudflare,
+}
+
+impl FromStr for DnsProvider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "cloudflare" => Ok(DnsProvider::Cloudflare),
+            _ => Err(anyhow!("Unsupported DNS provider: {}. Use: cloudflare", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    pub provider: DnsProvider,
+    pub zone: String,
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<serde_json::Value>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct CloudflareObject {
+    id: String,
+}
+
+/// Create or update an A record for `name` pointing at `ip`
+pub async fn upsert_a_record(config: &DnsConfig, name: &str, ip: &str) -> Result<()> {
+    match config.provider {
+        DnsProvider::Cloudflare => upsert_cloudflare_record(config, name, ip).await,
+    }
+}
+
+async fn upsert_cloudflare_record(config: &DnsConfig, name: &str, ip: &str) -> Result<()> {
+    info!("🌍 Updating Cloudflare A record {} -> {}", name, ip);
+
+    let zones: Vec<CloudflareObject> = cloudflare_request(
+        config,
+        http::client()
+            .get(format!("{}/zones", CLOUDFLARE_API_URL))
+            .query(&[("name", config.zone.as_str())]),
+    )
+    .await?;
+    let zone_id = &zones
+        .first()
+        .ok_or_else(|| anyhow!("Cloudflare zone '{}' not found", config.zone))?
+        .id;
+
+    let records_url = format!("{}/zones/{}/dns_records", CLOUDFLARE_API_URL, zone_id);
+    let existing: Vec<CloudflareObject> = cloudflare_request(
+        config,
+        http::client()
+            .get(&records_url)
+            .query(&[("type", "A"), ("name", name)]),
+    )
+    .await?;
+
+    let body = a_record_body(name, ip);
+    let request = match existing.first() {
+        Some(record) => http::client()
+            .put(format!("{}/{}", records_url, record.id))
+            .json(&body),
+        None => http::client().post(&records_url).json(&body),
+    };
+    let _: CloudflareObject = cloudflare_request(config, request).await?;
+
+    info!("✅ Cloudflare A record {} points at {}", name, ip);
+    Ok(())
+}
+
+async fn cloudflare_request<T: for<'de> Deserialize<'de>>(
+    config: &DnsConfig,
+    request: reqwest::RequestBuilder,
+) -> Result<T> {
+    let response = request
+        .bearer_auth(&config.token)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to call Cloudflare API: {}", e))?;
+
+    let status = response.status();
+    let parsed: CloudflareResponse<T> = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to parse Cloudflare response ({}): {}", status, e))?;
+
+    if !parsed.success {
+        return Err(anyhow!("Cloudflare API error {}: {:?}", status, parsed.errors));
+    }
+
+    parsed
+        .result
+        .ok_or_else(|| anyhow!("Cloudflare API returned no result"))
+}
+
+fn a_record_body(name: &str, ip: &str) -> serde_json::Value {
+    json!({
+        "type": "A",
+        "name": name,
+        "content": ip,
+        "ttl": 1,
+        "proxied": false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dns_provider() {
+        assert_eq!("cloudflare".parse::<DnsProvider>().unwrap(), DnsProvider::Cloudflare);
+        assert_eq!("Cloudflare".parse::<DnsProvider>().unwrap(), DnsProvider::Cloudflare);
+        assert!("route53".parse::<DnsProvider>().is_err());
+    }
+
+    #[test]
+    fn test_a_record_body() {
+        let body = a_record_body("app.example.com", "203.0.113.10");
+        assert_eq!(body["type"], "A");
+        assert_eq!(body["name"], "app.example.com");
+        assert_eq!(body["content"], "203.0.113.10");
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Result};
+
+use crate::nlp::ApplicationType;
+use crate::repository::RepositoryAnalysis;
+use crate::settings;
+
+/// Name the generated Dockerfile is written under, in the build context and the deployment directory
+pub const DOCKERFILE: &str = "Dockerfile";
+
+/// Where the build context is copied to in the image
+const IMAGE_APP_ROOT: &str = "/app";
+
+/// Name the app's image is built under
+pub const IMAGE_NAME: &str = "app";
+
+/// Parser directive enabling the BuildKit frontend that understands `RUN --mount`
+const SYNTAX_DIRECTIVE: &str = "# syntax=docker/dockerfile:1";
+
+/// Package manager download caches, kept between builds with `RUN --mount=type=cache`
+const PACKAGE_CACHES: &[(&str, &str)] = &[
+    ("pip install", "/root/.cache/pip"),
+    ("pip3 install", "/root/.cache/pip"),
+    ("npm install", "/root/.npm"),
+    ("npm ci", "/root/.npm"),
+    ("yarn install", "/usr/local/share/.cache/yarn"),
+    ("cargo build", "/usr/local/cargo/registry"),
+];
+
+/// How container targets get the app's image
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContainerBuild {
+    /// Run the app in this image (`--base-image` with the repository's own Dockerfile)
+    BaseImage(String),
+    /// Build from this Dockerfile, generated because the repository has none
+    Dockerfile(String),
+}
+
+impl ContainerBuild {
+    /// Prompt section telling the AI how the app's image is made
+    pub fn prompt_requirements(&self) -> String {
+        match self {
+            ContainerBuild::BaseImage(image) => format!(
+                "\nCONTAINER IMAGE (required):\n- Run the app in the official {} image: clone the repository, install its dependencies and start it inside the container\n- Do not install the language runtime with apt or another package manager\n",
+                image
+            ),
+            ContainerBuild::Dockerfile(dockerfile) => format!(
+                "\nCONTAINER IMAGE (required):\n- The repository has no Dockerfile: clone it, write this Dockerfile at the root of the clone (the build context) and build the app image from it\n- Do not install the language runtime with apt or another package manager\n```\n{}```\n- Build the image as `{}` with exactly these commands, run in the clone:\n```\n{}```\n",
+                dockerfile,
+                IMAGE_NAME,
+                build_script(IMAGE_NAME, settings::get().build_cache_image.as_deref())
+            ),
+        }
+    }
+}
+
+/// Render a Dockerfile for a repository without one: `base_image`, the source copied in,
+/// the detected build commands, the app's port and its start command
+pub fn render_dockerfile(analysis: &RepositoryAnalysis, base_image: &str) -> Result<String> {
+    if analysis.start_commands.is_empty() || analysis.app_type == ApplicationType::Unknown {
+        return Err(anyhow!("No start command was detected, so there is nothing for the image to run"));
+    }
+    let mut dockerfile = format!("{}\n", SYNTAX_DIRECTIVE);
+    dockerfile.push_str("# Generated from the repository analysis; commit a Dockerfile to customize the build\n");
+    dockerfile.push_str(&format!("FROM {}\n", base_image));
+    if matches!(analysis.app_type, ApplicationType::Flask | ApplicationType::Django | ApplicationType::FastAPI) {
+        // Logs show up as they are written instead of when the buffer fills
+        dockerfile.push_str("ENV PYTHONUNBUFFERED=1\n");
+    }
+    dockerfile.push_str(&format!("WORKDIR {}\nCOPY . .\n", IMAGE_APP_ROOT));
+    if let Some(dir) = &analysis.working_dir {
+        dockerfile.push_str(&format!("WORKDIR {}/{}\n", IMAGE_APP_ROOT, dir.trim_end_matches('/')));
+    }
+
+    // Passed with --build-arg so they are baked into the bundle, like on a VM
+    for name in &analysis.build_env_vars {
+        dockerfile.push_str(&format!("ARG {}\n", name));
+    }
+    for command in &analysis.build_commands {
+        match cache_target(command) {
+            Some(target) => dockerfile.push_str(&format!("RUN --mount=type=cache,target={} {}\n", target, command)),
+            None => dockerfile.push_str(&format!("RUN {}\n", command)),
+        }
+    }
+
+    if let Some(port) = analysis.internal_port {
+        dockerfile.push_str(&format!("ENV PORT={}\nEXPOSE {}\n", port, port));
+    }
+    // Every process but the last runs in the background; the last replaces the shell so it gets the signals
+    let (last, others) = analysis.start_commands.split_last().expect("start commands checked above");
+    let mut start = String::new();
+    for command in others {
+        start.push_str(&format!("{} & ", command));
+    }
+    start.push_str(&format!("exec {}", last));
+    dockerfile.push_str(&format!("CMD [\"sh\", \"-c\", {}]\n", serde_json::to_string(&start)?));
+    Ok(dockerfile)
+}
+
+/// Commands building the Dockerfile in the current directory as `image`. With BuildKit the
+/// cache mounts and the inline layer cache are used, and `cache_image` (a registry image, the
+/// `build_cache_image` setting) is pulled as `--cache-from` and pushed afterwards so the next
+/// host reuses the layers. Without BuildKit the cache mounts are dropped for a plain build.
+pub fn build_script(image: &str, cache_image: Option<&str>) -> String {
+    let mut buildkit = String::from("DOCKER_BUILDKIT=1 docker build --build-arg BUILDKIT_INLINE_CACHE=1");
+    if let Some(cache_image) = cache_image {
+        buildkit.push_str(&format!(" --cache-from {0} -t {0}", cache_image));
+    }
+    buildkit.push_str(&format!(" -t {} .\n", image));
+    if let Some(cache_image) = cache_image {
+        buildkit.push_str(&format!("  docker push {} || echo 'Warning: could not push the build cache' >&2\n", cache_image));
+    }
+    format!(
+        "if docker buildx version >/dev/null 2>&1; then\n  {}else\n  sed -i 's/^RUN --mount=[^ ]* /RUN /' {}\n  docker build -t {} .\nfi\n",
+        buildkit, DOCKERFILE, image
+    )
+}
+
+/// Download cache of the package manager `command` runs, if it is one we know
+fn cache_target(command: &str) -> Option<&'static str> {
+    PACKAGE_CACHES
+        .iter()
+        .find(|(prefix, _)| command == *prefix || command.starts_with(&format!("{} ", prefix)))
+        .map(|(_, target)| *target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::analyze_repository;
+    use std::fs;
+
+    #[test]
+    fn test_render_dockerfile_for_flask_and_node() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo = temp_dir.path();
+        fs::write(repo.join("requirements.txt"), "Flask\ngunicorn\n").unwrap();
+        fs::write(repo.join("app.py"), "from flask import Flask\napp = Flask(__name__)\n").unwrap();
+        let analysis = analyze_repository(repo).unwrap();
+        let dockerfile = render_dockerfile(&analysis, &analysis.container_base_image().unwrap()).unwrap();
+        assert_eq!(
+            dockerfile.lines().collect::<Vec<_>>(),
+            [
+                "# syntax=docker/dockerfile:1",
+                "# Generated from the repository analysis; commit a Dockerfile to customize the build",
+                "FROM python:3.12-slim",
+                "ENV PYTHONUNBUFFERED=1",
+                "WORKDIR /app",
+                "COPY . .",
+                "RUN --mount=type=cache,target=/root/.cache/pip pip install -r requirements.txt",
+                "ENV PORT=5000",
+                "EXPOSE 5000",
+                "CMD [\"sh\", \"-c\", \"exec gunicorn --bind 0.0.0.0:5000 app:app\"]",
+            ]
+        );
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo = temp_dir.path();
+        fs::write(
+            repo.join("package.json"),
+            r#"{"name": "web", "scripts": {"build": "react-scripts build", "start": "serve -s build"}, "dependencies": {"react": "18.2.0"}}"#,
+        )
+        .unwrap();
+        fs::write(repo.join(".env.example"), "REACT_APP_API_URL=\n").unwrap();
+        let mut analysis = analyze_repository(repo).unwrap();
+        analysis.working_dir = Some("web".to_string());
+        let dockerfile = render_dockerfile(&analysis, "node:20-alpine").unwrap();
+        assert!(dockerfile.contains("FROM node:20-alpine\nWORKDIR /app\nCOPY . .\nWORKDIR /app/web\n"), "{}", dockerfile);
+        assert!(
+            dockerfile.contains("ARG REACT_APP_API_URL\nRUN --mount=type=cache,target=/root/.npm npm install\nRUN npm run build\n"),
+            "{}",
+            dockerfile
+        );
+        assert!(dockerfile.ends_with("CMD [\"sh\", \"-c\", \"exec npm start\"]\n"), "{}", dockerfile);
+
+        let unknown = RepositoryAnalysis { app_type: ApplicationType::Unknown, ..analysis };
+        assert!(render_dockerfile(&unknown, "alpine").is_err());
+    }
+
+    #[test]
+    fn test_build_script_caches_with_buildkit() {
+        assert_eq!(
+            build_script("app", None),
+            "if docker buildx version >/dev/null 2>&1; then\n\
+             \x20 DOCKER_BUILDKIT=1 docker build --build-arg BUILDKIT_INLINE_CACHE=1 -t app .\n\
+             else\n\
+             \x20 sed -i 's/^RUN --mount=[^ ]* /RUN /' Dockerfile\n\
+             \x20 docker build -t app .\n\
+             fi\n"
+        );
+
+        // A registry cache is pulled from and pushed to, only when BuildKit can use it
+        let script = build_script("app", Some("ghcr.io/acme/app-cache"));
+        let (buildkit, plain) = script.split_once("else\n").unwrap();
+        assert!(buildkit.contains("--cache-from ghcr.io/acme/app-cache -t ghcr.io/acme/app-cache -t app .\n"), "{}", script);
+        assert!(buildkit.contains("docker push ghcr.io/acme/app-cache || echo"), "{}", script);
+        assert!(!plain.contains("ghcr.io"), "{}", script);
+
+        let prompt = ContainerBuild::Dockerfile("FROM node:20-alpine\n".to_string()).prompt_requirements();
+        assert!(prompt.contains(&build_script("app", None)), "{}", prompt);
+        assert_eq!(cache_target("cargo build --release"), Some("/usr/local/cargo/registry"));
+        assert_eq!(cache_target("npm run build"), None);
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use crate::database;
+use crate::infrastructure::{TerraformConfig, TerraformResource};
+use crate::nlp::{CloudProvider, DatabaseType};
+
+const ANYWHERE: &str = "0.0.0.0/0";
+
+/// Outbound traffic the startup script itself needs, allowed to anywhere whatever else is
+/// restricted: package mirrors (apt's are plain HTTP), HTTPS for pip/npm and the git clone, DNS
+const BOOTSTRAP_RULES: &[(&str, u16, &str)] = &[
+    ("tcp", 80, "HTTP package mirrors"),
+    ("tcp", 443, "HTTPS: packages, git, APIs"),
+    ("udp", 53, "DNS"),
+    ("tcp", 53, "DNS"),
+];
+
+/// Network tag the GCP firewall rules target, added to the deployed instances so the
+/// deny-all rule never reaches other machines in the network
+const GCP_EGRESS_TAG: &str = "autodeployment-egress";
+
+/// Compute resources whose security groups get the restricted egress
+const AWS_COMPUTE_TYPES: &[&str] = &["aws_instance", "aws_launch_template", "aws_launch_configuration"];
+
+/// Outbound traffic allowed from the instance instead of everything (`--restrict-egress`,
+/// `--egress-ports`, `--egress-cidr`). The bootstrap's own traffic is always allowed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EgressPolicy {
+    /// TCP ports the app connects out on: the databases' unless given explicitly
+    pub ports: Vec<u16>,
+    /// Destinations those ports may reach; anywhere when empty
+    pub cidrs: Vec<String>,
+}
+
+/// One allowed protocol/port to a set of destinations
+struct Rule {
+    protocol: &'static str,
+    port: u16,
+    cidrs: Vec<String>,
+    description: String,
+}
+
+impl EgressPolicy {
+    /// The policy from the flags, or `None` to keep allowing all outbound traffic
+    pub fn from_flags(
+        restrict: bool,
+        cidrs: Vec<String>,
+        ports: Vec<u16>,
+        databases: &[DatabaseType],
+        cloud_provider: &CloudProvider,
+    ) -> Result<Option<Self>> {
+        if !restrict && cidrs.is_empty() && ports.is_empty() {
+            return Ok(None);
+        }
+        if !matches!(cloud_provider, CloudProvider::AWS | CloudProvider::GCP) {
+            return Err(anyhow!("Egress restrictions are not supported on {:?}", cloud_provider));
+        }
+        for cidr in &cidrs {
+            validate_cidr(cidr)?;
+        }
+        if let Some(port) = ports.iter().find(|port| **port == 0) {
+            return Err(anyhow!("Invalid --egress-ports {}: ports are 1-65535", port));
+        }
+        let mut ports = if ports.is_empty() {
+            database::managed_databases(databases).iter().map(database::port).collect()
+        } else {
+            ports
+        };
+        ports.sort_unstable();
+        ports.dedup();
+        Ok(Some(EgressPolicy { ports, cidrs }))
+    }
+
+    fn rules(&self) -> Vec<Rule> {
+        let cidrs = if self.cidrs.is_empty() { vec![ANYWHERE.to_string()] } else { self.cidrs.clone() };
+        let mut rules: Vec<Rule> = BOOTSTRAP_RULES
+            .iter()
+            .map(|(protocol, port, description)| Rule {
+                protocol,
+                port: *port,
+                cidrs: vec![ANYWHERE.to_string()],
+                description: description.to_string(),
+            })
+            .collect();
+        for port in &self.ports {
+            // Already open to anywhere
+            if self.cidrs.is_empty() && BOOTSTRAP_RULES.iter().any(|(protocol, open, _)| *protocol == "tcp" && open == port) {
+                continue;
+            }
+            rules.push(Rule { protocol: "tcp", port: *port, cidrs: cidrs.clone(), description: format!("App traffic on {}", port) });
+        }
+        rules
+    }
+
+    /// Replace the allow-all egress of the instance's security groups (AWS) or add egress
+    /// firewall rules that deny everything else (GCP). Returns the restricted resources.
+    pub fn apply(&self, config: &mut TerraformConfig, cloud_provider: &CloudProvider) -> Result<Vec<String>> {
+        let restricted = match cloud_provider {
+            CloudProvider::AWS => self.apply_aws(config),
+            CloudProvider::GCP => self.apply_gcp(config),
+            other => return Err(anyhow!("Egress restrictions are not supported on {:?}", other)),
+        };
+        if restricted.is_empty() {
+            return Err(anyhow!("The generated configuration has no instance firewall to restrict egress on"));
+        }
+        Ok(restricted)
+    }
+
+    fn apply_aws(&self, config: &mut TerraformConfig) -> Vec<String> {
+        let group_reference = Regex::new(r"aws_security_group\.(\w+)\.").unwrap();
+        let mut groups: Vec<String> = Vec::new();
+        for resource in config.resources.iter().filter(|r| AWS_COMPUTE_TYPES.contains(&r.resource_type.as_str())) {
+            let text = serde_json::to_string(&resource.config).unwrap_or_default();
+            for captures in group_reference.captures_iter(&text) {
+                if !groups.contains(&captures[1].to_string()) {
+                    groups.push(captures[1].to_string());
+                }
+            }
+        }
+
+        // Separate egress rules on those groups would open everything up again
+        let references = |resource: &TerraformResource| {
+            let text = serde_json::to_string(&resource.config).unwrap_or_default();
+            group_reference.captures_iter(&text).any(|captures| groups.contains(&captures[1].to_string()))
+        };
+        config.resources.retain(|resource| match resource.resource_type.as_str() {
+            "aws_vpc_security_group_egress_rule" => !references(resource),
+            "aws_security_group_rule" => resource.config.get("type").and_then(Value::as_str) != Some("egress") || !references(resource),
+            _ => true,
+        });
+
+        let egress: Vec<Value> = self
+            .rules()
+            .into_iter()
+            .map(|rule| {
+                json!({
+                    "from_port": rule.port,
+                    "to_port": rule.port,
+                    "protocol": rule.protocol,
+                    "cidr_blocks": rule.cidrs,
+                    "description": rule.description,
+                    "ipv6_cidr_blocks": [],
+                    "prefix_list_ids": [],
+                    "security_groups": [],
+                    "self": false,
+                })
+            })
+            .collect();
+        let mut restricted = Vec::new();
+        for resource in &mut config.resources {
+            if resource.resource_type == "aws_security_group" && groups.contains(&resource.name) {
+                resource.config.insert("egress".to_string(), Value::Array(egress.clone()));
+                restricted.push(format!("aws_security_group.{}", resource.name));
+            }
+        }
+        restricted
+    }
+
+    fn apply_gcp(&self, config: &mut TerraformConfig) -> Vec<String> {
+        let mut instances: Vec<&mut HashMap<String, Value>> = config
+            .resources
+            .iter_mut()
+            .filter(|r| matches!(r.resource_type.as_str(), "google_compute_instance" | "google_compute_instance_template"))
+            .map(|r| &mut r.config)
+            .collect();
+        if instances.is_empty() {
+            return Vec::new();
+        }
+        let network = instances
+            .iter()
+            .find_map(|instance| {
+                let interface = instance.get("network_interface")?;
+                let interface = interface.as_array().and_then(|items| items.first()).unwrap_or(interface);
+                interface.get("network")?.as_str().map(str::to_string)
+            })
+            .unwrap_or_else(|| "default".to_string());
+        let tag = Value::String(GCP_EGRESS_TAG.to_string());
+        for instance in &mut instances {
+            let tags = instance.entry("tags".to_string()).or_insert_with(|| json!([]));
+            if !tags.is_array() {
+                *tags = json!([]);
+            }
+            let tags = tags.as_array_mut().unwrap();
+            if !tags.contains(&tag) {
+                tags.push(tag.clone());
+            }
+        }
+
+        // One allow rule per set of destinations; the deny rule sits just above the implied allow
+        let mut allows: Vec<(Vec<String>, Vec<Value>)> = Vec::new();
+        for rule in self.rules() {
+            let block = json!({"protocol": rule.protocol, "ports": [rule.port.to_string()]});
+            match allows.iter_mut().find(|(cidrs, _)| *cidrs == rule.cidrs) {
+                Some((_, blocks)) => blocks.push(block),
+                None => allows.push((rule.cidrs, vec![block])),
+            }
+        }
+        let mut firewalls: Vec<(String, Value)> = allows
+            .into_iter()
+            .enumerate()
+            .map(|(index, (cidrs, allow))| {
+                let name = if index == 0 { "egress_allow".to_string() } else { format!("egress_allow_{}", index) };
+                let rule = json!({
+                    "name": name.replace('_', "-"),
+                    "network": network,
+                    "direction": "EGRESS",
+                    "priority": 1000,
+                    "allow": allow,
+                    "destination_ranges": cidrs,
+                });
+                (name, rule)
+            })
+            .collect();
+        firewalls.push(("egress_deny".to_string(), json!({
+            "name": "egress-deny",
+            "network": network,
+            "direction": "EGRESS",
+            "priority": 65534,
+            "deny": [{"protocol": "all"}],
+            "destination_ranges": [ANYWHERE],
+        })));
+
+        let mut added = Vec::new();
+        for (name, mut rule) in firewalls {
+            rule["target_tags"] = json!([GCP_EGRESS_TAG]);
+            config.resources.retain(|r| !(r.resource_type == "google_compute_firewall" && r.name == name));
+            config.resources.push(TerraformResource {
+                resource_type: "google_compute_firewall".to_string(),
+                name: name.clone(),
+                config: serde_json::from_value(rule).unwrap_or_default(),
+            });
+            added.push(format!("google_compute_firewall.{}", name));
+        }
+        added
+    }
+}
+
+impl std::fmt::Display for EgressPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HTTP, HTTPS and DNS")?;
+        if !self.ports.is_empty() {
+            let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+            write!(f, ", tcp {}", ports.join("/"))?;
+        }
+        if !self.cidrs.is_empty() {
+            write!(f, " to {}", self.cidrs.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_cidr(cidr: &str) -> Result<()> {
+    let valid = cidr
+        .split_once('/')
+        .is_some_and(|(address, prefix)| address.parse::<Ipv4Addr>().is_ok() && prefix.parse::<u8>().is_ok_and(|prefix| prefix <= 32));
+    if !valid {
+        return Err(anyhow!("Invalid --egress-cidr {:?}, expected an IPv4 CIDR block like 10.0.0.0/16", cidr));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(resource_type: &str, name: &str, config: Value) -> TerraformResource {
+        TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            config: serde_json::from_value(config).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_restricts_egress_to_bootstrap_and_app_ports() {
+        let aws = &CloudProvider::AWS;
+        assert_eq!(EgressPolicy::from_flags(false, vec![], vec![], &[DatabaseType::PostgreSQL], aws).unwrap(), None);
+        assert!(EgressPolicy::from_flags(false, vec!["10.0.0.0/33".to_string()], vec![], &[], aws).is_err());
+        assert!(EgressPolicy::from_flags(true, vec![], vec![], &[], &CloudProvider::Azure).is_err());
+        let policy = EgressPolicy::from_flags(true, vec![], vec![], &[DatabaseType::PostgreSQL, DatabaseType::SQLite], aws).unwrap().unwrap();
+        assert_eq!(policy.ports, vec![5432]);
+
+        let all_outbound = json!([{"from_port": 0, "to_port": 0, "protocol": "-1", "cidr_blocks": ["0.0.0.0/0"]}]);
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                resource("aws_instance", "app", json!({"vpc_security_group_ids": ["aws_security_group.app_sg.id"]})),
+                resource("aws_security_group", "app_sg", json!({"egress": all_outbound})),
+                resource("aws_security_group", "lb", json!({"egress": all_outbound})),
+                resource("aws_security_group_rule", "out", json!({"type": "egress", "security_group_id": "${aws_security_group.app_sg.id}"})),
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        assert_eq!(policy.apply(&mut config, &CloudProvider::AWS).unwrap(), vec!["aws_security_group.app_sg"]);
+        assert_eq!(config.resources.len(), 3, "the separate allow-all rule is removed");
+        let egress = config.resources[1].config["egress"].as_array().unwrap();
+        let ports: Vec<(String, u64)> = egress.iter().map(|rule| (rule["protocol"].as_str().unwrap().to_string(), rule["to_port"].as_u64().unwrap())).collect();
+        assert_eq!(ports, vec![("tcp".into(), 80), ("tcp".into(), 443), ("udp".into(), 53), ("tcp".into(), 53), ("tcp".into(), 5432)]);
+        assert_eq!(config.resources[2].config["egress"], all_outbound, "the load balancer keeps its egress");
+
+        let policy = EgressPolicy::from_flags(false, vec!["10.0.0.0/16".to_string()], vec![443, 6379], &[], &CloudProvider::GCP).unwrap().unwrap();
+        let mut gcp = TerraformConfig {
+            provider: "gcp".to_string(),
+            resources: vec![resource("google_compute_instance", "app", json!({"tags": ["web"], "network_interface": {"network": "default"}}))],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let added = policy.apply(&mut gcp, &CloudProvider::GCP).unwrap();
+        assert_eq!(added, vec!["google_compute_firewall.egress_allow", "google_compute_firewall.egress_allow_1", "google_compute_firewall.egress_deny"]);
+        let app_rule = &gcp.resources[2].config;
+        assert_eq!(app_rule["destination_ranges"], json!(["10.0.0.0/16"]));
+        assert_eq!(app_rule["allow"], json!([{"protocol": "tcp", "ports": ["443"]}, {"protocol": "tcp", "ports": ["6379"]}]));
+        assert_eq!(gcp.resources[0].config["tags"], json!(["web", "autodeployment-egress"]));
+        assert_eq!(gcp.resources[3].config["target_tags"], json!(["autodeployment-egress"]));
+        assert_eq!(policy.to_string(), "HTTP, HTTPS and DNS, tcp 443/6379 to 10.0.0.0/16");
+        assert!(policy.apply(&mut gcp, &CloudProvider::Azure).is_err());
+    }
+
+    #[test]
+    fn test_gcp_deny_rule_only_reaches_the_deployed_instance() {
+        let policy = EgressPolicy::from_flags(true, vec![], vec![], &[], &CloudProvider::GCP).unwrap().unwrap();
+        let mut gcp = TerraformConfig {
+            provider: "gcp".to_string(),
+            resources: vec![resource("google_compute_instance", "app", json!({"network_interface": {"network": "default"}}))],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        policy.apply(&mut gcp, &CloudProvider::GCP).unwrap();
+        assert_eq!(gcp.resources[0].config["tags"], json!(["autodeployment-egress"]), "the untagged instance is tagged");
+        let deny = gcp.resources.iter().find(|r| r.name == "egress_deny").unwrap();
+        assert_eq!(deny.config["target_tags"], json!(["autodeployment-egress"]));
+    }
+}
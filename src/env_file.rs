@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+
+/// Where the env file is written on the instance; only root can read it
+pub const REMOTE_ENV_FILE: &str = "/etc/autodeployment/app.env";
+
+/// A local env file delivered to the instance out-of-band instead of through `user_data`.
+/// `Debug` is implemented by hand so the values never end up in logs.
+#[derive(Clone)]
+pub struct EnvFile {
+    pub contents: String,
+    pub keys: Vec<String>,
+}
+
+impl std::fmt::Debug for EnvFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnvFile").field("keys", &self.keys).finish_non_exhaustive()
+    }
+}
+
+impl EnvFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read env file {}: {}", path.display(), e))?;
+        Self::parse(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Validate `KEY=value` lines (comments, blank lines and `export` prefixes allowed)
+    fn parse(contents: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let key = line
+                .split_once('=')
+                .map(|(key, _)| key.trim())
+                .filter(|key| is_valid_key(key))
+                // Only the line number is reported, the line may hold a secret
+                .ok_or_else(|| anyhow!("line {} is not a KEY=value assignment", number + 1))?;
+            keys.push(key.to_string());
+        }
+
+        Ok(Self {
+            contents: contents.to_string(),
+            keys,
+        })
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file() {
+        let env_file = EnvFile::parse(
+            "# production secrets\n\nDATABASE_URL=postgres://u:p@db/app\nexport SECRET_KEY=\"s3cr3t\"\n",
+        )
+        .unwrap();
+        assert_eq!(env_file.keys, vec!["DATABASE_URL", "SECRET_KEY"]);
+
+        let err = EnvFile::parse("GOOD=1\nthis is a s3cr3t\n").unwrap_err().to_string();
+        assert!(err.contains("line 2"));
+        assert!(!err.contains("s3cr3t"));
+
+        assert!(EnvFile::parse("1BAD=x\n").is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_values() {
+        let env_file = EnvFile::parse("SECRET_KEY=s3cr3t\n").unwrap();
+        let debug = format!("{:?}", env_file);
+        assert!(debug.contains("SECRET_KEY"));
+        assert!(!debug.contains("s3cr3t"));
+    }
+}
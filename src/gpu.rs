@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::infrastructure::TerraformConfig;
+use crate::nlp::{CloudProvider, OsFamily};
+
+/// Smallest instance with one NVIDIA T4 on each provider; on GCP the GPU is attached to it
+pub fn gpu_instance_type(cloud_provider: &CloudProvider) -> Option<&'static str> {
+    match cloud_provider {
+        CloudProvider::AWS => Some("g4dn.xlarge"),
+        CloudProvider::GCP => Some("n1-standard-4"),
+        _ => None,
+    }
+}
+
+/// Accelerator attached to the GCP instance
+const GCP_ACCELERATOR: &str = "nvidia-tesla-t4";
+
+/// Monthly on-demand price of the GPU instances, GCP's attached T4 included
+const GPU_INSTANCE_COSTS: &[(&str, f64)] = &[
+    ("g4dn.xlarge", 383.98),   // $0.526/h
+    ("n1-standard-4", 394.20), // $0.19/h + $0.35/h for the T4
+];
+
+/// Monthly price of `instance_type`, if it is one of the GPU instances
+pub fn monthly_cost(instance_type: &str) -> Option<f64> {
+    GPU_INSTANCE_COSTS
+        .iter()
+        .find(|(name, _)| *name == instance_type)
+        .map(|(_, cost)| *cost)
+}
+
+/// Prompt lines asking for the GPU instance, so the generated config is built around it
+pub fn prompt_requirements(cloud_provider: &CloudProvider) -> String {
+    let Some(instance_type) = gpu_instance_type(cloud_provider) else { return String::new() };
+    let accelerator = match cloud_provider {
+        CloudProvider::GCP => format!(
+            " with one {} in guest_accelerator and scheduling.on_host_maintenance = \"TERMINATE\"",
+            GCP_ACCELERATOR
+        ),
+        _ => String::new(),
+    };
+    format!(
+        "\nGPU (required):\n- Run the app on a {} instance{}\n- Boot an image with the NVIDIA driver and CUDA (a deep learning image)\n",
+        instance_type, accelerator
+    )
+}
+
+/// SSM parameter of AWS's Deep Learning Base AMI for the OS, which ships the NVIDIA driver and CUDA
+fn aws_image_parameter(os: OsFamily) -> Option<&'static str> {
+    match os {
+        OsFamily::Ubuntu => Some("/aws/service/deeplearning/ami/x86_64/base-oss-nvidia-driver-gpu-ubuntu-22.04/latest/ami-id"),
+        OsFamily::AmazonLinux => Some("/aws/service/deeplearning/ami/x86_64/base-oss-nvidia-driver-gpu-amazon-linux-2023/latest/ami-id"),
+        OsFamily::Debian => None,
+    }
+}
+
+/// GCP Deep Learning VM image family for the OS; the driver is installed on first boot
+fn gcp_image(os: OsFamily) -> Option<&'static str> {
+    match os {
+        OsFamily::Ubuntu => Some("deeplearning-platform-release/common-cu123-ubuntu-2204-py310"),
+        OsFamily::Debian => Some("deeplearning-platform-release/common-cu121-debian-11-py310"),
+        OsFamily::AmazonLinux => None,
+    }
+}
+
+/// Put every VM in the config on the provider's GPU instance, booting an image with the NVIDIA
+/// driver. GCP instances also get the T4 attached and stop instead of migrating on host
+/// maintenance, which GPUs cannot do. Returns the changed resources.
+pub fn apply_gpu(config: &mut TerraformConfig, cloud_provider: &CloudProvider, os: OsFamily) -> Result<Vec<String>> {
+    let instance_type = gpu_instance_type(cloud_provider)
+        .ok_or_else(|| anyhow!("GPU instances are not supported on {:?}", cloud_provider))?;
+    let mut changed = Vec::new();
+    for resource in &mut config.resources {
+        let settings = &mut resource.config;
+        match resource.resource_type.as_str() {
+            "aws_instance" => {
+                let parameter = aws_image_parameter(os).ok_or_else(|| anyhow!("AWS has no GPU driver image for {:?}; use --os ubuntu", os))?;
+                settings.insert("instance_type".to_string(), json!(instance_type));
+                settings.insert("ami".to_string(), json!(format!("resolve:ssm:{}", parameter)));
+            }
+            "google_compute_instance" => {
+                let image = gcp_image(os).ok_or_else(|| anyhow!("{:?} images are not available on GCP", os))?;
+                settings.insert("machine_type".to_string(), json!(instance_type));
+                settings.insert("guest_accelerator".to_string(), json!([{"type": GCP_ACCELERATOR, "count": 1}]));
+                settings.insert("scheduling".to_string(), json!({"on_host_maintenance": "TERMINATE", "automatic_restart": true}));
+                object_entry(settings.entry("metadata".to_string()).or_insert_with(|| json!({})))
+                    .insert("install-nvidia-driver".to_string(), json!("True"));
+                let boot_disk = object_entry(settings.entry("boot_disk".to_string()).or_insert_with(|| json!({})));
+                object_entry(boot_disk.entry("initialize_params".to_string()).or_insert_with(|| json!({})))
+                    .insert("image".to_string(), json!(image));
+            }
+            _ => continue,
+        }
+        changed.push(format!("{}.{}", resource.resource_type, resource.name));
+    }
+    Ok(changed)
+}
+
+/// The object a block holds, replacing anything else; a block given as a list uses its first item
+fn object_entry(value: &mut Value) -> &mut serde_json::Map<String, Value> {
+    if let Value::Array(items) = value {
+        let first = items.drain(..).next().unwrap_or_else(|| json!({}));
+        *value = first;
+    }
+    if !value.is_object() {
+        *value = json!({});
+    }
+    value.as_object_mut().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::TerraformResource;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_apply_gpu_instances() {
+        let resource = |resource_type: &str, config: Value| TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: "app".to_string(),
+            config: serde_json::from_value(config).unwrap(),
+        };
+        let mut config = TerraformConfig {
+            provider: "gcp".to_string(),
+            resources: vec![
+                resource("google_compute_instance", json!({"machine_type": "e2-micro", "metadata": {"enable-oslogin": "TRUE"}, "boot_disk": [{"initialize_params": {"image": "debian-cloud/debian-12", "size": 20}}]})),
+                resource("google_compute_firewall", json!({})),
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        assert_eq!(apply_gpu(&mut config, &CloudProvider::GCP, OsFamily::Debian).unwrap(), vec!["google_compute_instance.app"]);
+        let instance = &config.resources[0].config;
+        assert_eq!(instance["machine_type"], "n1-standard-4");
+        assert_eq!(instance["guest_accelerator"], json!([{"type": "nvidia-tesla-t4", "count": 1}]));
+        assert_eq!(instance["scheduling"]["on_host_maintenance"], "TERMINATE");
+        assert_eq!(instance["metadata"], json!({"enable-oslogin": "TRUE", "install-nvidia-driver": "True"}));
+        assert_eq!(instance["boot_disk"]["initialize_params"], json!({"image": "deeplearning-platform-release/common-cu121-debian-11-py310", "size": 20}));
+
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![resource("aws_instance", json!({"instance_type": "t3.micro"}))],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        assert!(apply_gpu(&mut config, &CloudProvider::AWS, OsFamily::Debian).is_err());
+        apply_gpu(&mut config, &CloudProvider::AWS, OsFamily::Ubuntu).unwrap();
+        assert_eq!(config.resources[0].config["instance_type"], "g4dn.xlarge");
+        assert!(config.resources[0].config["ami"].as_str().unwrap().contains("nvidia-driver-gpu-ubuntu"));
+        assert!(apply_gpu(&mut config, &CloudProvider::DigitalOcean, OsFamily::Ubuntu).is_err());
+        assert_eq!(monthly_cost("g4dn.xlarge"), Some(383.98));
+        assert_eq!(monthly_cost("t3.micro"), None);
+        assert!(prompt_requirements(&CloudProvider::GCP).contains("n1-standard-4 instance with one nvidia-tesla-t4"));
+        assert!(prompt_requirements(&CloudProvider::Azure).is_empty());
+    }
+}
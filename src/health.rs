@@ -0,0 +1,344 @@
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::http;
+use crate::infrastructure::TerraformConfig;
+
+/// Default time to wait for a new instance to become healthy
+pub const DEFAULT_HEALTH_TIMEOUT: Duration = Duration::from_secs(300);
+/// Default delay between health polls
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+/// Longest delay between health polls once the backoff has grown
+const MAX_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a deploy of `infrastructure_type` waits for the app to become healthy without
+/// `--wait`: VMs install and build the app in their startup script, while serverless functions
+/// and static sites serve almost as soon as they are published
+pub fn default_wait(infrastructure_type: &str) -> Duration {
+    match infrastructure_type {
+        "Serverless" => Duration::from_secs(60),
+        "GitHub Pages" | "Netlify" | "StaticSite" => Duration::from_secs(120),
+        "ContainerService" => Duration::from_secs(180),
+        "Kubernetes" => Duration::from_secs(600),
+        _ => DEFAULT_HEALTH_TIMEOUT,
+    }
+}
+
+/// Poll `url`, following redirects, until it answers with a 2xx/3xx status or `timeout`
+/// elapses. The delay between polls starts at `interval` and doubles up to 30s.
+/// Returns how long the app took to become healthy.
+pub async fn wait_until_healthy(url: &str, timeout: Duration, interval: Duration) -> Result<Duration> {
+    info!("🩺 Waiting up to {}s for {} to become healthy...", timeout.as_secs(), url);
+    let started = Instant::now();
+    let mut delay = interval;
+
+    loop {
+        match http::client().get(url).timeout(Duration::from_secs(10)).send().await {
+            Ok(response) if is_healthy_status(response.status()) => {
+                let elapsed = started.elapsed();
+                info!("✅ {} is healthy after {}s", url, elapsed.as_secs());
+                return Ok(elapsed);
+            }
+            Ok(response) => debug!("Health check {} returned {}", url, response.status()),
+            Err(e) => debug!("Health check {} failed: {}", url, e),
+        }
+
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(anyhow!(
+                "{} did not become healthy within {}s",
+                url,
+                timeout.as_secs()
+            ));
+        }
+        // The last poll lands on the deadline
+        tokio::time::sleep(delay.min(remaining)).await;
+        delay = backoff(delay);
+    }
+}
+
+/// The delay after `delay`: doubled, up to 30s unless it already started above that
+fn backoff(delay: Duration) -> Duration {
+    (delay * 2).min(MAX_HEALTH_INTERVAL.max(delay))
+}
+
+/// `url` with the app's `port` when it names none, for addresses that reach the instance
+/// directly rather than through a load balancer
+pub fn url_with_port(url: &str, port: u16) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+    if parsed.port().is_some() || parsed.port_or_known_default() == Some(port) || parsed.set_port(Some(port)).is_err() {
+        return url.to_string();
+    }
+    let with_port = parsed.to_string();
+    // Url adds a root path the address did not have
+    match url.ends_with('/') {
+        true => with_port,
+        false => with_port.trim_end_matches('/').to_string(),
+    }
+}
+
+/// Request `url` `requests` times so caches, connection pools or models are loaded before
+/// the deploy is reported done. The first request waits up to `timeout` for the app to come up,
+/// retried every `interval`.
+pub async fn warm_up(url: &str, requests: u32, timeout: Duration, interval: Duration) -> Result<()> {
+    wait_until_healthy(url, timeout, interval).await?;
+    
+    for request in 2..=requests {
+        let response = http::client()
+            .get(url)
+            .timeout(Duration::from_secs(60))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Warmup request {}/{} to {} failed: {}", request, requests, url, e))?;
+        if !is_healthy_status(response.status()) {
+            return Err(anyhow!(
+                "Warmup request {}/{} to {} returned {}",
+                request,
+                requests,
+                url,
+                response.status()
+            ));
+        }
+    }
+    
+    info!("🔥 {} warmed up with {} request(s)", url, requests.max(1));
+    Ok(())
+}
+
+/// Health check of the generated load balancer (target group, health check, probe)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    /// `None` until resolved: `--health-path`, else the detected endpoint, else `/`
+    pub path: Option<String>,
+    pub interval_secs: u32,
+    pub healthy_threshold: u32,
+    pub unhealthy_threshold: u32,
+    /// Status codes counted as healthy, e.g. `200` or `200-399`
+    pub codes: String,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval_secs: 30,
+            healthy_threshold: 3,
+            unhealthy_threshold: 3,
+            codes: "200-399".to_string(),
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn new(path: Option<String>, interval_secs: u32, codes: &str) -> Result<Self> {
+        if !(5..=300).contains(&interval_secs) {
+            return Err(anyhow!("--health-interval must be between 5 and 300 seconds, got {}", interval_secs));
+        }
+        let codes_regex = Regex::new(r"^\d{3}(-\d{3})?(,\d{3}(-\d{3})?)*$").unwrap();
+        if !codes_regex.is_match(codes) {
+            return Err(anyhow!("Invalid --health-codes: {}. Use codes and ranges, e.g. 200 or 200-399", codes));
+        }
+        Ok(Self {
+            path: path.map(|path| format!("/{}", path.trim_start_matches('/'))),
+            interval_secs,
+            codes: codes.to_string(),
+            ..Default::default()
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or("/")
+    }
+
+    /// Time allowed per probe; must be shorter than the interval
+    fn timeout_secs(&self) -> u32 {
+        (self.interval_secs / 2).clamp(2, 10)
+    }
+
+    /// Configure every load balancer health check in `config`. Returns the `type.name` of each
+    /// resource that was configured.
+    pub fn apply(&self, config: &mut TerraformConfig) -> Vec<String> {
+        let mut configured = Vec::new();
+        for resource in &mut config.resources {
+            let settings = match resource.resource_type.as_str() {
+                "aws_lb_target_group" => {
+                    let mut health_check = resource.config.get("health_check").cloned().unwrap_or_default();
+                    merge(&mut health_check, serde_json::json!({
+                        "enabled": true,
+                        "path": self.path(),
+                        "interval": self.interval_secs,
+                        "timeout": self.timeout_secs(),
+                        "healthy_threshold": self.healthy_threshold,
+                        "unhealthy_threshold": self.unhealthy_threshold,
+                        "matcher": self.codes,
+                    }));
+                    serde_json::json!({ "health_check": health_check })
+                }
+                // GCP health checks accept any 2xx/3xx; there is no status code setting
+                "google_compute_health_check" => {
+                    let mut http_check = resource.config.get("http_health_check").cloned().unwrap_or_default();
+                    merge(&mut http_check, serde_json::json!({ "request_path": self.path() }));
+                    serde_json::json!({
+                        "check_interval_sec": self.interval_secs,
+                        "timeout_sec": self.timeout_secs(),
+                        "healthy_threshold": self.healthy_threshold,
+                        "unhealthy_threshold": self.unhealthy_threshold,
+                        "http_health_check": http_check,
+                    })
+                }
+                "google_compute_http_health_check" => serde_json::json!({
+                    "request_path": self.path(),
+                    "check_interval_sec": self.interval_secs,
+                    "timeout_sec": self.timeout_secs(),
+                    "healthy_threshold": self.healthy_threshold,
+                    "unhealthy_threshold": self.unhealthy_threshold,
+                }),
+                "azurerm_lb_probe" => serde_json::json!({
+                    "protocol": "Http",
+                    "request_path": self.path(),
+                    "interval_in_seconds": self.interval_secs,
+                    "number_of_probes": self.unhealthy_threshold,
+                }),
+                _ => continue,
+            };
+            if let serde_json::Value::Object(settings) = settings {
+                resource.config.extend(settings);
+            }
+            configured.push(format!("{}.{}", resource.resource_type, resource.name));
+        }
+        configured
+    }
+}
+
+impl std::fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GET {} every {}s, healthy after {} and unhealthy after {} checks, status {}",
+            self.path(),
+            self.interval_secs,
+            self.healthy_threshold,
+            self.unhealthy_threshold,
+            self.codes
+        )
+    }
+}
+
+/// Overwrite `target`'s keys with `values`, keeping its other keys (e.g. the probe port)
+fn merge(target: &mut serde_json::Value, values: serde_json::Value) {
+    if !target.is_object() {
+        *target = serde_json::json!({});
+    }
+    if let (Some(target), serde_json::Value::Object(values)) = (target.as_object_mut(), values) {
+        target.extend(values);
+    }
+}
+
+/// `path_or_url` as is when it is a full URL, otherwise joined onto `base_url`
+pub fn resolve_url(base_url: &str, path_or_url: &str) -> String {
+    if path_or_url.contains("://") {
+        path_or_url.to_string()
+    } else {
+        health_url(base_url, path_or_url)
+    }
+}
+
+/// Join a base URL and a health path without doubling slashes
+pub fn health_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+fn is_healthy_status(status: reqwest::StatusCode) -> bool {
+    status.is_success() || status.is_redirection()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_url() {
+        assert_eq!(health_url("http://1.2.3.4:5000", "/"), "http://1.2.3.4:5000/");
+        assert_eq!(health_url("http://1.2.3.4:5000/", "/health"), "http://1.2.3.4:5000/health");
+        assert_eq!(health_url("http://1.2.3.4:5000", "healthz"), "http://1.2.3.4:5000/healthz");
+
+        assert_eq!(resolve_url("http://1.2.3.4:5000", "/warmup"), "http://1.2.3.4:5000/warmup");
+        assert_eq!(resolve_url("http://1.2.3.4:5000", "https://app.example.com/ready"), "https://app.example.com/ready");
+    }
+
+    #[test]
+    fn test_apply_health_check() {
+        let mut config: TerraformConfig = serde_json::from_value(serde_json::json!({
+            "provider": "aws",
+            "resources": [
+                {"resource_type": "aws_lb_target_group", "name": "app", "config": {"port": 8000, "health_check": {"port": "traffic-port", "path": "/"}}},
+                {"resource_type": "google_compute_health_check", "name": "app", "config": {"http_health_check": {"port": 8000}}},
+                {"resource_type": "aws_instance", "name": "app", "config": {}}
+            ],
+            "variables": {},
+            "outputs": {}
+        }))
+        .unwrap();
+        let check = HealthCheck::new(Some("healthz".to_string()), 60, "200,204").unwrap();
+        assert_eq!(check.apply(&mut config), vec!["aws_lb_target_group.app", "google_compute_health_check.app"]);
+
+        let target_group = &config.resources[0].config["health_check"];
+        assert_eq!(target_group["path"], "/healthz");
+        assert_eq!(target_group["interval"], 60);
+        assert_eq!(target_group["timeout"], 10);
+        assert_eq!(target_group["matcher"], "200,204");
+        assert_eq!(target_group["port"], "traffic-port");
+        let gcp = &config.resources[1].config;
+        assert_eq!(gcp["check_interval_sec"], 60);
+        assert_eq!(gcp["http_health_check"], serde_json::json!({"port": 8000, "request_path": "/healthz"}));
+        assert!(config.resources[2].config.is_empty());
+
+        assert!(HealthCheck::new(None, 2, "200").is_err());
+        assert!(HealthCheck::new(None, 30, "ok").is_err());
+        assert_eq!(HealthCheck::default().path(), "/");
+    }
+
+    #[test]
+    fn test_is_healthy_status() {
+        assert!(is_healthy_status(reqwest::StatusCode::OK));
+        assert!(is_healthy_status(reqwest::StatusCode::FOUND));
+        assert!(!is_healthy_status(reqwest::StatusCode::BAD_GATEWAY));
+    }
+
+    #[test]
+    fn test_default_wait() {
+        assert_eq!(default_wait("SingleVM"), DEFAULT_HEALTH_TIMEOUT);
+        assert_eq!(default_wait("MultiRegion(us-east-1,eu-west-1)"), DEFAULT_HEALTH_TIMEOUT);
+        assert!(default_wait("Netlify") < default_wait("SingleVM"));
+        assert!(default_wait("Kubernetes") > default_wait("SingleVM"));
+    }
+
+    #[test]
+    fn test_backoff_and_url_port() {
+        assert_eq!(backoff(Duration::from_secs(5)), Duration::from_secs(10));
+        assert_eq!(backoff(Duration::from_secs(20)), MAX_HEALTH_INTERVAL);
+        assert_eq!(backoff(Duration::from_secs(60)), Duration::from_secs(60), "a longer --poll-interval is kept");
+
+        assert_eq!(url_with_port("http://203.0.113.10", 5000), "http://203.0.113.10:5000");
+        assert_eq!(url_with_port("http://ec2.example.com/", 3000), "http://ec2.example.com:3000/");
+        assert_eq!(url_with_port("http://203.0.113.10:8080", 5000), "http://203.0.113.10:8080");
+        assert_eq!(url_with_port("http://203.0.113.10", 80), "http://203.0.113.10");
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_healthy_times_out() {
+        // Nothing listens on port 1
+        let result = wait_until_healthy(
+            "http://127.0.0.1:1/",
+            Duration::from_millis(300),
+            Duration::from_millis(100),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::infrastructure::DeploymentResult;
+use crate::nlp::CloudProvider;
+use crate::stack::StackRecord;
+
+/// Where a deployment is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentState {
+    /// Terraform files generated, nothing provisioned yet
+    Planned,
+    /// `terraform apply` is running
+    Provisioning,
+    Running,
+    Failed,
+    Destroyed,
+}
+
+impl DeploymentState {
+    /// Whether a deployment in this state may move to `next`. A failed deployment can be
+    /// provisioned again; a running one only destroyed; a destroyed one is final.
+    pub fn can_transition_to(self, next: DeploymentState) -> bool {
+        use DeploymentState::*;
+        matches!(
+            (self, next),
+            (Planned, Provisioning)
+                | (Provisioning, Running)
+                | (Provisioning, Failed)
+                | (Failed, Provisioning)
+                | (Planned | Running | Failed, Destroyed)
+        )
+    }
+}
+
+impl fmt::Display for DeploymentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeploymentState::Planned => "planned",
+            DeploymentState::Provisioning => "provisioning",
+            DeploymentState::Running => "running",
+            DeploymentState::Failed => "failed",
+            DeploymentState::Destroyed => "destroyed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One deployment in the index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Deployment id, as shown in the deploy's log lines
+    pub id: String,
+    pub repository: String,
+    pub cloud_provider: CloudProvider,
+    pub state: DeploymentState,
+    /// Directory holding the deployment's Terraform files and state
+    pub deployment_dir: PathBuf,
+    pub created_at: String,
+    pub updated_at: String,
+    /// What was provisioned, e.g. `SingleVM`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment_type: Option<String>,
+    /// Where the app is reached, once running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Region the deployment was pinned to; `None` means the provider's configured default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// `terraform output` values once running, e.g. `instance_ip`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, String>,
+    /// Why the last provisioning or teardown failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Process running the deploy in the background (`deploy --detach`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Where the background deploy writes its output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+    /// SHA of the source commit that was deployed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// SSH port of the instance (`--ssh-port`) when it is not 22
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_port: Option<u16>,
+    /// Bastion host SSH connections go through (`--bastion`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bastion: Option<String>,
+}
+
+impl IndexEntry {
+    /// A new `Planned` entry
+    pub fn new(id: &str, repository: &str, cloud_provider: &CloudProvider, deployment_dir: &Path) -> Self {
+        let now = Utc::now().to_rfc3339();
+        IndexEntry {
+            id: id.to_string(),
+            repository: repository.to_string(),
+            cloud_provider: cloud_provider.clone(),
+            state: DeploymentState::Planned,
+            deployment_dir: deployment_dir.to_path_buf(),
+            created_at: now.clone(),
+            updated_at: now,
+            deployment_type: None,
+            url: None,
+            region: None,
+            outputs: BTreeMap::new(),
+            error: None,
+            pid: None,
+            log_file: None,
+            commit: None,
+            ssh_port: None,
+            bastion: None,
+        }
+    }
+
+    /// `ssh` command reaching the instance on its recorded port and through its bastion, once
+    /// Terraform has reported the instance's IP
+    pub fn ssh_command(&self) -> Option<String> {
+        let host = self.outputs.get("instance_ip").or_else(|| self.outputs.get("public_ip"))?;
+        let mut command = vec!["ssh".to_string()];
+        if let Some(port) = self.ssh_port {
+            command.push(format!("-p {}", port));
+        }
+        if let Some(bastion) = &self.bastion {
+            command.push(format!("-J {}", bastion));
+        }
+        command.push(host.clone());
+        Some(command.join(" "))
+    }
+}
+
+/// Index of deployments: one JSON file per deployment in `~/.autodeployment/deployments`
+pub struct Index {
+    dir: PathBuf,
+}
+
+impl Index {
+    pub fn open() -> Result<Self> {
+        let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+        Ok(Self::at(home_dir.join(".autodeployment").join("deployments")))
+    }
+
+    pub fn at(dir: PathBuf) -> Self {
+        Index { dir }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Output of the deployment's background run, next to its entry
+    pub fn log_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.log", id))
+    }
+
+    pub fn load(&self, id: &str) -> Result<IndexEntry> {
+        let path = self.path(id);
+        let contents = fs::read_to_string(&path).map_err(|e| anyhow!("No deployment {} in the index: {}", id, e))?;
+        serde_json::from_str(&contents).map_err(|e| anyhow!("Invalid index entry {}: {}", path.display(), e))
+    }
+
+    /// Every readable entry, in no particular order. Files that fail to parse are skipped
+    /// with a warning so one corrupt entry doesn't hide the rest.
+    pub fn list(&self) -> Result<Vec<IndexEntry>> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let parsed = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|contents| serde_json::from_str(&contents).map_err(anyhow::Error::from));
+            match parsed {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("⚠️ Skipping unreadable deployment index entry {}: {}", path.display(), e),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Write the entry through a temporary file, so a crash never leaves it half-written
+    pub fn save(&self, entry: &IndexEntry) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut temp_file = tempfile::NamedTempFile::new_in(&self.dir)?;
+        temp_file.write_all(serde_json::to_string_pretty(entry)?.as_bytes())?;
+        temp_file
+            .persist(self.path(&entry.id))
+            .map_err(|e| anyhow!("Failed to write index entry {}: {}", entry.id, e.error))?;
+        Ok(())
+    }
+
+    /// Move the entry to `next` and persist it, refusing transitions the lifecycle doesn't allow
+    pub fn transition(&self, entry: &mut IndexEntry, next: DeploymentState) -> Result<()> {
+        if !entry.state.can_transition_to(next) {
+            return Err(anyhow!("Deployment {} is {}; it cannot become {}", entry.id, entry.state, next));
+        }
+        entry.state = next;
+        entry.updated_at = Utc::now().to_rfc3339();
+        if next != DeploymentState::Failed {
+            entry.error = None;
+        }
+        self.save(entry)
+    }
+
+    /// `transition`, for callers that treat the index as bookkeeping: a failed write is logged
+    /// rather than failing the deploy
+    pub fn record(&self, entry: &mut IndexEntry, next: DeploymentState) {
+        if let Err(e) = self.transition(entry, next) {
+            warn!("⚠️ Failed to record deployment {} as {}: {}", entry.id, next, e);
+        }
+    }
+
+    /// Record how provisioning ended: running with the Terraform outputs, or failed with the error
+    pub fn record_applied(&self, entry: &mut IndexEntry, applied: &Result<DeploymentResult>) {
+        match applied {
+            Ok(result) => {
+                entry.url = Some(result.url.clone());
+                entry.outputs = result.outputs.clone().into_iter().collect();
+                self.record(entry, DeploymentState::Running);
+            }
+            Err(e) => {
+                entry.error = Some(e.to_string());
+                self.record(entry, DeploymentState::Failed);
+            }
+        }
+    }
+
+    /// Delete the entry of a deployment that no longer exists
+    pub fn remove(&self, id: &str) -> Result<()> {
+        fs::remove_file(self.path(id)).map_err(|e| anyhow!("Failed to remove deployment {} from the index: {}", id, e))
+    }
+
+    /// Stacks (`deploy --all`) are kept in a subdirectory, so `list` only sees deployments
+    fn stack_path(&self, id: &str) -> PathBuf {
+        self.dir.join("stacks").join(format!("{}.json", id))
+    }
+
+    /// Write the stack's record under its id, returning where it went
+    pub fn save_stack(&self, record: &StackRecord) -> Result<PathBuf> {
+        let path = self.stack_path(&record.id);
+        let dir = path.parent().expect("stack path has a directory");
+        fs::create_dir_all(dir)?;
+        let mut temp_file = tempfile::NamedTempFile::new_in(dir)?;
+        temp_file.write_all(serde_json::to_string_pretty(record)?.as_bytes())?;
+        temp_file
+            .persist(&path)
+            .map_err(|e| anyhow!("Failed to write stack {}: {}", record.id, e.error))?;
+        Ok(path)
+    }
+
+    /// The stack with this id, or `None` when `id` is not a stack
+    pub fn load_stack(&self, id: &str) -> Result<Option<StackRecord>> {
+        let path = self.stack_path(id);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)?;
+        serde_json::from_str(&contents).map(Some).map_err(|e| anyhow!("Invalid stack record {}: {}", path.display(), e))
+    }
+
+    pub fn remove_stack(&self, id: &str) -> Result<()> {
+        fs::remove_file(self.stack_path(id)).map_err(|e| anyhow!("Failed to remove stack {} from the index: {}", id, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle_transitions() {
+        use DeploymentState::*;
+        assert!(Planned.can_transition_to(Provisioning));
+        assert!(Provisioning.can_transition_to(Failed));
+        assert!(Failed.can_transition_to(Provisioning));
+        assert!(Running.can_transition_to(Destroyed));
+        assert!(!Running.can_transition_to(Provisioning), "a running deployment cannot be resumed");
+        assert!(!Planned.can_transition_to(Running));
+        assert!(!Destroyed.can_transition_to(Provisioning));
+    }
+
+    #[test]
+    fn test_ssh_command_uses_recorded_port_and_bastion() {
+        let mut entry = IndexEntry::new("abcd1234", "https://github.com/org/app", &CloudProvider::AWS, Path::new("/tmp/deployment_1"));
+        assert_eq!(entry.ssh_command(), None);
+        entry.outputs.insert("instance_ip".to_string(), "10.0.1.5".to_string());
+        assert_eq!(entry.ssh_command().as_deref(), Some("ssh 10.0.1.5"));
+        entry.ssh_port = Some(2222);
+        entry.bastion = Some("ops@bastion.example.com:2200".to_string());
+        assert_eq!(entry.ssh_command().as_deref(), Some("ssh -p 2222 -J ops@bastion.example.com:2200 10.0.1.5"));
+    }
+
+    #[test]
+    fn test_index_persists_transitions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index = Index::at(temp_dir.path().join("deployments"));
+        let mut entry = IndexEntry::new("abcd1234", "https://github.com/org/app", &CloudProvider::AWS, Path::new("/tmp/deployment_1"));
+        index.save(&entry).unwrap();
+
+        index.transition(&mut entry, DeploymentState::Provisioning).unwrap();
+        entry.error = Some("quota exceeded".to_string());
+        index.transition(&mut entry, DeploymentState::Failed).unwrap();
+        assert_eq!(index.load("abcd1234").unwrap(), entry);
+
+        index.transition(&mut entry, DeploymentState::Provisioning).unwrap();
+        index.transition(&mut entry, DeploymentState::Running).unwrap();
+        let err = index.transition(&mut entry, DeploymentState::Provisioning).unwrap_err();
+        assert!(err.to_string().contains("is running; it cannot become provisioning"));
+        let loaded = index.load("abcd1234").unwrap();
+        assert_eq!(loaded.state, DeploymentState::Running);
+        assert_eq!(loaded.error, None);
+    }
+
+    #[test]
+    fn test_index_keeps_outputs_until_removed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index = Index::at(temp_dir.path().join("deployments"));
+        let mut entry = IndexEntry::new("ef567890", "https://github.com/org/app", &CloudProvider::GCP, Path::new("/tmp/deployment_2"));
+        entry.region = Some("europe-west1".to_string());
+        entry.outputs.insert("instance_ip".to_string(), "203.0.113.7".to_string());
+        index.save(&entry).unwrap();
+        assert_eq!(index.load("ef567890").unwrap(), entry);
+
+        // Entries written before outputs were recorded still load
+        fs::write(
+            temp_dir.path().join("deployments/old.json"),
+            r#"{"id": "old", "repository": "r", "cloud_provider": "AWS", "state": "running", "deployment_dir": "/tmp/d", "created_at": "", "updated_at": ""}"#,
+        )
+        .unwrap();
+        assert!(index.load("old").unwrap().outputs.is_empty());
+
+        index.remove("ef567890").unwrap();
+        assert!(index.load("ef567890").is_err());
+    }
+
+    #[test]
+    fn test_list_skips_corrupt_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index = Index::at(temp_dir.path().join("deployments"));
+        assert!(index.list().unwrap().is_empty(), "no index directory yet");
+
+        index.save(&IndexEntry::new("abcd1234", "https://github.com/org/app", &CloudProvider::AWS, Path::new("/tmp/d"))).unwrap();
+        fs::write(temp_dir.path().join("deployments/broken.json"), "{\"id\": ").unwrap();
+        fs::write(temp_dir.path().join("deployments/notes.txt"), "not an entry").unwrap();
+        let entries = index.list().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "abcd1234");
+    }
+
+    #[test]
+    fn test_stacks_are_indexed_apart_from_deployments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index = Index::at(temp_dir.path().join("deployments"));
+        assert!(index.load_stack("5a7c0001").unwrap().is_none());
+
+        index.save(&IndexEntry::new("abcd1234", "https://github.com/org/app", &CloudProvider::AWS, Path::new("/tmp/d"))).unwrap();
+        let record = StackRecord {
+            id: "5a7c0001".to_string(),
+            repository: "https://github.com/org/app".to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            members: Vec::new(),
+        };
+        index.save_stack(&record).unwrap();
+        assert_eq!(index.load_stack("5a7c0001").unwrap().unwrap().repository, record.repository);
+        assert_eq!(index.list().unwrap().len(), 1, "a stack is not a deployment");
+
+        index.remove_stack("5a7c0001").unwrap();
+        assert!(index.load_stack("5a7c0001").unwrap().is_none());
+    }
+}
@@ -1,25 +1,319 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::time::Instant;
 use which::which;
 use chrono::Utc;
-use log::info;
+use log::{info, warn};
 
-use crate::nlp::{ApplicationType, CloudProvider, DeploymentRequirements, ScalingRequirements};
-use crate::repository::RepositoryAnalysis;
+use crate::nlp::{ApplicationType, CloudProvider, DatabaseType, DeploymentRequirements, ScalingRequirements};
+use crate::repository::{self, RepositoryAnalysis, StaticServer, StaticServing};
 use crate::ai_nlp;
+use crate::bootstrap;
+use crate::correlation;
+use crate::cost::{CostCategory, CostEstimate};
 use crate::credentials::CloudCredentials;
+use crate::database;
+use crate::disk;
+use crate::dockerfile::{self, ContainerBuild};
+use crate::gpu;
+use crate::egress::EgressPolicy;
+use crate::health::HealthCheck;
+use crate::index::{DeploymentState, Index, IndexEntry};
+use crate::interpolation;
+use crate::network::ExistingNetwork;
+use crate::policy::{self, Policy};
+use crate::protection;
+use crate::redact::redact_secrets_with;
+use crate::secrets::SecretsSource;
+use crate::settings;
+use crate::ssh::{self, AuthorizedKey};
+use crate::tags;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InfrastructureDecision {
     pub deployment_type: DeploymentType,
     pub instance_type: String,
     pub terraform_config: TerraformConfig,
-    pub estimated_cost: f64,
+    pub estimated_cost: CostEstimate,
     pub justification: String,
+    /// Private registries the app's images are pulled from
+    #[serde(default)]
+    pub required_registries: Vec<String>,
+    /// Ports the app is reached on; empty for background workers
+    #[serde(default)]
+    pub exposed_ports: Vec<u16>,
+    /// Health check applied to the generated load balancer, if there is one
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+    /// `--name` of the deployment; its directory is `deployment_<name>`
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Root disk size in GB of the deployment's VMs
+    #[serde(default)]
+    pub disk_size_gb: Option<u32>,
+    /// SHA of the source commit being deployed
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Dockerfile generated for a container target whose repository has none
+    #[serde(default)]
+    pub dockerfile: Option<String>,
+}
+
+/// LocalStack's edge endpoint, which `--localstack` points the AWS provider at
+pub const LOCALSTACK_ENDPOINT: &str = "http://localhost:4566";
+
+/// Endpoint every AWS service is reached at instead of AWS (`--endpoint`, `--localstack`)
+static AWS_ENDPOINT: OnceLock<String> = OnceLock::new();
+
+/// AWS services the generated configurations use, each pointed at the custom endpoint
+const AWS_ENDPOINT_SERVICES: &[&str] = &[
+    "apigateway", "autoscaling", "cloudwatch", "docdb", "dynamodb", "ec2", "ecr", "ecs", "elasticache",
+    "elbv2", "iam", "lambda", "logs", "rds", "route53", "s3", "secretsmanager", "sns", "sqs", "ssm", "sts",
+];
+
+/// Access key and secret sent to a custom endpoint; emulators accept any, and real
+/// credentials never leave for an endpoint that is not AWS
+const DUMMY_AWS_CREDENTIAL: &str = "test";
+
+/// Send all AWS calls to `endpoint`, e.g. LocalStack's, with dummy credentials
+pub fn set_aws_endpoint(endpoint: &str) -> Result<()> {
+    if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+        return Err(anyhow!("--endpoint {} must be an http:// or https:// URL", endpoint));
+    }
+    AWS_ENDPOINT
+        .set(endpoint.trim_end_matches('/').to_string())
+        .map_err(|_| anyhow!("The AWS endpoint is already set"))
+}
+
+/// The custom AWS endpoint, if one is set
+pub fn aws_endpoint() -> Option<&'static str> {
+    AWS_ENDPOINT.get().map(String::as_str)
+}
+
+/// Arguments of the `aws` provider block; with `endpoint`, every service is sent there with
+/// dummy credentials and the checks an emulator cannot answer are skipped
+fn aws_provider_arguments(endpoint: Option<&str>) -> serde_json::Map<String, serde_json::Value> {
+    let mut arguments = serde_json::Map::new();
+    arguments.insert("region".to_string(), json!("var.region"));
+    if let Some(endpoint) = endpoint {
+        arguments.insert("access_key".to_string(), json!(DUMMY_AWS_CREDENTIAL));
+        arguments.insert("secret_key".to_string(), json!(DUMMY_AWS_CREDENTIAL));
+        arguments.insert("skip_credentials_validation".to_string(), json!(true));
+        arguments.insert("skip_metadata_api_check".to_string(), json!(true));
+        arguments.insert("skip_requesting_account_id".to_string(), json!(true));
+        arguments.insert("s3_use_path_style".to_string(), json!(true));
+        let endpoints: serde_json::Map<String, serde_json::Value> =
+            AWS_ENDPOINT_SERVICES.iter().map(|service| (service.to_string(), json!(endpoint))).collect();
+        arguments.insert("endpoints".to_string(), serde_json::Value::Object(endpoints));
+    }
+    arguments
+}
+
+/// Instance resources and the argument holding their size
+const INSTANCE_SIZE_ARGUMENTS: &[(&str, &str)] = &[
+    ("aws_instance", "instance_type"),
+    ("aws_launch_template", "instance_type"),
+    ("google_compute_instance", "machine_type"),
+    ("google_compute_instance_template", "machine_type"),
+    ("azurerm_linux_virtual_machine", "size"),
+    ("digitalocean_droplet", "size"),
+];
+
+impl InfrastructureDecision {
+    /// Size every instance in the config as `instance_type`, e.g. `t3.small`.
+    /// Returns the resources that were changed.
+    pub fn set_instance_type(&mut self, instance_type: &str) -> Vec<String> {
+        let mut changed = Vec::new();
+        for resource in &mut self.terraform_config.resources {
+            let Some((_, argument)) = INSTANCE_SIZE_ARGUMENTS.iter().find(|(resource_type, _)| *resource_type == resource.resource_type) else {
+                continue;
+            };
+            resource.config.insert(argument.to_string(), json!(instance_type));
+            changed.push(format!("{}.{}", resource.resource_type, resource.name));
+        }
+        // Only a single VM's compute line is priced by its instance type
+        let previous = format!("{} ", self.instance_type);
+        let priced = self.estimated_cost.breakdown.iter().position(|item| {
+            item.category == CostCategory::Compute && item.description.starts_with(&previous)
+        });
+        if let Some(index) = priced {
+            match instance_cost(instance_type) {
+                Some(cost) => self.estimated_cost.replace(index, instance_item(instance_type), cost),
+                None => {
+                    warn!("⚠️ No price is known for {}; the estimate still prices it as {}", instance_type, self.instance_type);
+                    let cost = self.estimated_cost.breakdown[index].monthly;
+                    let description = format!("{} (priced as {})", instance_item(instance_type), self.instance_type);
+                    self.estimated_cost.replace(index, description, cost);
+                }
+            }
+        }
+        self.instance_type = instance_type.to_string();
+        changed
+    }
+
+    /// Open TCP `port` to anywhere in the firewalls that already open the app's ports (or SSH).
+    /// Returns the resources that were changed.
+    pub fn open_port(&mut self, port: u16) -> Vec<String> {
+        let mut app_ports = self.exposed_ports.clone();
+        app_ports.push(22);
+        let mut changed = Vec::new();
+        for resource in &mut self.terraform_config.resources {
+            let opened = match resource.resource_type.as_str() {
+                "aws_security_group" if opens_any(resource.config.get("ingress"), &app_ports) => {
+                    let rule = json!({
+                        "from_port": port,
+                        "to_port": port,
+                        "protocol": "tcp",
+                        "cidr_blocks": ["0.0.0.0/0"],
+                        "description": format!("port {}", port),
+                        "ipv6_cidr_blocks": [],
+                        "prefix_list_ids": [],
+                        "security_groups": [],
+                        "self": false,
+                    });
+                    rules_array(resource.config.get_mut("ingress")).map(|rules| rules.push(rule)).is_some()
+                }
+                "google_compute_firewall" if opens_any(resource.config.get("allow"), &app_ports) => {
+                    rules_array(resource.config.get_mut("allow")).map(|rules| {
+                        let tcp = rules.iter_mut().find(|rule| rule.get("protocol").and_then(|p| p.as_str()) == Some("tcp"));
+                        match tcp.and_then(|rule| rule.get_mut("ports")).and_then(|ports| ports.as_array_mut()) {
+                            Some(ports) => ports.push(json!(port.to_string())),
+                            None => rules.push(json!({"protocol": "tcp", "ports": [port.to_string()]})),
+                        }
+                    }).is_some()
+                }
+                _ => false,
+            };
+            if opened {
+                changed.push(format!("{}.{}", resource.resource_type, resource.name));
+            }
+        }
+        if !self.exposed_ports.contains(&port) {
+            self.exposed_ports.push(port);
+        }
+        changed
+    }
+
+    /// Stop opening `port` in the firewalls. Ranges that include it are left alone, and a GCP
+    /// firewall left with nothing to allow is removed. Returns the resources that were changed.
+    pub fn close_port(&mut self, port: u16) -> Vec<String> {
+        let mut changed = Vec::new();
+        let mut emptied = Vec::new();
+        for resource in &mut self.terraform_config.resources {
+            let key = match resource.resource_type.as_str() {
+                "aws_security_group" => "ingress",
+                "google_compute_firewall" => "allow",
+                _ => continue,
+            };
+            if !opens_any(resource.config.get(key), &[port]) {
+                continue;
+            }
+            let Some(rules) = rules_array(resource.config.get_mut(key)) else { continue };
+            let port_value = json!(port.to_string());
+            rules.retain_mut(|rule| {
+                if rule.get("from_port").and_then(|p| p.as_u64()) == Some(port as u64) {
+                    return rule.get("to_port").and_then(|p| p.as_u64()) != Some(port as u64);
+                }
+                match rule.get_mut("ports").and_then(|ports| ports.as_array_mut()) {
+                    Some(ports) => {
+                        ports.retain(|allowed| *allowed != port_value && allowed.as_u64() != Some(port as u64));
+                        // No ports at all would allow every port
+                        !ports.is_empty()
+                    }
+                    None => true,
+                }
+            });
+            if rules.is_empty() && key == "allow" {
+                emptied.push(resource.name.clone());
+            }
+            changed.push(format!("{}.{}", resource.resource_type, resource.name));
+        }
+        self.terraform_config
+            .resources
+            .retain(|resource| !(resource.resource_type == "google_compute_firewall" && emptied.contains(&resource.name)));
+        self.exposed_ports.retain(|exposed| *exposed != port);
+        changed
+    }
+}
+
+/// Whether the firewall rules open any of `ports`
+fn opens_any(rules: Option<&serde_json::Value>, ports: &[u16]) -> bool {
+    rules.is_some_and(|rules| firewall_ports(rules).iter().any(|port| ports.contains(port)))
+}
+
+/// Inline firewall rules as a list, turning a single rule object into a list of one
+fn rules_array(rules: Option<&mut serde_json::Value>) -> Option<&mut Vec<serde_json::Value>> {
+    let rules = rules?;
+    if rules.is_object() {
+        *rules = json!([rules.take()]);
+    }
+    rules.as_array_mut()
+}
+
+/// Deployment shape and price of the app on one provider, for comparing clouds
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderEstimate {
+    pub cloud_provider: CloudProvider,
+    pub deployment_type: DeploymentType,
+    pub instance_type: String,
+    pub estimated_cost: CostEstimate,
+    /// Whether the app or a reverse proxy serves static files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub static_serving: Option<StaticServing>,
+    /// nginx block serving the static files, when the proxy should serve them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nginx_location: Option<String>,
+    /// Regions of a `--regions` deployment; the cost is summed across them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<String>,
+    /// Root disk size in GB, priced into the estimate; `None` without a VM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_size_gb: Option<u32>,
+    /// Startup script the VM would run, secrets masked; `None` without a VM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_script: Option<String>,
+}
+
+/// Providers `plan --compare-providers` prices the app on
+pub const COMPARED_PROVIDERS: [CloudProvider; 3] = [CloudProvider::AWS, CloudProvider::GCP, CloudProvider::Azure];
+
+/// Syntax the generated Terraform files are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TerraformFormat {
+    /// `main.tf`, `variables.tf`, `outputs.tf`; easiest to read and edit
+    #[default]
+    Hcl,
+    /// `main.tf.json` and friends, serialized straight from the `TerraformConfig`
+    Json,
+}
+
+impl TerraformFormat {
+    /// Name of the file holding the provider and resources
+    pub fn main_file(&self) -> &'static str {
+        match self {
+            TerraformFormat::Hcl => "main.tf",
+            TerraformFormat::Json => "main.tf.json",
+        }
+    }
+}
+
+impl std::str::FromStr for TerraformFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "hcl" => Ok(TerraformFormat::Hcl),
+            "json" => Ok(TerraformFormat::Json),
+            _ => Err(anyhow!("Unsupported Terraform format: {}. Use: hcl, json", s)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +346,90 @@ pub struct DeploymentResult {
     pub infrastructure_type: String,
     pub public_ip: Option<String>,
     pub logs: Vec<String>,
+    /// Correlation id of the deploy that produced this result
+    pub deployment_id: Option<String>,
+    /// False for background workers: there is no URL to open, health-check or point DNS at
+    pub serves_http: bool,
+    /// Directory holding this deploy's Terraform files and full log, when it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment_dir: Option<PathBuf>,
+    /// Why the app is deployed but not ready: it did not become healthy within `--wait`, or a
+    /// `--readiness-cmd` or `--warmup-url` failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_ready: Option<String>,
+    /// `terraform output` values, non-string values as JSON
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub outputs: HashMap<String, String>,
+    /// Itemized monthly cost of what was deployed, when it was estimated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<CostEstimate>,
+    /// Decoded startup script the instance runs, shown by `--dry-run` for review
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startup_script: Option<String>,
+    /// SHA of the source commit that was deployed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+/// Log lines a `DeploymentResult` keeps by default; error lines are kept on top of these
+pub const DEFAULT_MAX_LOG_LINES: usize = 200;
+
+/// Name of the full, unpruned log written to the deployment directory by `--full-logs`
+const FULL_LOG_FILE: &str = "deployment.log";
+
+impl DeploymentResult {
+    /// Bound `logs` to the last `max_lines` lines plus every error line. With `full_logs`,
+    /// every line is written to `deployment.log` in the deployment directory first.
+    pub fn prune_logs(&mut self, max_lines: usize, full_logs: bool) -> Result<()> {
+        if full_logs {
+            let deployment_dir = match &self.deployment_dir {
+                Some(dir) => dir.clone(),
+                None => new_deployment_dir(None)?,
+            };
+            let log_file = deployment_dir.join(FULL_LOG_FILE);
+            fs::write(&log_file, self.logs.join("\n") + "\n")
+                .map_err(|e| anyhow!("Failed to write full log {}: {}", log_file.display(), e))?;
+            self.logs.push(format!("📄 Full log: {}", log_file.display()));
+            self.deployment_dir = Some(deployment_dir);
+        }
+        
+        let omitted = bound_logs(&mut self.logs, max_lines);
+        if omitted > 0 && !full_logs {
+            self.logs[0].push_str(" (use --full-logs to keep them)");
+        }
+        Ok(())
+    }
+
+    /// The full log written by `--full-logs`, if there is one
+    pub fn log_file(&self) -> Option<PathBuf> {
+        self.deployment_dir
+            .as_ref()
+            .map(|dir| dir.join(FULL_LOG_FILE))
+            .filter(|path| path.exists())
+    }
+}
+
+/// Drop all but the last `max_lines` lines, keeping earlier error lines, and note how many
+/// were dropped in a first line. Returns the number dropped.
+fn bound_logs(logs: &mut Vec<String>, max_lines: usize) -> usize {
+    let tail_start = logs.len().saturating_sub(max_lines);
+    let before = logs.len();
+    let mut index = 0;
+    logs.retain(|line| {
+        let keep = index >= tail_start || is_error_line(line);
+        index += 1;
+        keep
+    });
+    
+    let omitted = before - logs.len();
+    if omitted > 0 {
+        logs.insert(0, format!("✂️ {} earlier log lines omitted", omitted));
+    }
+    omitted
+}
+
+fn is_error_line(line: &str) -> bool {
+    ["❌", "🚫", "⚠️"].iter().any(|marker| line.starts_with(marker))
 }
 
 pub async fn decide_infrastructure(
@@ -60,17 +438,130 @@ pub async fn decide_infrastructure(
     description: &str,
     repository_url: &str,
 ) -> Result<InfrastructureDecision> {
-    let deployment_type = determine_deployment_type(requirements, analysis);
-    let instance_type = determine_instance_type(&deployment_type, &requirements.cloud_provider);
-    let terraform_config = ai_nlp::generate_terraform_with_ai(
+    for database in database::unrequested(&requirements.database_requirements, &analysis.databases) {
+        info!(
+            "💡 The repository's dependencies point to {:?}, which is not provisioned; rerun with --database {} to add a managed instance (billed monthly)",
+            database,
+            database::flag_value(&database)
+        );
+    }
+    let deployment_type = determine_deployment_type(requirements, analysis, &requirements.cloud_provider);
+    let gpu = gpu_for(requirements, analysis, &deployment_type, &requirements.cloud_provider);
+    if !gpu && (requirements.gpu || !analysis.gpu_reasons.is_empty()) {
+        warn!(
+            "⚠️ The app needs a GPU, but GPU instances are only selected for single VMs on AWS and GCP; the {:?} {:?} deployment runs on CPUs",
+            requirements.cloud_provider,
+            deployment_type
+        );
+    }
+    let instance_type = determine_instance_type(&deployment_type, &requirements.cloud_provider, gpu);
+    let container = container_build(requirements, analysis, &deployment_type);
+    match &container {
+        Some(ContainerBuild::BaseImage(image)) => info!("🐳 Running the app in the {} image", image),
+        Some(ContainerBuild::Dockerfile(_)) => info!("🐳 Generated a Dockerfile for the repository, which has none"),
+        None => {}
+    }
+    let mut terraform_config = ai_nlp::generate_terraform_with_ai(
         description,
-        &requirements.cloud_provider,
+        requirements,
         &format!("{:?}", deployment_type),
-        &analysis.app_type,
+        analysis,
         repository_url,
+        container.as_ref(),
     ).await?;
-    let estimated_cost = estimate_cost(&deployment_type, &requirements.cloud_provider);
-    let justification = generate_justification(&deployment_type, requirements, analysis);
+    let databases = database::apply(&mut terraform_config, &requirements.database_requirements, &requirements.cloud_provider);
+    if !databases.is_empty() {
+        info!("🗄️ Added managed database resources: {}", databases.join(", "));
+    }
+    for database in database::missing(&terraform_config, &requirements.database_requirements, &requirements.cloud_provider) {
+        warn!("⚠️ No managed {:?} database was generated for {:?}; provision it separately", database, requirements.cloud_provider);
+    }
+    // Values reach the instance through Terraform, which fills in the database endpoints
+    let endpoints = database::endpoint_expressions(&terraform_config);
+    let mut environment_variables = HashMap::new();
+    for (name, value) in &requirements.environment_variables {
+        for reference in interpolation::references(value) {
+            if database::is_endpoint_variable(&reference) && !endpoints.contains_key(&reference) {
+                warn!("⚠️ {} references ${}, but no such database is provisioned; it is left as written", name, reference);
+            }
+        }
+        environment_variables.insert(name.clone(), interpolation::terraform_template(value, &endpoints));
+    }
+    let requirements = &DeploymentRequirements { environment_variables, ..requirements.clone() };
+    if requirements.private_network {
+        require_nat_gateway(&terraform_config, &requirements.cloud_provider)?;
+    }
+    // A containerized app keeps running its own image, given the GPU
+    let gpu_container = (gpu && analysis.docker_config.is_some()).then(|| bootstrap::gpu_container(analysis, requirements));
+    let startup_script = bootstrap::render_startup_script(gpu_container.as_ref().unwrap_or(analysis), repository_url, requirements);
+    bootstrap::apply_startup_script(&mut terraform_config, &startup_script);
+    bootstrap::apply_base_image(&mut terraform_config, requirements.os)?;
+    if gpu {
+        let changed = gpu::apply_gpu(&mut terraform_config, &requirements.cloud_provider, requirements.os)?;
+        warn!(
+            "⚠️ GPU instance {} for {}: about ${:.2}/mo, far more than a CPU instance",
+            instance_type,
+            changed.join(", "),
+            gpu::monthly_cost(&instance_type).unwrap_or_default()
+        );
+    }
+    let health_checked = requirements.health_check.apply(&mut terraform_config);
+    if !health_checked.is_empty() {
+        info!("🩺 Health check on {}: {}", health_checked.join(", "), requirements.health_check);
+    }
+    if let Some(network) = &requirements.existing_network {
+        for change in network.apply(&mut terraform_config, &requirements.cloud_provider)? {
+            info!("🔌 {}", change);
+        }
+    }
+    if let Some(port) = requirements.ssh_port {
+        let opened = ssh::apply_ssh_port(&mut terraform_config, port);
+        info!("🔐 SSH on port {} instead of 22 in {}", port, opened.join(", "));
+    }
+    if let Some(key) = &requirements.authorized_key {
+        let authorized = key.apply(&mut terraform_config);
+        info!("🔑 SSH key authorized for {} on {}", key.user, authorized.join(", "));
+    }
+    if let Some(egress) = &requirements.egress {
+        let restricted = egress.apply(&mut terraform_config, &requirements.cloud_provider)?;
+        info!("🚧 Egress from {} limited to {}", restricted.join(", "), egress);
+    }
+    if let Some(source) = &requirements.secrets_source {
+        let granted = source.apply(&mut terraform_config)?;
+        info!("🔐 {} can read secrets from {}", granted.join(", "), source);
+    }
+    let mut disk_size_gb = disk_size_for(requirements, analysis, &deployment_type, &requirements.cloud_provider);
+    if let Some(size) = disk_size_gb {
+        let sized = disk::apply_disk_size(&mut terraform_config, size);
+        if sized.is_empty() {
+            disk_size_gb = None;
+        } else {
+            info!("💾 {} GB root disk on {}", size, sized.join(", "));
+        }
+    }
+    if !requirements.allow_destroy_stateful {
+        let protected = protection::apply_prevent_destroy(&mut terraform_config, &protection::protected_types());
+        if !protected.is_empty() {
+            info!("🛡️ prevent_destroy on {}; destroying them needs --allow-destroy-stateful", protected.join(", "));
+        }
+    }
+    if let Some(name) = &requirements.deployment_name {
+        let tagged = policy::tag_resources(&mut terraform_config, "deployment", name);
+        info!("🏷️ Tagged {} resource(s) with deployment={}", tagged.len(), name);
+    }
+    if let Some(commit) = &analysis.commit {
+        let tagged = policy::tag_resources(&mut terraform_config, "git_commit", commit);
+        info!("🏷️ Tagged {} resource(s) with git_commit={}", tagged.len(), commit);
+    }
+    let estimated_cost = estimate_cost(
+        &deployment_type,
+        &instance_type,
+        &requirements.cloud_provider,
+        requirements.private_network,
+        disk_size_gb,
+        &requirements.database_requirements,
+    );
+    let justification = generate_justification(&deployment_type, requirements, analysis, gpu.then_some(&estimated_cost));
 
     Ok(InfrastructureDecision {
         deployment_type,
@@ -78,13 +569,86 @@ pub async fn decide_infrastructure(
         terraform_config,
         estimated_cost,
         justification,
+        required_registries: repository::private_registries(analysis),
+        exposed_ports: analysis.exposed_ports.clone(),
+        health_check: (!health_checked.is_empty()).then(|| requirements.health_check.clone()),
+        name: requirements.deployment_name.clone(),
+        disk_size_gb,
+        commit: analysis.commit.clone(),
+        dockerfile: match container {
+            Some(ContainerBuild::Dockerfile(dockerfile)) => Some(dockerfile),
+            _ => None,
+        },
+    })
+}
+
+/// How a container or Kubernetes deployment gets the app's image. A repository without a
+/// Dockerfile gets one generated on `--base-image` or the official image matching the detected
+/// runtime version; one with a Dockerfile only has its image overridden by `--base-image`.
+/// VMs install the runtime themselves.
+fn container_build(
+    requirements: &DeploymentRequirements,
+    analysis: &RepositoryAnalysis,
+    deployment_type: &DeploymentType,
+) -> Option<ContainerBuild> {
+    if !matches!(deployment_type, DeploymentType::ContainerService | DeploymentType::Kubernetes) {
+        return None;
+    }
+    if analysis.docker_config.is_some() {
+        return requirements.base_image.clone().map(ContainerBuild::BaseImage);
+    }
+    let Some(image) = requirements.base_image.clone().or_else(|| analysis.container_base_image()) else {
+        warn!("⚠️ No Dockerfile in the repository and no official image for {:?} apps; pass --base-image to generate one", analysis.app_type);
+        return None;
+    };
+    match dockerfile::render_dockerfile(analysis, &image) {
+        Ok(dockerfile) => Some(ContainerBuild::Dockerfile(dockerfile)),
+        Err(e) => {
+            warn!("⚠️ Could not generate a Dockerfile: {}", e);
+            Some(ContainerBuild::BaseImage(image))
+        }
+    }
+}
+
+/// Whether the deployment runs on a GPU instance: asked for with `--gpu` or needed by the
+/// repository, and a single VM on a provider with a GPU instance
+fn gpu_for(
+    requirements: &DeploymentRequirements,
+    analysis: &RepositoryAnalysis,
+    deployment_type: &DeploymentType,
+    cloud_provider: &CloudProvider,
+) -> bool {
+    (requirements.gpu || !analysis.gpu_reasons.is_empty())
+        && matches!(deployment_type, DeploymentType::SingleVM)
+        && gpu::gpu_instance_type(cloud_provider).is_some()
+}
+
+/// Root disk for a VM deployment: `--disk-size`, kept within the provider's limits, or a
+/// size derived from the build
+fn disk_size_for(
+    requirements: &DeploymentRequirements,
+    analysis: &RepositoryAnalysis,
+    deployment_type: &DeploymentType,
+    cloud_provider: &CloudProvider,
+) -> Option<u32> {
+    if !matches!(deployment_type, DeploymentType::SingleVM | DeploymentType::ContainerService) {
+        return None;
+    }
+    let (min, max) = disk::disk_size_limits(cloud_provider);
+    Some(match requirements.disk_size_gb {
+        Some(size) => size.clamp(min, max),
+        None => disk::recommended_disk_size(analysis.build_size_mb, cloud_provider),
     })
 }
 
 fn determine_deployment_type(
     requirements: &DeploymentRequirements,
     analysis: &RepositoryAnalysis,
+    cloud_provider: &CloudProvider,
 ) -> DeploymentType {
+    // Managed container services have no GPUs, so a containerized app that needs one runs its
+    // image on a GPU VM
+    let needs_gpu = (requirements.gpu || !analysis.gpu_reasons.is_empty()) && gpu::gpu_instance_type(cloud_provider).is_some();
     match requirements.scaling_requirements {
         ScalingRequirements::Serverless => DeploymentType::Serverless,
         ScalingRequirements::LoadBalanced => DeploymentType::Kubernetes,
@@ -92,7 +656,7 @@ fn determine_deployment_type(
             ApplicationType::React | ApplicationType::NextJS if !analysis.requires_build_step => {
                 DeploymentType::StaticSite
             }
-            _ if analysis.docker_config.is_some() => DeploymentType::ContainerService,
+            _ if analysis.docker_config.is_some() && !needs_gpu => DeploymentType::ContainerService,
             _ => DeploymentType::SingleVM,
         },
     }
@@ -101,7 +665,11 @@ fn determine_deployment_type(
 fn determine_instance_type(
     deployment_type: &DeploymentType,
     cloud_provider: &CloudProvider,
+    gpu: bool,
 ) -> String {
+    if let Some(instance_type) = gpu::gpu_instance_type(cloud_provider).filter(|_| gpu) {
+        return instance_type.to_string();
+    }
     match (deployment_type, cloud_provider) {
         (DeploymentType::SingleVM, CloudProvider::AWS) => "t3.micro".to_string(),
         (DeploymentType::SingleVM, CloudProvider::GCP) => "e2-micro".to_string(),
@@ -116,9 +684,127 @@ fn determine_instance_type(
     }
 }
 
+/// Estimate the deployment on `cloud_provider` without calling the AI or touching credentials
+pub fn estimate_for_provider(
+    requirements: &DeploymentRequirements,
+    analysis: &RepositoryAnalysis,
+    cloud_provider: &CloudProvider,
+) -> ProviderEstimate {
+    let deployment_type = determine_deployment_type(requirements, analysis, cloud_provider);
+    let nginx_location = analysis
+        .static_serving
+        .as_ref()
+        .filter(|serving| serving.served_by == StaticServer::Proxy)
+        .map(|serving| serving.nginx_location(&bootstrap::app_dir(analysis)));
+    let disk_size_gb = disk_size_for(requirements, analysis, &deployment_type, cloud_provider);
+    let gpu = gpu_for(requirements, analysis, &deployment_type, cloud_provider);
+    let instance_type = determine_instance_type(&deployment_type, cloud_provider, gpu);
+    ProviderEstimate {
+        cloud_provider: cloud_provider.clone(),
+        estimated_cost: estimate_cost(
+            &deployment_type,
+            &instance_type,
+            cloud_provider,
+            requirements.private_network,
+            disk_size_gb,
+            &requirements.database_requirements,
+        ),
+        instance_type,
+        deployment_type,
+        static_serving: analysis.static_serving.clone(),
+        nginx_location,
+        regions: Vec::new(),
+        disk_size_gb,
+        startup_script: None,
+    }
+}
+
+/// Side-by-side estimates for every provider in `COMPARED_PROVIDERS`
+pub fn compare_providers(requirements: &DeploymentRequirements, analysis: &RepositoryAnalysis) -> Vec<ProviderEstimate> {
+    COMPARED_PROVIDERS
+        .iter()
+        .map(|provider| estimate_for_provider(requirements, analysis, provider))
+        .collect()
+}
+
 // Note: All Terraform generation now handled by AI in ai_nlp module
 
-fn estimate_cost(deployment_type: &DeploymentType, cloud_provider: &CloudProvider) -> f64 {
+/// Monthly cost of the deployment, itemized: the compute, its root disk, the managed
+/// databases, outbound traffic, and the NAT gateway and load balancer of `--private`
+fn estimate_cost(
+    deployment_type: &DeploymentType,
+    instance_type: &str,
+    cloud_provider: &CloudProvider,
+    private_network: bool,
+    disk_size_gb: Option<u32>,
+    databases: &[DatabaseType],
+) -> CostEstimate {
+    let mut estimate = CostEstimate::default();
+    let compute = match deployment_type {
+        DeploymentType::SingleVM => format!("{} instance", instance_type),
+        DeploymentType::ContainerService => "Container service".to_string(),
+        DeploymentType::Kubernetes => "Kubernetes control plane".to_string(),
+        DeploymentType::Serverless => "Serverless functions".to_string(),
+        DeploymentType::StaticSite => "Static hosting".to_string(),
+    };
+    match gpu::monthly_cost(instance_type).filter(|_| matches!(deployment_type, DeploymentType::SingleVM)) {
+        Some(cost) => estimate.add(CostCategory::Compute, instance_item(instance_type), cost),
+        None => estimate.add(CostCategory::Compute, compute, compute_cost(deployment_type, cloud_provider)),
+    }
+    if let Some(size) = disk_size_gb {
+        estimate.add(CostCategory::Storage, format!("{} GB root disk", size), disk::storage_cost(size, cloud_provider));
+    }
+    for (database, cost) in database::database_costs(databases, cloud_provider) {
+        estimate.add(CostCategory::Database, database, cost);
+    }
+    if private_network {
+        for (item, cost) in private_network_items(cloud_provider) {
+            estimate.add(CostCategory::Networking, item, cost);
+        }
+    }
+    let (free_gb, per_gb) = data_transfer_pricing(cloud_provider);
+    estimate.add(
+        CostCategory::Networking,
+        format!("Data transfer out, {} GB ({} GB/mo free)", ASSUMED_TRANSFER_GB, free_gb),
+        (ASSUMED_TRANSFER_GB - free_gb).max(0.0) * per_gb,
+    );
+    estimate
+}
+
+/// Monthly on-demand price of common CPU instance types, for repricing an edited plan
+const INSTANCE_COSTS: &[(&str, f64)] = &[
+    ("t3.nano", 3.80),
+    ("t3.micro", 8.76),
+    ("t3.small", 15.18),
+    ("t3.medium", 30.37),
+    ("t3.large", 60.74),
+    ("t3.xlarge", 121.47),
+    ("e2-micro", 5.32),
+    ("e2-small", 12.23),
+    ("e2-medium", 24.46),
+    ("e2-standard-2", 48.92),
+    ("e2-standard-4", 97.84),
+    ("Standard_B1s", 7.59),
+    ("Standard_B1ms", 15.11),
+    ("Standard_B2s", 30.37),
+    ("Standard_B2ms", 60.74),
+];
+
+/// Monthly price of `instance_type`, GPU instances included, if it is known
+fn instance_cost(instance_type: &str) -> Option<f64> {
+    gpu::monthly_cost(instance_type)
+        .or_else(|| INSTANCE_COSTS.iter().find(|(name, _)| *name == instance_type).map(|(_, cost)| *cost))
+}
+
+/// Compute line item of a single VM of `instance_type`
+fn instance_item(instance_type: &str) -> String {
+    match gpu::monthly_cost(instance_type) {
+        Some(_) => format!("{} GPU instance (1x NVIDIA T4)", instance_type),
+        None => format!("{} instance", instance_type),
+    }
+}
+
+fn compute_cost(deployment_type: &DeploymentType, cloud_provider: &CloudProvider) -> f64 {
     match (deployment_type, cloud_provider) {
         (DeploymentType::SingleVM, CloudProvider::AWS) => 8.76, // t3.micro monthly
         (DeploymentType::SingleVM, CloudProvider::GCP) => 5.32, // e2-micro monthly
@@ -130,11 +816,287 @@ fn estimate_cost(deployment_type: &DeploymentType, cloud_provider: &CloudProvide
     }
 }
 
+/// Outbound traffic in GB a month assumed for a small app
+const ASSUMED_TRANSFER_GB: f64 = 10.0;
+
+/// Free outbound GB a month and the price per GB beyond it
+fn data_transfer_pricing(cloud_provider: &CloudProvider) -> (f64, f64) {
+    match cloud_provider {
+        CloudProvider::AWS => (100.0, 0.09),
+        CloudProvider::GCP => (200.0, 0.085), // Standard tier
+        CloudProvider::Azure => (100.0, 0.087),
+        CloudProvider::DigitalOcean => (500.0, 0.01), // smallest droplet's allowance
+        _ => (0.0, 0.09),
+    }
+}
+
+/// NAT gateway and load balancer a `--private` deployment adds, with their monthly cost
+fn private_network_items(cloud_provider: &CloudProvider) -> Vec<(&'static str, f64)> {
+    match cloud_provider {
+        CloudProvider::AWS => vec![("NAT gateway", 32.85), ("Application load balancer", 16.43)],
+        CloudProvider::GCP => vec![("Cloud NAT", 1.02), ("Load balancer forwarding rule", 18.25)],
+        CloudProvider::Azure => vec![("NAT gateway", 32.85), ("Standard load balancer", 18.25)],
+        _ => vec![("NAT gateway and load balancer", 50.0)],
+    }
+}
+
+/// Monthly cost of the NAT gateway and load balancer a `--private` deployment adds
+pub fn private_network_cost(private_network: bool, cloud_provider: &CloudProvider) -> f64 {
+    if !private_network {
+        return 0.0;
+    }
+    private_network_items(cloud_provider).iter().map(|(_, cost)| cost).sum()
+}
+
+/// Monthly price of one Route53 health check on an AWS endpoint
+const ROUTE53_HEALTH_CHECK_COST: f64 = 0.50;
+
+/// Monthly cost of a deployment copied into `regions` regions: the regional cost in each,
+/// plus the Route53 health check that lets DNS fail over away from it
+pub fn multi_region_cost(regional_cost: &CostEstimate, regions: usize) -> CostEstimate {
+    if regions < 2 {
+        return regional_cost.clone();
+    }
+    let mut cost = regional_cost.times(regions);
+    cost.add(
+        CostCategory::Networking,
+        format!("Route53 health check x{}", regions),
+        ROUTE53_HEALTH_CHECK_COST * regions as f64,
+    );
+    cost
+}
+
+/// Check a `--regions` list: at least two distinct AWS regions, since routing between them
+/// uses Route53 latency records
+pub fn validate_regions(regions: &[String], cloud_provider: &CloudProvider) -> Result<()> {
+    if *cloud_provider != CloudProvider::AWS {
+        return Err(anyhow!("--regions is only supported on AWS (Route53 latency routing), not {:?}", cloud_provider));
+    }
+    let region_name = regex::Regex::new(r"^[a-z]{2}(-gov)?-[a-z]+-\d$").unwrap();
+    if let Some(invalid) = regions.iter().find(|region| !region_name.is_match(region)) {
+        return Err(anyhow!("Invalid AWS region: {:?}, e.g. us-east-1", invalid));
+    }
+    let mut distinct: Vec<&String> = regions.iter().collect();
+    distinct.sort();
+    distinct.dedup();
+    if distinct.len() != regions.len() {
+        return Err(anyhow!("--regions lists a region more than once: {}", regions.join(",")));
+    }
+    if regions.len() < 2 {
+        return Err(anyhow!("--regions needs at least two regions; use the region setting for a single one"));
+    }
+    Ok(())
+}
+
+/// One region of a multi-region deployment, as DNS routes to it
+#[derive(Debug, Clone)]
+pub struct RegionalEndpoint {
+    pub region: String,
+    pub ip: String,
+    pub port: u16,
+}
+
+/// Write the Terraform for Route53 latency routing of `domain` across the regions into a new
+/// `routing_<name>` (or `routing_<timestamp>`) directory. `zone` is the hosted zone holding
+/// the domain, by default the domain without its first label.
+pub fn generate_latency_routing_files(
+    domain: &str,
+    zone: Option<&str>,
+    endpoints: &[RegionalEndpoint],
+    health_path: &str,
+    name: Option<&str>,
+) -> Result<PathBuf> {
+    let dir_name = match name {
+        Some(name) => format!("routing_{}", name),
+        None => format!("routing_{}", Utc::now().format("%Y%m%d_%H%M%S")),
+    };
+    let routing_dir = new_unique_dir(&terraform_output_dir()?, &dir_name)?;
+    fs::write(routing_dir.join("main.tf"), latency_routing_tf(domain, zone, endpoints, health_path))?;
+    info!("📁 Latency routing saved to: {}", routing_dir.display());
+    Ok(routing_dir)
+}
+
+/// Route53 records for `domain`, one per region, each answering only while its region's
+/// health check passes. Route53 serves the healthy record with the lowest latency to the user.
+fn latency_routing_tf(domain: &str, zone: Option<&str>, endpoints: &[RegionalEndpoint], health_path: &str) -> String {
+    let zone = zone.map(str::to_string).unwrap_or_else(|| {
+        let labels: Vec<&str> = domain.trim_end_matches('.').split('.').collect();
+        if labels.len() > 2 { labels[1..].join(".") } else { domain.to_string() }
+    });
+
+    let mut main_tf = String::new();
+    main_tf.push_str("terraform {\n  required_providers {\n    aws = {\n      source  = \"hashicorp/aws\"\n      version = \"~> 5.0\"\n    }\n  }\n}\n\n");
+    // Route53 is global; the region only picks the API endpoint
+    main_tf.push_str("variable \"region\" {\n  type = string\n  default = \"us-east-1\"\n}\n\n");
+    main_tf.push_str("provider \"aws\" {\n  region = var.region\n}\n\n");
+    main_tf.push_str(&format!("data \"aws_route53_zone\" \"zone\" {{\n  name = \"{}\"\n}}\n\n", escape_hcl_string(&zone)));
+
+    for endpoint in endpoints {
+        let id = endpoint.region.replace('-', "_");
+        main_tf.push_str(&format!("resource \"aws_route53_health_check\" \"{}\" {{\n", id));
+        main_tf.push_str(&format!("  ip_address = \"{}\"\n", endpoint.ip));
+        main_tf.push_str(&format!("  port = {}\n", endpoint.port));
+        main_tf.push_str("  type = \"HTTP\"\n");
+        main_tf.push_str(&format!("  resource_path = \"{}\"\n", escape_hcl_string(health_path)));
+        main_tf.push_str("  failure_threshold = 3\n");
+        main_tf.push_str("  request_interval = 30\n");
+        main_tf.push_str("}\n\n");
+
+        main_tf.push_str(&format!("resource \"aws_route53_record\" \"{}\" {{\n", id));
+        main_tf.push_str("  zone_id = data.aws_route53_zone.zone.zone_id\n");
+        main_tf.push_str(&format!("  name = \"{}\"\n", escape_hcl_string(domain)));
+        main_tf.push_str("  type = \"A\"\n");
+        main_tf.push_str("  ttl = 60\n");
+        main_tf.push_str(&format!("  records = [\"{}\"]\n", endpoint.ip));
+        main_tf.push_str(&format!("  set_identifier = \"{}\"\n", endpoint.region));
+        main_tf.push_str(&format!("  health_check_id = aws_route53_health_check.{}.id\n", id));
+        main_tf.push_str(&format!("  latency_routing_policy {{\n    region = \"{}\"\n  }}\n", endpoint.region));
+        main_tf.push_str("}\n\n");
+    }
+
+    main_tf.push_str(&format!("output \"website_url\" {{\n  value = \"http://{}\"\n}}\n", escape_hcl_string(domain)));
+    main_tf
+}
+
+/// NAT gateway resource type for each provider, which gives private instances outbound
+/// internet access
+const NAT_GATEWAYS: &[(CloudProvider, &str)] = &[
+    (CloudProvider::AWS, "aws_nat_gateway"),
+    (CloudProvider::GCP, "google_compute_router_nat"),
+    (CloudProvider::Azure, "azurerm_nat_gateway"),
+];
+
+/// Fail when a private instance would have no outbound access: its startup script could not
+/// install packages or clone the repository, so it would come up without the app
+fn require_nat_gateway(config: &TerraformConfig, cloud_provider: &CloudProvider) -> Result<()> {
+    let has_nat_gateway = config
+        .resources
+        .iter()
+        .any(|resource| NAT_GATEWAYS.iter().any(|(_, nat)| resource.resource_type == *nat));
+    if has_nat_gateway {
+        return Ok(());
+    }
+    let expected = NAT_GATEWAYS
+        .iter()
+        .find(|(provider, _)| provider == cloud_provider)
+        .map_or("a NAT gateway", |(_, nat)| *nat);
+    Err(anyhow!(
+        "The generated configuration has no NAT gateway ({}) for the private instance, which could not install packages or clone the repository without one. Rerun to generate the configuration again, or deploy without --private",
+        expected
+    ))
+}
+
+/// Human-readable list of the concrete cloud actions a decision will perform, e.g.
+/// "Create 1 EC2 t3.micro instance in us-east-1". Built from the typed config, not terraform output.
+pub fn summarize_actions(decision: &InfrastructureDecision, region: &str) -> Vec<String> {
+    let mut actions: Vec<(String, usize)> = Vec::new();
+    
+    for resource in &decision.terraform_config.resources {
+        let config = &resource.config;
+        let config_str = |key: &str| config.get(key).and_then(|v| v.as_str()).filter(|v| !v.starts_with("var."));
+        
+        let action = match resource.resource_type.as_str() {
+            "aws_instance" => format!(
+                "EC2 {} instance in {}",
+                config_str("instance_type").unwrap_or(&decision.instance_type),
+                region
+            ),
+            "google_compute_instance" => format!(
+                "Compute Engine {} VM in {}",
+                config_str("machine_type").unwrap_or(&decision.instance_type),
+                config_str("zone").unwrap_or(region)
+            ),
+            "aws_security_group" => format!(
+                "security group opening ports {}",
+                describe_ports(&aws_ingress_ports(config))
+            ),
+            "google_compute_firewall" => format!(
+                "firewall rule opening ports {}",
+                describe_ports(&gcp_allowed_ports(config))
+            ),
+            other => format!("{} ({})", other, resource.name),
+        };
+        
+        match actions.iter_mut().find(|(existing, _)| *existing == action) {
+            Some((_, count)) => *count += 1,
+            None => actions.push((action, 1)),
+        }
+    }
+    
+    let mut summary: Vec<String> = actions
+        .into_iter()
+        .map(|(action, count)| format!("Create {} {}", count, action))
+        .collect();
+    if let Some(health_check) = &decision.health_check {
+        summary.push(format!("Health check: {}", health_check));
+    }
+    if let Some(size) = decision.disk_size_gb {
+        summary.push(format!("Root disk: {} GB", size));
+    }
+    summary.push(format!("Estimated cost: ${:.2}/mo", decision.estimated_cost.total));
+    summary
+}
+
+fn aws_ingress_ports(config: &HashMap<String, serde_json::Value>) -> Vec<String> {
+    let rules = match config.get("ingress") {
+        Some(serde_json::Value::Array(rules)) => rules.clone(),
+        Some(rule @ serde_json::Value::Object(_)) => vec![rule.clone()],
+        _ => return vec![],
+    };
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let from = rule.get("from_port")?.as_u64()?;
+            let to = rule.get("to_port")?.as_u64()?;
+            Some(if from == to { from.to_string() } else { format!("{}-{}", from, to) })
+        })
+        .collect()
+}
+
+fn gcp_allowed_ports(config: &HashMap<String, serde_json::Value>) -> Vec<String> {
+    let rules = match config.get("allow") {
+        Some(serde_json::Value::Array(rules)) => rules.clone(),
+        Some(rule @ serde_json::Value::Object(_)) => vec![rule.clone()],
+        _ => return vec![],
+    };
+    rules
+        .iter()
+        .filter_map(|rule| rule.get("ports")?.as_array().cloned())
+        .flatten()
+        .filter_map(|port| match port {
+            serde_json::Value::String(port) => Some(port),
+            serde_json::Value::Number(port) => Some(port.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn describe_ports(ports: &[String]) -> String {
+    if ports.is_empty() {
+        "(none)".to_string()
+    } else {
+        ports.join("/")
+    }
+}
+
 fn generate_justification(
     deployment_type: &DeploymentType,
-    _requirements: &DeploymentRequirements,
+    requirements: &DeploymentRequirements,
     analysis: &RepositoryAnalysis,
+    gpu_cost: Option<&CostEstimate>,
 ) -> String {
+    if let Some(cost) = gpu_cost {
+        let reason = match requirements.gpu {
+            true => "--gpu was given".to_string(),
+            false => format!("the repository needs CUDA ({})", analysis.gpu_reasons.join(", ")),
+        };
+        return format!(
+            "Single GPU VM chosen for {:?} application because {}. A CPU micro instance cannot run it; the NVIDIA T4 instance boots an image with the driver installed. Estimated cost: ${:.2}/month.",
+            analysis.app_type,
+            reason,
+            cost.total
+        );
+    }
     match deployment_type {
         DeploymentType::SingleVM => {
             format!(
@@ -163,37 +1125,9 @@ pub async fn provision_infrastructure(
     _work_dir: &Path,
     dry_run: bool,
     cloud_provider: &CloudProvider,
+    timeout: Option<Duration>,
 ) -> Result<DeploymentResult> {
-    // Create persistent terraform output directory
-    let current_dir = std::env::current_dir()?;
-    let terraform_output_dir = current_dir.join("terraform-output");
-    fs::create_dir_all(&terraform_output_dir)?;
-    
-    // Create timestamped subdirectory for this deployment
-    let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-    let terraform_dir = terraform_output_dir.join(format!("deployment_{}", timestamp));
-    fs::create_dir_all(&terraform_dir)?;
-
-    // Generate Terraform files
-    generate_terraform_files(&decision.terraform_config, &terraform_dir, repo_url)?;
-
-    let mut logs = Vec::new();
-    logs.push("✅ Terraform files generated successfully".to_string());
-    logs.push(format!(
-        "📁 Generated terraform configuration for {:?}",
-        decision.deployment_type
-    ));
-    logs.push(format!("📄 Files saved to: {}", terraform_dir.display()));
-    
-    // Log the file locations for easy access
-    info!("📁 Terraform files saved to: {}", terraform_dir.display());
-    info!("📄 Generated files:");
-    info!("   - main.tf");
-    info!("   - variables.tf"); 
-    info!("   - outputs.tf");
-    
-    println!("📁 Terraform files saved to: {}", terraform_dir.display());
-    println!("📄 You can now review and test the generated Terraform configuration!");
+    let (terraform_dir, mut logs) = generate_deployment_files(decision, repo_url, TerraformFormat::Hcl)?;
 
     if dry_run {
         logs.push("🧪 Dry run - no infrastructure provisioned".to_string());
@@ -203,85 +1137,502 @@ pub async fn provision_infrastructure(
             infrastructure_type: format!("{:?}", decision.deployment_type),
             public_ip: None,
             logs,
+            deployment_id: None,
+            serves_http: true,
+            deployment_dir: Some(terraform_dir),
+            not_ready: None,
+            outputs: HashMap::new(),
+            cost: None,
+            startup_script: None,
+            commit: None,
         });
     }
 
+    let id = correlation::current().unwrap_or_else(correlation::new_id);
+    let index = Index::open()?;
+    let mut entry = IndexEntry::new(&id, repo_url, cloud_provider, &terraform_dir);
+    entry.deployment_type = Some(format!("{:?}", decision.deployment_type));
+    if let Err(e) = index.save(&entry) {
+        warn!("⚠️ Failed to record deployment {} in the index: {}", id, e);
+    }
+    index.record(&mut entry, DeploymentState::Provisioning);
+    let applied = apply_terraform(
+        &terraform_dir,
+        cloud_provider,
+        &format!("{:?}", decision.deployment_type),
+        logs,
+        None,
+        None,
+        timeout,
+    ).await;
+    index.record_applied(&mut entry, &applied);
+    let mut result = applied?;
+    result.deployment_id = Some(id);
+    Ok(result)
+}
+
+/// Persistent directory deployments are written to (the `output_dir` setting, else
+/// `./terraform-output`)
+pub fn terraform_output_dir() -> Result<PathBuf> {
+    let terraform_output_dir = match &settings::get().output_dir {
+        Some(output_dir) => output_dir.clone(),
+        None => std::env::current_dir()?.join("terraform-output"),
+    };
+    fs::create_dir_all(&terraform_output_dir)?;
+    Ok(terraform_output_dir)
+}
+
+/// Create the directory for one deployment under the persistent output directory:
+/// `deployment_<name>` for a named deployment, else `deployment_<timestamp>`. Deployments
+/// started in the same second (e.g. the services of a stack) get a `_2`, `_3`, ... suffix.
+pub fn new_deployment_dir(name: Option<&str>) -> Result<PathBuf> {
+    let dir_name = match name {
+        Some(name) => format!("deployment_{}", name),
+        None => format!("deployment_{}", Utc::now().format("%Y%m%d_%H%M%S")),
+    };
+    new_unique_dir(&terraform_output_dir()?, &dir_name)
+}
+
+/// Longest name accepted by `--name`: a DNS label, and short enough for every provider's tags
+const MAX_DEPLOYMENT_NAME_LEN: usize = 63;
+
+/// Check that a `--name` is usable in DNS names, directory names and every provider's tags
+/// and labels: lowercase letters, digits and inner hyphens
+pub fn validate_deployment_name(name: &str) -> Result<()> {
+    let valid_chars = name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if name.is_empty()
+        || name.len() > MAX_DEPLOYMENT_NAME_LEN
+        || !valid_chars
+        || name.starts_with('-')
+        || name.ends_with('-')
+    {
+        return Err(anyhow!(
+            "Invalid deployment name {:?}: use 1-{} lowercase letters, digits and hyphens, not starting or ending with a hyphen",
+            name,
+            MAX_DEPLOYMENT_NAME_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// `name`, or `name-2`, `name-3`, ... when a deployment of that name already exists
+pub fn unique_deployment_name(name: &str) -> Result<String> {
+    validate_deployment_name(name)?;
+    let output_dir = terraform_output_dir()?;
+    let mut unique = name.to_string();
+    let mut suffix = 2;
+    while output_dir.join(format!("deployment_{}", unique)).exists() {
+        unique = format!("{}-{}", name, suffix);
+        suffix += 1;
+    }
+    if unique != name {
+        warn!("⚠️ A deployment named {} already exists, using {}", name, unique);
+    }
+    validate_deployment_name(&unique)?;
+    Ok(unique)
+}
+
+/// Create `parent/name`, or `parent/name_2`, `parent/name_3`, ... if it already exists
+pub fn new_unique_dir(parent: &Path, name: &str) -> Result<PathBuf> {
+    let mut dir = parent.join(name);
+    let mut suffix = 2;
+    loop {
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                dir = parent.join(format!("{}_{}", name, suffix));
+                suffix += 1;
+            }
+            Err(e) => return Err(anyhow!("Failed to create {}: {}", dir.display(), e)),
+        }
+    }
+}
+
+/// Write the Terraform files for a decision into a new timestamped directory under
+/// `./terraform-output` (or the `output_dir` setting). Returns the directory and the generation log lines.
+pub fn generate_deployment_files(
+    decision: &InfrastructureDecision,
+    repo_url: &str,
+    format: TerraformFormat,
+) -> Result<(PathBuf, Vec<String>)> {
+    let terraform_dir = new_deployment_dir(decision.name.as_deref())?;
+    let logs = write_deployment_files(decision, repo_url, format, &terraform_dir)?;
+    Ok((terraform_dir, logs))
+}
+
+/// Write the Terraform files for a decision into an existing deployment directory. Returns
+/// the generation log lines.
+pub fn write_deployment_files(
+    decision: &InfrastructureDecision,
+    repo_url: &str,
+    format: TerraformFormat,
+    terraform_dir: &Path,
+) -> Result<Vec<String>> {
+    // Tags from the AI, the policy and --name must pass the provider's rules, or apply fails
+    let mut terraform_config = decision.terraform_config.clone();
+    let tag_changes = tags::normalize_tags(&mut terraform_config);
+    
+    // Generate Terraform files
+    let files = match format {
+        TerraformFormat::Hcl => {
+            generate_terraform_files(&terraform_config, terraform_dir, repo_url)?;
+            ["main.tf", "variables.tf", "outputs.tf"]
+        }
+        TerraformFormat::Json => {
+            generate_terraform_json_files(&terraform_config, terraform_dir, repo_url)?;
+            ["main.tf.json", "variables.tf.json", "outputs.tf.json"]
+        }
+    };
+    // A copy for review; the instance writes its own into the clone
+    if let Some(dockerfile) = &decision.dockerfile {
+        fs::write(terraform_dir.join(dockerfile::DOCKERFILE), dockerfile)?;
+        info!("🐳 Generated Dockerfile saved to: {}", terraform_dir.join(dockerfile::DOCKERFILE).display());
+    }
+
+    let mut logs = vec![
+        "✅ Terraform files generated successfully".to_string(),
+        format!(
+            "📁 Generated terraform configuration for {:?}",
+            decision.deployment_type
+        ),
+        format!("📄 Files saved to: {}", terraform_dir.display()),
+    ];
+    logs.extend(tag_changes.iter().map(|change| format!("🏷️ {}", change)));
+    
+    // Log the file locations for easy access
+    info!("📁 Terraform files saved to: {}", terraform_dir.display());
+    info!("📄 Generated files:");
+    for file in files {
+        info!("   - {}", file);
+    }
+
+    Ok(logs)
+}
+
+/// Maximum number of times a failing plan is sent back to the AI with `--ai-repair`
+pub const MAX_AI_REPAIR_ATTEMPTS: u32 = 3;
+
+/// Regenerates a config that `terraform plan` rejected by feeding the plan error to the AI
+pub struct PlanRepair {
+    pub config: TerraformConfig,
+    pub repo_url: String,
+    pub cloud_provider: CloudProvider,
+    pub max_attempts: u32,
+    /// Applied to every repaired config, so a fix cannot sneak past the limits
+    pub policy: Option<Policy>,
+    pub fallback_instance_type: String,
+    /// Existing network re-applied to every repaired config, which may have recreated the VPC
+    pub network: Option<ExistingNetwork>,
+    /// Root disk size re-applied to every repaired config
+    pub disk_size_gb: Option<u32>,
+    /// Secret read access re-applied to every repaired config
+    pub secrets_source: Option<SecretsSource>,
+    /// Egress restrictions re-applied to every repaired config
+    pub egress: Option<EgressPolicy>,
+    /// SSH port re-applied to every repaired config
+    pub ssh_port: Option<u16>,
+    /// Deploy key re-applied to every repaired config
+    pub authorized_key: Option<AuthorizedKey>,
+}
+
+impl PlanRepair {
+    async fn repair(&mut self, terraform_dir: &Path, plan_error: &str, attempt: u32, logs: &mut Vec<String>) -> Result<()> {
+        info!("🩹 AI repair attempt {}/{}", attempt, self.max_attempts);
+        let before = fs::read_to_string(terraform_dir.join("main.tf")).unwrap_or_default();
+        
+        let mut repaired = ai_nlp::repair_terraform_with_ai(&self.config, &self.cloud_provider, plan_error).await?;
+        // The startup script is rendered locally; never let the repair rewrite it
+        bootstrap::preserve_startup_script(&self.config, &mut repaired);
+        if let Some(policy) = &self.policy {
+            for violation in policy.enforce(&mut repaired, &self.fallback_instance_type)? {
+                logs.push(format!("🛡️ Policy: {}", violation));
+            }
+        }
+        if let Some(network) = &self.network {
+            for change in network.apply(&mut repaired, &self.cloud_provider)? {
+                logs.push(format!("🔌 {}", change));
+            }
+        }
+        if let Some(size) = self.disk_size_gb {
+            disk::apply_disk_size(&mut repaired, size);
+        }
+        if let Some(source) = &self.secrets_source {
+            source.apply(&mut repaired)?;
+        }
+        if let Some(port) = self.ssh_port {
+            ssh::apply_ssh_port(&mut repaired, port);
+        }
+        if let Some(key) = &self.authorized_key {
+            key.apply(&mut repaired);
+        }
+        if let Some(egress) = &self.egress {
+            egress.apply(&mut repaired, &self.cloud_provider)?;
+        }
+        for change in tags::normalize_tags(&mut repaired) {
+            logs.push(format!("🏷️ {}", change));
+        }
+        generate_terraform_files(&repaired, terraform_dir, &self.repo_url)?;
+        
+        let after = fs::read_to_string(terraform_dir.join("main.tf")).unwrap_or_default();
+        let diff = similar::TextDiff::from_lines(&before, &after)
+            .unified_diff()
+            .header("main.tf (before)", "main.tf (after)")
+            .to_string();
+        info!("🩹 Repair attempt {} changed main.tf:\n{}", attempt, diff);
+        logs.push(format!("🩹 AI repair attempt {}:\n{}", attempt, diff));
+        
+        self.config = repaired;
+        Ok(())
+    }
+}
+
+/// `terraform validate` rejected the generated configuration: the files are malformed, so
+/// planning them would only fail later with less readable errors
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidConfiguration {
+    /// What `terraform validate` reported
+    pub output: String,
+}
+
+impl InvalidConfiguration {
+    /// Errors go to stderr; fall back to stdout for versions that print them there
+    fn from_output(stderr: &[u8], stdout: &[u8]) -> Self {
+        let stderr = String::from_utf8_lossy(stderr).trim().to_string();
+        let output = if stderr.is_empty() {
+            String::from_utf8_lossy(stdout).trim().to_string()
+        } else {
+            stderr
+        };
+        InvalidConfiguration { output }
+    }
+}
+
+impl std::fmt::Display for InvalidConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Terraform validate rejected the generated configuration:\n{}", self.output)
+    }
+}
+
+impl std::error::Error for InvalidConfiguration {}
+
+/// Terraform failed because the account hit a cloud quota; no config change or retry of the
+/// same deploy helps, the quota has to be raised or another region used
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    pub cloud_provider: CloudProvider,
+    /// Human name of the exhausted quota, e.g. "EC2 instance limit"
+    pub quota: String,
+    pub region: String,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let account = match self.cloud_provider {
+            CloudProvider::GCP => "GCP project",
+            CloudProvider::Azure => "Azure subscription",
+            _ => "AWS account",
+        };
+        write!(
+            f,
+            "Your {} has reached its {} in {}; request a quota increase or try another region",
+            account, self.quota, self.region
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// AWS error codes that mean a service quota was hit, with a readable name for each
+const AWS_QUOTA_ERRORS: &[(&str, &str)] = &[
+    ("InstanceLimitExceeded", "EC2 instance limit"),
+    ("VcpuLimitExceeded", "EC2 vCPU limit"),
+    ("AddressLimitExceeded", "Elastic IP limit"),
+    ("VpcLimitExceeded", "VPC limit"),
+    ("SecurityGroupLimitExceeded", "security group limit"),
+    ("RulesPerSecurityGroupLimitExceeded", "security group rule limit"),
+    ("InstanceQuotaExceeded", "instance quota"),
+];
+
+/// Recognise a quota failure in terraform's stderr. `region` is used when the error itself
+/// doesn't name one.
+pub fn detect_quota_error(stderr: &str, cloud_provider: &CloudProvider, region: &str) -> Option<QuotaExceeded> {
+    let quota_error = |quota: String, region: String| QuotaExceeded {
+        cloud_provider: cloud_provider.clone(),
+        quota,
+        region,
+    };
+    
+    match cloud_provider {
+        CloudProvider::GCP => {
+            // e.g. "Quota 'CPUS' exceeded.  Limit: 8.0 in region us-central1."
+            let quota_regex = regex::Regex::new(r"Quota '([A-Z0-9_]+)' exceeded\.\s+Limit: [\d.]+(?: in region ([a-z0-9-]+))?").ok()?;
+            if let Some(caps) = quota_regex.captures(stderr) {
+                let region = caps.get(2).map_or(region, |m| m.as_str());
+                return Some(quota_error(format!("{} quota", &caps[1]), region.to_string()));
+            }
+            stderr
+                .contains("QUOTA_EXCEEDED")
+                .then(|| quota_error("compute quota".to_string(), region.to_string()))
+        }
+        CloudProvider::Azure => (stderr.contains("QuotaExceeded")
+            || (stderr.contains("OperationNotAllowed") && stderr.contains("quota")))
+            .then(|| quota_error("vCPU quota".to_string(), region.to_string())),
+        _ => AWS_QUOTA_ERRORS
+            .iter()
+            .find(|(code, _)| stderr.contains(code))
+            .map(|(_, quota)| quota_error(quota.to_string(), region.to_string())),
+    }
+}
+
+/// How long init, plan and apply may take together before the deploy is aborted
+pub const DEFAULT_TERRAFORM_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// How long an interrupted Terraform command gets to write its state and release the state
+/// lock before it is killed
+const TERRAFORM_INTERRUPT_GRACE: Duration = Duration::from_secs(90);
+
+/// How long a destroy waits for a state lock, e.g. one an interrupted apply is still releasing
+const DESTROY_LOCK_TIMEOUT: &str = "-lock-timeout=5m";
+
+/// Run a Terraform command, stopping it if it is still running at `deadline`. `None` means it
+/// was stopped: it is interrupted first, as Ctrl-C would, so an apply saves the state of what
+/// it already created, and killed only if it is still running `grace` later.
+async fn terraform_output(cmd: Command, deadline: Option<Instant>, grace: Duration) -> Result<Option<Output>> {
+    let mut cmd = tokio::process::Command::from(cmd);
+    // Dropping the output future after the grace period kills the child
+    cmd.kill_on_drop(true).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let child = cmd.spawn()?;
+    let pid = child.id();
+    let output = child.wait_with_output();
+    tokio::pin!(output);
+    let Some(deadline) = deadline else {
+        return Ok(Some(output.await?));
+    };
+    if let Ok(output) = tokio::time::timeout_at(deadline, &mut output).await {
+        return Ok(Some(output?));
+    }
+    if let Some(pid) = pid {
+        warn!("⏰ Interrupting Terraform (pid {}) so it can save its state", pid);
+        let interrupted = Command::new("kill").arg("-INT").arg(pid.to_string()).stderr(Stdio::null()).status();
+        if interrupted.is_ok_and(|status| status.success()) {
+            let _ = tokio::time::timeout(grace, &mut output).await;
+        }
+    }
+    Ok(None)
+}
+
+fn timeout_error(step: &str, timeout: Option<Duration>) -> anyhow::Error {
+    let minutes = timeout.map_or(0, |timeout| timeout.as_secs().div_ceil(60));
+    anyhow!("Terraform {} timed out after {} minutes (raise it with --timeout)", step, minutes)
+}
+
+/// Init, plan and apply the configuration in `terraform_dir`. With a `timeout`, a Terraform
+/// command still running when it expires is killed; if that was the apply, whatever it
+/// provisioned is destroyed before the timeout error is returned.
+pub async fn apply_terraform(
+    terraform_dir: &Path,
+    cloud_provider: &CloudProvider,
+    infrastructure_type: &str,
+    mut logs: Vec<String>,
+    mut repair: Option<&mut PlanRepair>,
+    region: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<DeploymentResult> {
     // Check if Terraform is installed
     if which("terraform").is_err() {
         return Err(anyhow!(
             "Terraform is not installed. Please install Terraform to deploy for real."
         ));
     }
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
 
     // Load and set up credentials
     let credentials = CloudCredentials::load_from_file()
         .unwrap_or_else(|_| CloudCredentials::new());
+    // `--regions` pins each regional deployment to its own region
+    let region = region.map(str::to_string).unwrap_or_else(|| credentials.region_for(cloud_provider));
     
-    let env_vars = if let Some(cred_env) = credentials.get_credentials_for(cloud_provider) {
-        info!("🔑 Setting up {} credentials for Terraform", format!("{:?}", cloud_provider));
-        cred_env
-    } else {
-        return Err(anyhow!(
-            "No credentials found for {:?}. Set up with: cargo run -- credentials setup {}",
-            cloud_provider,
-            format!("{:?}", cloud_provider).to_lowercase()
-        ));
-    };
+    let env_vars = terraform_env(&credentials, cloud_provider)?;
 
     // Initialize Terraform with credentials
     logs.push("🔧 Initializing Terraform...".to_string());
     let mut cmd = Command::new("terraform");
-    cmd.arg("init").current_dir(&terraform_dir);
+    cmd.arg("init").current_dir(terraform_dir);
     
     // Add credentials as environment variables
     for (key, value) in &env_vars {
         cmd.env(key, value);
     }
     
-    let output = cmd.output()?;
+    let output = terraform_output(cmd, deadline, TERRAFORM_INTERRUPT_GRACE).await?.ok_or_else(|| timeout_error("init", timeout))?;
 
     if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
+        let error_msg = redacted_output(&output.stderr, &env_vars);
         logs.push(format!("❌ Terraform init failed: {}", error_msg));
         return Err(anyhow!("Terraform init failed: {}", error_msg));
     }
 
     logs.push("✅ Terraform initialized successfully".to_string());
 
-    // Plan Terraform
-    logs.push("📋 Planning Terraform deployment...".to_string());
+    // Catch malformed generated configs before plan buries them in provider errors
+    logs.push("🔍 Validating Terraform configuration...".to_string());
     let mut cmd = Command::new("terraform");
-    cmd.arg("plan").arg("-out=tfplan").current_dir(&terraform_dir);
-    
-    match cloud_provider {
-        CloudProvider::GCP => {
-            if let Some(gcp_creds) = &credentials.gcp {
-                cmd.arg("-var").arg(format!("project_id={}", gcp_creds.project_id));
-                let region = gcp_creds.region.as_deref().unwrap_or("us-central1");
-                cmd.arg("-var").arg(format!("region={}", region));
-                cmd.arg("-var").arg(format!("zone={}-a", region));
-            }
-        },
-        CloudProvider::AWS => {
-            if let Some(aws_creds) = &credentials.aws {
-                let region = aws_creds.region.as_deref().unwrap_or("us-east-1");
-                cmd.arg("-var").arg(format!("region={}", region));
-            }
-        },
-        _ => {}
-    }
-    
-    // Add credentials as environment variables
+    cmd.arg("validate").arg("-no-color").current_dir(terraform_dir);
     for (key, value) in &env_vars {
         cmd.env(key, value);
     }
-    
-    let output = cmd.output()?;
-
+    let output = terraform_output(cmd, deadline, TERRAFORM_INTERRUPT_GRACE).await?.ok_or_else(|| timeout_error("validate", timeout))?;
     if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
+        let invalid = InvalidConfiguration::from_output(
+            redacted_output(&output.stderr, &env_vars).as_bytes(),
+            redacted_output(&output.stdout, &env_vars).as_bytes(),
+        );
+        logs.push(format!("❌ {}", invalid));
+        return Err(invalid.into());
+    }
+    logs.push("✅ Terraform configuration is valid".to_string());
+
+    // Plan Terraform
+    logs.push("📋 Planning Terraform deployment...".to_string());
+    let mut repair_attempts = 0;
+    loop {
+        let mut cmd = Command::new("terraform");
+        cmd.arg("plan").arg("-out=tfplan").current_dir(terraform_dir);
+        cmd.args(terraform_var_args(&credentials, cloud_provider, &region));
+        
+        // Add credentials as environment variables
+        for (key, value) in &env_vars {
+            cmd.env(key, value);
+        }
+        
+        let output = terraform_output(cmd, deadline, TERRAFORM_INTERRUPT_GRACE).await?.ok_or_else(|| timeout_error("plan", timeout))?;
+        if output.status.success() {
+            break;
+        }
+        
+        let error_msg = redacted_output(&output.stderr, &env_vars);
         logs.push(format!("❌ Terraform plan failed: {}", error_msg));
-        return Err(anyhow!("Terraform plan failed: {}", error_msg));
+        
+        // No AI repair can raise a quota
+        if let Some(quota) = detect_quota_error(&error_msg, cloud_provider, &region) {
+            logs.push(format!("🚫 {}", quota));
+            return Err(quota.into());
+        }
+        
+        match repair.as_deref_mut() {
+            Some(repair) if repair_attempts < repair.max_attempts => {
+                repair_attempts += 1;
+                repair.repair(terraform_dir, &error_msg, repair_attempts, &mut logs).await?;
+            }
+            Some(_) => {
+                return Err(anyhow!(
+                    "Terraform plan failed after {} AI repair attempts: {}",
+                    repair_attempts,
+                    error_msg
+                ));
+            }
+            None => return Err(anyhow!("Terraform plan failed: {}", error_msg)),
+        }
     }
 
     logs.push("✅ Terraform plan completed successfully".to_string());
@@ -289,26 +1640,52 @@ pub async fn provision_infrastructure(
     // Apply Terraform
     logs.push("🚀 Applying Terraform configuration...".to_string());
     let mut cmd = Command::new("terraform");
-    cmd.arg("apply").arg("-auto-approve").arg("tfplan").current_dir(&terraform_dir);
+    cmd.arg("apply").arg("-auto-approve").arg("tfplan").current_dir(terraform_dir);
     
     // Add credentials as environment variables
     for (key, value) in &env_vars {
         cmd.env(key, value);
     }
     
-    let output = cmd.output()?;
+    let Some(output) = terraform_output(cmd, deadline, TERRAFORM_INTERRUPT_GRACE).await? else {
+        // Whatever the interrupted apply created would otherwise keep running and billing; it
+        // is in the state the apply saved when it stopped
+        warn!(
+            "⏰ Terraform apply timed out, destroying the partially provisioned resources in {}",
+            terraform_dir.display()
+        );
+        // Nothing in the new resources is worth keeping
+        let cleanup = match protection::lift_protection(terraform_dir) {
+            Ok(_lifted) => destroy_terraform(terraform_dir, cloud_provider, Some(&region)).await,
+            Err(e) => Err(e),
+        };
+        match cleanup {
+            Ok(()) => info!("🧹 Timeout cleanup destroyed the partially provisioned resources"),
+            Err(e) => warn!(
+                "⚠️ Timeout cleanup failed, resources may be left running; retry with `terraform destroy` in {}: {}",
+                terraform_dir.display(),
+                e
+            ),
+        }
+        return Err(timeout_error("apply", timeout));
+    };
 
     if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
+        let error_msg = redacted_output(&output.stderr, &env_vars);
         logs.push(format!("❌ Terraform apply failed: {}", error_msg));
+        if let Some(quota) = detect_quota_error(&error_msg, cloud_provider, &region) {
+            logs.push(format!("🚫 {}", quota));
+            return Err(quota.into());
+        }
         return Err(anyhow!("Terraform apply failed: {}", error_msg));
     }
 
+    logs.extend(redacted_output(&output.stdout, &env_vars).lines().map(|line| line.to_string()));
     logs.push("✅ Infrastructure provisioned successfully!".to_string());
 
     // Get outputs
     let mut cmd = Command::new("terraform");
-    cmd.arg("output").arg("-json").current_dir(&terraform_dir);
+    cmd.arg("output").arg("-json").current_dir(terraform_dir);
     
     // Add credentials as environment variables
     for (key, value) in &env_vars {
@@ -320,7 +1697,8 @@ pub async fn provision_infrastructure(
     let url = if output.status.success() {
         if let Ok(outputs) = serde_json::from_slice::<serde_json::Value>(&output.stdout) {
             outputs
-                .get("instance_ip")
+                .get("load_balancer_url")
+                .or_else(|| outputs.get("instance_ip"))
                 .or_else(|| outputs.get("public_ip"))
                 .or_else(|| outputs.get("public_dns"))
                 .or_else(|| outputs.get("website_url"))
@@ -350,13 +1728,395 @@ pub async fn provision_infrastructure(
         None
     };
 
+    let outputs = if output.status.success() {
+        parse_terraform_outputs(&output.stdout)
+    } else {
+        HashMap::new()
+    };
+
     logs.push(format!("🌐 Deployment URL: {}", url));
 
     Ok(DeploymentResult {
-        url: format!("http://{}", url),
-        infrastructure_type: format!("{:?}", decision.deployment_type),
+        url: if url.contains("://") { url } else { format!("http://{}", url) },
+        infrastructure_type: infrastructure_type.to_string(),
         public_ip,
         logs,
+        deployment_id: None,
+        serves_http: true,
+        deployment_dir: Some(terraform_dir.to_path_buf()),
+        not_ready: None,
+        outputs,
+        cost: None,
+        startup_script: None,
+        commit: None,
+    })
+}
+
+/// `terraform output -json` as name to value; strings as-is, other values as JSON
+fn parse_terraform_outputs(stdout: &[u8]) -> HashMap<String, String> {
+    let Ok(serde_json::Value::Object(outputs)) = serde_json::from_slice(stdout) else {
+        return HashMap::new();
+    };
+    outputs
+        .into_iter()
+        .filter_map(|(name, output)| {
+            let value = match output.get("value")? {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Environment variables giving Terraform the stored credentials for `cloud_provider`
+fn terraform_env(credentials: &CloudCredentials, cloud_provider: &CloudProvider) -> Result<HashMap<String, String>> {
+    if let (CloudProvider::AWS, Some(endpoint)) = (cloud_provider, aws_endpoint()) {
+        info!("🧪 Using dummy AWS credentials for {}", endpoint);
+        return Ok(HashMap::from([
+            ("AWS_ACCESS_KEY_ID".to_string(), DUMMY_AWS_CREDENTIAL.to_string()),
+            ("AWS_SECRET_ACCESS_KEY".to_string(), DUMMY_AWS_CREDENTIAL.to_string()),
+        ]));
+    }
+    match credentials.get_credentials_for(cloud_provider) {
+        Some(env_vars) => {
+            info!("🔑 Setting up {:?} credentials for Terraform", cloud_provider);
+            Ok(env_vars)
+        }
+        None => Err(anyhow!(
+            "No credentials found for {:?}. Set up with: cargo run -- credentials setup {}",
+            cloud_provider,
+            format!("{:?}", cloud_provider).to_lowercase()
+        )),
+    }
+}
+
+/// Terraform's output as text for logs and errors, with the credentials it ran with and
+/// anything else secret-looking masked
+fn redacted_output(bytes: &[u8], env_vars: &HashMap<String, String>) -> String {
+    redact_secrets_with(&String::from_utf8_lossy(bytes), env_vars)
+}
+
+/// `-var` arguments for the variables the generated configurations declare
+fn terraform_var_args(credentials: &CloudCredentials, cloud_provider: &CloudProvider, region: &str) -> Vec<String> {
+    let vars = match cloud_provider {
+        CloudProvider::GCP => match &credentials.gcp {
+            Some(gcp_creds) => vec![
+                format!("project_id={}", gcp_creds.project_id),
+                format!("region={}", region),
+                format!("zone={}-a", region),
+            ],
+            None => Vec::new(),
+        },
+        CloudProvider::AWS if credentials.aws.is_some() || aws_endpoint().is_some() => vec![format!("region={}", region)],
+        CloudProvider::DigitalOcean if credentials.digitalocean.is_some() => vec![format!("region={}", region)],
+        _ => Vec::new(),
+    };
+    vars.into_iter().flat_map(|var| ["-var".to_string(), var]).collect()
+}
+
+/// Tear down everything the Terraform configuration in `terraform_dir` created, with the
+/// stored credentials. `region` is the one the deploy used; `None` means the default.
+pub async fn destroy_terraform(terraform_dir: &Path, cloud_provider: &CloudProvider, region: Option<&str>) -> Result<()> {
+    if which("terraform").is_err() {
+        return Err(anyhow!("Terraform is not installed. Please install Terraform to destroy the deployment."));
+    }
+    if !terraform_dir.is_dir() {
+        return Err(anyhow!("Terraform directory {} no longer exists", terraform_dir.display()));
+    }
+    let credentials = CloudCredentials::load_from_file().unwrap_or_else(|_| CloudCredentials::new());
+    let region = region.map(str::to_string).unwrap_or_else(|| credentials.region_for(cloud_provider));
+    let env_vars = terraform_env(&credentials, cloud_provider)?;
+
+    info!("💣 Destroying the infrastructure in {}...", terraform_dir.display());
+    let mut cmd = Command::new("terraform");
+    cmd.args(destroy_args(&credentials, cloud_provider, &region)).current_dir(terraform_dir);
+    for (key, value) in &env_vars {
+        cmd.env(key, value);
+    }
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(anyhow!("Terraform destroy failed: {}", redacted_output(&output.stderr, &env_vars)));
+    }
+    info!("✅ Infrastructure destroyed");
+    Ok(())
+}
+
+/// Arguments of `terraform destroy`, which waits for a state lock that is still held
+fn destroy_args(credentials: &CloudCredentials, cloud_provider: &CloudProvider, region: &str) -> Vec<String> {
+    let mut args: Vec<String> = ["destroy", "-auto-approve", "-no-color", DESTROY_LOCK_TIMEOUT].map(String::from).into();
+    args.extend(terraform_var_args(credentials, cloud_provider, region));
+    args
+}
+
+/// `type.name` of every resource in the `.tf` and `.tf.json` files in `terraform_dir`
+pub fn config_resource_addresses(terraform_dir: &Path) -> Result<Vec<String>> {
+    let resource_block = regex::Regex::new(r#"(?m)^\s*resource\s+"([^"]+)"\s+"([^"]+)""#).unwrap();
+    let mut addresses = Vec::new();
+    for entry in fs::read_dir(terraform_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if file_name.ends_with(".tf") {
+            let contents = fs::read_to_string(&path)?;
+            addresses.extend(resource_block.captures_iter(&contents).map(|caps| format!("{}.{}", &caps[1], &caps[2])));
+        } else if file_name.ends_with(".tf.json") {
+            let contents: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            if let Some(types) = contents.get("resource").and_then(|resources| resources.as_object()) {
+                for (resource_type, names) in types {
+                    let names = names.as_object().into_iter().flat_map(|names| names.keys());
+                    addresses.extend(names.map(|name| format!("{}.{}", resource_type, name)));
+                }
+            }
+        }
+    }
+    addresses.sort();
+    Ok(addresses)
+}
+
+/// Variables `generate_terraform_files` declares for every configuration
+const BUILTIN_VARIABLES: [&str; 2] = ["repository_url", "region"];
+
+/// Resource type prefixes a `type.name` reference is recognized by, so that file names such
+/// as `hello_world.git` in startup scripts are not taken for references
+const REFERENCE_PREFIXES: [&str; 5] = ["aws_", "google_", "azurerm_", "digitalocean_", "random_"];
+
+/// Read a hand-written `TerraformConfig` JSON file (`deploy --terraform-config`), rejecting
+/// one that does not match the struct or has references that resolve to nothing
+pub fn load_terraform_config(path: &Path) -> Result<TerraformConfig> {
+    let contents = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let deserializer = &mut serde_json::Deserializer::from_str(&contents);
+    let config: TerraformConfig = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let field = e.path().to_string();
+        if field == "." {
+            anyhow!("Invalid Terraform config {}: {}", path.display(), e.inner())
+        } else {
+            anyhow!("Invalid Terraform config {} at `{}`: {}", path.display(), field, e.inner())
+        }
+    })?;
+    check_references(&config)?;
+    Ok(config)
+}
+
+/// Check that the config has resources with unique addresses, and that every `type.name` and
+/// `var.name` reference in a resource or output names one of its resources or variables
+pub fn check_references(config: &TerraformConfig) -> Result<()> {
+    if config.resources.is_empty() {
+        return Err(anyhow!("The Terraform config has no resources"));
+    }
+    let mut problems = Vec::new();
+    let mut addresses = std::collections::HashSet::new();
+    for resource in &config.resources {
+        let address = format!("{}.{}", resource.resource_type, resource.name);
+        if !addresses.insert(address.clone()) {
+            problems.push(format!("{} is defined more than once", address));
+        }
+    }
+    
+    let reference = regex::Regex::new(r"\b(var|[a-z][a-z0-9]*_[a-z0-9_]+)\.([A-Za-z_][A-Za-z0-9_-]*)").unwrap();
+    let mut check = |location: &str, text: &str| {
+        for caps in reference.captures_iter(text) {
+            let (kind, name) = (&caps[1], &caps[2]);
+            let start = caps.get(0).unwrap().start();
+            if kind == "var" {
+                if !BUILTIN_VARIABLES.contains(&name) && !config.variables.contains_key(name) {
+                    problems.push(format!("{} uses undeclared variable var.{}", location, name));
+                }
+            } else if REFERENCE_PREFIXES.iter().any(|prefix| kind.starts_with(prefix))
+                && !text[..start].ends_with("data.")
+                && !addresses.contains(&format!("{}.{}", kind, name))
+            {
+                problems.push(format!("{} references missing resource {}.{}", location, kind, name));
+            }
+        }
+    };
+    for resource in &config.resources {
+        let location = format!("{}.{}", resource.resource_type, resource.name);
+        for value in resource.config.values() {
+            for text in string_values(value) {
+                check(&location, text);
+            }
+        }
+    }
+    for (name, output) in sorted_entries(&config.outputs) {
+        for text in string_values(output) {
+            check(&format!("output {}", name), text);
+        }
+    }
+    
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid references in the Terraform config:\n  {}", problems.join("\n  ")))
+    }
+}
+
+/// Every string inside `value`, however deeply nested
+fn string_values(value: &serde_json::Value) -> Vec<&str> {
+    match value {
+        serde_json::Value::String(s) => vec![s.as_str()],
+        serde_json::Value::Array(items) => items.iter().flat_map(string_values).collect(),
+        serde_json::Value::Object(fields) => fields.values().flat_map(string_values).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Provider of the first resource type with a known prefix, e.g. `aws_instance` is AWS
+pub fn provider_for_resource_types<'a>(resource_types: impl IntoIterator<Item = &'a str>) -> Option<CloudProvider> {
+    let prefixes = [
+        ("aws_", CloudProvider::AWS),
+        ("google_", CloudProvider::GCP),
+        ("azurerm_", CloudProvider::Azure),
+        ("digitalocean_", CloudProvider::DigitalOcean),
+    ];
+    let resource_types: Vec<&str> = resource_types.into_iter().collect();
+    prefixes
+        .into_iter()
+        .find(|(prefix, _)| resource_types.iter().any(|t| t.starts_with(prefix)))
+        .map(|(_, provider)| provider)
+}
+
+/// Decision for a prebuilt config: it is written and applied as is, with nothing analyzed
+/// or generated. The ports are the ones its firewall rules open, other than SSH.
+pub fn prebuilt_decision(terraform_config: TerraformConfig, cloud_provider: &CloudProvider) -> InfrastructureDecision {
+    let resource_types: Vec<&str> = terraform_config.resources.iter().map(|r| r.resource_type.as_str()).collect();
+    let has = |types: &[&str]| resource_types.iter().any(|t| types.contains(t));
+    let deployment_type = if has(&["aws_lambda_function", "google_cloudfunctions_function", "google_cloudfunctions2_function"]) {
+        DeploymentType::Serverless
+    } else if has(&["aws_eks_cluster", "google_container_cluster", "azurerm_kubernetes_cluster"]) {
+        DeploymentType::Kubernetes
+    } else if has(&["aws_ecs_service", "google_cloud_run_service", "google_cloud_run_v2_service"]) {
+        DeploymentType::ContainerService
+    } else {
+        DeploymentType::SingleVM
+    };
+    let instance_type = terraform_config
+        .resources
+        .iter()
+        .find_map(|r| ["instance_type", "machine_type", "size"].iter().find_map(|key| r.config.get(*key)?.as_str()))
+        .unwrap_or("custom")
+        .to_string();
+    let mut exposed_ports: Vec<u16> = terraform_config
+        .resources
+        .iter()
+        .filter(|r| matches!(r.resource_type.as_str(), "aws_security_group" | "google_compute_firewall"))
+        .flat_map(|r| r.config.get("ingress").or_else(|| r.config.get("allow")).into_iter().flat_map(firewall_ports))
+        .filter(|port| *port != 22)
+        .collect();
+    exposed_ports.sort_unstable();
+    exposed_ports.dedup();
+    
+    InfrastructureDecision {
+        estimated_cost: estimate_cost(&deployment_type, &instance_type, cloud_provider, false, None, &[]),
+        justification: "Prebuilt Terraform configuration; repository analysis and AI generation were skipped".to_string(),
+        deployment_type,
+        instance_type,
+        terraform_config,
+        required_registries: Vec::new(),
+        exposed_ports,
+        health_check: None,
+        name: None,
+        disk_size_gb: None,
+        commit: None,
+        dockerfile: None,
+    }
+}
+
+/// Ports opened by AWS `ingress` blocks (`from_port`) or GCP `allow` blocks (`ports`)
+fn firewall_ports(rules: &serde_json::Value) -> Vec<u16> {
+    let rules: Vec<&serde_json::Value> = match rules {
+        serde_json::Value::Array(rules) => rules.iter().collect(),
+        rule => vec![rule],
+    };
+    let mut ports = Vec::new();
+    for rule in rules {
+        if let Some(port) = rule.get("from_port").and_then(|port| port.as_u64()) {
+            ports.push(port.to_string());
+        }
+        if let Some(allowed) = rule.get("ports").and_then(|ports| ports.as_array()) {
+            ports.extend(allowed.iter().filter_map(|port| port.as_str().map(str::to_string)));
+        }
+    }
+    ports.iter().filter_map(|port| port.parse::<u16>().ok()).filter(|port| *port > 0).collect()
+}
+
+/// Bring an existing cloud resource under the management of the configuration in
+/// `terraform_dir` with `terraform import`. `address` must name a resource block in the
+/// configuration, optionally with an index such as `aws_instance.app[0]`.
+pub async fn import_terraform(
+    terraform_dir: &Path,
+    cloud_provider: &CloudProvider,
+    region: Option<&str>,
+    address: &str,
+    resource_id: &str,
+) -> Result<()> {
+    if which("terraform").is_err() {
+        return Err(anyhow!("Terraform is not installed. Please install Terraform to import resources."));
+    }
+    if !terraform_dir.is_dir() {
+        return Err(anyhow!("Terraform directory {} no longer exists", terraform_dir.display()));
+    }
+    let addresses = config_resource_addresses(terraform_dir)?;
+    let block = address.split('[').next().unwrap_or(address);
+    if !addresses.iter().any(|known| known == block) {
+        return Err(anyhow!(
+            "{} is not in the configuration in {}. Resources: {}",
+            address,
+            terraform_dir.display(),
+            addresses.join(", ")
+        ));
+    }
+    let credentials = CloudCredentials::load_from_file().unwrap_or_else(|_| CloudCredentials::new());
+    let region = region.map(str::to_string).unwrap_or_else(|| credentials.region_for(cloud_provider));
+    let env_vars = terraform_env(&credentials, cloud_provider)?;
+
+    // A deploy that failed before init leaves no providers to import with
+    if !terraform_dir.join(".terraform").is_dir() {
+        info!("🔧 Initializing Terraform...");
+        let mut cmd = Command::new("terraform");
+        cmd.arg("init").arg("-input=false").arg("-no-color").current_dir(terraform_dir);
+        for (key, value) in &env_vars {
+            cmd.env(key, value);
+        }
+        let output = cmd.output()?;
+        if !output.status.success() {
+            return Err(anyhow!("Terraform init failed: {}", redacted_output(&output.stderr, &env_vars)));
+        }
+    }
+
+    info!("📦 Importing {} as {}...", resource_id, address);
+    let mut cmd = Command::new("terraform");
+    cmd.arg("import").arg("-input=false").arg("-no-color").current_dir(terraform_dir);
+    cmd.args(terraform_var_args(&credentials, cloud_provider, &region));
+    cmd.arg(address).arg(resource_id);
+    for (key, value) in &env_vars {
+        cmd.env(key, value);
+    }
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(anyhow!("Terraform import failed: {}", redacted_output(&output.stderr, &env_vars)));
+    }
+    info!("✅ Imported {}", address);
+    Ok(())
+}
+
+/// Cloud the config's `provider` names; the AI writes `google` as often as `gcp`, and
+/// `azurerm` for `azure`. An unrecognized name is inferred from the resource types.
+fn config_cloud_provider(config: &TerraformConfig) -> Option<CloudProvider> {
+    match config.provider.to_lowercase().as_str() {
+        "aws" => Some(CloudProvider::AWS),
+        "gcp" | "google" => Some(CloudProvider::GCP),
+        "azure" | "azurerm" => Some(CloudProvider::Azure),
+        "digitalocean" | "do" => Some(CloudProvider::DigitalOcean),
+        _ => provider_for_resource_types(config.resources.iter().map(|r| r.resource_type.as_str())),
+    }
+}
+
+/// Suffix of the deployment's firewall rule names: its id, or for files generated outside a
+/// deploy the name of their directory. Regenerating a deployment's files keeps its names.
+fn firewall_name_suffix(terraform_dir: &Path) -> String {
+    correlation::current().unwrap_or_else(|| {
+        let dir_name = terraform_dir.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+        dir_name.trim_start_matches("deployment_").replace(|c: char| !c.is_ascii_alphanumeric(), "-")
     })
 }
 
@@ -365,13 +2125,13 @@ fn generate_terraform_files(
     terraform_dir: &Path,
     repo_url: &str,
 ) -> Result<()> {
-    let timestamp = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let firewall_suffix = firewall_name_suffix(terraform_dir);
     // Generate main.tf
     let mut main_tf = String::new();
 
     // Provider configuration
-    match config.provider.as_str() {
-        "aws" => {
+    match config_cloud_provider(config) {
+        Some(CloudProvider::AWS) => {
             main_tf.push_str("terraform {\n");
             main_tf.push_str("  required_providers {\n");
             main_tf.push_str("    aws = {\n");
@@ -381,10 +2141,12 @@ fn generate_terraform_files(
             main_tf.push_str("  }\n");
             main_tf.push_str("}\n\n");
             main_tf.push_str("provider \"aws\" {\n");
-            main_tf.push_str("  region = var.region\n");
+            for (key, value) in aws_provider_arguments(aws_endpoint()) {
+                main_tf.push_str(&format!("  {}\n", json_to_hcl(&key, &value, 1)));
+            }
             main_tf.push_str("}\n\n");
         }
-        "gcp" => {
+        Some(CloudProvider::GCP) => {
             main_tf.push_str("terraform {\n");
             main_tf.push_str("  required_providers {\n");
             main_tf.push_str("    google = {\n");
@@ -398,6 +2160,33 @@ fn generate_terraform_files(
             main_tf.push_str("  region  = var.region\n");
             main_tf.push_str("}\n\n");
         }
+        Some(CloudProvider::Azure) => {
+            main_tf.push_str("terraform {\n");
+            main_tf.push_str("  required_providers {\n");
+            main_tf.push_str("    azurerm = {\n");
+            main_tf.push_str("      source  = \"hashicorp/azurerm\"\n");
+            main_tf.push_str("      version = \"~> 3.0\"\n");
+            main_tf.push_str("    }\n");
+            main_tf.push_str("  }\n");
+            main_tf.push_str("}\n\n");
+            // azurerm refuses to initialize without a features block, even an empty one;
+            // the service principal comes from the ARM_* variables
+            main_tf.push_str("provider \"azurerm\" {\n");
+            main_tf.push_str("  features {}\n");
+            main_tf.push_str("}\n\n");
+        }
+        Some(CloudProvider::DigitalOcean) => {
+            main_tf.push_str("terraform {\n");
+            main_tf.push_str("  required_providers {\n");
+            main_tf.push_str("    digitalocean = {\n");
+            main_tf.push_str("      source  = \"digitalocean/digitalocean\"\n");
+            main_tf.push_str("      version = \"~> 2.0\"\n");
+            main_tf.push_str("    }\n");
+            main_tf.push_str("  }\n");
+            main_tf.push_str("}\n\n");
+            // The token comes from DIGITALOCEAN_TOKEN
+            main_tf.push_str("provider \"digitalocean\" {}\n\n");
+        }
         _ => {}
     }
 
@@ -407,23 +2196,16 @@ fn generate_terraform_files(
             "resource \"{}\" \"{}\" {{\n",
             resource.resource_type, resource.name
         ));
-        for (key, value) in &resource.config {
-            // Add sed commands to startup scripts to replace localhost with 0.0.0.0
-            let processed_value = if key == "metadata_startup_script" || key == "user_data" {
-                replace_git_clone_with_download(value, repo_url)
-            } else {
-                value.clone()
-            };
-            
-            // Add timestamp to firewall rule names to avoid conflicts
+        for (key, value) in sorted_entries(&resource.config) {
+            // Firewall rule names are unique per project, so each deployment suffixes its own
             if key == "name" && resource.resource_type.contains("firewall") {
-                if let serde_json::Value::String(name) = &processed_value {
-                    let unique_name = format!("{}-{}", name, timestamp);
+                if let serde_json::Value::String(name) = value {
+                    let unique_name = format!("{}-{}", name, firewall_suffix);
                     main_tf.push_str(&format!("  name = \"{}\"\n", unique_name));
                     continue;
                 }
             }
-            main_tf.push_str(&format!("  {}\n", json_to_hcl(key, &processed_value, 1)));
+            main_tf.push_str(&format!("  {}\n", json_to_hcl(key, value, 1)));
         }
         main_tf.push_str("}\n\n");
     }
@@ -439,7 +2221,7 @@ fn generate_terraform_files(
     added_vars.insert("repository_url".to_string());
     added_vars.insert("region".to_string());
     
-    for (var_name, var_config) in &config.variables {
+    for (var_name, var_config) in sorted_entries(&config.variables) {
         // Skip if we already added this variable
         if added_vars.contains(var_name) {
             continue;
@@ -473,7 +2255,7 @@ fn generate_terraform_files(
 
     // Generate outputs.tf
     let mut outputs_tf = String::new();
-    for (output_name, output_config) in &config.outputs {
+    for (output_name, output_config) in sorted_entries(&config.outputs) {
         outputs_tf.push_str(&format!("output \"{}\" {{\n", output_name));
         
         if let Some(value) = output_config.get("value") {
@@ -497,11 +2279,263 @@ fn generate_terraform_files(
     Ok(())
 }
 
+/// Map entries sorted by key. The config maps are `HashMap`s whose iteration order changes
+/// between runs, which would make identical configs produce different files.
+fn sorted_entries<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Write the config as native Terraform JSON (`*.tf.json`). Unlike the HCL path this is a
+/// direct serialization, so only bare references (`var.x`, output values) need wrapping in `${}`.
+fn generate_terraform_json_files(
+    config: &TerraformConfig,
+    terraform_dir: &Path,
+    repo_url: &str,
+) -> Result<()> {
+    let firewall_suffix = firewall_name_suffix(terraform_dir);
+    let mut main = serde_json::Map::new();
+
+    // Provider configuration
+    match config_cloud_provider(config) {
+        Some(CloudProvider::AWS) => {
+            main.insert("terraform".to_string(), json!({
+                "required_providers": {
+                    "aws": { "source": "hashicorp/aws", "version": "~> 5.0" }
+                }
+            }));
+            main.insert("provider".to_string(), json!({
+                "aws": json_expression(&serde_json::Value::Object(aws_provider_arguments(aws_endpoint())))
+            }));
+        }
+        Some(CloudProvider::GCP) => {
+            main.insert("terraform".to_string(), json!({
+                "required_providers": {
+                    "google": { "source": "hashicorp/google", "version": "~> 4.0" }
+                }
+            }));
+            main.insert("provider".to_string(), json!({
+                "google": { "project": "${var.project}", "region": "${var.region}" }
+            }));
+        }
+        Some(CloudProvider::Azure) => {
+            main.insert("terraform".to_string(), json!({
+                "required_providers": {
+                    "azurerm": { "source": "hashicorp/azurerm", "version": "~> 3.0" }
+                }
+            }));
+            main.insert("provider".to_string(), json!({ "azurerm": { "features": {} } }));
+        }
+        Some(CloudProvider::DigitalOcean) => {
+            main.insert("terraform".to_string(), json!({
+                "required_providers": {
+                    "digitalocean": { "source": "digitalocean/digitalocean", "version": "~> 2.0" }
+                }
+            }));
+            main.insert("provider".to_string(), json!({ "digitalocean": {} }));
+        }
+        _ => {}
+    }
+
+    // Resources, keyed by type then name
+    let mut resources = serde_json::Map::new();
+    for resource in &config.resources {
+        let mut body = serde_json::Map::new();
+        for (key, value) in &resource.config {
+            // Firewall rule names are unique per project, so each deployment suffixes its own
+            if key == "name" && resource.resource_type.contains("firewall") {
+                if let serde_json::Value::String(name) = value {
+                    body.insert(key.clone(), json!(format!("{}-{}", name, firewall_suffix)));
+                    continue;
+                }
+            }
+            body.insert(key.clone(), json_expression(value));
+        }
+        resources
+            .entry(resource.resource_type.clone())
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .expect("resource type entries are objects")
+            .insert(resource.name.clone(), serde_json::Value::Object(body));
+    }
+    if !resources.is_empty() {
+        main.insert("resource".to_string(), serde_json::Value::Object(resources));
+    }
+
+    fs::write(terraform_dir.join("main.tf.json"), serde_json::to_string_pretty(&main)?)?;
+
+    // Variables; repository_url and region always come first, as in variables.tf
+    let mut variables = serde_json::Map::new();
+    variables.insert("repository_url".to_string(), json!({
+        "description": "Repository URL",
+        "type": "string",
+        "default": repo_url,
+    }));
+    variables.insert("region".to_string(), json!({
+        "description": "Cloud region",
+        "type": "string",
+        "default": "us-east-1",
+    }));
+    for (var_name, var_config) in &config.variables {
+        if variables.contains_key(var_name) {
+            continue;
+        }
+        let mut variable = serde_json::Map::new();
+        for field in ["type", "description", "default"] {
+            if let Some(value) = var_config.get(field).and_then(|v| v.as_str()) {
+                variable.insert(field.to_string(), json!(value));
+            }
+        }
+        variables.insert(var_name.clone(), serde_json::Value::Object(variable));
+    }
+
+    fs::write(
+        terraform_dir.join("variables.tf.json"),
+        serde_json::to_string_pretty(&json!({ "variable": variables }))?,
+    )?;
+
+    // Outputs; their values are expressions, not literal strings
+    let mut outputs = serde_json::Map::new();
+    for (output_name, output_config) in &config.outputs {
+        let mut output = serde_json::Map::new();
+        if let Some(value) = output_config.get("value").and_then(|v| v.as_str()) {
+            output.insert("value".to_string(), json!(wrap_expression(value)));
+        }
+        if let Some(description) = output_config.get("description").and_then(|v| v.as_str()) {
+            output.insert("description".to_string(), json!(description));
+        }
+        outputs.insert(output_name.clone(), serde_json::Value::Object(output));
+    }
+
+    let outputs_json = if outputs.is_empty() { json!({}) } else { json!({ "output": outputs }) };
+    fs::write(terraform_dir.join("outputs.tf.json"), serde_json::to_string_pretty(&outputs_json)?)?;
+
+    Ok(())
+}
+
+/// Like `json_to_hcl`, treat `var.*` strings as references; everything else is a literal
+/// and passes through unchanged
+fn json_expression(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) if s.starts_with("var.") => json!(wrap_expression(s)),
+        serde_json::Value::Object(obj) => serde_json::Value::Object(
+            obj.iter().map(|(k, v)| (k.clone(), json_expression(v))).collect(),
+        ),
+        serde_json::Value::Array(arr) => {
+            serde_json::Value::Array(arr.iter().map(json_expression).collect())
+        }
+        other => other.clone(),
+    }
+}
+
+/// Turn a bare HCL expression into a Terraform JSON template string
+fn wrap_expression(expression: &str) -> String {
+    if expression.starts_with("${") {
+        expression.to_string()
+    } else {
+        format!("${{{}}}", expression)
+    }
+}
+
+fn escape_hcl_string(s: &str) -> String {
+    s.replace('\\', "\\\\")
+     .replace('"', "\\\"")
+     .replace('\n', "\\n")
+     .replace('\r', "\\r")
+     .replace('\t', "\\t")
+}
+
+/// Object-valued arguments that are maps rather than nested blocks. Their keys may be valid
+/// identifiers (GCP's `ssh-keys`, `enable-oslogin`), so the keys alone cannot tell.
+/// Lambda's `environment` is a block; the map is its `variables`.
+const MAP_ARGUMENTS: &[&str] = &[
+    "tags",
+    "labels",
+    "resource_labels",
+    "metadata",
+    "user_data_vars",
+    "variables",
+    "environment_variables",
+];
+
+/// Keys HCL accepts unquoted as attribute and block names
+fn is_hcl_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// `key = value` for an argument, or `key { ... }` for a nested block: an object becomes one
+/// block and a list of objects one block per object, recursing into their contents. Objects
+/// with keys that cannot name attributes, and `MAP_ARGUMENTS`, are written as maps instead.
+/// Lines after the first are indented for `indent_level`; the first is left to the caller.
+fn json_to_hcl(key: &str, value: &serde_json::Value, indent_level: usize) -> String {
+    let indent = "  ".repeat(indent_level);
+    let is_block = |value: &serde_json::Value| {
+        value.as_object().is_some_and(|obj| obj.keys().all(|subkey| is_hcl_identifier(subkey)))
+    };
+
+    match value {
+        serde_json::Value::Object(obj) if !MAP_ARGUMENTS.contains(&key) && is_block(value) => hcl_block(key, obj, indent_level),
+        serde_json::Value::Array(arr) if !arr.is_empty() && arr.iter().all(is_block) => arr
+            .iter()
+            .filter_map(|item| item.as_object())
+            .map(|obj| hcl_block(key, obj, indent_level))
+            .collect::<Vec<_>>()
+            .join(&format!("\n{}", indent)),
+        _ => format!("{} = {}", key, hcl_expression(value, indent_level)),
+    }
+}
+
+fn hcl_block(key: &str, obj: &serde_json::Map<String, serde_json::Value>, indent_level: usize) -> String {
+    let indent = "  ".repeat(indent_level);
+    let mut result = format!("{} {{\n", key);
+    for (subkey, subvalue) in obj {
+        result.push_str(&format!("{}  {}\n", indent, json_to_hcl(subkey, subvalue, indent_level + 1)));
+    }
+    result.push_str(&format!("{}}}", indent));
+    result
+}
+
+/// The value of an argument: strings quoted (but `var.*` references bare, `${...}` stays a
+/// quoted template), and lists and maps written out recursively
+fn hcl_expression(value: &serde_json::Value, indent_level: usize) -> String {
+    match value {
+        serde_json::Value::String(s) if s.starts_with("var.") => s.clone(),
+        serde_json::Value::String(s) => format!("\"{}\"", escape_hcl_string(s)),
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::Bool(b) => b.to_string(),
+        serde_json::Value::Null => "null".to_string(),
+        serde_json::Value::Array(arr) => {
+            let items: Vec<String> = arr.iter().map(|item| hcl_expression(item, indent_level)).collect();
+            format!("[{}]", items.join(", "))
+        }
+        serde_json::Value::Object(obj) if obj.is_empty() => "{}".to_string(),
+        serde_json::Value::Object(obj) => {
+            // A map, e.g. `tags = { "Name" = "app" }`
+            let indent = "  ".repeat(indent_level);
+            let mut result = String::from("{\n");
+            for (subkey, subvalue) in obj {
+                result.push_str(&format!(
+                    "{}  \"{}\" = {}\n",
+                    indent,
+                    escape_hcl_string(subkey),
+                    hcl_expression(subvalue, indent_level + 1)
+                ));
+            }
+            result.push_str(&format!("{}}}", indent));
+            result
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::nlp::{
-        ApplicationType, CloudProvider, DatabaseType, DeploymentRequirements, ScalingRequirements,
+        ApplicationType, CloudProvider, DatabaseType, DeploymentRequirements, OsFamily,
+        ScalingRequirements,
     };
     use crate::repository::{PackageManager, RepositoryAnalysis};
     
@@ -516,6 +2550,29 @@ mod tests {
             port_requirements: vec![80, 443],
             ssl_required: true,
             custom_domain: Some("example.com".to_string()),
+            os: OsFamily::Ubuntu,
+            registry_credentials: HashMap::new(),
+            expects_env_file: false,
+            environment: None,
+            health_check: Default::default(),
+            private_network: false,
+            service_dir: None,
+            deployment_name: None,
+            deploy_non_service: false,
+            region: None,
+            existing_network: None,
+            audit: None,
+            disk_size_gb: None,
+            gpu: false,
+            allow_destroy_stateful: false,
+            git_ref: None,
+            rewrite_localhost: false,
+            secrets_source: None,
+            egress: None,
+            start_script: None,
+            base_image: None,
+            ssh_port: None,
+            authorized_key: None,
         }
     }
 
@@ -527,14 +2584,82 @@ mod tests {
             start_commands: vec!["python app.py".to_string()],
             environment_variables: vec!["DATABASE_URL".to_string()],
             exposed_ports: vec![5000],
+            internal_port: Some(5000),
             static_files_dir: Some("static".to_string()),
             database_migrations: true,
             requires_build_step: true,
             docker_config: None,
             package_manager: PackageManager::Pip,
+            working_dir: None,
+            build_env_vars: vec![],
+            container_images: vec![],
+            framework_version: None,
+            environment_files: Default::default(),
+            static_serving: None,
+            health_endpoint: None,
+            not_a_service: None,
+            build_size_mb: 0,
+            node_package: None,
+            cargo_binary: None,
+            app_module: None,
+            runtime_version: None,
+            python_version: None,
+            commit: None,
+            configured_env: Default::default(),
+            databases: Vec::new(),
+            gpu_reasons: Vec::new(),
         }
     }
 
+    #[test]
+    fn test_summarize_actions() {
+        let decision = InfrastructureDecision {
+            deployment_type: DeploymentType::SingleVM,
+            instance_type: "t3.micro".to_string(),
+            terraform_config: TerraformConfig {
+                provider: "aws".to_string(),
+                resources: vec![
+                    TerraformResource {
+                        resource_type: "aws_instance".to_string(),
+                        name: "app".to_string(),
+                        config: HashMap::from([("instance_type".to_string(), serde_json::json!("t3.small"))]),
+                    },
+                    TerraformResource {
+                        resource_type: "aws_security_group".to_string(),
+                        name: "app_sg".to_string(),
+                        config: HashMap::from([(
+                            "ingress".to_string(),
+                            serde_json::json!([
+                                {"from_port": 22, "to_port": 22, "protocol": "tcp"},
+                                {"from_port": 5000, "to_port": 5000, "protocol": "tcp"}
+                            ]),
+                        )]),
+                    },
+                ],
+                variables: HashMap::new(),
+                outputs: HashMap::new(),
+            },
+            estimated_cost: estimate_cost(&DeploymentType::SingleVM, "t3.small", &CloudProvider::AWS, false, None, &[]),
+            justification: String::new(),
+            required_registries: vec![],
+            exposed_ports: vec![22, 5000],
+            health_check: None,
+            name: None,
+            disk_size_gb: None,
+            commit: None,
+            dockerfile: None,
+        };
+        
+        assert_eq!(
+            summarize_actions(&decision, "us-east-1"),
+            vec![
+                "Create 1 EC2 t3.small instance in us-east-1",
+                "Create 1 security group opening ports 22/5000",
+                "Estimated cost: $8.76/mo",
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_decide_infrastructure_single_vm() {
         let requirements = create_test_requirements();
@@ -544,7 +2669,7 @@ mod tests {
 
         assert!(matches!(decision.deployment_type, DeploymentType::SingleVM));
         assert_eq!(decision.instance_type, "t3.micro");
-        assert!(decision.estimated_cost > 0.0);
+        assert!(decision.estimated_cost.total > 0.0);
         assert!(decision.justification.contains("Flask"));
     }
 
@@ -631,6 +2756,297 @@ mod tests {
         assert!(main_tf_content.contains("aws_instance"));
     }
 
+    fn sample_aws_config() -> TerraformConfig {
+        TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                TerraformResource {
+                    resource_type: "aws_instance".to_string(),
+                    name: "app".to_string(),
+                    config: HashMap::from([
+                        ("ami".to_string(), serde_json::json!("resolve:ssm:/aws/service/canonical/ubuntu/server/22.04/stable/current/amd64/hvm/ebs-gp2/ami-id")),
+                        ("instance_type".to_string(), serde_json::json!("t3.micro")),
+                        ("user_data".to_string(), serde_json::json!("#!/bin/bash\necho \"hello\"\n")),
+                        ("tags".to_string(), serde_json::json!({"Name": "app", "managed-by": "autodeployment", "Region": "var.region"})),
+                    ]),
+                },
+                TerraformResource {
+                    resource_type: "aws_security_group".to_string(),
+                    name: "app_sg".to_string(),
+                    config: HashMap::from([(
+                        "ingress".to_string(),
+                        serde_json::json!([{
+                            "from_port": 5000,
+                            "to_port": 5000,
+                            "protocol": "tcp",
+                            "cidr_blocks": ["0.0.0.0/0"],
+                            "description": "app",
+                            "ipv6_cidr_blocks": [],
+                            "prefix_list_ids": [],
+                            "security_groups": [],
+                            "self": false
+                        }]),
+                    )]),
+                },
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::from([(
+                "instance_ip".to_string(),
+                serde_json::json!({"value": "aws_instance.app.public_ip", "description": "Public IP"}),
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_generated_files_are_deterministic() {
+        // Each config gets fresh HashMaps, so their iteration orders differ
+        let config = || {
+            let mut config = sample_aws_config();
+            for i in 0..16 {
+                config.variables.insert(
+                    format!("var_{:02}", i),
+                    serde_json::json!({"type": "string", "default": i.to_string()}),
+                );
+                config.outputs.insert(
+                    format!("output_{:02}", i),
+                    serde_json::json!({"value": format!("var.var_{:02}", i)}),
+                );
+            }
+            config
+        };
+
+        for format in [TerraformFormat::Hcl, TerraformFormat::Json] {
+            let first = tempfile::tempdir().unwrap();
+            let second = tempfile::tempdir().unwrap();
+            for dir in [&first, &second] {
+                match format {
+                    TerraformFormat::Hcl => generate_terraform_files(&config(), dir.path(), "https://github.com/test/repo"),
+                    TerraformFormat::Json => generate_terraform_json_files(&config(), dir.path(), "https://github.com/test/repo"),
+                }
+                .unwrap();
+            }
+
+            for entry in fs::read_dir(first.path()).unwrap() {
+                let name = entry.unwrap().file_name();
+                assert_eq!(
+                    fs::read(first.path().join(&name)).unwrap(),
+                    fs::read(second.path().join(&name)).unwrap(),
+                    "{:?} differs between runs",
+                    name
+                );
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        generate_terraform_files(&config(), temp_dir.path(), "https://github.com/test/repo").unwrap();
+        let variables = fs::read_to_string(temp_dir.path().join("variables.tf")).unwrap();
+        assert!(variables.find("var_00").unwrap() < variables.find("var_15").unwrap());
+    }
+
+    #[test]
+    fn test_provider_blocks_for_every_cloud() {
+        let config = |provider: &str, resource_type: &str| TerraformConfig {
+            provider: provider.to_string(),
+            resources: vec![TerraformResource {
+                resource_type: resource_type.to_string(),
+                name: "app".to_string(),
+                config: HashMap::new(),
+            }],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let cases = [
+            (config("google", "google_compute_instance"), "provider \"google\" {"),
+            (config("azure", "azurerm_linux_virtual_machine"), "provider \"azurerm\" {\n  features {}\n}"),
+            (config("digitalocean", "digitalocean_droplet"), "source  = \"digitalocean/digitalocean\""),
+            // An unknown provider name is inferred from the resources
+            (config("microsoft", "azurerm_linux_virtual_machine"), "source  = \"hashicorp/azurerm\""),
+        ];
+        for (config, expected) in cases {
+            let temp_dir = tempfile::tempdir().unwrap();
+            generate_terraform_files(&config, temp_dir.path(), "https://github.com/test/repo").unwrap();
+            let main_tf = fs::read_to_string(temp_dir.path().join("main.tf")).unwrap();
+            assert!(main_tf.contains(expected), "{} has no {}:\n{}", config.provider, expected, main_tf);
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        generate_terraform_json_files(&config("azurerm", "azurerm_linux_virtual_machine"), temp_dir.path(), "https://github.com/test/repo").unwrap();
+        let main: serde_json::Value = serde_json::from_str(&fs::read_to_string(temp_dir.path().join("main.tf.json")).unwrap()).unwrap();
+        assert_eq!(main["provider"]["azurerm"]["features"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_generate_terraform_json_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        generate_terraform_json_files(&sample_aws_config(), temp_dir.path(), "https://github.com/test/repo").unwrap();
+
+        let read = |name: &str| -> serde_json::Value {
+            serde_json::from_str(&fs::read_to_string(temp_dir.path().join(name)).unwrap()).unwrap()
+        };
+        let main = read("main.tf.json");
+        assert_eq!(main["provider"]["aws"]["region"], "${var.region}");
+        let instance = &main["resource"]["aws_instance"]["app"];
+        assert_eq!(instance["instance_type"], "t3.micro");
+        // Literal strings are serialized as-is, no HCL escaping involved
+        assert_eq!(instance["user_data"], "#!/bin/bash\necho \"hello\"\n");
+        assert_eq!(instance["tags"]["Region"], "${var.region}");
+        assert_eq!(main["resource"]["aws_security_group"]["app_sg"]["ingress"][0]["from_port"], 5000);
+
+        let variables = read("variables.tf.json");
+        assert_eq!(variables["variable"]["repository_url"]["default"], "https://github.com/test/repo");
+        assert_eq!(read("outputs.tf.json")["output"]["instance_ip"]["value"], "${aws_instance.app.public_ip}");
+
+        assert_eq!("json".parse::<TerraformFormat>().unwrap(), TerraformFormat::Json);
+        assert_eq!("HCL".parse::<TerraformFormat>().unwrap(), TerraformFormat::Hcl);
+        assert!("yaml".parse::<TerraformFormat>().is_err());
+    }
+
+    #[test]
+    fn test_edit_decision_instance_type_and_ports() {
+        let mut decision = prebuilt_decision(sample_aws_config(), &CloudProvider::AWS);
+        assert_eq!((decision.instance_type.as_str(), decision.exposed_ports.clone()), ("t3.micro", vec![5000]));
+
+        assert_eq!(decision.set_instance_type("t3.small"), vec!["aws_instance.app"]);
+        assert_eq!(decision.terraform_config.resources[0].config["instance_type"], "t3.small");
+        assert_eq!(decision.estimated_cost.breakdown[0].description, "t3.small instance");
+        assert_eq!(decision.estimated_cost.breakdown[0].monthly, 15.18);
+        assert!((decision.estimated_cost.total - decision.estimated_cost.breakdown.iter().map(|item| item.monthly).sum::<f64>()).abs() < 1e-9);
+        decision.set_instance_type("g4dn.xlarge");
+        assert_eq!(decision.estimated_cost.compute, 383.98);
+        decision.set_instance_type("m7i.large");
+        assert_eq!(decision.estimated_cost.breakdown[0].description, "m7i.large instance (priced as g4dn.xlarge)");
+        assert_eq!(decision.estimated_cost.compute, 383.98);
+        decision.set_instance_type("t3.small");
+
+        assert_eq!(decision.open_port(8080), vec!["aws_security_group.app_sg"]);
+        assert_eq!(firewall_ports(&decision.terraform_config.resources[1].config["ingress"]), vec![5000, 8080]);
+        assert_eq!(decision.close_port(5000), vec!["aws_security_group.app_sg"]);
+        assert_eq!(firewall_ports(&decision.terraform_config.resources[1].config["ingress"]), vec![8080]);
+        assert_eq!(decision.exposed_ports, vec![8080]);
+
+        let firewall = TerraformResource {
+            resource_type: "google_compute_firewall".to_string(),
+            name: "web".to_string(),
+            config: HashMap::from([("allow".to_string(), serde_json::json!({"protocol": "tcp", "ports": ["22", "5000"]}))]),
+        };
+        let mut gcp = prebuilt_decision(TerraformConfig { resources: vec![firewall], ..sample_aws_config() }, &CloudProvider::GCP);
+        gcp.open_port(443);
+        assert_eq!(gcp.terraform_config.resources[0].config["allow"], serde_json::json!([{"protocol": "tcp", "ports": ["22", "5000", "443"]}]));
+        for port in [22, 5000, 443] {
+            gcp.close_port(port);
+        }
+        assert!(gcp.terraform_config.resources.is_empty(), "a firewall allowing nothing would allow every port");
+        assert_eq!("load-balanced".parse::<ScalingRequirements>().unwrap(), ScalingRequirements::LoadBalanced);
+    }
+
+    #[test]
+    fn test_json_to_hcl_nested_blocks_in_lists() {
+        let network_interface = serde_json::json!([{
+            "network": "default",
+            "access_config": [{"network_tier": "PREMIUM", "nat_ip": null}],
+            "alias_ip_range": [{"ip_cidr_range": "10.2.0.0/24", "subnetwork_range_name": "pods"}],
+            "labels": {"team": "web", "kubernetes.io/role": "node"},
+            "ports": [80, 443],
+        }, {
+            "network": "var.extra_network",
+            "access_config": [],
+        }]);
+        let hcl = json_to_hcl("network_interface", &network_interface, 1);
+        assert!(
+            hcl.starts_with("network_interface {\n    access_config {\n      nat_ip = null\n      network_tier = \"PREMIUM\"\n    }\n    alias_ip_range {\n"),
+            "{}",
+            hcl
+        );
+        assert!(hcl.contains("\n    ports = [80, 443]\n"), "{}", hcl);
+        assert!(hcl.contains("\n      \"kubernetes.io/role\" = \"node\"\n"), "{}", hcl);
+        assert!(hcl.ends_with("\n  }\n  network_interface {\n    access_config = []\n    network = var.extra_network\n  }"), "{}", hcl);
+
+        // Keys that cannot name attributes make a map, not a block
+        let metadata = serde_json::json!({"startup-script": "echo hi", "enable-oslogin": "TRUE", "ssh.keys": {"admin": "ssh-ed25519 AAAA"}});
+        assert!(json_to_hcl("metadata", &metadata, 1).starts_with("metadata = {\n"));
+
+        if which("terraform").is_err() {
+            eprintln!("terraform not installed, skipping the HCL syntax check");
+            return;
+        }
+        let instance = format!(
+            "resource \"google_compute_instance\" \"app\" {{\n  {}\n  {}\n  {}\n}}\n",
+            hcl,
+            json_to_hcl("metadata", &metadata, 1),
+            json_to_hcl("boot_disk", &serde_json::json!({"initialize_params": {"image": "debian-cloud/debian-12", "labels": {"disk": "root"}}}), 1)
+        );
+        let mut fmt = Command::new("terraform")
+            .args(["fmt", "-no-color", "-"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(&mut fmt.stdin.take().unwrap(), instance.as_bytes()).unwrap();
+        let output = fmt.wait_with_output().unwrap();
+        assert!(output.status.success(), "invalid HCL:\n{}\n{}", instance, String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn test_json_to_hcl_metadata_with_hyphenated_keys() {
+        let metadata = serde_json::json!({"ssh-keys": "ubuntu:ssh-ed25519 AAAA", "enable-oslogin": "FALSE", "install-nvidia-driver": "True"});
+        let instance = serde_json::json!({"machine_type": "e2-small", "metadata": metadata});
+        let hcl = hcl_block("google_compute_instance", instance.as_object().unwrap(), 0);
+        assert!(hcl.contains("\n  metadata = {\n    \"enable-oslogin\" = \"FALSE\"\n"), "{}", hcl);
+        assert!(!hcl.contains("metadata {"), "{}", hcl);
+
+        let lambda = serde_json::json!({"environment": {"variables": {"LOG_LEVEL": "info"}}});
+        let hcl = hcl_block("aws_lambda_function", lambda.as_object().unwrap(), 0);
+        assert!(hcl.contains("environment {\n    variables = {\n"), "{}", hcl);
+    }
+
+    #[test]
+    fn test_aws_provider_points_at_custom_endpoint() {
+        let arguments = aws_provider_arguments(None);
+        assert_eq!(serde_json::Value::Object(arguments), serde_json::json!({"region": "var.region"}));
+
+        let arguments = aws_provider_arguments(Some(LOCALSTACK_ENDPOINT));
+        assert_eq!(arguments["access_key"], "test");
+        assert_eq!((arguments["skip_credentials_validation"].clone(), arguments["s3_use_path_style"].clone()), (json!(true), json!(true)));
+        let provider: Vec<String> = arguments.iter().map(|(key, value)| json_to_hcl(key, value, 1)).collect();
+        let provider = provider.join("\n  ");
+        assert!(provider.contains("endpoints {\n    apigateway = \"http://localhost:4566\"\n"), "{}", provider);
+        assert!(provider.contains("\n    s3 = \"http://localhost:4566\"\n"), "{}", provider);
+        assert!(provider.contains("region = var.region"), "{}", provider);
+
+        assert!(set_aws_endpoint("localhost:4566").unwrap_err().to_string().contains("http://"));
+    }
+
+    #[test]
+    fn test_both_formats_pass_terraform_validate() {
+        if which("terraform").is_err() {
+            eprintln!("terraform not installed, skipping validation");
+            return;
+        }
+
+        let terraform = |dir: &Path, args: &[&str]| {
+            let output = Command::new("terraform").args(args).current_dir(dir).output().unwrap();
+            assert!(
+                output.status.success(),
+                "terraform {:?} failed in {}:\n{}",
+                args,
+                dir.display(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        };
+
+        let config = sample_aws_config();
+        let hcl_dir = tempfile::tempdir().unwrap();
+        let json_dir = tempfile::tempdir().unwrap();
+        generate_terraform_files(&config, hcl_dir.path(), "https://github.com/test/repo").unwrap();
+        generate_terraform_json_files(&config, json_dir.path(), "https://github.com/test/repo").unwrap();
+
+        for dir in [hcl_dir.path(), json_dir.path()] {
+            terraform(dir, &["init", "-backend=false", "-input=false", "-no-color"]);
+            terraform(dir, &["validate", "-no-color"]);
+        }
+    }
+
     #[tokio::test]
     async fn test_provision_infrastructure_dry_run() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -646,6 +3062,7 @@ mod tests {
             temp_dir.path(),
             true, // dry_run
             &requirements.cloud_provider, // Add the missing fifth argument
+            None,
         ));
 
         assert!(result.is_ok());
@@ -670,7 +3087,8 @@ mod tests {
             "https://github.com/test/repo",
             temp_dir.path(),
             false,
-            &requirements.cloud_provider // not dry_run
+            &requirements.cloud_provider, // not dry_run
+            None,
         ));
 
         // Should fail because Terraform is not installed
@@ -681,131 +3099,324 @@ mod tests {
             .contains("Terraform is not installed"));
     }
 
+    #[tokio::test]
+    async fn test_terraform_command_killed_at_deadline() {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("30");
+        let started = std::time::Instant::now();
+        let output = terraform_output(sleep, Some(Instant::now() + Duration::from_millis(200)), Duration::from_secs(5)).await.unwrap();
+        assert!(output.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5), "sleep stops on the interrupt");
+
+        let mut echo = Command::new("echo");
+        echo.arg("done");
+        let output = terraform_output(echo, Some(Instant::now() + Duration::from_secs(30)), Duration::from_secs(5)).await.unwrap().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "done");
+        assert!(timeout_error("apply", Some(DEFAULT_TERRAFORM_TIMEOUT)).to_string().contains("after 20 minutes"));
+    }
+
+    #[tokio::test]
+    async fn test_terraform_apply_interrupted_then_destroyed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let state = temp_dir.path().join("terraform.tfstate");
+        // Like terraform apply: on an interrupt, finish writing the state, then exit
+        let mut apply = Command::new("sh");
+        apply
+            .arg("-c")
+            .arg(format!("trap 'kill $!; sleep 0.3; echo saved > {}; exit 1' INT; sleep 30 & wait", state.display()));
+        let started = std::time::Instant::now();
+        let output = terraform_output(apply, Some(Instant::now() + Duration::from_millis(300)), Duration::from_secs(10)).await.unwrap();
+        assert!(output.is_none());
+        assert_eq!(fs::read_to_string(&state).unwrap().trim(), "saved", "the apply got to save its state");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // One that ignores the interrupt is killed once the grace period is over
+        let mut stuck = Command::new("sh");
+        stuck.arg("-c").arg("trap '' INT; sleep 30 & wait");
+        let started = std::time::Instant::now();
+        let output = terraform_output(stuck, Some(Instant::now() + Duration::from_millis(200)), Duration::from_millis(300)).await.unwrap();
+        assert!(output.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // The destroy that follows waits for the lock the apply is releasing
+        let args = destroy_args(&CloudCredentials::new(), &CloudProvider::AWS, "us-east-1");
+        assert_eq!(args[..4], ["destroy", "-auto-approve", "-no-color", "-lock-timeout=5m"]);
+    }
+
+    #[test]
+    fn test_invalid_configuration_output() {
+        let invalid = InvalidConfiguration::from_output(b"\nError: Unsupported argument\n  on main.tf line 4\n", b"");
+        assert_eq!(
+            invalid.to_string(),
+            "Terraform validate rejected the generated configuration:\nError: Unsupported argument\n  on main.tf line 4"
+        );
+        assert_eq!(InvalidConfiguration::from_output(b"", b"Error: Missing required argument\n").output, "Error: Missing required argument");
+        let err: anyhow::Error = invalid.into();
+        assert!(err.downcast_ref::<InvalidConfiguration>().is_some());
+    }
+
+    #[test]
+    fn test_detect_quota_error() {
+        let aws = "Error: creating EC2 Instance: InstanceLimitExceeded: You have requested more instances (21) than your current instance limit of 20 allows";
+        let quota = detect_quota_error(aws, &CloudProvider::AWS, "us-east-1").unwrap();
+        assert_eq!(
+            quota.to_string(),
+            "Your AWS account has reached its EC2 instance limit in us-east-1; request a quota increase or try another region"
+        );
+        
+        let gcp = "Error: Error creating instance: googleapi: Error 403: Quota 'CPUS' exceeded.  Limit: 8.0 in region europe-west1., quotaExceeded";
+        let quota = detect_quota_error(gcp, &CloudProvider::GCP, "us-central1").unwrap();
+        assert_eq!(quota.quota, "CPUS quota");
+        assert_eq!(quota.region, "europe-west1");
+        
+        let err: anyhow::Error = quota.into();
+        assert!(err.downcast_ref::<QuotaExceeded>().is_some());
+        
+        assert!(detect_quota_error("Error: Invalid AMI ID", &CloudProvider::AWS, "us-east-1").is_none());
+    }
+
+    #[test]
+    fn test_compare_providers() {
+        let requirements = create_test_requirements();
+        let analysis = create_test_analysis();
+        let estimates = compare_providers(&requirements, &analysis);
+
+        let rows: Vec<_> = estimates
+            .iter()
+            .map(|e| (e.cloud_provider.clone(), e.instance_type.as_str(), (e.estimated_cost.total * 100.0).round() / 100.0, e.disk_size_gb))
+            .collect();
+        // Instance, the default root disk (which starts at 30 GB on Azure) and the managed PostgreSQL
+        assert_eq!(rows, vec![
+            (CloudProvider::AWS, "t3.micro", 24.27, Some(10)),
+            (CloudProvider::GCP, "e2-micro", 15.69, Some(10)),
+            (CloudProvider::Azure, "Standard_B1s", 26.96, Some(30)),
+        ]);
+        assert!(estimates.iter().all(|e| matches!(e.deployment_type, DeploymentType::SingleVM)));
+    }
+
+    #[test]
+    fn test_private_network_adds_nat_and_lb_cost() {
+        let mut requirements = create_test_requirements();
+        requirements.private_network = true;
+        let estimate = estimate_for_provider(&requirements, &create_test_analysis(), &CloudProvider::AWS);
+        assert!((estimate.estimated_cost.total - (8.76 + 32.85 + 16.43 + 0.80 + 12.41 + 2.30)).abs() < 1e-9);
+        assert!((estimate.estimated_cost.networking - (32.85 + 16.43)).abs() < 1e-9);
+        assert_eq!(private_network_cost(false, &CloudProvider::AWS), 0.0);
+
+        let mut config = sample_aws_config();
+        let error = require_nat_gateway(&config, &CloudProvider::AWS).unwrap_err().to_string();
+        assert!(error.contains("no NAT gateway (aws_nat_gateway)"), "{}", error);
+        assert!(error.contains("deploy without --private"), "{}", error);
+        config.resources.push(TerraformResource {
+            resource_type: "aws_nat_gateway".to_string(),
+            name: "nat".to_string(),
+            config: HashMap::new(),
+        });
+        assert!(require_nat_gateway(&config, &CloudProvider::AWS).is_ok());
+    }
+
     #[test]
     fn test_cost_estimation() {
-        let single_vm_cost = estimate_cost(&DeploymentType::SingleVM, &CloudProvider::AWS);
-        let serverless_cost = estimate_cost(&DeploymentType::Serverless, &CloudProvider::AWS);
-        let static_cost = estimate_cost(&DeploymentType::StaticSite, &CloudProvider::AWS);
+        let cost = |deployment_type: DeploymentType| estimate_cost(&deployment_type, "t3.micro", &CloudProvider::AWS, false, None, &[]).total;
+        let single_vm_cost = cost(DeploymentType::SingleVM);
+        let serverless_cost = cost(DeploymentType::Serverless);
+        let static_cost = cost(DeploymentType::StaticSite);
 
         assert!(single_vm_cost > 0.0);
         assert!(serverless_cost > 0.0);
         assert!(static_cost > 0.0);
         assert!(single_vm_cost > static_cost); // VM should cost more than static hosting
+        assert_eq!(single_vm_cost, 8.76);
+        
+        let itemized = estimate_cost(
+            &DeploymentType::SingleVM,
+            "t3.micro",
+            &CloudProvider::AWS,
+            true,
+            Some(20),
+            &[DatabaseType::PostgreSQL, DatabaseType::Redis],
+        );
+        let lines: Vec<_> = itemized.breakdown.iter().map(|item| (item.category, item.description.as_str())).collect();
+        assert_eq!(lines, vec![
+            (CostCategory::Compute, "t3.micro instance"),
+            (CostCategory::Storage, "20 GB root disk"),
+            (CostCategory::Database, "PostgreSQL, RDS db.t3.micro + 20 GB gp2"),
+            (CostCategory::Database, "Redis, ElastiCache cache.t3.micro"),
+            (CostCategory::Networking, "NAT gateway"),
+            (CostCategory::Networking, "Application load balancer"),
+            (CostCategory::Networking, "Data transfer out, 10 GB (100 GB/mo free)"),
+        ]);
+        let categories = itemized.compute + itemized.storage + itemized.database + itemized.networking;
+        assert!((itemized.total - categories).abs() < 1e-9);
+        assert!((itemized.database - (14.71 + 12.41)).abs() < 1e-9);
     }
 
     #[test]
     fn test_cloud_provider_instance_types() {
         // Test AWS
-        let aws_vm = determine_instance_type(&DeploymentType::SingleVM, &CloudProvider::AWS);
+        let aws_vm = determine_instance_type(&DeploymentType::SingleVM, &CloudProvider::AWS, false);
         assert_eq!(aws_vm, "t3.micro");
 
         // Test GCP
-        let gcp_vm = determine_instance_type(&DeploymentType::SingleVM, &CloudProvider::GCP);
+        let gcp_vm = determine_instance_type(&DeploymentType::SingleVM, &CloudProvider::GCP, false);
         assert_eq!(gcp_vm, "e2-micro");
 
         // Test serverless
-        let serverless = determine_instance_type(&DeploymentType::Serverless, &CloudProvider::AWS);
+        let serverless = determine_instance_type(&DeploymentType::Serverless, &CloudProvider::AWS, false);
         assert_eq!(serverless, "lambda");
+
+        // A containerized app that needs a GPU runs on a GPU VM where the provider has one
+        let mut analysis = create_test_analysis();
+        analysis.docker_config = Some(crate::repository::DockerConfig {
+            dockerfile_path: "Dockerfile".to_string(),
+            exposed_ports: vec![8000],
+            volumes: vec![],
+            start_command: None,
+        });
+        let mut requirements = DeploymentRequirements::default();
+        assert!(matches!(determine_deployment_type(&requirements, &analysis, &CloudProvider::AWS), DeploymentType::ContainerService));
+        requirements.gpu = true;
+        assert!(matches!(determine_deployment_type(&requirements, &analysis, &CloudProvider::AWS), DeploymentType::SingleVM));
+        assert!(matches!(determine_deployment_type(&requirements, &analysis, &CloudProvider::Azure), DeploymentType::ContainerService));
     }
-}
 
-fn escape_hcl_string(s: &str) -> String {
-    s.replace('\\', "\\\\")
-     .replace('"', "\\\"")
-     .replace('\n', "\\n")
-     .replace('\r', "\\r")
-     .replace('\t', "\\t")
-}
+    #[test]
+    fn test_multi_region_routing() {
+        let regions = |list: &str| list.split(',').map(str::to_string).collect::<Vec<_>>();
+        assert!(validate_regions(&regions("us-east-1,eu-west-1"), &CloudProvider::AWS).is_ok());
+        assert!(validate_regions(&regions("us-east-1"), &CloudProvider::AWS).is_err());
+        assert!(validate_regions(&regions("us-east-1,us-east-1"), &CloudProvider::AWS).is_err());
+        assert!(validate_regions(&regions("us-east-1,europe"), &CloudProvider::AWS).is_err());
+        assert!(validate_regions(&regions("us-east1,europe-west1"), &CloudProvider::GCP).is_err());
+        let regional = estimate_cost(&DeploymentType::SingleVM, "t3.micro", &CloudProvider::AWS, false, None, &[]);
+        assert_eq!(multi_region_cost(&regional, 1).total, 8.76);
+        let summed = multi_region_cost(&regional, 2);
+        assert!((summed.total - 18.52).abs() < 0.001);
+        assert_eq!(summed.breakdown.last().unwrap().description, "Route53 health check x2");
+
+        let endpoints = vec![
+            RegionalEndpoint { region: "us-east-1".to_string(), ip: "203.0.113.10".to_string(), port: 80 },
+            RegionalEndpoint { region: "eu-west-1".to_string(), ip: "203.0.113.20".to_string(), port: 80 },
+        ];
+        let main_tf = latency_routing_tf("app.example.com", None, &endpoints, "/health");
+        assert!(main_tf.contains("name = \"example.com\""));
+        assert!(main_tf.contains("resource \"aws_route53_health_check\" \"eu_west_1\""));
+        assert!(main_tf.contains("resource_path = \"/health\""));
+        assert!(main_tf.contains("set_identifier = \"us-east-1\""));
+        assert!(main_tf.contains("health_check_id = aws_route53_health_check.us_east_1.id"));
+        assert!(main_tf.contains("records = [\"203.0.113.20\"]"));
+        assert_eq!(main_tf.matches("latency_routing_policy").count(), 2);
+    }
 
-fn json_to_hcl(key: &str, value: &serde_json::Value, indent_level: usize) -> String {
-    let indent = "  ".repeat(indent_level);
-    
-    match value {
-        serde_json::Value::String(s) => {
-            // Don't quote if it's a Terraform variable reference
-            if s.starts_with("var.") || s.starts_with("${") {
-                format!("{} = {}", key, s)
-            } else {
-                // Properly escape the string for HCL
-                let escaped = escape_hcl_string(s);
-                format!("{} = \"{}\"", key, escaped)
-            }
-        }
-        serde_json::Value::Number(n) => {
-            format!("{} = {}", key, n)
-        }
-        serde_json::Value::Bool(b) => {
-            format!("{} = {}", key, b)
-        }
-        serde_json::Value::Array(arr) => {
-            if arr.is_empty() {
-                format!("{} = []", key)
-            } else if arr.iter().all(|v| v.is_string()) {
-                // Simple string array
-                let items: Vec<String> = arr.iter()
-                    .filter_map(|v| v.as_str())
-                    .map(|s| format!("\"{}\"", escape_hcl_string(s)))
-                    .collect();
-                format!("{} = [{}]", key, items.join(", "))
-            } else {
-                // Complex array - format as multiple blocks
-                let mut result = String::new();
-                for item in arr {
-                    if let serde_json::Value::Object(obj) = item {
-                        result.push_str(&format!("{} {{\n", key));
-                        for (subkey, subvalue) in obj {
-                            result.push_str(&format!("{}  {}\n", indent, json_to_hcl(subkey, subvalue, indent_level + 1)));
-                        }
-                        result.push_str(&format!("{}}}\n", indent));
-                    }
-                }
-                result.trim_end().to_string()
-            }
-        }
-        serde_json::Value::Object(obj) => {
-            // Handle as a block
-            let mut result = format!("{} {{\n", key);
-            for (subkey, subvalue) in obj {
-                result.push_str(&format!("{}  {}\n", indent, json_to_hcl(subkey, subvalue, indent_level + 1)));
-            }
-            result.push_str(&format!("{}}}", indent));
-            result
-        }
-        serde_json::Value::Null => {
-            format!("{} = null", key)
+    #[test]
+    fn test_deployment_names() {
+        assert!(validate_deployment_name("my-demo").is_ok());
+        assert!(validate_deployment_name("api2").is_ok());
+        for invalid in ["", "My-Demo", "my_demo", "-demo", "demo-", "demo.example", &"a".repeat(64)] {
+            assert!(validate_deployment_name(invalid).is_err(), "{:?} accepted", invalid);
         }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let first = new_unique_dir(temp_dir.path(), "deployment_my-demo").unwrap();
+        let second = new_unique_dir(temp_dir.path(), "deployment_my-demo").unwrap();
+        assert!(first.ends_with("deployment_my-demo"));
+        assert!(second.ends_with("deployment_my-demo_2"));
     }
-}
 
-/// Add sed commands to startup scripts to replace localhost with 0.0.0.0 after git clone
-fn replace_git_clone_with_download(script: &serde_json::Value, _download_url: &str) -> serde_json::Value {
-    if let serde_json::Value::String(script_str) = script {
-        let mut modified_script = script_str.clone();
-        
-        // If script contains git clone, add localhost replacement commands after it
-        if script_str.contains("git clone") {
-            // Add comprehensive sed commands to replace localhost references in all relevant files
-            let sed_commands = " && find . -name '*.py' -exec sed -i 's/127\\.0\\.0\\.1/0.0.0.0/g' {} \\; && find . -name '*.py' -exec sed -i 's/localhost/0.0.0.0/g' {} \\; && find . -name '*.html' -exec sed -i 's/http:\\/\\/localhost:5000//g' {} \\; && find . -name '*.js' -exec sed -i 's/http:\\/\\/localhost:5000//g' {} \\; && find . -name '*.ts' -exec sed -i 's/http:\\/\\/localhost:5000//g' {} \\;";
-            
-            // Insert sed commands after any git clone and cd commands
-            if let Some(pos) = script_str.rfind(" && cd ") {
-                // Find the end of the cd command (next && or end of string)
-                let after_cd = &script_str[pos + 6..]; // Skip " && cd "
-                if let Some(next_and) = after_cd.find(" && ") {
-                    let insert_pos = pos + 6 + next_and;
-                    modified_script.insert_str(insert_pos, sed_commands);
-                } else {
-                    // cd is at the end, append sed commands
-                    modified_script.push_str(sed_commands);
-                }
-            } else if script_str.contains("git clone") {
-                // No cd command, just append sed commands at the end
-                modified_script.push_str(sed_commands);
-            }
-        }
-        
-        serde_json::Value::String(modified_script)
-    } else {
-        script.clone()
+    #[test]
+    fn test_firewall_names_follow_deployment_id() {
+        let mut config = sample_aws_config();
+        config.resources.push(TerraformResource {
+            resource_type: "google_compute_firewall".to_string(),
+            name: "web".to_string(),
+            config: HashMap::from([("name".to_string(), serde_json::json!("allow-http"))]),
+        });
+        let temp_dir = tempfile::tempdir().unwrap();
+        let generate = |dir: &str, id: Option<&str>| {
+            let dir = temp_dir.path().join(dir);
+            fs::create_dir(&dir).unwrap();
+            correlation::sync_scope(id.map(str::to_string), || {
+                generate_terraform_files(&config, &dir, "https://github.com/test/repo").unwrap();
+                generate_terraform_json_files(&config, &dir, "https://github.com/test/repo").unwrap();
+            });
+            (fs::read_to_string(dir.join("main.tf")).unwrap(), fs::read_to_string(dir.join("main.tf.json")).unwrap())
+        };
+
+        let (hcl, json) = generate("first", Some("a1b2c3d4"));
+        assert!(hcl.contains("  name = \"allow-http-a1b2c3d4\"\n"), "{}", hcl);
+        assert!(json.contains("\"allow-http-a1b2c3d4\""), "{}", json);
+        assert_eq!(generate("again", Some("a1b2c3d4")), (hcl, json));
+        let (hcl, _) = generate("deployment_20261016_120000", None);
+        assert!(hcl.contains("  name = \"allow-http-20261016-120000\"\n"), "{}", hcl);
+    }
+
+    #[test]
+    fn test_config_resource_addresses() {
+        let hcl_dir = tempfile::tempdir().unwrap();
+        generate_terraform_files(&sample_aws_config(), hcl_dir.path(), "https://github.com/test/repo").unwrap();
+        let json_dir = tempfile::tempdir().unwrap();
+        generate_terraform_json_files(&sample_aws_config(), json_dir.path(), "https://github.com/test/repo").unwrap();
+
+        let addresses = config_resource_addresses(hcl_dir.path()).unwrap();
+        assert!(addresses.contains(&"aws_instance.app".to_string()), "{:?}", addresses);
+        assert_eq!(config_resource_addresses(json_dir.path()).unwrap(), addresses);
+    }
+
+    #[test]
+    fn test_load_prebuilt_terraform_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.json");
+        fs::write(&path, serde_json::to_string(&sample_aws_config()).unwrap()).unwrap();
+        let decision = prebuilt_decision(load_terraform_config(&path).unwrap(), &CloudProvider::AWS);
+        assert_eq!((decision.instance_type.as_str(), decision.exposed_ports.clone()), ("t3.micro", vec![5000]));
+        assert!(matches!(decision.deployment_type, DeploymentType::SingleVM));
+
+        let mut dangling = sample_aws_config();
+        dangling.outputs.insert("db".to_string(), serde_json::json!({"value": "aws_db_instance.db.address"}));
+        dangling.resources[0].config.insert("key_name".to_string(), serde_json::json!("var.key_name"));
+        dangling.resources[0].config.insert("ami".to_string(), serde_json::json!("${data.aws_ami.ubuntu.id}"));
+        let error = check_references(&dangling).unwrap_err().to_string();
+        assert!(error.contains("output db references missing resource aws_db_instance.db"), "{}", error);
+        assert!(error.contains("aws_instance.app uses undeclared variable var.key_name"), "{}", error);
+        assert!(!error.contains("aws_ami"), "data sources are not resources: {}", error);
+
+        fs::write(&path, r#"{"provider": "aws", "resources": [{"resource_type": "aws_instance", "name": 1}]}"#).unwrap();
+        let error = load_terraform_config(&path).unwrap_err().to_string();
+        assert!(error.contains("at `resources[0].name`"), "{}", error);
+    }
+
+    #[test]
+    fn test_prune_logs_keeps_tail_and_errors() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut logs: Vec<String> = (0..500).map(|i| format!("terraform line {}", i)).collect();
+        logs[10] = "❌ Terraform plan failed: quota".to_string();
+        let mut result = DeploymentResult {
+            url: "http://203.0.113.10".to_string(),
+            infrastructure_type: "SingleVM".to_string(),
+            public_ip: None,
+            logs: logs.clone(),
+            deployment_id: None,
+            serves_http: true,
+            deployment_dir: Some(temp_dir.path().to_path_buf()),
+            not_ready: None,
+            outputs: HashMap::new(),
+            cost: None,
+            startup_script: None,
+            commit: None,
+        };
+
+        let mut pruned = result.clone();
+        pruned.prune_logs(DEFAULT_MAX_LOG_LINES, false).unwrap();
+        assert_eq!(pruned.logs.len(), DEFAULT_MAX_LOG_LINES + 2);
+        assert!(pruned.logs[0].starts_with("✂️ 299 earlier log lines omitted"));
+        assert_eq!(pruned.logs[1], "❌ Terraform plan failed: quota");
+        assert_eq!(pruned.logs.last().unwrap(), "terraform line 499");
+        assert!(pruned.log_file().is_none());
+
+        result.prune_logs(3, true).unwrap();
+        let log_file = result.log_file().unwrap();
+        assert_eq!(fs::read_to_string(&log_file).unwrap(), logs.join("\n") + "\n");
+        assert_eq!(result.logs.len(), 5);
+        assert!(result.logs.last().unwrap().starts_with("📄 Full log:"));
     }
 }
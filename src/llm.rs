@@ -0,0 +1,281 @@
+use anyhow::{anyhow, Result};
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::future::Future;
+use std::str::FromStr;
+
+use crate::http;
+use crate::redact::redact_secrets;
+
+const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+const OPENAI_API_BASE: &str = "https://api.openai.com/v1";
+const OLLAMA_HOST: &str = "http://localhost:11434";
+
+/// A language model the prompts in `ai_nlp` are sent to
+pub trait LlmProvider {
+    /// The model's text answer to `prompt`
+    fn complete(&self, prompt: &str) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// Backend selected with the `LLM_PROVIDER` environment variable
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ProviderKind {
+    #[default]
+    Gemini,
+    OpenAi,
+    Ollama,
+}
+
+impl FromStr for ProviderKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "gemini" => Ok(ProviderKind::Gemini),
+            "openai" => Ok(ProviderKind::OpenAi),
+            "ollama" => Ok(ProviderKind::Ollama),
+            _ => Err(anyhow!("Unsupported LLM_PROVIDER: {}. Use: gemini, openai, ollama", s)),
+        }
+    }
+}
+
+impl ProviderKind {
+    /// `LLM_PROVIDER`, defaulting to Gemini
+    pub fn from_env() -> Result<Self> {
+        match env::var("LLM_PROVIDER") {
+            Ok(provider) if !provider.trim().is_empty() => provider.trim().parse(),
+            _ => Ok(ProviderKind::default()),
+        }
+    }
+
+    pub fn default_model(self) -> &'static str {
+        match self {
+            ProviderKind::Gemini => "gemini-2.5-flash",
+            ProviderKind::OpenAi => "gpt-4o-mini",
+            ProviderKind::Ollama => "llama3.1",
+        }
+    }
+
+    /// Environment variable holding the API key; a local Ollama needs none
+    pub fn api_key_var(self) -> Option<&'static str> {
+        match self {
+            ProviderKind::Gemini => Some("GEMINI_API_KEY"),
+            ProviderKind::OpenAi => Some("OPENAI_API_KEY"),
+            ProviderKind::Ollama => None,
+        }
+    }
+
+    fn api_key(self) -> Result<String> {
+        let var = self.api_key_var().unwrap_or_default();
+        env::var(var)
+            .ok()
+            .filter(|key| !key.trim().is_empty())
+            .ok_or_else(|| anyhow!("{} environment variable not set", var))
+    }
+}
+
+/// The configured backend, built from its environment
+pub enum Provider {
+    Gemini(Gemini),
+    OpenAi(OpenAi),
+    Ollama(Ollama),
+}
+
+impl Provider {
+    pub fn new(kind: ProviderKind, model: &str, max_output_tokens: u32) -> Result<Self> {
+        let model = model.to_string();
+        Ok(match kind {
+            ProviderKind::Gemini => Provider::Gemini(Gemini { api_key: kind.api_key()?, model, max_output_tokens }),
+            ProviderKind::OpenAi => Provider::OpenAi(OpenAi {
+                api_key: kind.api_key()?,
+                base_url: env::var("OPENAI_BASE_URL").unwrap_or_else(|_| OPENAI_API_BASE.to_string()),
+                model,
+                max_output_tokens,
+            }),
+            ProviderKind::Ollama => Provider::Ollama(Ollama {
+                host: env::var("OLLAMA_HOST").unwrap_or_else(|_| OLLAMA_HOST.to_string()),
+                model,
+                max_output_tokens,
+            }),
+        })
+    }
+}
+
+impl LlmProvider for Provider {
+    async fn complete(&self, prompt: &str) -> Result<String> {
+        match self {
+            Provider::Gemini(gemini) => gemini.complete(prompt).await,
+            Provider::OpenAi(openai) => openai.complete(prompt).await,
+            Provider::Ollama(ollama) => ollama.complete(prompt).await,
+        }
+    }
+}
+
+/// Google Gemini `generateContent`
+pub struct Gemini {
+    api_key: String,
+    model: String,
+    max_output_tokens: u32,
+}
+
+#[derive(Serialize)]
+struct GeminiRequest {
+    contents: Vec<GeminiContent>,
+    generation_config: GeminiGenerationConfig,
+}
+
+#[derive(Serialize)]
+struct GeminiContent {
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Serialize)]
+struct GeminiPart {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct GeminiGenerationConfig {
+    temperature: f32,
+    #[serde(rename = "topK")]
+    top_k: i32,
+    #[serde(rename = "topP")]
+    top_p: f32,
+    #[serde(rename = "maxOutputTokens")]
+    max_output_tokens: i32,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponse {
+    candidates: Vec<GeminiCandidate>,
+}
+
+#[derive(Deserialize)]
+struct GeminiCandidate {
+    content: GeminiResponseContent,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponseContent {
+    parts: Vec<GeminiResponsePart>,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponsePart {
+    text: String,
+}
+
+impl LlmProvider for Gemini {
+    async fn complete(&self, prompt: &str) -> Result<String> {
+        let request = GeminiRequest {
+            contents: vec![GeminiContent {
+                parts: vec![GeminiPart {
+                    text: prompt.to_string(),
+                }],
+            }],
+            generation_config: GeminiGenerationConfig {
+                temperature: 0.1,
+                top_k: 32,
+                top_p: 1.0,
+                max_output_tokens: self.max_output_tokens as i32,
+            },
+        };
+
+        let endpoint = format!("{}/{}:generateContent", GEMINI_API_BASE, self.model);
+        debug!("🔍 Making API call to: {}", endpoint);
+        // A header rather than `?key=`, so the key never shows up in a URL in an error
+        let response_text = send("Gemini", http::client().post(&endpoint).header("x-goog-api-key", &self.api_key).json(&request)).await?;
+
+        let gemini_response: GeminiResponse = serde_json::from_str(&response_text)
+            .map_err(|e| anyhow!("Failed to parse Gemini response as JSON: {}. Response: {}", e, redact_secrets(&response_text)))?;
+        gemini_response
+            .candidates
+            .into_iter()
+            .next()
+            .and_then(|candidate| candidate.content.parts.into_iter().next())
+            .map(|part| part.text)
+            .ok_or_else(|| anyhow!("No candidates in Gemini response. Full response: {}", redact_secrets(&response_text)))
+    }
+}
+
+/// OpenAI chat completions, or any API compatible with it at `OPENAI_BASE_URL`
+pub struct OpenAi {
+    api_key: String,
+    base_url: String,
+    model: String,
+    max_output_tokens: u32,
+}
+
+impl LlmProvider for OpenAi {
+    async fn complete(&self, prompt: &str) -> Result<String> {
+        let request = json!({
+            "model": self.model,
+            "messages": [{"role": "user", "content": prompt}],
+            "temperature": 0.1,
+            "max_completion_tokens": self.max_output_tokens,
+        });
+        let endpoint = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        debug!("🔍 Making API call to: {}", endpoint);
+        let response_text = send("OpenAI", http::client().post(&endpoint).bearer_auth(&self.api_key).json(&request)).await?;
+
+        let response: serde_json::Value = serde_json::from_str(&response_text)
+            .map_err(|e| anyhow!("Failed to parse OpenAI response as JSON: {}. Response: {}", e, redact_secrets(&response_text)))?;
+        response
+            .pointer("/choices/0/message/content")
+            .and_then(|content| content.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("No choices in OpenAI response. Full response: {}", redact_secrets(&response_text)))
+    }
+}
+
+/// A local Ollama server at `OLLAMA_HOST`
+pub struct Ollama {
+    host: String,
+    model: String,
+    max_output_tokens: u32,
+}
+
+impl LlmProvider for Ollama {
+    async fn complete(&self, prompt: &str) -> Result<String> {
+        let request = json!({
+            "model": self.model,
+            "prompt": prompt,
+            "stream": false,
+            "options": {"temperature": 0.1, "num_predict": self.max_output_tokens},
+        });
+        let endpoint = format!("{}/api/generate", self.host.trim_end_matches('/'));
+        debug!("🔍 Making API call to: {}", endpoint);
+        let response_text = send("Ollama", http::client().post(&endpoint).json(&request)).await?;
+
+        let response: serde_json::Value = serde_json::from_str(&response_text)
+            .map_err(|e| anyhow!("Failed to parse Ollama response as JSON: {}. Response: {}", e, redact_secrets(&response_text)))?;
+        response
+            .get("response")
+            .and_then(|text| text.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("No response text from Ollama. Full response: {}", redact_secrets(&response_text)))
+    }
+}
+
+/// Send the request and return the body of a successful response. Errors and the logged
+/// body are redacted: reqwest errors include the URL, and bodies can echo the prompt.
+async fn send(backend: &str, request: reqwest::RequestBuilder) -> Result<String> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to call {} API: {}", backend, redact_secrets(&e.to_string())))?;
+    let status = response.status();
+    debug!("🔍 Response status: {}", status);
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| anyhow!("Failed to read response text: {}", redact_secrets(&e.to_string())))?;
+    if !status.is_success() {
+        return Err(anyhow!("{} API error {}: {}", backend, status, redact_secrets(&body)));
+    }
+    trace!("🔍 Raw response body: {}", redact_secrets(&body));
+    Ok(body)
+}
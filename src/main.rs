@@ -1,13 +1,48 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
 use log::{info, error};
+use std::path::PathBuf;
 
 mod deployment;
 mod repository;
 mod infrastructure;
 mod nlp;
 mod ai_nlp;
+mod audit;
+mod bootstrap;
+mod checkpoint;
+mod concurrency;
+mod correlation;
+mod cost;
 mod credentials;
+mod database;
+mod detach;
+mod disk;
+mod dns;
+mod dockerfile;
+mod egress;
+mod env_file;
+mod feedback;
+mod gpu;
+mod health;
+mod http;
+mod index;
+mod interpolation;
+mod llm;
+mod network;
+mod policy;
+mod protection;
+mod redact;
+mod repo_config;
+mod safety;
+mod secrets;
+mod settings;
+mod source_index;
+mod ssh;
+mod stack;
+mod static_hosting;
+mod tags;
+mod version;
 
 #[derive(Parser)]
 #[clap(name = "autodeployment")]
@@ -15,13 +50,49 @@ mod credentials;
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+
+    #[clap(long, global = true, help = "Maximum parallel repository scan threads and file rewrites (default: CPU count)")]
+    concurrency: Option<usize>,
+
+    #[clap(long, global = true, help = "Maximum tokens the AI model may generate per response (default: 65536)")]
+    max_output_tokens: Option<u32>,
+
+    #[clap(long, global = true, help = "Fail on AI output that is not a single clean JSON object instead of guessing")]
+    strict_json: bool,
+
+    #[clap(long, global = true, help = "Do not initialize git submodules after cloning the repository")]
+    no_recurse_submodules: bool,
+
+    #[clap(long, global = true, value_name = "LEVELS", help = "Directory levels below the repository root scanned during analysis (default: 5)")]
+    scan_depth: Option<usize>,
+
+    #[clap(long, global = true, value_name = "DIR", help = "Write every prompt sent to the AI to DIR, secrets redacted")]
+    dump_prompts: Option<PathBuf>,
+
+    #[clap(long, global = true, value_name = "DIR", help = "Write every raw AI response to DIR, secrets redacted")]
+    dump_responses: Option<PathBuf>,
+
+    #[clap(long, global = true, help = "Parse requirements with keyword rules only, without calling the AI")]
+    offline: bool,
+
+    #[clap(long, global = true, value_name = "URL", help = "Send every AWS call to this endpoint with dummy credentials, e.g. an emulator")]
+    endpoint: Option<String>,
+
+    #[clap(long, global = true, conflicts_with = "endpoint", help = "Deploy to LocalStack at http://localhost:4566 instead of AWS")]
+    localstack: bool,
+
+    #[clap(short, long, global = true, action = clap::ArgAction::Count, help = "Log more: -v for debug, -vv for trace (raw AI requests and responses)")]
+    verbose: u8,
+
+    #[clap(long, global = true, help = "Never log the AI's raw requests and responses, even with -vv or RUST_LOG=debug")]
+    quiet_ai: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Deploy {
-        #[clap(short, long)]
-        description: String,
+        #[clap(short, long, required_unless_present = "terraform-config")]
+        description: Option<String>,
         
         #[clap(short, long)]
         repository: String,
@@ -32,28 +103,169 @@ enum Commands {
         #[clap(long)]
         dry_run: bool,
 
-        #[clap(long)]
-        force_deploy: bool,
+        #[clap(
+            long,
+            min_values = 0,
+            use_value_delimiter = true,
+            value_name = "CHECKS",
+            help = "Bypass safety checks: all of them, or only the listed ones (e.g. --force-deploy policy)"
+        )]
+        force_deploy: Option<Vec<String>>,
+
+        #[clap(long, help = "Deploy every service of a compose file or monorepo, each as its own deployment, dependencies first")]
+        all: bool,
+
+        #[clap(long, conflicts_with = "all", help = "Deploy in the background and return at once; follow it with logs <id> --follow")]
+        detach: bool,
+
+        #[clap(long, default_value = "text", help = "Output format: text or json (the result on stdout, logs on stderr)")]
+        output: deployment::OutputFormat,
+
+        #[clap(flatten)]
+        options: Box<deployment::DeployOptions>,
+    },
+    /// Generate Terraform files for review without provisioning anything
+    Generate {
+        #[clap(short, long)]
+        description: String,
+        
+        #[clap(short, long)]
+        repository: String,
+        
+        #[clap(short, long)]
+        cloud_provider: Option<String>,
+
+        #[clap(long, default_value = "ubuntu", help = "VM operating system: ubuntu, debian, amazon-linux")]
+        os: nlp::OsFamily,
+
+        #[clap(long, default_value = "hcl", help = "Terraform file format: hcl (main.tf) or json (main.tf.json)")]
+        format: infrastructure::TerraformFormat,
+
+        #[clap(long = "ref", value_name = "REF", help = "Branch, tag or commit to generate for instead of the default branch")]
+        git_ref: Option<String>,
+    },
+    /// Estimate deployment type, instance type and cost without provisioning or credentials
+    Plan {
+        #[clap(short, long)]
+        description: Option<String>,
+        
+        #[clap(short, long)]
+        repository: String,
+        
+        #[clap(short, long)]
+        cloud_provider: Option<String>,
+        
+        #[clap(flatten)]
+        options: deployment::PlanOptions,
+        
+        #[clap(long, default_value = "text", help = "Output format: text or json")]
+        output: deployment::OutputFormat,
+    },
+    /// Provision a previously generated Terraform directory
+    Apply {
+        #[clap(help = "Directory containing the generated Terraform files")]
+        dir: PathBuf,
+        
+        #[clap(short, long, help = "Cloud provider (detected from main.tf when omitted)")]
+        cloud_provider: Option<String>,
+    },
+    /// Show a deployment's lifecycle state from the deployment index
+    Status {
+        #[clap(help = "Deployment id, as printed by deploy")]
+        id: String,
+    },
+    /// Retry a failed deploy from the phase that failed, reusing the checkpointed output of the
+    /// phases before it
+    Resume {
+        #[clap(help = "Deployment id, as printed by deploy")]
+        id: String,
+        
+        #[clap(long, help = "DNS provider API token, which the checkpoint does not keep (default: CLOUDFLARE_API_TOKEN)")]
+        dns_token: Option<String>,
+    },
+    /// Show the output of a deployment started with deploy --detach
+    Logs {
+        #[clap(help = "Deployment id, as printed by deploy")]
+        id: String,
+        #[clap(short, long, help = "Keep printing new output until the deploy finishes")]
+        follow: bool,
+    },
+    /// List the deployments in the deployment index, newest first
+    List {
+        #[clap(long, default_value = "text", help = "Output format: text or json")]
+        output: deployment::OutputFormat,
+    },
+    /// Tear down a deployment with terraform destroy and remove it from the deployment index
+    Destroy {
+        #[clap(help = "Deployment id, as printed by deploy")]
+        id: String,
+        #[clap(long, help = "Also destroy databases, disks and buckets protected with prevent_destroy, losing their data")]
+        allow_destroy_stateful: bool,
+    },
+    /// Bring a manually created cloud resource under a deployment's management with terraform import
+    Import {
+        #[clap(help = "Deployment id, as printed by deploy")]
+        id: String,
+        #[clap(help = "Resource address in the generated configuration, e.g. aws_instance.app")]
+        address: String,
+        #[clap(help = "The cloud provider's id for the resource, e.g. i-0123456789abcdef0")]
+        resource_id: String,
     },
     Chat {
         #[clap(short, long)]
         repository: Option<String>,
+        #[clap(long = "ref", value_name = "REF", help = "Branch, tag or commit of --repository to load")]
+        git_ref: Option<String>,
+        #[clap(long, default_value = "text", help = "Output format of plans and deploy results: text or json")]
+        output: deployment::OutputFormat,
     },
     Credentials {
         #[clap(subcommand)]
         command: CredentialsCommand,
     },
+    /// View and change persistent settings in ~/.autodeployment/config.toml
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print tool, Terraform and dependency versions for bug reports
+    Version,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the stored value of a setting
+    Get {
+        #[clap(help = "Setting: cloud_provider, region, model, output_dir, concurrency, max_output_tokens")]
+        key: String,
+    },
+    /// Store a setting; command-line flags still take precedence
+    Set {
+        key: String,
+        value: String,
+    },
+    /// Remove a stored setting, restoring the built-in default
+    Unset {
+        key: String,
+    },
+    /// Print every setting
+    List,
 }
 
 #[derive(Subcommand)]
 enum CredentialsCommand {
     Setup {
-        #[clap(help = "Cloud provider: aws, gcp, azure")]
+        #[clap(help = "Cloud provider: aws, gcp, azure, digitalocean")]
         provider: String,
     },
     Status,
+    /// Store a login for a private container registry
+    Registry {
+        #[clap(help = "Registry host, e.g. ghcr.io")]
+        registry: String,
+    },
     Clear {
-        #[clap(help = "Cloud provider to clear: aws, gcp, azure, all")]
+        #[clap(help = "Cloud provider or registry host to clear: aws, gcp, azure, digitalocean, <registry>, all")]
         provider: String,
     },
 }
@@ -63,40 +275,353 @@ async fn main() -> Result<()> {
     // Load environment variables from .env file
     dotenv::dotenv().ok();
     
-    env_logger::init();
+    let mut cli = Cli::parse();
+    correlation::init_logger(cli.verbose, cli.quiet_ai);
     
-    let cli = Cli::parse();
+    // `resume` runs the deploy's own command line again, which then skips the phases its
+    // checkpoint already has
+    if let Commands::Resume { id, dns_token } = &cli.command {
+        let secret_args: Vec<String> = dns_token.iter().flat_map(|token| ["--dns-token".to_string(), token.clone()]).collect();
+        let resumed = checkpoint::start_resume(id, &secret_args).and_then(|args| Cli::try_parse_from(args).map_err(anyhow::Error::from));
+        cli = match resumed {
+            Ok(resumed) => match &resumed.command {
+                Commands::Deploy { all: false, options, .. } if options.regions.is_empty() => resumed,
+                _ => {
+                    error!("Deployment {} is part of a stack or multi-region deploy; run that deploy again instead", id);
+                    std::process::exit(1);
+                }
+            },
+            Err(e) => {
+                error!("Resume failed: {}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+    
+    // Config commands must work on a config file the other commands reject, to repair it
+    if let Commands::Config { command } = &cli.command {
+        if let Err(e) = run_config_command(command) {
+            error!("Config failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    // Flags override stored settings, which override built-in defaults
+    let stored = match settings::Settings::load() {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    
+    if let Err(e) = concurrency::init(cli.concurrency.or(stored.concurrency)) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    
+    // Only commands that call the AI (or report its model) depend on LLM_PROVIDER
+    if uses_ai(&cli.command) {
+        if let Err(e) = configure_ai(&cli, &stored) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    
+    settings::init(stored)?;
+    if let Some(depth) = cli.scan_depth {
+        repository::set_scan_depth(depth);
+    }
+    if cli.no_recurse_submodules {
+        repository::disable_submodules();
+    }
+    let endpoint = cli.endpoint.as_deref().or(cli.localstack.then_some(infrastructure::LOCALSTACK_ENDPOINT));
+    if let Some(endpoint) = endpoint {
+        if let Err(e) = infrastructure::set_aws_endpoint(endpoint) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
     
     match cli.command {
-        Commands::Deploy { description, repository, cloud_provider, dry_run, force_deploy } => {
+        Commands::Deploy { description, repository, cloud_provider, dry_run, force_deploy, all, detach, output, options } => {
+            let description = description.unwrap_or_default();
+            let json = output == deployment::OutputFormat::Json;
+            if options.dockerfile_only {
+                match deployment::generate_dockerfile(&repository, &options).await {
+                    Ok((dockerfile, path)) if json => {
+                        deployment::print_json(&serde_json::json!({"dockerfile": dockerfile, "path": path}))?;
+                    }
+                    Ok((dockerfile, path)) => {
+                        println!("🐳 Generated Dockerfile, saved to {}:\n{}", path.display(), dockerfile);
+                    }
+                    Err(e) => {
+                        error!("Dockerfile generation failed: {}", e);
+                        if json {
+                            deployment::print_json_error(&e);
+                        }
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+            if detach {
+                match deployment::detach_deploy(&repository, cloud_provider.as_deref(), &options) {
+                    Ok(entry) if json => deployment::print_json(&entry)?,
+                    Ok(entry) => {
+                        println!("🧵 Deploying in the background as {} (pid {})", entry.id, entry.pid.unwrap_or_default());
+                        println!("  Follow it with: cargo run -- logs {} --follow", entry.id);
+                        println!("  Check on it with: cargo run -- status {}", entry.id);
+                    }
+                    Err(e) => {
+                        error!("Deployment failed: {:#}", e);
+                        if json {
+                            deployment::print_json_error(&e);
+                        }
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
             info!("Starting deployment process...");
             info!("Description: {}", description);
             info!("Repository: {}", repository);
             info!("Cloud Provider: {:?}", cloud_provider);
             
+            let force_deploy = match safety::ForceDeploy::from_flag(force_deploy) {
+                Ok(force_deploy) => force_deploy,
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if all {
+                match deployment::deploy_stack(
+                    &description,
+                    &repository,
+                    cloud_provider.as_deref(),
+                    dry_run,
+                    &force_deploy,
+                    &options,
+                ).await {
+                    Ok((stack, _)) if json => {
+                        deployment::print_json(&stack)?;
+                        if !stack.is_complete() {
+                            std::process::exit(1);
+                        }
+                    }
+                    Ok((stack, path)) => {
+                        println!("🧩 Stack deployment of {} services:", stack.members.len());
+                        for member in &stack.members {
+                            match (&member.error, &member.url) {
+                                (Some(error), _) => println!("  ❌ {:<16} {}", member.service, error),
+                                (None, Some(url)) => println!("  ✅ {:<16} {}", member.service, url),
+                                (None, None) => println!("  ✅ {}", member.service),
+                            }
+                        }
+                        println!("Stack id: {} (record: {})", stack.id, path.display());
+                        println!("  Destroy every service with: cargo run -- destroy {}", stack.id);
+                        if !stack.is_complete() {
+                            std::process::exit(1);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Deployment failed: {:#}", e);
+                        if json {
+                            deployment::print_json_error(&e);
+                        } else if options.explain_errors {
+                            deployment::explain_error(&e).await;
+                        }
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+            
             let deployment_result = deployment::deploy_application(
                 &description,
                 &repository,
                 cloud_provider.as_deref(),
                 dry_run,
-                force_deploy,
+                &force_deploy,
+                &options,
             ).await;
             
             match deployment_result {
+                Ok(deployment_info) if json => {
+                    deployment::print_json(&deployment_info)?;
+                    if deployment_info.not_ready.is_some() {
+                        std::process::exit(2);
+                    }
+                }
                 Ok(deployment_info) => {
-                    println!("🚀 Deployment successful!");
+                    match &deployment_info.not_ready {
+                        Some(reason) => println!("⚠️ Deployed but not ready: {}", reason),
+                        None => println!("🚀 Deployment successful!"),
+                    }
                     println!("Application URL: {}", deployment_info.url);
                     println!("Infrastructure: {}", deployment_info.infrastructure_type);
+                    if let Some(deployment_id) = &deployment_info.deployment_id {
+                        println!("Deployment ID: {}", deployment_id);
+                    }
+                    if let Some(commit) = &deployment_info.commit {
+                        println!("Commit: {}", commit);
+                    }
+                    if let (Some(_), Some(dir)) = (&options.name, &deployment_info.deployment_dir) {
+                        println!("Deployment directory: {}", dir.display());
+                    }
+                    if let Some(log_file) = deployment_info.log_file() {
+                        println!("Full log: {}", log_file.display());
+                    }
+                    if let Some(script) = &deployment_info.startup_script {
+                        println!("📜 Startup script:\n{}", script);
+                    }
+                    if let Some(cost) = &deployment_info.cost {
+                        println!("Estimated cost: ${:.2}/month", cost.total);
+                        deployment::print_cost_breakdown(cost);
+                    }
+                    if deployment_info.not_ready.is_some() {
+                        // Distinct from a failed deploy (1): the infrastructure is up
+                        std::process::exit(2);
+                    }
+                }
+                Err(e) => {
+                    error!("Deployment failed: {:#}", e);
+                    if json {
+                        deployment::print_json_error(&e);
+                    } else if options.explain_errors {
+                        deployment::explain_error(&e).await;
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Generate { description, repository, cloud_provider, os, format, git_ref } => {
+            match deployment::generate_deployment(&description, &repository, cloud_provider.as_deref(), os, format, git_ref).await {
+                Ok(terraform_dir) => {
+                    println!("✅ Terraform files generated in {}", terraform_dir.display());
+                    println!("💡 Apply them with: cargo run -- apply {}", terraform_dir.display());
+                }
+                Err(e) => {
+                    error!("Generation failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Plan { description, repository, cloud_provider, options, output } => {
+            let plan = deployment::estimate_deployment(description.as_deref(), &repository, cloud_provider.as_deref(), &options).await;
+            match plan.and_then(|estimates| deployment::print_estimates(&estimates, output)) {
+                Ok(()) => {}
+                Err(e) => {
+                    error!("Planning failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Apply { dir, cloud_provider } => {
+            match deployment::apply_deployment(&dir, cloud_provider.as_deref()).await {
+                Ok(deployment_info) => {
+                    println!("🚀 Deployment successful!");
+                    println!("Application URL: {}", deployment_info.url);
+                }
+                Err(e) => {
+                    error!("Apply failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Status { id } => {
+            match index::Index::open().and_then(|index| index.load(&id)) {
+                Ok(entry) => {
+                    println!("📋 Deployment {}: {}", entry.id, entry.state);
+                    println!("  Repository: {}", entry.repository);
+                    if let Some(commit) = &entry.commit {
+                        println!("  Commit: {}", commit);
+                    }
+                    println!("  Cloud Provider: {:?}", entry.cloud_provider);
+                    println!("  Directory: {}", entry.deployment_dir.display());
+                    if let Some(command) = entry.ssh_command() {
+                        println!("  SSH: {}", command);
+                    } else if entry.ssh_port.is_some() || entry.bastion.is_some() {
+                        println!(
+                            "  SSH: port {}{}",
+                            entry.ssh_port.unwrap_or(22),
+                            entry.bastion.as_deref().map(|bastion| format!(" via {}", bastion)).unwrap_or_default()
+                        );
+                    }
+                    println!("  Updated: {}", entry.updated_at);
+                    for (name, value) in &entry.outputs {
+                        println!("  {}: {}", name, value);
+                    }
+                    if let Some(error) = &entry.error {
+                        println!("  ❌ Error: {}", error);
+                    }
+                    if let (Some(pid), Some(log_file)) = (entry.pid, &entry.log_file) {
+                        let run = if detach::is_running(pid) { "running" } else { "finished" };
+                        println!("  Background run: pid {} ({}), log {}", pid, run, log_file.display());
+                    }
                 }
                 Err(e) => {
-                    error!("Deployment failed: {}", e);
+                    error!("Status failed: {}", e);
                     std::process::exit(1);
                 }
             }
         }
-        Commands::Chat { repository } => {
+        Commands::Resume { .. } => unreachable!("resume runs the deploy it resumes"),
+        Commands::Logs { id, follow } => {
+            if let Err(e) = deployment::print_deployment_log(&id, follow) {
+                error!("Logs failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::List { output } => {
+            if let Err(e) = deployment::list_deployments().and_then(|entries| deployment::print_deployments(&entries, output)) {
+                error!("List failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Destroy { id, allow_destroy_stateful } if deployment::is_stack(&id) => {
+            match deployment::destroy_stack(&id, allow_destroy_stateful).await {
+                Ok(entries) => {
+                    println!("💣 Stack {} destroyed", id);
+                    for entry in &entries {
+                        println!("  {} (Terraform files kept in: {})", entry.id, entry.deployment_dir.display());
+                    }
+                }
+                Err(e) => {
+                    error!("Destroy failed: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Destroy { id, allow_destroy_stateful } => {
+            match deployment::destroy_deployment(&id, allow_destroy_stateful).await {
+                Ok(entry) => {
+                    println!("💣 Deployment {} destroyed", entry.id);
+                    println!("  Terraform files kept in: {}", entry.deployment_dir.display());
+                }
+                Err(e) => {
+                    error!("Destroy failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Import { id, address, resource_id } => {
+            match deployment::import_resource(&id, &address, &resource_id).await {
+                Ok(entry) => {
+                    println!("📦 Imported {} into deployment {}", address, entry.id);
+                    println!("  Run terraform plan in {} to review any differences", entry.deployment_dir.display());
+                }
+                Err(e) => {
+                    error!("Import failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Chat { repository, git_ref, output } => {
             info!("Starting interactive chat mode...");
-            deployment::interactive_chat(repository).await?;
+            deployment::interactive_chat(repository, git_ref, output).await?;
         }
         Commands::Credentials { command } => {
             match command {
@@ -105,8 +630,9 @@ async fn main() -> Result<()> {
                         "aws" => nlp::CloudProvider::AWS,
                         "gcp" | "google" => nlp::CloudProvider::GCP,
                         "azure" => nlp::CloudProvider::Azure,
+                        "digitalocean" | "do" => nlp::CloudProvider::DigitalOcean,
                         _ => {
-                            error!("Unsupported cloud provider: {}. Use: aws, gcp, azure", provider);
+                            error!("Unsupported cloud provider: {}. Use: aws, gcp, azure, digitalocean", provider);
                             std::process::exit(1);
                         }
                     };
@@ -122,6 +648,12 @@ async fn main() -> Result<()> {
                         std::process::exit(1);
                     }
                 }
+                CredentialsCommand::Registry { registry } => {
+                    if let Err(e) = credentials::prompt_for_registry_credentials(&registry) {
+                        error!("Failed to set up registry credentials: {}", e);
+                        std::process::exit(1);
+                    }
+                }
                 CredentialsCommand::Clear { provider } => {
                     if let Err(e) = clear_credentials(&provider).await {
                         error!("Failed to clear credentials: {}", e);
@@ -130,6 +662,69 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Config { .. } => unreachable!("config commands run before the settings are loaded"),
+        Commands::Version => {
+            version::print_report(&version::collect());
+        }
+    }
+    
+    Ok(())
+}
+
+fn uses_ai(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Deploy { .. } | Commands::Generate { .. } | Commands::Plan { .. } | Commands::Chat { .. } | Commands::Version
+    )
+}
+
+fn configure_ai(cli: &Cli, stored: &settings::Settings) -> Result<()> {
+    let provider = llm::ProviderKind::from_env()?;
+    ai_nlp::configure(ai_nlp::AiSettings {
+        provider,
+        model: stored.model.clone().unwrap_or_else(|| provider.default_model().to_string()),
+        max_output_tokens: cli.max_output_tokens.or(stored.max_output_tokens).unwrap_or(ai_nlp::DEFAULT_MAX_OUTPUT_TOKENS),
+        strict_json: cli.strict_json,
+        dump_prompts: cli.dump_prompts.clone(),
+        dump_responses: cli.dump_responses.clone(),
+        offline: cli.offline,
+    })
+}
+
+fn run_config_command(command: &ConfigCommand) -> Result<()> {
+    let mut stored = match (settings::Settings::load(), command) {
+        (Ok(stored), _) => stored,
+        // Unsetting is how a file that no longer loads gets repaired
+        (Err(e), ConfigCommand::Unset { key }) => {
+            let path = settings::config_path()?;
+            if !settings::Settings::remove_from_file(&path, key)? {
+                return Err(e);
+            }
+            println!("✅ {} removed from {}", key, path.display());
+            return Ok(());
+        }
+        (Err(e), _) => return Err(e),
+    };
+    
+    match command {
+        ConfigCommand::Get { key } => {
+            println!("{}", stored.get(key)?.unwrap_or_default());
+        }
+        ConfigCommand::Set { key, value } => {
+            stored.set(key, value)?;
+            let path = stored.save()?;
+            println!("✅ {} saved to {}", key, path.display());
+        }
+        ConfigCommand::Unset { key } => {
+            stored.unset(key)?;
+            stored.save()?;
+            println!("✅ {} reset to its default", key);
+        }
+        ConfigCommand::List => {
+            for key in settings::KEYS {
+                println!("{} = {}", key, stored.get(key)?.as_deref().unwrap_or("(default)"));
+            }
+        }
     }
     
     Ok(())
@@ -153,14 +748,23 @@ async fn clear_credentials(provider: &str) -> Result<()> {
             credentials.azure = None;
             println!("✅ Azure credentials cleared");
         }
+        "digitalocean" | "do" => {
+            credentials.digitalocean = None;
+            println!("✅ DigitalOcean credentials cleared");
+        }
         "all" => {
             credentials.aws = None;
             credentials.gcp = None;
             credentials.azure = None;
+            credentials.digitalocean = None;
+            credentials.registries.clear();
             println!("✅ All credentials cleared");
         }
+        registry if credentials.registries.remove(registry).is_some() => {
+            println!("✅ Credentials for registry {} cleared", registry);
+        }
         _ => {
-            return Err(anyhow::anyhow!("Unknown provider: {}. Use: aws, gcp, azure, all", provider));
+            return Err(anyhow::anyhow!("Unknown provider: {}. Use: aws, gcp, azure, digitalocean, all", provider));
         }
     }
     
@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::infrastructure::TerraformConfig;
+use crate::nlp::CloudProvider;
+
+/// Pre-existing network a deployment is placed into instead of creating its own
+/// (`--vpc-id`, `--subnet-id`, `--security-group-id`). On GCP the VPC is the network name and
+/// the subnet the subnetwork name; GCP has no security groups.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExistingNetwork {
+    pub vpc_id: Option<String>,
+    pub subnet_id: Option<String>,
+    pub security_group_id: Option<String>,
+}
+
+impl ExistingNetwork {
+    /// The network from the flags, or `None` when none of them is set
+    pub fn from_flags(
+        vpc_id: Option<String>,
+        subnet_id: Option<String>,
+        security_group_id: Option<String>,
+        cloud_provider: &CloudProvider,
+    ) -> Result<Option<Self>> {
+        let network = ExistingNetwork { vpc_id, subnet_id, security_group_id };
+        if network == ExistingNetwork::default() {
+            return Ok(None);
+        }
+        network.check_formats(cloud_provider)?;
+        Ok(Some(network))
+    }
+
+    /// Check the IDs' format and how the flags combine. Whether the network exists is not
+    /// looked up; Terraform reports a missing one when it plans.
+    fn check_formats(&self, cloud_provider: &CloudProvider) -> Result<()> {
+        match cloud_provider {
+            CloudProvider::AWS => {
+                check_id("--vpc-id", self.vpc_id.as_deref(), r"^vpc-[0-9a-f]{8,17}$", "vpc-0abc1234")?;
+                check_id("--subnet-id", self.subnet_id.as_deref(), r"^subnet-[0-9a-f]{8,17}$", "subnet-0abc1234")?;
+                check_id("--security-group-id", self.security_group_id.as_deref(), r"^sg-[0-9a-f]{8,17}$", "sg-0abc1234")?;
+                if self.vpc_id.is_some() && self.subnet_id.is_none() {
+                    return Err(anyhow!("--vpc-id needs --subnet-id: the instance goes into an existing subnet of the VPC"));
+                }
+                // A security group created for the instance must be in the subnet's VPC
+                if self.subnet_id.is_some() && self.vpc_id.is_none() && self.security_group_id.is_none() {
+                    return Err(anyhow!("--subnet-id needs --vpc-id (or --security-group-id) so the security group is created in the subnet's VPC"));
+                }
+            }
+            CloudProvider::GCP => {
+                if self.security_group_id.is_some() {
+                    return Err(anyhow!("GCP has no security groups; firewall rules are created in the --vpc-id network instead"));
+                }
+                let name = r"^[a-z]([-a-z0-9]{0,61}[a-z0-9])?$";
+                check_id("--vpc-id", self.vpc_id.as_deref(), name, "shared-vpc")?;
+                check_id("--subnet-id", self.subnet_id.as_deref(), name, "app-subnet")?;
+                if self.subnet_id.is_some() && self.vpc_id.is_none() {
+                    return Err(anyhow!("--subnet-id needs --vpc-id on GCP: the instance's network must be the subnetwork's"));
+                }
+            }
+            other => return Err(anyhow!("Existing networks are not supported on {:?}", other)),
+        }
+        Ok(())
+    }
+
+    /// Remove the network resources the generated config would create and point the
+    /// remaining resources at the existing ones. Returns a description of each change.
+    pub fn apply(&self, config: &mut TerraformConfig, cloud_provider: &CloudProvider) -> Result<Vec<String>> {
+        let replaced = self.replaced_types(cloud_provider);
+        let mut changes = Vec::new();
+
+        // References to removed resources, e.g. `${aws_subnet.main.id}`, become the existing ID
+        let mut references: Vec<(String, String)> = Vec::new();
+        config.resources.retain(|resource| {
+            match replaced.iter().find(|(resource_type, _)| *resource_type == resource.resource_type) {
+                Some((_, existing)) => {
+                    if let Some(existing) = existing {
+                        references.push((format!("{}.{}.", resource.resource_type, resource.name), existing.clone()));
+                    }
+                    changes.push(format!("Not creating {}.{}: using the existing network", resource.resource_type, resource.name));
+                    false
+                }
+                None => true,
+            }
+        });
+        for resource in &mut config.resources {
+            for value in resource.config.values_mut() {
+                replace_references(value, &references);
+            }
+        }
+        for output in config.outputs.values_mut() {
+            replace_references(output, &references);
+        }
+
+        let mut placed = 0;
+        for resource in &mut config.resources {
+            let settings = &mut resource.config;
+            match resource.resource_type.as_str() {
+                "aws_instance" => {
+                    if let Some(subnet_id) = &self.subnet_id {
+                        settings.insert("subnet_id".to_string(), Value::String(subnet_id.clone()));
+                    }
+                    if let Some(security_group_id) = &self.security_group_id {
+                        // `security_groups` takes names and only works in the default VPC
+                        settings.remove("security_groups");
+                        settings.insert("vpc_security_group_ids".to_string(), Value::Array(vec![Value::String(security_group_id.clone())]));
+                    }
+                    placed += 1;
+                }
+                "aws_security_group" | "aws_lb_target_group" => {
+                    if let Some(vpc_id) = &self.vpc_id {
+                        settings.insert("vpc_id".to_string(), Value::String(vpc_id.clone()));
+                    }
+                }
+                "google_compute_instance" => {
+                    let interface = settings.entry("network_interface".to_string()).or_insert_with(|| Value::Object(Default::default()));
+                    let interfaces: Vec<&mut Value> = match interface {
+                        Value::Array(interfaces) => interfaces.iter_mut().collect(),
+                        other => vec![other],
+                    };
+                    for interface in interfaces.into_iter().filter_map(|interface| interface.as_object_mut()) {
+                        if let Some(vpc_id) = &self.vpc_id {
+                            interface.insert("network".to_string(), Value::String(vpc_id.clone()));
+                        }
+                        match &self.subnet_id {
+                            Some(subnet_id) => {
+                                interface.insert("subnetwork".to_string(), Value::String(subnet_id.clone()));
+                            }
+                            // An auto-mode network picks the region's subnetwork itself
+                            None => {
+                                interface.remove("subnetwork");
+                            }
+                        }
+                    }
+                    placed += 1;
+                }
+                "google_compute_firewall" => {
+                    if let Some(vpc_id) = &self.vpc_id {
+                        settings.insert("network".to_string(), Value::String(vpc_id.clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if placed == 0 {
+            return Err(anyhow!("The generated configuration has no instance to place into the existing network"));
+        }
+        changes.push(format!("Placed {} instance(s) into {}", placed, self));
+        Ok(changes)
+    }
+
+    /// Resource types the existing network replaces, with the ID references to them become
+    fn replaced_types(&self, cloud_provider: &CloudProvider) -> Vec<(&'static str, Option<String>)> {
+        let mut replaced = Vec::new();
+        match cloud_provider {
+            CloudProvider::AWS => {
+                if self.vpc_id.is_some() {
+                    replaced.push(("aws_vpc", self.vpc_id.clone()));
+                    for resource_type in ["aws_internet_gateway", "aws_route_table", "aws_route_table_association", "aws_route"] {
+                        replaced.push((resource_type, None));
+                    }
+                }
+                if self.subnet_id.is_some() {
+                    replaced.push(("aws_subnet", self.subnet_id.clone()));
+                }
+                if self.security_group_id.is_some() {
+                    for resource_type in ["aws_security_group", "aws_security_group_rule", "aws_vpc_security_group_ingress_rule", "aws_vpc_security_group_egress_rule"] {
+                        replaced.push((resource_type, self.security_group_id.clone()));
+                    }
+                }
+            }
+            CloudProvider::GCP if self.vpc_id.is_some() => {
+                replaced.push(("google_compute_network", self.vpc_id.clone()));
+                replaced.push(("google_compute_subnetwork", self.subnet_id.clone()));
+            }
+            _ => {}
+        }
+        replaced
+    }
+}
+
+impl std::fmt::Display for ExistingNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<&str> = [&self.vpc_id, &self.subnet_id, &self.security_group_id]
+            .into_iter()
+            .filter_map(|id| id.as_deref())
+            .collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+fn check_id(flag: &str, id: Option<&str>, pattern: &str, example: &str) -> Result<()> {
+    match id {
+        Some(id) if !Regex::new(pattern).unwrap().is_match(id) => {
+            Err(anyhow!("Invalid {} {:?}, expected something like {}", flag, id, example))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replace whole-value references like `${aws_subnet.main.id}` to a removed resource with the
+/// existing resource's ID
+fn replace_references(value: &mut Value, references: &[(String, String)]) {
+    match value {
+        Value::String(s) => {
+            let expression = s.trim_start_matches("${").trim_end_matches('}');
+            if let Some((_, existing)) = references.iter().find(|(prefix, _)| expression.starts_with(prefix.as_str())) {
+                *s = existing.clone();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| replace_references(item, references)),
+        Value::Object(map) => map.values_mut().for_each(|item| replace_references(item, references)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::TerraformResource;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn resource(resource_type: &str, name: &str, config: Value) -> TerraformResource {
+        TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            config: serde_json::from_value(config).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_places_aws_instance_into_existing_network() {
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                resource("aws_vpc", "main", json!({"cidr_block": "10.0.0.0/16"})),
+                resource("aws_subnet", "public", json!({"vpc_id": "${aws_vpc.main.id}"})),
+                resource("aws_security_group", "web", json!({"vpc_id": "${aws_vpc.main.id}"})),
+                resource("aws_instance", "app", json!({
+                    "subnet_id": "${aws_subnet.public.id}",
+                    "security_groups": ["web"],
+                    "vpc_security_group_ids": ["${aws_security_group.web.id}"]
+                })),
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let network = ExistingNetwork::from_flags(
+            Some("vpc-0abc1234".to_string()),
+            Some("subnet-0def5678".to_string()),
+            Some("sg-0123abcd".to_string()),
+            &CloudProvider::AWS,
+        )
+        .unwrap()
+        .unwrap();
+
+        let changes = network.apply(&mut config, &CloudProvider::AWS).unwrap();
+        let types: Vec<&str> = config.resources.iter().map(|r| r.resource_type.as_str()).collect();
+        assert_eq!(types, vec!["aws_instance"]);
+        let instance = &config.resources[0].config;
+        assert_eq!(instance["subnet_id"], "subnet-0def5678");
+        assert_eq!(instance["vpc_security_group_ids"], json!(["sg-0123abcd"]));
+        assert!(!instance.contains_key("security_groups"));
+        assert_eq!(changes.last().unwrap(), "Placed 1 instance(s) into vpc-0abc1234, subnet-0def5678, sg-0123abcd");
+    }
+
+    #[test]
+    fn test_validates_existing_network_flags() {
+        assert_eq!(ExistingNetwork::from_flags(None, None, None, &CloudProvider::AWS).unwrap(), None);
+        assert!(ExistingNetwork::from_flags(Some("my-vpc".to_string()), None, None, &CloudProvider::AWS).is_err());
+        assert!(ExistingNetwork::from_flags(None, Some("subnet-0def5678".to_string()), None, &CloudProvider::AWS).is_err());
+        assert!(ExistingNetwork::from_flags(None, None, Some("sg-0123abcd".to_string()), &CloudProvider::GCP).is_err());
+        assert!(ExistingNetwork::from_flags(Some("shared-vpc".to_string()), Some("app-subnet".to_string()), None, &CloudProvider::GCP).is_ok());
+    }
+}
@@ -1,5 +1,16 @@
+use anyhow::anyhow;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::audit::AuditMode;
+use crate::credentials::RegistryCredentials;
+use crate::egress::EgressPolicy;
+use crate::health::HealthCheck;
+use crate::network::ExistingNetwork;
+use crate::secrets::SecretsSource;
+use crate::ssh::AuthorizedKey;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeploymentRequirements {
@@ -11,9 +22,76 @@ pub struct DeploymentRequirements {
     pub port_requirements: Vec<u16>,
     pub ssl_required: bool,
     pub custom_domain: Option<String>,
+    #[serde(default)]
+    pub os: OsFamily,
+    /// Logins for private registries the app's images are pulled from; never serialized
+    #[serde(skip)]
+    pub registry_credentials: HashMap<String, RegistryCredentials>,
+    /// Whether an env file is delivered over SSH after boot; the startup script waits for it
+    #[serde(skip)]
+    pub expects_env_file: bool,
+    /// Target environment (`--environment`); selects the repository's `.env.<environment>` file
+    #[serde(skip)]
+    pub environment: Option<String>,
+    /// Load balancer health check settings; the path is filled in from the analysis if unset
+    #[serde(skip)]
+    pub health_check: HealthCheck,
+    /// Place the instance in a private subnet behind a NAT gateway and load balancer (`--private`)
+    #[serde(skip)]
+    pub private_network: bool,
+    /// Directory of the service being deployed in a multi-service repository (`deploy --all`)
+    #[serde(skip)]
+    pub service_dir: Option<String>,
+    /// `--name` of the deployment: names its directory and tags its resources
+    #[serde(skip)]
+    pub deployment_name: Option<String>,
+    /// Deploy even when the repository does not look like a service (`--force-deploy not-a-service`)
+    #[serde(skip)]
+    pub deploy_non_service: bool,
+    /// Region this deploy goes to instead of the configured one, for each of `--regions`
+    #[serde(skip)]
+    pub region: Option<String>,
+    /// Existing VPC, subnet and security group to deploy into instead of creating them
+    #[serde(skip)]
+    pub existing_network: Option<ExistingNetwork>,
+    /// Check the dependencies for known vulnerabilities before deploying
+    #[serde(skip)]
+    pub audit: Option<AuditMode>,
+    /// Root disk size in GB (`--disk-size`); derived from the build size when unset
+    #[serde(skip)]
+    pub disk_size_gb: Option<u32>,
+    /// Run on a GPU instance (`--gpu`), whether or not the analysis finds GPU dependencies
+    #[serde(skip)]
+    pub gpu: bool,
+    /// Leave `prevent_destroy` off stateful resources (`--allow-destroy-stateful`)
+    #[serde(skip)]
+    pub allow_destroy_stateful: bool,
+    /// Branch, tag or commit to deploy (`--ref`); the default branch when unset
+    #[serde(skip)]
+    pub git_ref: Option<String>,
+    /// Point the clone's localhost references at this machine's public IP before analysis;
+    /// only real deploys do, as it needs an outbound lookup
+    #[serde(skip)]
+    pub rewrite_localhost: bool,
+    /// Secret manager the instance loads its environment from at startup
+    #[serde(skip)]
+    pub secrets_source: Option<SecretsSource>,
+    /// Outbound traffic the instance is limited to; everything when unset
+    #[serde(skip)]
+    pub egress: Option<EgressPolicy>,
+    /// package.json script the app is started with (`--start-script`), overriding the detected one
+    pub start_script: Option<String>,
+    /// Image container targets run the app in (`--base-image`), overriding the version-matched one
+    pub base_image: Option<String>,
+    /// Port sshd listens on and the firewall opens on new instances (`--ssh-port`), instead of 22
+    pub ssh_port: Option<u16>,
+    /// Public half of `--ssh-key`, installed on new instances so files can be delivered over SSH
+    #[serde(skip)]
+    pub authorized_key: Option<AuthorizedKey>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum CloudProvider {
     AWS,
     GCP,
@@ -22,6 +100,39 @@ pub enum CloudProvider {
     Unknown,
 }
 
+/// Operating system family of the VM image; selects the image and package manager
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum OsFamily {
+    #[default]
+    Ubuntu,
+    Debian,
+    AmazonLinux,
+}
+
+impl OsFamily {
+    /// Login user the cloud images of this OS accept SSH keys for
+    pub fn default_ssh_user(&self) -> &'static str {
+        match self {
+            OsFamily::Ubuntu => "ubuntu",
+            OsFamily::Debian => "admin",
+            OsFamily::AmazonLinux => "ec2-user",
+        }
+    }
+}
+
+impl FromStr for OsFamily {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "ubuntu" => Ok(OsFamily::Ubuntu),
+            "debian" => Ok(OsFamily::Debian),
+            "amazon-linux" | "amazonlinux" | "al2023" => Ok(OsFamily::AmazonLinux),
+            _ => Err(anyhow!("Unsupported OS: {}. Use: ubuntu, debian, amazon-linux", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ApplicationType {
     Flask,
@@ -33,6 +144,8 @@ pub enum ApplicationType {
     FastAPI,
     Rails,
     Spring,
+    Go,
+    Rust,
     Unknown,
 }
 
@@ -44,6 +157,20 @@ pub enum ScalingRequirements {
     Serverless,
 }
 
+impl FromStr for ScalingRequirements {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "single" => Ok(ScalingRequirements::Single),
+            "autoscale" | "autoscaling" => Ok(ScalingRequirements::AutoScale),
+            "loadbalanced" => Ok(ScalingRequirements::LoadBalanced),
+            "serverless" => Ok(ScalingRequirements::Serverless),
+            _ => Err(anyhow!("Unsupported scaling: {}. Use: single, auto-scale, load-balanced, serverless", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum DatabaseType {
     PostgreSQL,
@@ -54,6 +181,20 @@ pub enum DatabaseType {
     None,
 }
 
+impl FromStr for DatabaseType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "postgres" | "postgresql" => Ok(DatabaseType::PostgreSQL),
+            "mysql" | "mariadb" => Ok(DatabaseType::MySQL),
+            "mongo" | "mongodb" => Ok(DatabaseType::MongoDB),
+            "redis" => Ok(DatabaseType::Redis),
+            _ => Err(anyhow!("Unsupported database: {}. Use: postgresql, mysql, mongodb, redis", s)),
+        }
+    }
+}
+
 impl Default for DeploymentRequirements {
     fn default() -> Self {
         Self {
@@ -65,7 +206,305 @@ impl Default for DeploymentRequirements {
             port_requirements: vec![80, 443],
             ssl_required: false,
             custom_domain: None,
+            os: OsFamily::default(),
+            registry_credentials: HashMap::new(),
+            expects_env_file: false,
+            environment: None,
+            health_check: Default::default(),
+            private_network: false,
+            service_dir: None,
+            deployment_name: None,
+            deploy_non_service: false,
+            region: None,
+            existing_network: None,
+            audit: None,
+            disk_size_gb: None,
+            gpu: false,
+            allow_destroy_stateful: false,
+            git_ref: None,
+            rewrite_localhost: false,
+            secrets_source: None,
+            egress: None,
+            start_script: None,
+            base_image: None,
+            ssh_port: None,
+            authorized_key: None,
         }
     }
 }
 
+/// Keywords and the signal each one gives, matched as whole words of the lowercased description
+const PROVIDER_KEYWORDS: &[(&str, CloudProvider)] = &[
+    ("aws", CloudProvider::AWS),
+    ("amazon", CloudProvider::AWS),
+    ("gcp", CloudProvider::GCP),
+    ("google", CloudProvider::GCP),
+    ("azure", CloudProvider::Azure),
+    ("digitalocean", CloudProvider::DigitalOcean),
+];
+
+const SCALING_KEYWORDS: &[(&str, ScalingRequirements)] = &[
+    ("serverless", ScalingRequirements::Serverless),
+    ("lambda", ScalingRequirements::Serverless),
+    ("autoscaling", ScalingRequirements::AutoScale),
+    ("autoscale", ScalingRequirements::AutoScale),
+    ("auto-scaling", ScalingRequirements::AutoScale),
+    ("load-balanced", ScalingRequirements::LoadBalanced),
+    ("load balanced", ScalingRequirements::LoadBalanced),
+    ("load balancer", ScalingRequirements::LoadBalanced),
+];
+
+const DATABASE_KEYWORDS: &[(&str, DatabaseType)] = &[
+    ("postgres", DatabaseType::PostgreSQL),
+    ("postgresql", DatabaseType::PostgreSQL),
+    ("mysql", DatabaseType::MySQL),
+    ("mariadb", DatabaseType::MySQL),
+    ("mongo", DatabaseType::MongoDB),
+    ("mongodb", DatabaseType::MongoDB),
+    ("redis", DatabaseType::Redis),
+    ("sqlite", DatabaseType::SQLite),
+];
+
+const APPLICATION_KEYWORDS: &[(&str, ApplicationType)] = &[
+    ("flask", ApplicationType::Flask),
+    ("django", ApplicationType::Django),
+    ("fastapi", ApplicationType::FastAPI),
+    ("express", ApplicationType::Express),
+    ("next.js", ApplicationType::NextJS),
+    ("nextjs", ApplicationType::NextJS),
+    ("react", ApplicationType::React),
+    ("node", ApplicationType::NodeJS),
+    ("node.js", ApplicationType::NodeJS),
+    ("nodejs", ApplicationType::NodeJS),
+    ("rails", ApplicationType::Rails),
+    ("spring", ApplicationType::Spring),
+    ("golang", ApplicationType::Go),
+    ("rust", ApplicationType::Rust),
+];
+
+/// Keywords meaning the deploy should have SSL
+const SSL_KEYWORDS: &[&str] = &["https", "ssl", "tls", "production"];
+
+/// Words that carry no requirement. A description made only of these and keywords needs no AI.
+const FILLER_WORDS: &[&str] = &[
+    "a", "an", "and", "api", "app", "application", "at", "backend", "balanced", "balancer",
+    "cloud", "custom", "database", "db", "deploy", "deployment", "domain", "for", "frontend",
+    "host", "in", "instance", "it", "load", "my", "of", "on", "please", "port", "prod",
+    "server", "service", "simple", "single", "site", "the", "this", "to", "use", "using", "vm",
+    "web", "website", "with",
+];
+
+/// File extensions that make `name.ext` a file or framework name rather than a domain
+const NON_DOMAIN_SUFFIXES: &[&str] = &[
+    "js", "ts", "py", "rb", "rs", "go", "txt", "json", "yml", "yaml", "toml", "md", "html", "env",
+    "php", "jsp", "asp", "aspx", "cgi", "sh", "xml", "ini", "cfg", "conf", "lock", "sql", "csv", "log",
+];
+
+/// Frameworks and libraries whose names end in a real TLD
+const NON_DOMAIN_NAMES: &[&str] = &[
+    "socket.io", "express.io", "engine.io", "asp.net", "ado.net", "vb.net", "sails.io", "deno.land",
+];
+
+/// Words that introduce a domain; a bare `name.tld` only counts as one after these
+const DOMAIN_CUES: &[&str] = &["domain", "at", "on", "to", "under", "via", "url", "host", "hostname"];
+
+/// Requirements read from a description with keywords alone, before (or instead of) the AI.
+/// `None` and empty values mean the description does not say.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeywordSignals {
+    pub cloud_provider: Option<CloudProvider>,
+    pub application_type: Option<ApplicationType>,
+    pub scaling_requirements: Option<ScalingRequirements>,
+    pub database_requirements: Vec<DatabaseType>,
+    pub port_requirements: Vec<u16>,
+    pub ssl_required: bool,
+    pub custom_domain: Option<String>,
+    /// Words that are neither keywords nor filler; any of these means the AI is needed
+    pub unrecognized: Vec<String>,
+}
+
+/// Extract provider, scaling, database, framework, port, SSL and domain signals from
+/// `description` without calling the AI
+pub fn extract_keywords(description: &str) -> KeywordSignals {
+    let mut text = description.to_lowercase();
+    let mut signals = KeywordSignals::default();
+
+    let domain_regex = Regex::new(r"\b(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z]{2,}\b").unwrap();
+    let original = text.clone();
+    for found in domain_regex.find_iter(&original) {
+        let domain = found.as_str();
+        let suffix = domain.rsplit('.').next().unwrap_or_default();
+        if NON_DOMAIN_SUFFIXES.contains(&suffix) || NON_DOMAIN_NAMES.contains(&domain) {
+            continue;
+        }
+        // `api.example.com` is a domain wherever it appears; `name.io` could be a library
+        let cued = original[..found.start()]
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .rfind(|word| !word.is_empty())
+            .is_some_and(|word| DOMAIN_CUES.contains(&word));
+        if domain.matches('.').count() < 2 && !cued {
+            continue;
+        }
+        if signals.custom_domain.is_none() {
+            signals.custom_domain = Some(domain.to_string());
+        }
+        text = text.replace(domain, " ");
+    }
+
+    let port_regex = Regex::new(r"\bport\s+(\d{2,5})\b|:(\d{2,5})\b").unwrap();
+    for caps in port_regex.captures_iter(&text) {
+        let port = caps.get(1).or_else(|| caps.get(2)).and_then(|m| m.as_str().parse::<u16>().ok());
+        if let Some(port) = port.filter(|port| !signals.port_requirements.contains(port)) {
+            signals.port_requirements.push(port);
+        }
+    }
+
+    let words: Vec<&str> = text
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+        .map(|word| word.trim_matches(|c: char| c == '.' || c == '-'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let phrase = words.join(" ");
+    let has = |keyword: &str| {
+        if keyword.contains(' ') {
+            phrase.contains(keyword)
+        } else {
+            words.contains(&keyword)
+        }
+    };
+
+    signals.cloud_provider = first_match(PROVIDER_KEYWORDS, has);
+    signals.scaling_requirements = first_match(SCALING_KEYWORDS, has);
+    signals.application_type = first_match(APPLICATION_KEYWORDS, has);
+    for (keyword, database) in DATABASE_KEYWORDS {
+        if has(keyword) && !signals.database_requirements.contains(database) {
+            signals.database_requirements.push(database.clone());
+        }
+    }
+    signals.ssl_required = SSL_KEYWORDS.iter().any(|keyword| has(keyword));
+
+    let is_keyword = |word: &str| {
+        PROVIDER_KEYWORDS.iter().any(|(keyword, _)| *keyword == word)
+            || SCALING_KEYWORDS.iter().any(|(keyword, _)| *keyword == word)
+            || DATABASE_KEYWORDS.iter().any(|(keyword, _)| *keyword == word)
+            || APPLICATION_KEYWORDS.iter().any(|(keyword, _)| *keyword == word)
+            || SSL_KEYWORDS.contains(&word)
+            || FILLER_WORDS.contains(&word)
+            || word.chars().all(|c| c.is_ascii_digit())
+    };
+    for word in words {
+        if !is_keyword(word) && !signals.unrecognized.iter().any(|seen| seen == word) {
+            signals.unrecognized.push(word.to_string());
+        }
+    }
+
+    signals
+}
+
+fn first_match<T: Clone>(table: &[(&str, T)], has: impl Fn(&str) -> bool) -> Option<T> {
+    table.iter().find(|(keyword, _)| has(keyword)).map(|(_, value)| value.clone())
+}
+
+impl KeywordSignals {
+    /// Whether every word of the description was understood, so the AI has nothing to add
+    pub fn is_complete(&self) -> bool {
+        self.unrecognized.is_empty()
+    }
+
+    /// Requirements from the signals alone, with the same defaults the AI is told to use
+    pub fn to_requirements(&self) -> DeploymentRequirements {
+        self.merge_under(DeploymentRequirements {
+            cloud_provider: CloudProvider::Unknown,
+            ..Default::default()
+        })
+    }
+
+    /// Fill in what `requirements` (the AI's result) left unset from the keyword signals.
+    /// Values the AI extracted win.
+    pub fn merge_under(&self, mut requirements: DeploymentRequirements) -> DeploymentRequirements {
+        if let (CloudProvider::Unknown, Some(provider)) = (&requirements.cloud_provider, &self.cloud_provider) {
+            requirements.cloud_provider = provider.clone();
+        }
+        if requirements.application_type.is_none() {
+            requirements.application_type = self.application_type.clone();
+        }
+        if let (ScalingRequirements::Single, Some(scaling)) = (&requirements.scaling_requirements, &self.scaling_requirements) {
+            requirements.scaling_requirements = scaling.clone();
+        }
+        let no_databases = requirements.database_requirements.iter().all(|db| *db == DatabaseType::None);
+        if no_databases && !self.database_requirements.is_empty() {
+            requirements.database_requirements = self.database_requirements.clone();
+        }
+        for port in &self.port_requirements {
+            if !requirements.port_requirements.contains(port) {
+                requirements.port_requirements.push(*port);
+            }
+        }
+        requirements.ssl_required |= self.ssl_required;
+        if requirements.custom_domain.is_none() {
+            requirements.custom_domain = self.custom_domain.clone();
+        }
+        requirements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_keywords() {
+        let signals = extract_keywords("Deploy this Flask app on GCP with PostgreSQL and Redis on port 8080 at api.example.com");
+        assert_eq!(signals.cloud_provider, Some(CloudProvider::GCP));
+        assert_eq!(signals.application_type, Some(ApplicationType::Flask));
+        assert_eq!(signals.database_requirements, vec![DatabaseType::PostgreSQL, DatabaseType::Redis]);
+        assert_eq!(signals.port_requirements, vec![8080]);
+        assert_eq!(signals.custom_domain.as_deref(), Some("api.example.com"));
+        assert!(!signals.ssl_required);
+        assert!(signals.is_complete(), "unexpected words: {:?}", signals.unrecognized);
+
+        let signals = extract_keywords("Serverless Next.js site on Azure, production, https");
+        assert_eq!(signals.scaling_requirements, Some(ScalingRequirements::Serverless));
+        assert_eq!(signals.application_type, Some(ApplicationType::NextJS));
+        assert_eq!(signals.cloud_provider, Some(CloudProvider::Azure));
+        assert_eq!(signals.custom_domain, None, "next.js is not a domain");
+        assert!(signals.ssl_required);
+
+        assert_eq!(extract_keywords("socket.io app on aws").custom_domain, None);
+        assert_eq!(extract_keywords("PHP site serving index.php on AWS").custom_domain, None);
+        assert_eq!(extract_keywords("ASP.NET app at example.com").custom_domain.as_deref(), Some("example.com"));
+        assert_eq!(extract_keywords("Deploy to AWS with domain myapp.io").custom_domain.as_deref(), Some("myapp.io"));
+        assert_eq!(extract_keywords("Flask app, express.io style, on GCP").custom_domain, None);
+
+        let signals = extract_keywords("Load balanced Express API on AWS, listening on :3000");
+        assert_eq!(signals.scaling_requirements, Some(ScalingRequirements::LoadBalanced));
+        assert_eq!(signals.port_requirements, vec![3000]);
+    }
+
+    #[test]
+    fn test_unrecognized_words_need_the_ai() {
+        let signals = extract_keywords("Deploy on AWS and set DEBUG to false for the staging team");
+        assert!(!signals.is_complete());
+        assert!(signals.unrecognized.contains(&"staging".to_string()));
+    }
+
+    #[test]
+    fn test_keyword_requirements_merge_under_ai() {
+        let signals = extract_keywords("Flask app on GCP with MySQL at shop.example.com");
+        let base = signals.to_requirements();
+        assert_eq!(base.cloud_provider, CloudProvider::GCP);
+        assert_eq!(base.database_requirements, vec![DatabaseType::MySQL]);
+        assert_eq!(base.port_requirements, vec![80, 443]);
+
+        // The AI found a different domain and no database: its domain wins, the keyword database fills the gap
+        let ai = DeploymentRequirements {
+            cloud_provider: CloudProvider::GCP,
+            custom_domain: Some("www.example.com".to_string()),
+            ..Default::default()
+        };
+        let merged = signals.merge_under(ai);
+        assert_eq!(merged.custom_domain.as_deref(), Some("www.example.com"));
+        assert_eq!(merged.database_requirements, vec![DatabaseType::MySQL]);
+        assert_eq!(merged.application_type, Some(ApplicationType::Flask));
+    }
+}
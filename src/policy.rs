@@ -0,0 +1,374 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::infrastructure::TerraformConfig;
+
+/// Arguments holding the machine size, per resource type
+const INSTANCE_TYPE_ARGUMENTS: &[(&str, &str)] = &[
+    ("aws_instance", "instance_type"),
+    ("aws_launch_template", "instance_type"),
+    ("aws_db_instance", "instance_class"),
+    ("google_compute_instance", "machine_type"),
+    ("google_compute_instance_template", "machine_type"),
+    ("azurerm_linux_virtual_machine", "size"),
+    ("azurerm_windows_virtual_machine", "size"),
+];
+
+/// Resource types that accept tags, and the argument they go in
+const TAG_ARGUMENTS: &[(&str, &str)] = &[
+    ("aws_instance", "tags"),
+    ("aws_security_group", "tags"),
+    ("aws_db_instance", "tags"),
+    ("aws_s3_bucket", "tags"),
+    ("aws_lb", "tags"),
+    ("aws_eks_cluster", "tags"),
+    ("google_compute_instance", "labels"),
+    ("google_storage_bucket", "labels"),
+    ("google_sql_database_instance", "labels"),
+    ("google_container_cluster", "resource_labels"),
+    ("azurerm_linux_virtual_machine", "tags"),
+    ("azurerm_resource_group", "tags"),
+];
+
+/// Organisation limits applied to the AI-generated Terraform before anything is written.
+///
+/// ```json
+/// {
+///   "max_vcpus": 2,
+///   "denied_resource_types": ["aws_eks_*", "google_container_cluster"],
+///   "required_tags": {"team": "platform", "managed-by": "autodeployment"},
+///   "strict": false
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Largest machine allowed, in vCPUs; bigger instances are downgraded
+    #[serde(default)]
+    pub max_vcpus: Option<u32>,
+    /// Resource types that are removed; a trailing `*` matches a prefix
+    #[serde(default)]
+    pub denied_resource_types: Vec<String>,
+    /// Tags (labels on GCP) added to every taggable resource that lacks them
+    #[serde(default)]
+    pub required_tags: BTreeMap<String, String>,
+    /// Abort instead of fixing violations; also set by `--strict`
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// How a violation was dealt with
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyAction {
+    Downgraded,
+    Removed,
+    Tagged,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyViolation {
+    /// `type.name` of the offending resource
+    pub resource: String,
+    pub message: String,
+    pub action: PolicyAction,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.resource, self.message)
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read policy file {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid policy file {}: {}", path.display(), e))
+    }
+
+    /// Check `config` against the policy. Without `strict`, violations are fixed in place
+    /// (oversized machines become `fallback_instance_type`, denied resources are dropped,
+    /// missing tags are added) and returned; with `strict` they abort with an error. The fixed
+    /// config is checked again, so a fallback that breaks the policy itself is an error too.
+    pub fn enforce(&self, config: &mut TerraformConfig, fallback_instance_type: &str) -> Result<Vec<PolicyViolation>> {
+        let mut checked = config.clone();
+        let violations = self.apply(&mut checked, fallback_instance_type);
+
+        if self.strict && !violations.is_empty() {
+            let list: Vec<String> = violations.iter().map(|v| format!("  - {}", v)).collect();
+            return Err(anyhow!(
+                "🚫 Generated infrastructure violates the deployment policy:\n{}",
+                list.join("\n")
+            ));
+        }
+
+        let remaining = self.apply(&mut checked.clone(), fallback_instance_type);
+        if !remaining.is_empty() {
+            let list: Vec<String> = remaining.iter().map(|v| format!("  - {}", v)).collect();
+            return Err(anyhow!(
+                "🚫 Generated infrastructure still violates the deployment policy after fixing it; the fallback instance type {} is not allowed either:\n{}",
+                fallback_instance_type,
+                list.join("\n")
+            ));
+        }
+
+        for violation in &violations {
+            warn!("🛡️ Policy: {}", violation);
+        }
+        *config = checked;
+        Ok(violations)
+    }
+
+    fn apply(&self, config: &mut TerraformConfig, fallback_instance_type: &str) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+
+        config.resources.retain(|resource| {
+            let denied = self.is_denied(&resource.resource_type);
+            if denied {
+                violations.push(PolicyViolation {
+                    resource: format!("{}.{}", resource.resource_type, resource.name),
+                    message: format!("resource type {} is not allowed, removed", resource.resource_type),
+                    action: PolicyAction::Removed,
+                });
+            }
+            !denied
+        });
+
+        for resource in &mut config.resources {
+            let id = format!("{}.{}", resource.resource_type, resource.name);
+
+            if let (Some(max_vcpus), Some(argument)) = (self.max_vcpus, lookup(INSTANCE_TYPE_ARGUMENTS, &resource.resource_type)) {
+                let current = resource.config.get(argument).and_then(|v| v.as_str()).map(str::to_string);
+                if let Some(current) = current {
+                    if estimate_vcpus(&current).is_some_and(|vcpus| vcpus > max_vcpus) {
+                        resource.config.insert(argument.to_string(), serde_json::json!(fallback_instance_type));
+                        violations.push(PolicyViolation {
+                            resource: id.clone(),
+                            message: format!(
+                                "{} exceeds the {} vCPU limit, downgraded to {}",
+                                current, max_vcpus, fallback_instance_type
+                            ),
+                            action: PolicyAction::Downgraded,
+                        });
+                    }
+                }
+            }
+
+            if self.required_tags.is_empty() {
+                continue;
+            }
+            if let Some(argument) = lookup(TAG_ARGUMENTS, &resource.resource_type) {
+                let tags = resource
+                    .config
+                    .entry(argument.to_string())
+                    .or_insert_with(|| serde_json::json!({}));
+                if !tags.is_object() {
+                    *tags = serde_json::json!({});
+                }
+                let tags = tags.as_object_mut().expect("tags were just made an object");
+
+                let missing: Vec<&String> = self.required_tags.keys().filter(|key| !tags.contains_key(*key)).collect();
+                if missing.is_empty() {
+                    continue;
+                }
+                for key in &missing {
+                    tags.insert(key.to_string(), serde_json::json!(self.required_tags[*key]));
+                }
+                violations.push(PolicyViolation {
+                    resource: id,
+                    message: format!(
+                        "missing required {} {}, added",
+                        argument,
+                        missing.iter().map(|k| k.as_str()).collect::<Vec<_>>().join(", ")
+                    ),
+                    action: PolicyAction::Tagged,
+                });
+            }
+        }
+
+        violations
+    }
+
+    fn is_denied(&self, resource_type: &str) -> bool {
+        self.denied_resource_types.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => resource_type.starts_with(prefix),
+            None => resource_type == pattern,
+        })
+    }
+}
+
+/// Set tag `key` to `value` on every taggable resource (labels on GCP), overwriting an
+/// existing value. Returns the `type.name` of each tagged resource.
+pub fn tag_resources(config: &mut TerraformConfig, key: &str, value: &str) -> Vec<String> {
+    let mut tagged = Vec::new();
+    for resource in &mut config.resources {
+        let Some(argument) = lookup(TAG_ARGUMENTS, &resource.resource_type) else {
+            continue;
+        };
+        let tags = resource
+            .config
+            .entry(argument.to_string())
+            .or_insert_with(|| serde_json::json!({}));
+        if !tags.is_object() {
+            *tags = serde_json::json!({});
+        }
+        tags.as_object_mut()
+            .expect("tags were just made an object")
+            .insert(key.to_string(), serde_json::json!(value));
+        tagged.push(format!("{}.{}", resource.resource_type, resource.name));
+    }
+    tagged
+}
+
+fn lookup(table: &[(&str, &'static str)], resource_type: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(ty, _)| *ty == resource_type)
+        .map(|(_, argument)| *argument)
+}
+
+/// Rough vCPU count of a machine type, e.g. `t3.micro` = 2, `m5.24xlarge` = 96,
+/// `e2-standard-8` = 8, `Standard_D4s_v3` = 4. `None` when the name is not recognised.
+pub fn estimate_vcpus(instance_type: &str) -> Option<u32> {
+    // AWS: family.size (also db.family.size)
+    if let Some((_, size)) = instance_type.rsplit_once('.') {
+        return match size {
+            "nano" | "micro" | "small" | "medium" | "large" => Some(2),
+            "xlarge" => Some(4),
+            "metal" => Some(u32::MAX),
+            _ => size.strip_suffix("xlarge")?.parse::<u32>().ok().map(|n| n * 4),
+        };
+    }
+
+    // Azure: Standard_<family><vcpus><features>[_version]
+    if let Some(name) = instance_type.strip_prefix("Standard_") {
+        let digits: String = name
+            .chars()
+            .skip_while(|c| c.is_ascii_alphabetic())
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        return digits.parse().ok();
+    }
+
+    // GCP: family-type-vcpus or shared-core e2-micro/small/medium
+    let last = instance_type.rsplit('-').next()?;
+    match last {
+        "micro" | "small" | "medium" => Some(2),
+        _ => last.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::TerraformResource;
+    use std::collections::HashMap;
+
+    fn resource(resource_type: &str, name: &str, config: serde_json::Value) -> TerraformResource {
+        TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            config: serde_json::from_value(config).unwrap(),
+        }
+    }
+
+    fn ai_config() -> TerraformConfig {
+        TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                resource("aws_instance", "app", serde_json::json!({"instance_type": "m5.24xlarge", "tags": {"Name": "app"}})),
+                resource("aws_security_group", "app_sg", serde_json::json!({})),
+                resource("aws_eks_cluster", "cluster", serde_json::json!({"name": "app"})),
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        }
+    }
+
+    fn policy() -> Policy {
+        serde_json::from_str(
+            r#"{"max_vcpus": 2, "denied_resource_types": ["aws_eks_*"], "required_tags": {"team": "platform"}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_estimate_vcpus() {
+        assert_eq!(estimate_vcpus("t3.micro"), Some(2));
+        assert_eq!(estimate_vcpus("m5.xlarge"), Some(4));
+        assert_eq!(estimate_vcpus("m5.24xlarge"), Some(96));
+        assert_eq!(estimate_vcpus("db.r5.2xlarge"), Some(8));
+        assert_eq!(estimate_vcpus("e2-micro"), Some(2));
+        assert_eq!(estimate_vcpus("n2-standard-16"), Some(16));
+        assert_eq!(estimate_vcpus("Standard_B1s"), Some(1));
+        assert_eq!(estimate_vcpus("Standard_D4s_v3"), Some(4));
+        assert_eq!(estimate_vcpus("lambda"), None);
+    }
+
+    #[test]
+    fn test_enforce_fixes_violations() {
+        let mut config = ai_config();
+        let violations = policy().enforce(&mut config, "t3.micro").unwrap();
+
+        let actions: Vec<_> = violations.iter().map(|v| (v.resource.as_str(), v.action.clone())).collect();
+        assert_eq!(actions, vec![
+            ("aws_eks_cluster.cluster", PolicyAction::Removed),
+            ("aws_instance.app", PolicyAction::Downgraded),
+            ("aws_instance.app", PolicyAction::Tagged),
+            ("aws_security_group.app_sg", PolicyAction::Tagged),
+        ]);
+
+        assert_eq!(config.resources.len(), 2);
+        let instance = &config.resources[0].config;
+        assert_eq!(instance["instance_type"], "t3.micro");
+        assert_eq!(instance["tags"], serde_json::json!({"Name": "app", "team": "platform"}));
+
+        // A compliant config is left alone
+        assert!(policy().enforce(&mut config, "t3.micro").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_enforce_rechecks_the_fallback() {
+        let mut config = ai_config();
+        // e.g. a GPU instance chosen for the app, itself over the limit
+        let err = policy().enforce(&mut config, "m5.xlarge").unwrap_err().to_string();
+        assert!(err.contains("fallback instance type m5.xlarge is not allowed"), "{}", err);
+        assert!(err.contains("m5.xlarge exceeds the 2 vCPU limit"), "{}", err);
+        assert_eq!(config.resources[0].config["instance_type"], "m5.24xlarge", "nothing is changed");
+    }
+
+    #[test]
+    fn test_strict_policy_rejects_without_changes() {
+        let mut policy = policy();
+        policy.strict = true;
+        let mut config = ai_config();
+
+        let err = policy.enforce(&mut config, "t3.micro").unwrap_err().to_string();
+        assert!(err.contains("m5.24xlarge exceeds the 2 vCPU limit"));
+        assert!(err.contains("aws_eks_cluster.cluster"));
+        assert_eq!(config.resources.len(), 3);
+        assert_eq!(config.resources[0].config["instance_type"], "m5.24xlarge");
+    }
+
+    #[test]
+    fn test_tag_resources() {
+        let mut config = ai_config();
+        let tagged = tag_resources(&mut config, "deployment", "my-demo");
+        assert_eq!(tagged, vec!["aws_instance.app", "aws_security_group.app_sg", "aws_eks_cluster.cluster"]);
+        assert_eq!(config.resources[0].config["tags"], serde_json::json!({"Name": "app", "deployment": "my-demo"}));
+        assert_eq!(config.resources[1].config["tags"], serde_json::json!({"deployment": "my-demo"}));
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_fields() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("policy.json");
+        fs::write(&path, r#"{"max_vcpu": 2}"#).unwrap();
+        assert!(Policy::load(&path).unwrap_err().to_string().contains("max_vcpu"));
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use log::warn;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::infrastructure::TerraformConfig;
+use crate::settings;
+
+/// Resource types that hold data a destroy would lose: databases, caches, disks and buckets
+const STATEFUL_RESOURCE_TYPES: &[&str] = &[
+    "aws_db_instance",
+    "aws_rds_cluster",
+    "aws_docdb_cluster",
+    "aws_elasticache_cluster",
+    "aws_elasticache_replication_group",
+    "aws_dynamodb_table",
+    "aws_ebs_volume",
+    "aws_efs_file_system",
+    "aws_s3_bucket",
+    "google_sql_database_instance",
+    "google_redis_instance",
+    "google_compute_disk",
+    "google_filestore_instance",
+    "google_storage_bucket",
+    "azurerm_postgresql_flexible_server",
+    "azurerm_mysql_flexible_server",
+    "azurerm_cosmosdb_account",
+    "azurerm_redis_cache",
+    "azurerm_managed_disk",
+    "azurerm_storage_account",
+];
+
+/// Resource types given `prevent_destroy`: the `protected_resources` setting, or the stateful ones
+pub fn protected_types() -> Vec<String> {
+    match &settings::get().protected_resources {
+        Some(types) => types.clone(),
+        None => STATEFUL_RESOURCE_TYPES.iter().map(|t| t.to_string()).collect(),
+    }
+}
+
+/// Add `lifecycle { prevent_destroy = true }` to every resource of `types`, keeping any other
+/// lifecycle settings. Returns the protected resources.
+pub fn apply_prevent_destroy(config: &mut TerraformConfig, types: &[String]) -> Vec<String> {
+    let mut protected = Vec::new();
+    for resource in &mut config.resources {
+        if !types.contains(&resource.resource_type) {
+            continue;
+        }
+        let lifecycle = resource.config.entry("lifecycle".to_string()).or_insert_with(|| json!({}));
+        if let Value::Array(blocks) = lifecycle {
+            let first = blocks.drain(..).next().unwrap_or_else(|| json!({}));
+            *lifecycle = first;
+        }
+        if !lifecycle.is_object() {
+            *lifecycle = json!({});
+        }
+        lifecycle.as_object_mut().unwrap().insert("prevent_destroy".to_string(), json!(true));
+        protected.push(format!("{}.{}", resource.resource_type, resource.name));
+    }
+    protected
+}
+
+/// `prevent_destroy = true` inside a resource block of a `.tf` file
+const PREVENT_DESTROY_PATTERN: &str = r"prevent_destroy\s*=\s*true\b";
+
+/// `type.name` of every resource in the `.tf` and `.tf.json` files in `terraform_dir` that
+/// has `prevent_destroy` set, so that `terraform destroy` would refuse to run
+pub fn protected_resources(terraform_dir: &Path) -> Result<Vec<String>> {
+    let resource_block = Regex::new(r#"(?m)^\s*resource\s+"([^"]+)"\s+"([^"]+)""#).unwrap();
+    let prevent_destroy = Regex::new(PREVENT_DESTROY_PATTERN).unwrap();
+    let mut protected = Vec::new();
+    for entry in fs::read_dir(terraform_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if file_name.ends_with(".tf") {
+            let contents = fs::read_to_string(&path)?;
+            let headers: Vec<_> = resource_block.captures_iter(&contents).collect();
+            for (i, caps) in headers.iter().enumerate() {
+                // A resource's block runs until the next resource starts
+                let start = caps.get(0).unwrap().end();
+                let end = headers.get(i + 1).map_or(contents.len(), |next| next.get(0).unwrap().start());
+                if prevent_destroy.is_match(&contents[start..end]) {
+                    protected.push(format!("{}.{}", &caps[1], &caps[2]));
+                }
+            }
+        } else if file_name.ends_with(".tf.json") {
+            let contents: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            let Some(types) = contents.get("resource").and_then(|resources| resources.as_object()) else { continue };
+            for (resource_type, names) in types {
+                for (name, resource) in names.as_object().into_iter().flatten() {
+                    if json_prevents_destroy(resource) {
+                        protected.push(format!("{}.{}", resource_type, name));
+                    }
+                }
+            }
+        }
+    }
+    protected.sort();
+    Ok(protected)
+}
+
+fn json_prevents_destroy(resource: &Value) -> bool {
+    let lifecycle = resource.get("lifecycle");
+    let blocks = match lifecycle {
+        Some(Value::Array(blocks)) => blocks.iter().collect(),
+        Some(block) => vec![block],
+        None => Vec::new(),
+    };
+    blocks.iter().any(|block| block.get("prevent_destroy") == Some(&json!(true)))
+}
+
+/// Files whose `prevent_destroy` was turned off, put back as they were when this is dropped,
+/// so a destroy that fails or is interrupted never leaves the resources unprotected
+#[must_use = "the protection comes back as soon as this is dropped"]
+pub struct LiftedProtection {
+    originals: Vec<(PathBuf, String)>,
+}
+
+impl Drop for LiftedProtection {
+    fn drop(&mut self) {
+        for (path, contents) in &self.originals {
+            if let Err(e) = fs::write(path, contents) {
+                warn!("⚠️ Could not restore prevent_destroy in {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Turn `prevent_destroy` off in the files in `terraform_dir` so `terraform destroy` can
+/// remove the protected resources, until the returned guard is dropped
+pub fn lift_protection(terraform_dir: &Path) -> Result<LiftedProtection> {
+    let prevent_destroy = Regex::new(PREVENT_DESTROY_PATTERN).unwrap();
+    // Files changed before an error are restored when the partial guard drops
+    let mut lifted = LiftedProtection { originals: Vec::new() };
+    for entry in fs::read_dir(terraform_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if file_name.ends_with(".tf") {
+            let contents = fs::read_to_string(&path)?;
+            if prevent_destroy.is_match(&contents) {
+                let unprotected = prevent_destroy.replace_all(&contents, "prevent_destroy = false").into_owned();
+                lifted.originals.push((path.clone(), contents));
+                fs::write(&path, unprotected)?;
+            }
+        } else if file_name.ends_with(".tf.json") {
+            let original = fs::read_to_string(&path)?;
+            let mut contents: Value = serde_json::from_str(&original)?;
+            let Some(types) = contents.get_mut("resource").and_then(|resources| resources.as_object_mut()) else { continue };
+            for resource in types.values_mut().filter_map(Value::as_object_mut).flat_map(|names| names.values_mut()) {
+                match resource.get_mut("lifecycle") {
+                    Some(Value::Array(blocks)) => blocks.iter_mut().for_each(allow_destroy),
+                    Some(block) => allow_destroy(block),
+                    None => {}
+                }
+            }
+            lifted.originals.push((path.clone(), original));
+            fs::write(&path, serde_json::to_string_pretty(&contents)?)?;
+        }
+    }
+    Ok(lifted)
+}
+
+fn allow_destroy(block: &mut Value) {
+    if let Some(block) = block.as_object_mut().filter(|block| block.contains_key("prevent_destroy")) {
+        block.insert("prevent_destroy".to_string(), json!(false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::TerraformResource;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_prevent_destroy_on_stateful_resources() {
+        let resource = |resource_type: &str, name: &str, config: Value| TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            config: serde_json::from_value(config).unwrap(),
+        };
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![
+                resource("aws_instance", "app", json!({})),
+                resource("aws_db_instance", "app_db", json!({"lifecycle": {"ignore_changes": ["password"]}})),
+                resource("aws_ebs_volume", "data", json!({})),
+            ],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let protected = apply_prevent_destroy(&mut config, &protected_types());
+        assert_eq!(protected, vec!["aws_db_instance.app_db", "aws_ebs_volume.data"]);
+        assert_eq!(config.resources[1].config["lifecycle"], json!({"ignore_changes": ["password"], "prevent_destroy": true}));
+        assert!(!config.resources[0].config.contains_key("lifecycle"));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        fs::write(
+            dir.join("main.tf"),
+            "resource \"aws_db_instance\" \"app_db\" {\n  lifecycle {\n    prevent_destroy = true\n  }\n}\n\nresource \"aws_instance\" \"app\" {\n  ami = \"ami-1\"\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("extra.tf.json"),
+            r#"{"resource": {"aws_s3_bucket": {"assets": {"lifecycle": [{"prevent_destroy": true}]}, "logs": {}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(protected_resources(dir).unwrap(), vec!["aws_db_instance.app_db", "aws_s3_bucket.assets"]);
+        let lifted = lift_protection(dir).unwrap();
+        assert!(protected_resources(dir).unwrap().is_empty());
+        assert!(fs::read_to_string(dir.join("main.tf")).unwrap().contains("prevent_destroy = false"));
+        drop(lifted);
+        assert_eq!(protected_resources(dir).unwrap(), vec!["aws_db_instance.app_db", "aws_s3_bucket.assets"]);
+    }
+
+    #[test]
+    fn test_failed_destroy_restores_protection() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let main_tf = "resource \"aws_db_instance\" \"app_db\" {\n  lifecycle {\n    prevent_destroy = true\n  }\n}\n";
+        fs::write(dir.join("main.tf"), main_tf).unwrap();
+
+        let destroy = || -> Result<()> {
+            let _lifted = lift_protection(dir)?;
+            assert!(protected_resources(dir)?.is_empty());
+            Err(anyhow::anyhow!("terraform destroy failed: Error acquiring the state lock"))
+        };
+        assert!(destroy().is_err());
+        assert_eq!(fs::read_to_string(dir.join("main.tf")).unwrap(), main_tf, "a later plain destroy is refused again");
+    }
+}
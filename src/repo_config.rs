@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::feedback;
+use crate::nlp::ApplicationType;
+use crate::repository::RepositoryAnalysis;
+
+/// File at the repository root whose settings replace what analysis detected
+pub const CONFIG_FILE: &str = ".autodeployment.toml";
+
+/// Overrides committed with the repository in `.autodeployment.toml`. Every field is optional;
+/// a field that is set wins over the detected value.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    /// App type by name, e.g. `fastapi` or `nextjs`; build and start commands, the default
+    /// port and the production server follow from it
+    #[serde(deserialize_with = "app_type")]
+    pub app_type: Option<ApplicationType>,
+    /// Replaces the detected build commands
+    pub build_command: Option<String>,
+    /// Replaces the detected start command
+    pub start_command: Option<String>,
+    /// Ports the app listens on; the first is the one it binds to
+    pub ports: Option<Vec<u16>>,
+    /// Environment variables set on the app
+    pub env: BTreeMap<String, String>,
+}
+
+fn app_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ApplicationType>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    feedback::parse_app_type(&name).map(Some).ok_or_else(|| {
+        let names: Vec<&str> = feedback::APP_TYPES.iter().map(|(name, _)| *name).collect();
+        serde::de::Error::custom(format!("unknown app type {:?}, expected one of {}", name, names.join(", ")))
+    })
+}
+
+impl RepoConfig {
+    /// The config in `repo_path`; a missing file means no overrides
+    pub fn load(repo_path: &Path) -> Result<Self> {
+        let path = repo_path.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read {}: {}", CONFIG_FILE, e))?;
+        toml::from_str(&contents).map_err(|e| anyhow!("Invalid {}: {}", CONFIG_FILE, e))
+    }
+
+    /// Replace the detected ports and environment with the configured ones. Runs before the
+    /// commands are generated, so that they bind to the configured port. Returns the names of
+    /// the fields that were applied.
+    pub fn apply(&self, analysis: &mut RepositoryAnalysis) -> Vec<&'static str> {
+        let mut applied = Vec::new();
+        if self.app_type.is_some() {
+            applied.push("app_type");
+        }
+        if let Some(ports) = &self.ports {
+            analysis.internal_port = ports.first().copied();
+            analysis.exposed_ports = ports.clone();
+            applied.push("ports");
+        }
+        if !self.env.is_empty() {
+            for name in self.env.keys() {
+                if !analysis.environment_variables.contains(name) {
+                    analysis.environment_variables.push(name.clone());
+                }
+            }
+            analysis.configured_env = self.env.clone();
+            applied.push("env");
+        }
+        applied
+    }
+
+    /// Replace the generated build and start commands with the configured ones. Returns the
+    /// names of the fields that were applied.
+    pub fn apply_commands(&self, analysis: &mut RepositoryAnalysis) -> Vec<&'static str> {
+        let mut applied = Vec::new();
+        if let Some(command) = &self.build_command {
+            analysis.build_commands = vec![command.clone()];
+            analysis.requires_build_step = true;
+            applied.push("build_command");
+        }
+        if let Some(command) = &self.start_command {
+            analysis.start_commands = vec![command.clone()];
+            // Whatever the detection thought, there is now something to start
+            analysis.not_a_service = None;
+            applied.push("start_command");
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::analyze_repository;
+
+    #[test]
+    fn test_config_overrides_misdetected_app_type() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repo_path = temp_dir.path();
+        // Mentions Flask, so detection takes it for a Flask app
+        fs::write(repo_path.join("requirements.txt"), "FastAPI\nuvicorn\n").unwrap();
+        fs::write(repo_path.join("main.py"), "# Ported from Flask\nfrom fastapi import FastAPI\napp = FastAPI()\n").unwrap();
+        assert_eq!(analyze_repository(repo_path).unwrap().app_type, ApplicationType::Flask);
+
+        fs::write(
+            repo_path.join(CONFIG_FILE),
+            "app_type = \"fastapi\"\nports = [8080]\n\n[env]\nLOG_LEVEL = \"debug\"\n",
+        )
+        .unwrap();
+        let analysis = analyze_repository(repo_path).unwrap();
+        assert_eq!(analysis.app_type, ApplicationType::FastAPI);
+        assert_eq!(analysis.start_commands, vec!["uvicorn main:app --host 0.0.0.0 --port 8080"]);
+        assert_eq!((analysis.internal_port, analysis.exposed_ports.clone()), (Some(8080), vec![8080]));
+        assert_eq!(analysis.configured_env["LOG_LEVEL"], "debug");
+        assert!(analysis.environment_variables.contains(&"LOG_LEVEL".to_string()));
+
+        fs::write(repo_path.join(CONFIG_FILE), "start_command = \"./serve --port 8080\"\nbuild_command = \"make\"\n").unwrap();
+        let analysis = analyze_repository(repo_path).unwrap();
+        assert_eq!(analysis.start_commands, vec!["./serve --port 8080"]);
+        assert_eq!(analysis.build_commands, vec!["make"]);
+
+        fs::write(repo_path.join(CONFIG_FILE), "app_type = \"cobol\"\n").unwrap();
+        assert!(analyze_repository(repo_path).unwrap_err().to_string().contains("unknown app type"));
+        fs::write(repo_path.join(CONFIG_FILE), "start = \"npm start\"\n").unwrap();
+        assert!(analyze_repository(repo_path).unwrap_err().to_string().contains("Invalid .autodeployment.toml"));
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+/// A check that aborts a deploy when it fails. Each one is registered here under the name
+/// `--force-deploy <name>,...` bypasses it by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafetyCheck {
+    /// Violations of a `--strict` resource policy
+    Policy,
+    /// Repositories that look like a library or CLI rather than a service
+    NotAService,
+}
+
+impl SafetyCheck {
+    pub const ALL: &'static [SafetyCheck] = &[SafetyCheck::Policy, SafetyCheck::NotAService];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SafetyCheck::Policy => "policy",
+            SafetyCheck::NotAService => "not-a-service",
+        }
+    }
+}
+
+impl FromStr for SafetyCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_lowercase();
+        SafetyCheck::ALL
+            .iter()
+            .copied()
+            .find(|check| check.name() == name)
+            .ok_or_else(|| {
+                let known: Vec<&str> = SafetyCheck::ALL.iter().map(|check| check.name()).collect();
+                anyhow!("Unknown safety check: {}. Use: {}", s, known.join(", "))
+            })
+    }
+}
+
+/// Which safety checks `--force-deploy` bypasses
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ForceDeploy {
+    /// Flag absent: every check is enforced
+    #[default]
+    None,
+    /// Bare `--force-deploy`: every check is bypassed
+    All,
+    /// `--force-deploy policy,...`: only the listed checks are bypassed
+    Only(Vec<SafetyCheck>),
+}
+
+impl ForceDeploy {
+    /// Interpret the raw flag: `None` when absent, an empty list for a bare `--force-deploy`
+    pub fn from_flag(values: Option<Vec<String>>) -> Result<Self> {
+        match values {
+            None => Ok(ForceDeploy::None),
+            Some(values) if values.is_empty() => Ok(ForceDeploy::All),
+            Some(values) => Ok(ForceDeploy::Only(
+                values.iter().map(|value| value.parse()).collect::<Result<_>>()?,
+            )),
+        }
+    }
+
+    /// Whether `--force-deploy` was given at all
+    pub fn is_forced(&self) -> bool {
+        *self != ForceDeploy::None
+    }
+
+    pub fn bypasses(&self, check: SafetyCheck) -> bool {
+        match self {
+            ForceDeploy::None => false,
+            ForceDeploy::All => true,
+            ForceDeploy::Only(checks) => checks.contains(&check),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_force_deploy_from_flag() {
+        let absent = ForceDeploy::from_flag(None).unwrap();
+        assert!(!absent.is_forced());
+        assert!(!absent.bypasses(SafetyCheck::Policy));
+
+        let bare = ForceDeploy::from_flag(Some(vec![])).unwrap();
+        assert_eq!(bare, ForceDeploy::All);
+        assert!(bare.bypasses(SafetyCheck::Policy));
+
+        let listed = ForceDeploy::from_flag(Some(vec!["Policy".to_string()])).unwrap();
+        assert_eq!(listed, ForceDeploy::Only(vec![SafetyCheck::Policy]));
+        assert!(listed.bypasses(SafetyCheck::Policy));
+
+        let err = ForceDeploy::from_flag(Some(vec!["policy".to_string(), "secrets".to_string()])).unwrap_err();
+        assert!(err.to_string().contains("Unknown safety check: secrets. Use: policy, not-a-service"));
+    }
+}
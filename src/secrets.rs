@@ -0,0 +1,233 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::infrastructure::{TerraformConfig, TerraformResource};
+use crate::nlp::CloudProvider;
+
+/// Where the fetched secrets are written on the instance; only root can read it
+pub const REMOTE_SECRETS_FILE: &str = "/etc/autodeployment/secrets.env";
+
+/// Name of the IAM role / service account resources granting the instance read access
+const RESOURCE_NAME: &str = "app_secrets";
+
+/// Secret manager the instance reads its environment from at startup (`--secrets-source`).
+/// Every secret whose name starts with the prefix becomes a variable named after the rest,
+/// upper-cased: `myapp/prod/db_password` with prefix `myapp/prod/` is `DB_PASSWORD`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecretsSource {
+    pub cloud_provider: CloudProvider,
+    pub prefix: String,
+}
+
+impl FromStr for SecretsSource {
+    type Err = anyhow::Error;
+
+    /// `aws:<prefix>` for AWS Secrets Manager, `gcp:<prefix>` for GCP Secret Manager
+    fn from_str(s: &str) -> Result<Self> {
+        let (provider, prefix) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid --secrets-source {:?}, expected aws:<prefix> or gcp:<prefix>", s))?;
+        // Characters each manager allows in secret names; nothing here needs shell quoting
+        let (cloud_provider, allowed): (CloudProvider, &str) = match provider.to_lowercase().as_str() {
+            "aws" => (CloudProvider::AWS, "/_+=.@-"),
+            "gcp" => (CloudProvider::GCP, "_-"),
+            other => return Err(anyhow!("Unsupported --secrets-source provider {}, use aws or gcp", other)),
+        };
+        if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_alphanumeric() || allowed.contains(c)) {
+            return Err(anyhow!("Invalid secret name prefix {:?} for {}", prefix, provider));
+        }
+        Ok(SecretsSource { cloud_provider, prefix: prefix.to_string() })
+    }
+}
+
+impl fmt::Display for SecretsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cloud_provider {
+            CloudProvider::GCP => write!(f, "GCP Secret Manager ({}*)", self.prefix),
+            _ => write!(f, "AWS Secrets Manager ({}*)", self.prefix),
+        }
+    }
+}
+
+impl SecretsSource {
+    /// Give the config's instances read access to the secrets: an IAM role and instance
+    /// profile on AWS, a service account on GCP. Returns the instances granted access.
+    pub fn apply(&self, config: &mut TerraformConfig) -> Result<Vec<String>> {
+        let (instance_type, grants) = match self.cloud_provider {
+            CloudProvider::AWS => ("aws_instance", self.aws_role()),
+            CloudProvider::GCP => ("google_compute_instance", self.gcp_service_account()),
+            ref other => return Err(anyhow!("--secrets-source is not supported on {:?}", other)),
+        };
+        // Re-applied after repairs, so replace rather than duplicate
+        config
+            .resources
+            .retain(|resource| !(resource.name == RESOURCE_NAME && grants.iter().any(|grant| grant.resource_type == resource.resource_type)));
+        config.resources.extend(grants);
+
+        let mut granted = Vec::new();
+        for resource in config.resources.iter_mut().filter(|resource| resource.resource_type == instance_type) {
+            match self.cloud_provider {
+                CloudProvider::AWS => {
+                    resource.config.insert(
+                        "iam_instance_profile".to_string(),
+                        json!(format!("${{aws_iam_instance_profile.{}.name}}", RESOURCE_NAME)),
+                    );
+                }
+                _ => {
+                    resource.config.insert(
+                        "service_account".to_string(),
+                        json!({
+                            "email": format!("${{google_service_account.{}.email}}", RESOURCE_NAME),
+                            "scopes": ["cloud-platform"],
+                        }),
+                    );
+                }
+            }
+            granted.push(format!("{}.{}", resource.resource_type, resource.name));
+        }
+        if granted.is_empty() {
+            return Err(anyhow!("The generated configuration has no instance to read the secrets"));
+        }
+        Ok(granted)
+    }
+
+    fn aws_role(&self) -> Vec<TerraformResource> {
+        let assume_role = json!({
+            "Version": "2012-10-17",
+            "Statement": [{"Effect": "Allow", "Principal": {"Service": "ec2.amazonaws.com"}, "Action": "sts:AssumeRole"}],
+        });
+        let read_secrets = json!({
+            "Version": "2012-10-17",
+            "Statement": [
+                {
+                    "Effect": "Allow",
+                    "Action": ["secretsmanager:GetSecretValue", "secretsmanager:DescribeSecret"],
+                    "Resource": format!("arn:aws:secretsmanager:*:*:secret:{}*", self.prefix),
+                },
+                // Listing cannot be scoped to a name prefix
+                {"Effect": "Allow", "Action": "secretsmanager:ListSecrets", "Resource": "*"},
+            ],
+        });
+        vec![
+            resource("aws_iam_role", json!({
+                "name_prefix": "autodeploy-secrets-",
+                "assume_role_policy": assume_role.to_string(),
+            })),
+            resource("aws_iam_role_policy", json!({
+                "name_prefix": "read-secrets-",
+                "role": format!("${{aws_iam_role.{}.id}}", RESOURCE_NAME),
+                "policy": read_secrets.to_string(),
+            })),
+            resource("aws_iam_instance_profile", json!({
+                "name_prefix": "autodeploy-secrets-",
+                "role": format!("${{aws_iam_role.{}.name}}", RESOURCE_NAME),
+            })),
+        ]
+    }
+
+    fn gcp_service_account(&self) -> Vec<TerraformResource> {
+        let account_id = format!("autodeploy-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let member = format!("serviceAccount:${{google_service_account.{}.email}}", RESOURCE_NAME);
+        vec![
+            resource("google_service_account", json!({
+                "account_id": account_id,
+                "display_name": format!("Reads {} secrets", self.prefix),
+            })),
+            // Project-wide: Secret Manager IAM conditions need the project number to scope by name
+            resource("google_project_iam_member", json!({
+                "project": "var.project_id",
+                "role": "roles/secretmanager.secretAccessor",
+                "member": member,
+            })),
+        ]
+    }
+
+    /// Startup script lines fetching every matching secret into `REMOTE_SECRETS_FILE` and
+    /// exporting it. Avoids `${`, which Terraform would interpolate in `user_data`.
+    pub fn fetch_script(&self) -> String {
+        let strip_prefix = format!("sed 's|^{}||'", self.prefix.replace('.', "\\."));
+        let (list, access, setup) = match self.cloud_provider {
+            CloudProvider::GCP => (
+                format!(
+                    "gcloud secrets list --filter='name:{}' --format='value(name.basename())'",
+                    self.prefix
+                ),
+                "gcloud secrets versions access latest --secret=\"$name\"".to_string(),
+                String::new(),
+            ),
+            _ => (
+                format!(
+                    "aws secretsmanager list-secrets --filters Key=name,Values='{}' --query 'SecretList[].Name' --output text",
+                    self.prefix
+                ),
+                "aws secretsmanager get-secret-value --secret-id \"$name\" --query SecretString --output text".to_string(),
+                // Amazon Linux ships the CLI; other images get the official bundle
+                concat!(
+                    "if ! command -v aws >/dev/null; then\n",
+                    "  curl -sSL \"https://awscli.amazonaws.com/awscli-exe-linux-$(uname -m).zip\" -o /tmp/awscliv2.zip\n",
+                    "  unzip -qo /tmp/awscliv2.zip -d /tmp && /tmp/aws/install\n",
+                    "fi\n",
+                    "IMDS_TOKEN=$(curl -sX PUT http://169.254.169.254/latest/api/token -H 'X-aws-ec2-metadata-token-ttl-seconds: 60')\n",
+                    "export AWS_DEFAULT_REGION=$(curl -s -H \"X-aws-ec2-metadata-token: $IMDS_TOKEN\" http://169.254.169.254/latest/meta-data/placement/region)\n",
+                )
+                .to_string(),
+            ),
+        };
+        format!(
+            "# Secrets from {source}, read with the instance's own identity\n{setup}mkdir -p /etc/autodeployment\n(umask 077; : > {file})\nfor name in $({list}); do\n  case \"$name\" in {prefix}*) ;; *) continue ;; esac\n  key=$(printf '%s' \"$name\" | {strip_prefix} | tr '[:lower:]' '[:upper:]' | tr -c 'A-Z0-9_' '_')\n  value=$({access})\n  printf '%s=%q\\n' \"$key\" \"$value\" >> {file}\ndone\nset -a\n. {file}\nset +a\n\n",
+            source = self,
+            setup = setup,
+            file = REMOTE_SECRETS_FILE,
+            list = list,
+            prefix = self.prefix,
+            strip_prefix = strip_prefix,
+            access = access,
+        )
+    }
+}
+
+fn resource(resource_type: &str, config: Value) -> TerraformResource {
+    TerraformResource {
+        resource_type: resource_type.to_string(),
+        name: RESOURCE_NAME.to_string(),
+        config: serde_json::from_value(config).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_aws_secrets_source() {
+        assert!("vault:app/".parse::<SecretsSource>().is_err());
+        assert!("gcp:app/prod".parse::<SecretsSource>().is_err(), "GCP secret names have no slashes");
+        assert!("aws:app'; rm -rf /".parse::<SecretsSource>().is_err());
+        let source: SecretsSource = "aws:myapp/prod/".parse().unwrap();
+
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![TerraformResource {
+                resource_type: "aws_instance".to_string(),
+                name: "app".to_string(),
+                config: HashMap::new(),
+            }],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        assert_eq!(source.apply(&mut config).unwrap(), vec!["aws_instance.app"]);
+        source.apply(&mut config).unwrap();
+        assert_eq!(config.resources.len(), 4, "re-applying replaces the role instead of adding another");
+        assert_eq!(config.resources[0].config["iam_instance_profile"], "${aws_iam_instance_profile.app_secrets.name}");
+        let policy = config.resources.iter().find(|r| r.resource_type == "aws_iam_role_policy").unwrap();
+        assert!(policy.config["policy"].as_str().unwrap().contains("arn:aws:secretsmanager:*:*:secret:myapp/prod/*"));
+
+        let script = source.fetch_script();
+        assert!(script.contains("aws secretsmanager list-secrets --filters Key=name,Values='myapp/prod/'"));
+        assert!(script.contains(&format!(">> {}", REMOTE_SECRETS_FILE)));
+        assert!(!script.contains("${"), "Terraform would interpolate it");
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Keys accepted by `config get/set/unset`, in the order `config list` prints them
+pub const KEYS: &[&str] = &[
+    "cloud_provider",
+    "region",
+    "model",
+    "output_dir",
+    "concurrency",
+    "max_output_tokens",
+    "detection_feedback",
+    "protected_resources",
+    "build_cache_image",
+];
+
+/// Persistent tool settings from `~/.autodeployment/config.toml`.
+///
+/// Every field is optional: a command-line flag wins over the stored value, which wins over
+/// the built-in default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Provider used when `--cloud-provider` is omitted and the description names none
+    pub cloud_provider: Option<String>,
+    /// Region used when the provider's credentials do not set one
+    pub region: Option<String>,
+    /// Model for all AI calls
+    pub model: Option<String>,
+    /// Directory generated Terraform is written to (default: `./terraform-output`)
+    pub output_dir: Option<PathBuf>,
+    pub concurrency: Option<usize>,
+    pub max_output_tokens: Option<u32>,
+    /// Ask in chat whether the detected app type and port are right, and record the answers
+    /// locally for improving detection
+    pub detection_feedback: Option<bool>,
+    /// Resource types given `prevent_destroy`, replacing the built-in list of stateful ones
+    pub protected_resources: Option<Vec<String>>,
+    /// Registry image container builds pull their layer cache from and push it to
+    pub build_cache_image: Option<String>,
+}
+
+impl Settings {
+    /// Load the stored settings; a missing file means no settings
+    pub fn load() -> Result<Self> {
+        Self::load_from(&config_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| {
+            anyhow!(
+                "Invalid config file {}: {}. Remove the offending key with `config unset <key>` or edit the file",
+                path.display(),
+                e
+            )
+        })
+    }
+
+    /// Remove `key` from the file at `path` as written, without loading it, so a file `load`
+    /// rejects (a misspelled key, a value of the wrong type) can still be repaired. Returns
+    /// whether the key was there.
+    pub fn remove_from_file(path: &Path, key: &str) -> Result<bool> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+        let mut table: toml::value::Table = toml::from_str(&contents)
+            .map_err(|e| anyhow!("Config file {} is not valid TOML ({}); edit or delete it", path.display(), e))?;
+        if table.remove(key).is_none() {
+            return Ok(false);
+        }
+        fs::write(path, toml::to_string(&table)?)?;
+        Ok(true)
+    }
+
+    pub fn save(&self) -> Result<PathBuf> {
+        let path = config_path()?;
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Stored value of `key`, or `None` when it is unset
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = match key {
+            "cloud_provider" => self.cloud_provider.clone(),
+            "region" => self.region.clone(),
+            "model" => self.model.clone(),
+            "output_dir" => self.output_dir.as_ref().map(|dir| dir.display().to_string()),
+            "concurrency" => self.concurrency.map(|n| n.to_string()),
+            "max_output_tokens" => self.max_output_tokens.map(|n| n.to_string()),
+            "detection_feedback" => self.detection_feedback.map(|enabled| enabled.to_string()),
+            "protected_resources" => self.protected_resources.as_ref().map(|types| types.join(",")),
+            "build_cache_image" => self.build_cache_image.clone(),
+            _ => return Err(unknown_key(key)),
+        };
+        Ok(value)
+    }
+
+    /// Validate and store `value` under `key`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(anyhow!("Empty value for {}; use `config unset {}` to clear it", key, key));
+        }
+        match key {
+            "cloud_provider" => {
+                let provider = value.to_lowercase();
+                if !["aws", "gcp", "azure", "digitalocean"].contains(&provider.as_str()) {
+                    return Err(anyhow!("Unsupported cloud provider: {}. Use: aws, gcp, azure, digitalocean", value));
+                }
+                self.cloud_provider = Some(provider);
+            }
+            "region" => self.region = Some(value.to_string()),
+            "model" => self.model = Some(value.to_string()),
+            "output_dir" => self.output_dir = Some(PathBuf::from(value)),
+            "concurrency" => self.concurrency = Some(parse_positive(key, value)?),
+            "max_output_tokens" => self.max_output_tokens = Some(parse_positive(key, value)?),
+            "detection_feedback" => {
+                let enabled = value
+                    .parse::<bool>()
+                    .map_err(|_| anyhow!("{} must be true or false, got {}", key, value))?;
+                self.detection_feedback = Some(enabled);
+            }
+            "protected_resources" => {
+                let types: Vec<String> = value.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+                if let Some(invalid) = types.iter().find(|t| !t.contains('_') || !t.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')) {
+                    return Err(anyhow!("{} must be Terraform resource types such as aws_db_instance, got {}", key, invalid));
+                }
+                self.protected_resources = Some(types);
+            }
+            "build_cache_image" => {
+                if !value.contains('/') || value.contains(char::is_whitespace) {
+                    return Err(anyhow!("{} must be an image in a registry such as ghcr.io/acme/app-cache, got {}", key, value));
+                }
+                self.build_cache_image = Some(value.to_string());
+            }
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        match key {
+            "cloud_provider" => self.cloud_provider = None,
+            "region" => self.region = None,
+            "model" => self.model = None,
+            "output_dir" => self.output_dir = None,
+            "concurrency" => self.concurrency = None,
+            "max_output_tokens" => self.max_output_tokens = None,
+            "detection_feedback" => self.detection_feedback = None,
+            "protected_resources" => self.protected_resources = None,
+            "build_cache_image" => self.build_cache_image = None,
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+}
+
+/// Make the loaded settings available to the rest of the process. Call once at startup.
+pub fn init(settings: Settings) -> Result<()> {
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow!("Settings already initialized"))
+}
+
+/// Settings loaded at startup; defaults when none were loaded
+pub fn get() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+pub fn config_path() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir()
+        .ok_or_else(|| anyhow!("Could not find home directory"))?;
+
+    Ok(home_dir.join(".autodeployment").join("config.toml"))
+}
+
+fn parse_positive<T: std::str::FromStr + Default + PartialEq>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse::<T>()
+        .ok()
+        .filter(|n| *n != T::default())
+        .ok_or_else(|| anyhow!("{} must be a positive number, got {}", key, value))
+}
+
+fn unknown_key(key: &str) -> anyhow::Error {
+    anyhow!("Unknown setting: {}. Use: {}", key, KEYS.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_get_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("nested/config.toml");
+        assert_eq!(Settings::load_from(&path).unwrap(), Settings::default());
+
+        let mut settings = Settings::default();
+        settings.set("cloud_provider", "GCP").unwrap();
+        settings.set("concurrency", "4").unwrap();
+        settings.set("output_dir", "/tmp/tf").unwrap();
+        settings.set("protected_resources", "aws_db_instance, aws_ebs_volume").unwrap();
+        settings.save_to(&path).unwrap();
+
+        let mut loaded = Settings::load_from(&path).unwrap();
+        assert_eq!(loaded.get("cloud_provider").unwrap().as_deref(), Some("gcp"));
+        assert_eq!(loaded.get("concurrency").unwrap().as_deref(), Some("4"));
+        assert_eq!(loaded.get("output_dir").unwrap().as_deref(), Some("/tmp/tf"));
+        assert_eq!(loaded.get("region").unwrap(), None);
+        assert_eq!(loaded.get("protected_resources").unwrap().as_deref(), Some("aws_db_instance,aws_ebs_volume"));
+
+        loaded.unset("concurrency").unwrap();
+        assert_eq!(loaded.concurrency, None);
+    }
+
+    #[test]
+    fn test_rejects_invalid_settings() {
+        let mut settings = Settings::default();
+        assert!(settings.set("cloud_provider", "heroku").unwrap_err().to_string().contains("Unsupported cloud provider"));
+        let mut digitalocean = Settings::default();
+        digitalocean.set("cloud_provider", "DigitalOcean").unwrap();
+        assert_eq!(digitalocean.cloud_provider.as_deref(), Some("digitalocean"));
+        assert!(settings.set("concurrency", "0").is_err());
+        assert!(settings.set("max_output_tokens", "lots").is_err());
+        assert!(settings.set("detection_feedback", "yes").is_err());
+        assert!(settings.set("protected_resources", "aws_db_instance,Database").is_err());
+        assert!(settings.get("colour").unwrap_err().to_string().contains("Unknown setting: colour"));
+        assert_eq!(settings, Settings::default());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        fs::write(&path, "regoin = \"eu-west-1\"\nconcurrency = 4\n").unwrap();
+        assert!(Settings::load_from(&path).unwrap_err().to_string().contains("regoin"));
+        assert!(Settings::remove_from_file(&path, "regoin").unwrap());
+        assert!(!Settings::remove_from_file(&path, "regoin").unwrap());
+        assert_eq!(Settings::load_from(&path).unwrap().concurrency, Some(4));
+    }
+}
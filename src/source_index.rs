@@ -0,0 +1,247 @@
+use ignore::{WalkBuilder, WalkState};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::concurrency;
+
+/// Largest file whose content a scan reads up front; bigger ones are read when asked for
+pub const MAX_INDEXED_FILE_BYTES: u64 = 1024 * 1024;
+
+/// A file found by a scan
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    path: PathBuf,
+    depth: usize,
+    /// Whether the scan read the file; `content` is then its text, or `None` if it is not UTF-8
+    read: bool,
+    content: Option<Arc<str>>,
+}
+
+impl SourceFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    /// Directory levels below the scanned root; its direct children are at 1
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// The file's text, from the scan if it read the file, else from disk
+    pub fn content(&self) -> Option<Arc<str>> {
+        if self.read {
+            return self.content.clone();
+        }
+        fs::read_to_string(&self.path).ok().map(Arc::from)
+    }
+}
+
+/// Where a scan starts and how it walks
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOptions {
+    pub max_depth: usize,
+    /// Directory names never entered
+    pub skip_dirs: &'static [&'static str],
+    /// Files whose content is read during the walk; the others are read on demand
+    pub is_candidate: fn(&Path) -> bool,
+}
+
+/// Every file under a root, walked on several threads with each candidate file read once.
+/// Honors `.gitignore` files like `git` would, even outside a git checkout.
+#[derive(Debug)]
+pub struct SourceIndex {
+    root: PathBuf,
+    max_depth: usize,
+    /// In the order a walk sorted by file name visits them
+    files: Vec<SourceFile>,
+    positions: HashMap<PathBuf, usize>,
+}
+
+impl SourceIndex {
+    pub fn build(root: &Path, options: ScanOptions) -> Self {
+        let found = Mutex::new(Vec::new());
+        let skip_dirs = options.skip_dirs;
+        WalkBuilder::new(root)
+            .max_depth(Some(options.max_depth))
+            .hidden(false)
+            .parents(false)
+            .require_git(false)
+            .filter_entry(move |e| !e.file_name().to_str().is_some_and(|name| skip_dirs.contains(&name)))
+            .threads(concurrency::limit())
+            .build_parallel()
+            .run(|| {
+                let found = &found;
+                Box::new(move |entry| {
+                    let Ok(entry) = entry else { return WalkState::Continue };
+                    if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                        return WalkState::Continue;
+                    }
+                    let read = (options.is_candidate)(entry.path())
+                        && entry.metadata().is_ok_and(|metadata| metadata.len() <= MAX_INDEXED_FILE_BYTES);
+                    let content = if read { fs::read_to_string(entry.path()).ok().map(Arc::from) } else { None };
+                    let depth = entry.depth();
+                    found.lock().unwrap().push(SourceFile { path: entry.into_path(), depth, read, content });
+                    WalkState::Continue
+                })
+            });
+
+        let mut files = found.into_inner().unwrap();
+        // Comparing paths component by component puts a directory's files right after it,
+        // siblings by name: the order of a sequential walk sorted by file name
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let positions = files.iter().enumerate().map(|(i, file)| (file.path.clone(), i)).collect();
+        SourceIndex { root: root.to_path_buf(), max_depth: options.max_depth, files, positions }
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&SourceFile> {
+        self.positions.get(path).map(|&i| &self.files[i])
+    }
+}
+
+thread_local! {
+    /// Indexes built since the outermost `shared_scans` on this thread started
+    static SHARED: RefCell<Option<Vec<Rc<SourceIndex>>>> = const { RefCell::new(None) };
+}
+
+/// Ends the sharing when the outermost `shared_scans` returns or unwinds
+struct SharedScope {
+    outermost: bool,
+}
+
+impl Drop for SharedScope {
+    fn drop(&mut self) {
+        if self.outermost {
+            SHARED.with(|shared| *shared.borrow_mut() = None);
+        }
+    }
+}
+
+/// Run `f` with scans shared: every `scan` of the same root inside it walks and reads the
+/// tree only the first time
+pub fn shared_scans<T>(f: impl FnOnce() -> T) -> T {
+    let outermost = SHARED.with(|shared| {
+        let mut shared = shared.borrow_mut();
+        if shared.is_some() {
+            return false;
+        }
+        *shared = Some(Vec::new());
+        true
+    });
+    let _scope = SharedScope { outermost };
+    f()
+}
+
+/// The files under `root`, from the shared index of it when there is one
+pub fn scan(root: &Path, options: ScanOptions) -> Rc<SourceIndex> {
+    let shared = SHARED.with(|shared| {
+        shared
+            .borrow()
+            .as_ref()
+            .map(|indexes| indexes.iter().find(|index| index.root == root && index.max_depth == options.max_depth).cloned())
+    });
+    match shared {
+        Some(Some(index)) => index,
+        Some(None) => {
+            let index = Rc::new(SourceIndex::build(root, options));
+            SHARED.with(|shared| {
+                if let Some(indexes) = shared.borrow_mut().as_mut() {
+                    indexes.push(index.clone());
+                }
+            });
+            index
+        }
+        None => Rc::new(SourceIndex::build(root, options)),
+    }
+}
+
+/// The text of the file at `path`, from a shared index that read it, else from disk
+pub fn read(path: &Path) -> Option<Arc<str>> {
+    let indexed = SHARED.with(|shared| {
+        shared
+            .borrow()
+            .iter()
+            .flatten()
+            .find_map(|index| index.get(path).filter(|file| file.read).cloned())
+    });
+    match indexed {
+        Some(file) => file.content(),
+        None => fs::read_to_string(path).ok().map(Arc::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_scan_matches_sorted_walk() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["a", "a/b", "a.d", "node_modules/pkg", "z", "logs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["a.py", "a/b/c.py", "a/b.txt", "a.d/x.py", "node_modules/pkg/index.js", "z/app.py", "logs/out.log", "b.py"] {
+            fs::write(root.join(file), format!("# {}\n", file)).unwrap();
+        }
+        fs::write(root.join(".gitignore"), "logs/\n").unwrap();
+        fs::write(root.join("big.py"), "#".repeat(MAX_INDEXED_FILE_BYTES as usize + 1)).unwrap();
+        fs::write(root.join("binary.py"), [0xff, 0xfe, 0x00]).unwrap();
+
+        let options = ScanOptions {
+            max_depth: 5,
+            skip_dirs: &["node_modules"],
+            is_candidate: |path| path.extension().is_some_and(|ext| ext == "py"),
+        };
+        let sequential: Vec<PathBuf> = WalkBuilder::new(root)
+            .max_depth(Some(options.max_depth))
+            .hidden(false)
+            .parents(false)
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(|e| e.file_name() != "node_modules")
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+            .map(|entry| entry.into_path())
+            .collect();
+        let index = SourceIndex::build(root, options);
+        let parallel: Vec<PathBuf> = index.files().iter().map(|file| file.path().to_path_buf()).collect();
+        assert_eq!(parallel, sequential);
+        assert_eq!(index.get(&root.join("a/b/c.py")).unwrap().depth(), 3);
+
+        let file = |name: &str| index.get(&root.join(name)).unwrap();
+        assert!(file("a/b/c.py").read);
+        assert!(!file("a/b.txt").read, "not a candidate");
+        assert_eq!(file("a/b.txt").content().as_deref(), Some("# a/b.txt\n"));
+        assert!(!file("big.py").read, "over the size cap");
+        assert_eq!(file("big.py").content().unwrap().len(), MAX_INDEXED_FILE_BYTES as usize + 1);
+        assert!(file("binary.py").read && file("binary.py").content().is_none());
+
+        let first = shared_scans(|| {
+            let first = scan(root, options);
+            assert!(Rc::ptr_eq(&first, &scan(root, options)), "the second scan reuses the first");
+            fs::write(root.join("a.py"), "changed\n").unwrap();
+            assert_eq!(read(&root.join("a.py")).as_deref(), Some("# a.py\n"));
+            first
+        });
+        assert!(!Rc::ptr_eq(&first, &scan(root, options)), "sharing ends with the scope");
+        assert_eq!(read(&root.join("a.py")).as_deref(), Some("changed\n"));
+    }
+}
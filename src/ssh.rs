@@ -0,0 +1,482 @@
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use which::which;
+
+use crate::infrastructure::{TerraformConfig, TerraformResource};
+
+/// Options every `ssh` invocation, including the one through the bastion, runs with
+const SSH_OPTIONS: [&str; 6] = [
+    "-o",
+    "BatchMode=yes",
+    "-o",
+    "StrictHostKeyChecking=accept-new",
+    "-o",
+    "ConnectTimeout=10",
+];
+
+/// Firewall resources whose inbound rules may open SSH
+const FIREWALL_TYPES: [&str; 6] = [
+    "aws_security_group",
+    "aws_security_group_rule",
+    "aws_vpc_security_group_ingress_rule",
+    "google_compute_firewall",
+    "azurerm_network_security_group",
+    "azurerm_network_security_rule",
+];
+
+/// An existing machine reachable over SSH
+#[derive(Debug, Clone)]
+pub struct SshTarget {
+    pub host: String,
+    pub user: String,
+    pub key: Option<PathBuf>,
+    /// SSH port when it is not 22
+    pub port: Option<u16>,
+    /// Jump host the connection goes through, as `[user@]host[:port]`
+    pub bastion: Option<String>,
+}
+
+impl SshTarget {
+    fn destination(&self) -> String {
+        format!("{}@{}", self.user, self.host)
+    }
+
+    /// Arguments for a non-interactive `ssh` invocation running `command` on the target
+    fn ssh_args(&self, command: &str) -> Vec<String> {
+        let mut args: Vec<String> = SSH_OPTIONS.iter().map(|option| option.to_string()).collect();
+        if let Some(port) = self.port {
+            args.push("-p".to_string());
+            args.push(port.to_string());
+        }
+        if let Some(bastion) = &self.bastion {
+            args.push("-o".to_string());
+            args.push(format!("ProxyCommand={}", self.proxy_command(bastion)));
+        }
+        if let Some(key) = &self.key {
+            args.push("-i".to_string());
+            args.push(key.display().to_string());
+        }
+        args.push(self.destination());
+        args.push(command.to_string());
+        args
+    }
+
+    /// Jump through `bastion` like `-J` does, but with the same batch options and key as the
+    /// connection itself, so the hop can never stop to prompt
+    fn proxy_command(&self, bastion: &str) -> String {
+        let mut command: Vec<String> = std::iter::once("ssh").chain(SSH_OPTIONS).map(str::to_string).collect();
+        if let Some(key) = &self.key {
+            command.push("-i".to_string());
+            command.push(format!("'{}'", key.display().to_string().replace('\'', "'\\''")));
+        }
+        let host = match bastion.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => {
+                command.push("-p".to_string());
+                command.push(port.to_string());
+                host
+            }
+            _ => bastion,
+        };
+        command.push("-W".to_string());
+        command.push("%h:%p".to_string());
+        command.push(host.to_string());
+        command.join(" ")
+    }
+}
+
+/// Public key the deploy installs on new instances, so it can reach them over SSH with the
+/// matching `--ssh-key` to deliver the env file and registry logins
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizedKey {
+    /// Login user the key is authorized for
+    pub user: String,
+    pub public_key: String,
+}
+
+impl AuthorizedKey {
+    /// The public half of the private key at `key`: its `.pub` file, or else derived with
+    /// `ssh-keygen -y`, which only works for keys without a passphrase
+    pub fn from_private_key(key: &Path, user: &str) -> Result<Self> {
+        let public_path = PathBuf::from(format!("{}.pub", key.display()));
+        let public_key = match std::fs::read_to_string(&public_path) {
+            Ok(public_key) => public_key,
+            Err(_) => {
+                let output = std::process::Command::new("ssh-keygen")
+                    .args(["-y", "-f"])
+                    .arg(key)
+                    .stdin(Stdio::null())
+                    .output()
+                    .map_err(|e| anyhow!("Could not run ssh-keygen to read the public key of {}: {}", key.display(), e))?;
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "No {} and ssh-keygen could not derive the public key of {}: {}",
+                        public_path.display(),
+                        key.display(),
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                String::from_utf8_lossy(&output.stdout).to_string()
+            }
+        };
+        let public_key = public_key.trim().to_string();
+        if !public_key.starts_with("ssh-") && !public_key.starts_with("ecdsa-") {
+            return Err(anyhow!("{} is not an OpenSSH public key", public_path.display()));
+        }
+        Ok(AuthorizedKey { user: user.to_string(), public_key })
+    }
+
+    /// Authorize the key on every instance of the config: an `aws_key_pair` or
+    /// `digitalocean_ssh_key` the instances use, GCP `ssh-keys` metadata, or the Azure VM's
+    /// admin key. Returns the resources that were changed.
+    pub fn apply(&self, config: &mut TerraformConfig) -> Vec<String> {
+        let mut changed = Vec::new();
+        let mut key_resources = Vec::new();
+        for resource in &mut config.resources {
+            match resource.resource_type.as_str() {
+                "aws_instance" | "aws_launch_template" => {
+                    resource.config.insert("key_name".to_string(), Value::String("${aws_key_pair.deploy_key.key_name}".to_string()));
+                    key_resources.push("aws_key_pair");
+                }
+                "digitalocean_droplet" => {
+                    resource
+                        .config
+                        .insert("ssh_keys".to_string(), serde_json::json!(["${digitalocean_ssh_key.deploy_key.fingerprint}"]));
+                    key_resources.push("digitalocean_ssh_key");
+                }
+                "google_compute_instance" | "google_compute_instance_template" => {
+                    let metadata = resource.config.entry("metadata".to_string()).or_insert_with(|| serde_json::json!({}));
+                    if !metadata.is_object() {
+                        *metadata = serde_json::json!({});
+                    }
+                    let line = format!("{}:{}", self.user, self.public_key);
+                    let keys = match metadata.get("ssh-keys").and_then(Value::as_str).filter(|keys| !keys.trim().is_empty()) {
+                        Some(keys) if keys.lines().any(|existing| existing == line) => keys.to_string(),
+                        Some(keys) => format!("{}\n{}", keys.trim_end(), line),
+                        None => line,
+                    };
+                    metadata["ssh-keys"] = Value::String(keys);
+                }
+                "azurerm_linux_virtual_machine" => {
+                    let username = resource.config.get("admin_username").and_then(Value::as_str).unwrap_or(&self.user).to_string();
+                    resource
+                        .config
+                        .insert("admin_ssh_key".to_string(), serde_json::json!([{"username": username, "public_key": self.public_key}]));
+                }
+                _ => continue,
+            }
+            changed.push(format!("{}.{}", resource.resource_type, resource.name));
+        }
+
+        key_resources.sort();
+        key_resources.dedup();
+        for resource_type in key_resources {
+            let settings = match resource_type {
+                "aws_key_pair" => serde_json::json!({"key_name_prefix": "autodeployment-", "public_key": self.public_key}),
+                _ => serde_json::json!({"name": "autodeployment-deploy-key", "public_key": self.public_key}),
+            };
+            config.resources.retain(|r| !(r.resource_type == resource_type && r.name == "deploy_key"));
+            config.resources.push(TerraformResource {
+                resource_type: resource_type.to_string(),
+                name: "deploy_key".to_string(),
+                config: serde_json::from_value(settings).unwrap_or_default(),
+            });
+        }
+        changed
+    }
+}
+
+/// Lines for the startup script that move sshd to `port`, which is the only SSH port the
+/// firewall opens. Handles both the socket-activated sshd of newer Ubuntu and the service.
+pub fn sshd_port_script(port: u16) -> String {
+    format!(
+        "sed -i -E 's/^#?Port .*/Port {0}/' /etc/ssh/sshd_config\n\
+         grep -q '^Port {0}$' /etc/ssh/sshd_config || echo 'Port {0}' >> /etc/ssh/sshd_config\n\
+         if command -v semanage >/dev/null; then semanage port -a -t ssh_port_t -p tcp {0} || true; fi\n\
+         if systemctl is-enabled ssh.socket >/dev/null 2>&1; then systemctl daemon-reload; systemctl restart ssh.socket; \
+         else systemctl restart sshd 2>/dev/null || systemctl restart ssh; fi\n\n",
+        port
+    )
+}
+
+/// Open `port` instead of 22 in the inbound rules of the generated firewalls.
+/// Returns the resources that were changed.
+pub fn apply_ssh_port(config: &mut TerraformConfig, port: u16) -> Vec<String> {
+    let mut changed = Vec::new();
+    for resource in config.resources.iter_mut().filter(|r| FIREWALL_TYPES.contains(&r.resource_type.as_str())) {
+        let mut rules = Value::Object(resource.config.clone().into_iter().collect());
+        if rewrite_ssh_port(&mut rules, port) {
+            if let Value::Object(rules) = rules {
+                resource.config = rules.into_iter().collect();
+            }
+            changed.push(format!("{}.{}", resource.resource_type, resource.name));
+        }
+    }
+    changed
+}
+
+/// Replace port 22 in the inbound rules under `value`; outbound rules are left alone
+fn rewrite_ssh_port(value: &mut Value, port: u16) -> bool {
+    match value {
+        Value::Object(fields) => {
+            let outbound = ["type", "direction"].iter().any(|key| {
+                fields
+                    .get(*key)
+                    .and_then(Value::as_str)
+                    .is_some_and(|direction| matches!(direction.to_lowercase().as_str(), "egress" | "outbound"))
+            });
+            if outbound {
+                return false;
+            }
+            let mut rewritten = false;
+            for (key, field) in fields.iter_mut() {
+                rewritten |= match key.as_str() {
+                    "egress" => false,
+                    "from_port" | "to_port" | "destination_port_range" => replace_ssh_port(field, port),
+                    "ports" | "destination_port_ranges" => field
+                        .as_array_mut()
+                        .is_some_and(|ports| ports.iter_mut().fold(false, |rewritten, item| replace_ssh_port(item, port) | rewritten)),
+                    _ => rewrite_ssh_port(field, port),
+                };
+            }
+            rewritten
+        }
+        Value::Array(items) => items.iter_mut().fold(false, |rewritten, item| rewrite_ssh_port(item, port) | rewritten),
+        _ => false,
+    }
+}
+
+fn replace_ssh_port(value: &mut Value, port: u16) -> bool {
+    match value {
+        Value::String(text) if text == "22" => *text = port.to_string(),
+        Value::Number(number) if number.as_u64() == Some(22) => *value = port.into(),
+        _ => return false,
+    }
+    true
+}
+
+/// Run `script` as root on the target, streaming it over stdin.
+/// Returns the combined output so it can be included in the deployment logs.
+pub async fn run_script(target: &SshTarget, script: &str) -> Result<String> {
+    info!("🔐 Running bootstrap on {} over SSH...", target.destination());
+    let output = run_remote(target, "sudo bash -s", script)
+        .await
+        .map_err(|e| anyhow!("Bootstrap on {} failed: {}", target.destination(), e))?;
+    
+    info!("✅ Bootstrap finished on {}", target.host);
+    Ok(output)
+}
+
+/// Write `contents` to `remote_path` on the target, readable only by root.
+/// The contents travel over stdin and never appear on a command line.
+pub async fn upload_private_file(target: &SshTarget, contents: &str, remote_path: &str) -> Result<()> {
+    let dir = Path::new(remote_path)
+        .parent()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let command = format!(
+        "sudo sh -c 'umask 077 && mkdir -p {} && cat > {}'",
+        dir, remote_path
+    );
+    run_remote(target, &command, contents)
+        .await
+        .map_err(|e| anyhow!("Failed to upload {} to {}: {}", remote_path, target.destination(), e))?;
+    Ok(())
+}
+
+/// Poll until the target accepts SSH connections or `timeout` elapses
+pub async fn wait_for_ssh(target: &SshTarget, timeout: Duration, interval: Duration) -> Result<()> {
+    info!("⏳ Waiting up to {}s for SSH on {}...", timeout.as_secs(), target.destination());
+    let started = Instant::now();
+    
+    loop {
+        match run_remote(target, "true", "").await {
+            Ok(_) => return Ok(()),
+            Err(e) => debug!("SSH to {} not ready: {}", target.destination(), e),
+        }
+        if started.elapsed() + interval > timeout {
+            return Err(anyhow!(
+                "{} did not accept SSH connections within {}s",
+                target.destination(),
+                timeout.as_secs()
+            ));
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Run `command` on the target until it exits successfully or `timeout` elapses.
+/// Returns the output of the successful run.
+pub async fn wait_for_command(target: &SshTarget, command: &str, timeout: Duration, interval: Duration) -> Result<String> {
+    info!("⏳ Waiting up to {}s for `{}` to succeed on {}...", timeout.as_secs(), command, target.host);
+    let started = Instant::now();
+    
+    loop {
+        let last_error = match run_remote(target, command, "").await {
+            Ok(output) => return Ok(output),
+            Err(e) => e,
+        };
+        debug!("`{}` on {} failed: {}", command, target.destination(), last_error);
+        if started.elapsed() + interval > timeout {
+            return Err(anyhow!(
+                "`{}` did not succeed on {} within {}s: {}",
+                command,
+                target.host,
+                timeout.as_secs(),
+                last_error
+            ));
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Run `command` on the target with `input` on stdin, returning stdout and stderr
+async fn run_remote(target: &SshTarget, command: &str, input: &str) -> Result<String> {
+    which("ssh").map_err(|_| anyhow!("ssh not found. Please install an OpenSSH client."))?;
+
+    let mut child = Command::new("ssh")
+        .args(target.ssh_args(command))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("Failed to open ssh stdin"))?;
+    stdin.write_all(input.as_bytes()).await?;
+    // Closing stdin ends the remote input
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    if !output.status.success() {
+        return Err(anyhow!("{}: {}", output.status, stderr.trim()));
+    }
+
+    Ok(format!("{}{}", stdout, stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_args() {
+        let target = SshTarget {
+            host: "203.0.113.10".to_string(),
+            user: "ubuntu".to_string(),
+            key: Some(PathBuf::from("/home/me/.ssh/id_ed25519")),
+            port: None,
+            bastion: None,
+        };
+        let args = target.ssh_args("sudo bash -s");
+
+        assert!(args.contains(&"BatchMode=yes".to_string()));
+        assert_eq!(args[args.len() - 4..], [
+            "-i".to_string(),
+            "/home/me/.ssh/id_ed25519".to_string(),
+            "ubuntu@203.0.113.10".to_string(),
+            "sudo bash -s".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_ssh_args_without_key() {
+        let target = SshTarget {
+            host: "example.com".to_string(),
+            user: "root".to_string(),
+            key: None,
+            port: None,
+            bastion: None,
+        };
+        let args = target.ssh_args("true");
+        assert!(!args.contains(&"-i".to_string()));
+        assert_eq!(args[args.len() - 2], "root@example.com");
+    }
+
+    #[test]
+    fn test_ssh_port_and_bastion() {
+        let target = SshTarget {
+            host: "10.0.1.5".to_string(),
+            user: "ubuntu".to_string(),
+            key: Some(PathBuf::from("/keys/deploy key")),
+            port: Some(2222),
+            bastion: Some("jump@bastion.example.com:2200".to_string()),
+        };
+        let args = target.ssh_args("true");
+        assert_eq!(args[6..8], ["-p".to_string(), "2222".to_string()]);
+        assert_eq!(
+            args[9],
+            "ProxyCommand=ssh -o BatchMode=yes -o StrictHostKeyChecking=accept-new -o ConnectTimeout=10 -i '/keys/deploy key' -p 2200 -W %h:%p jump@bastion.example.com"
+        );
+
+        let mut config: TerraformConfig = serde_json::from_value(serde_json::json!({
+            "provider": "aws",
+            "resources": [
+                {"resource_type": "aws_security_group", "name": "app_sg", "config": {
+                    "ingress": [
+                        {"from_port": 22, "to_port": 22, "protocol": "tcp"},
+                        {"from_port": 5000, "to_port": 5000, "protocol": "tcp"}
+                    ],
+                    "egress": [{"from_port": 22, "to_port": 22, "protocol": "tcp"}]
+                }},
+                {"resource_type": "google_compute_firewall", "name": "ssh", "config": {"allow": [{"protocol": "tcp", "ports": ["22", "80"]}]}},
+                {"resource_type": "google_compute_firewall", "name": "out", "config": {"direction": "EGRESS", "allow": [{"protocol": "tcp", "ports": ["22"]}]}}
+            ],
+            "variables": {},
+            "outputs": {}
+        }))
+        .unwrap();
+        assert_eq!(apply_ssh_port(&mut config, 2222), ["aws_security_group.app_sg", "google_compute_firewall.ssh"]);
+        let group = &config.resources[0].config;
+        assert_eq!(group["ingress"][0]["from_port"], 2222);
+        assert_eq!(group["ingress"][1]["to_port"], 5000);
+        assert_eq!(group["egress"][0]["to_port"], 22);
+        assert_eq!(config.resources[1].config["allow"][0]["ports"], serde_json::json!(["2222", "80"]));
+        assert!(sshd_port_script(2222).contains("echo 'Port 2222' >> /etc/ssh/sshd_config"));
+    }
+
+    #[test]
+    fn test_authorized_key_on_generated_instances() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let key = temp_dir.path().join("deploy");
+        std::fs::write(&key, "private").unwrap();
+        std::fs::write(temp_dir.path().join("deploy.pub"), "ssh-ed25519 AAAAC3Nz deploy@laptop\n").unwrap();
+        let authorized = AuthorizedKey::from_private_key(&key, "ubuntu").unwrap();
+        assert_eq!(authorized.public_key, "ssh-ed25519 AAAAC3Nz deploy@laptop");
+        std::fs::write(temp_dir.path().join("deploy.pub"), "not a key").unwrap();
+        assert!(AuthorizedKey::from_private_key(&key, "ubuntu").is_err());
+
+        let mut config: TerraformConfig = serde_json::from_value(serde_json::json!({
+            "provider": "aws",
+            "resources": [
+                {"resource_type": "aws_instance", "name": "app", "config": {"ami": "ami-123"}},
+                {"resource_type": "google_compute_instance", "name": "vm", "config": {"metadata": {"ssh-keys": "admin:ssh-rsa AAAA admin"}}},
+                {"resource_type": "aws_security_group", "name": "app_sg", "config": {}}
+            ],
+            "variables": {},
+            "outputs": {}
+        }))
+        .unwrap();
+        assert_eq!(authorized.apply(&mut config), ["aws_instance.app", "google_compute_instance.vm"]);
+        assert_eq!(config.resources[0].config["key_name"], "${aws_key_pair.deploy_key.key_name}");
+        assert_eq!(
+            config.resources[1].config["metadata"]["ssh-keys"],
+            "admin:ssh-rsa AAAA admin\nubuntu:ssh-ed25519 AAAAC3Nz deploy@laptop"
+        );
+        let key_pair = config.resources.iter().find(|r| r.resource_type == "aws_key_pair").unwrap();
+        assert_eq!(key_pair.config["public_key"], "ssh-ed25519 AAAAC3Nz deploy@laptop");
+
+        authorized.apply(&mut config);
+        assert_eq!(config.resources.iter().filter(|r| r.resource_type == "aws_key_pair").count(), 1, "applying twice adds one key pair");
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::repository::{APP_MANIFESTS, SKIP_DIRS};
+
+/// Compose files looked for at the repository root, in order of precedence
+const COMPOSE_FILES: &[&str] = &["compose.yaml", "compose.yml", "docker-compose.yaml", "docker-compose.yml"];
+
+/// One deployable part of a repository that holds several, e.g. `frontend`, `api`, `worker`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Service {
+    pub name: String,
+    /// Directory relative to the repository root the service is built from
+    pub dir: String,
+    /// Services that must be deployed first; their URLs are passed in as `<NAME>_URL`
+    pub depends_on: Vec<String>,
+}
+
+impl Service {
+    /// Environment variable a dependent service finds this service's URL in, e.g. `API_URL`
+    pub fn url_variable(&self) -> String {
+        format!("{}_URL", self.name.to_uppercase().replace(['-', '.'], "_"))
+    }
+}
+
+#[derive(Deserialize)]
+struct ComposeFile {
+    #[serde(default)]
+    services: BTreeMap<String, ComposeService>,
+}
+
+#[derive(Deserialize)]
+struct ComposeService {
+    #[serde(default)]
+    build: Option<ComposeBuild>,
+    #[serde(default)]
+    depends_on: Option<DependsOn>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ComposeBuild {
+    Context(String),
+    Config {
+        #[serde(default)]
+        context: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DependsOn {
+    List(Vec<String>),
+    Conditions(BTreeMap<String, serde_yaml::Value>),
+}
+
+/// Find the deployable services of a multi-service repository, in the order they must be
+/// deployed. Services come from the compose file's services with a `build` context, or else
+/// from top-level app directories (`frontend/`, `apps/api/`, ...). Fewer than two services
+/// means the repository is a single app.
+pub fn detect_services(repo_path: &Path) -> Result<Vec<Service>> {
+    let services = match COMPOSE_FILES.iter().map(|name| repo_path.join(name)).find(|path| path.is_file()) {
+        Some(compose_file) => compose_services(&compose_file)?,
+        None => monorepo_services(repo_path)?,
+    };
+    deployment_order(services)
+}
+
+/// Services with a `build` context; image-only services (databases, caches) are backing
+/// services, provisioned from the requirements instead
+fn compose_services(compose_file: &Path) -> Result<Vec<Service>> {
+    let contents = fs::read_to_string(compose_file)?;
+    let compose: ComposeFile = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow!("Invalid compose file {}: {}", compose_file.display(), e))?;
+
+    let buildable: Vec<&String> = compose
+        .services
+        .iter()
+        .filter(|(_, service)| service.build.is_some())
+        .map(|(name, _)| name)
+        .collect();
+
+    let services = compose
+        .services
+        .iter()
+        .filter_map(|(name, service)| {
+            let context = match service.build.as_ref()? {
+                ComposeBuild::Context(context) => context.clone(),
+                ComposeBuild::Config { context } => context.clone().unwrap_or_else(|| ".".to_string()),
+            };
+            let depends_on = match &service.depends_on {
+                Some(DependsOn::List(names)) => names.clone(),
+                Some(DependsOn::Conditions(conditions)) => conditions.keys().cloned().collect(),
+                None => Vec::new(),
+            };
+            Some(Service {
+                name: name.clone(),
+                dir: normalize_dir(&context),
+                depends_on: depends_on.into_iter().filter(|dep| buildable.contains(&dep)).collect(),
+            })
+        })
+        .collect();
+    Ok(services)
+}
+
+/// Directories one level down (or two, under grouping directories like `apps/`) that hold
+/// an app manifest
+fn monorepo_services(repo_path: &Path) -> Result<Vec<Service>> {
+    let mut services = Vec::new();
+    for dir in subdirectories(repo_path)? {
+        if has_manifest(&dir) {
+            services.push(service_for(repo_path, &dir));
+            continue;
+        }
+        for child in subdirectories(&dir)? {
+            if has_manifest(&child) {
+                services.push(service_for(repo_path, &child));
+            }
+        }
+    }
+
+    // `apps/api` and `services/api` would otherwise share a name
+    let mut names: BTreeMap<String, usize> = BTreeMap::new();
+    for service in &services {
+        *names.entry(service.name.clone()).or_default() += 1;
+    }
+    for service in &mut services {
+        if names[&service.name] > 1 {
+            service.name = service.dir.replace('/', "-");
+        }
+    }
+    Ok(services)
+}
+
+fn subdirectories(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            !name.starts_with('.') && !SKIP_DIRS.contains(&name.as_str())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn has_manifest(dir: &Path) -> bool {
+    APP_MANIFESTS.iter().any(|manifest| dir.join(manifest).is_file())
+}
+
+fn service_for(repo_path: &Path, dir: &Path) -> Service {
+    let relative = dir.strip_prefix(repo_path).unwrap_or(dir).to_string_lossy().replace('\\', "/");
+    Service {
+        name: dir.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+        dir: relative,
+        depends_on: Vec::new(),
+    }
+}
+
+fn normalize_dir(context: &str) -> String {
+    let dir = context.trim_start_matches("./").trim_end_matches('/');
+    if dir == "." {
+        String::new()
+    } else {
+        dir.to_string()
+    }
+}
+
+/// Order services so each comes after the services it depends on, keeping the detected
+/// order otherwise
+fn deployment_order(mut pending: Vec<Service>) -> Result<Vec<Service>> {
+    let mut ordered: Vec<Service> = Vec::new();
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|service| service.depends_on.iter().all(|dep| ordered.iter().any(|done| &done.name == dep)));
+        match ready {
+            Some(index) => ordered.push(pending.remove(index)),
+            None => {
+                let names: Vec<&str> = pending.iter().map(|service| service.name.as_str()).collect();
+                return Err(anyhow!("Circular depends_on between services: {}", names.join(", ")));
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// One service of a deployed stack
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackMember {
+    pub service: String,
+    pub dir: String,
+    pub url: Option<String>,
+    pub deployment_id: Option<String>,
+    /// Terraform directory of the service's deployment, for tearing it down
+    pub deployment_dir: Option<PathBuf>,
+    /// Why the service was not deployed
+    pub error: Option<String>,
+}
+
+/// Record of a `deploy --all` run, grouping its services' deployments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackRecord {
+    /// Stack id, which `destroy` takes to tear down every service
+    pub id: String,
+    pub repository: String,
+    pub created_at: String,
+    pub members: Vec<StackMember>,
+}
+
+impl StackRecord {
+    pub fn is_complete(&self) -> bool {
+        self.members.iter().all(|member| member.error.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_services_in_dependency_order() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            temp_dir.path().join("docker-compose.yml"),
+            r#"
+services:
+  web:
+    build: ./frontend
+    depends_on: [api]
+  api:
+    build:
+      context: ./backend
+    depends_on:
+      db:
+        condition: service_healthy
+  worker:
+    build: { context: ./backend }
+    depends_on: [api, db]
+  db:
+    image: postgres:16
+"#,
+        )
+        .unwrap();
+
+        let services = detect_services(temp_dir.path()).unwrap();
+        let order: Vec<(&str, &str)> = services.iter().map(|s| (s.name.as_str(), s.dir.as_str())).collect();
+        assert_eq!(order, vec![("api", "backend"), ("web", "frontend"), ("worker", "backend")]);
+        assert!(services[0].depends_on.is_empty(), "image-only services are not deployed");
+        assert_eq!(services[0].url_variable(), "API_URL");
+    }
+
+    #[test]
+    fn test_monorepo_services() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["frontend", "apps/api", "services/api", "node_modules/lib", "docs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("frontend/package.json"), "{}").unwrap();
+        fs::write(root.join("apps/api/requirements.txt"), "flask\n").unwrap();
+        fs::write(root.join("services/api/requirements.txt"), "fastapi\n").unwrap();
+        fs::write(root.join("node_modules/lib/package.json"), "{}").unwrap();
+
+        let names: Vec<String> = detect_services(root).unwrap().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["apps-api", "frontend", "services-api"]);
+    }
+
+    #[test]
+    fn test_circular_dependencies_are_rejected() {
+        let service = |name: &str, dep: &str| Service {
+            name: name.to_string(),
+            dir: name.to_string(),
+            depends_on: vec![dep.to_string()],
+        };
+        let err = deployment_order(vec![service("a", "b"), service("b", "a")]).unwrap_err();
+        assert!(err.to_string().contains("Circular depends_on between services: a, b"));
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{anyhow, Result};
+use git2::{Cred, IndexAddOption, PushOptions, RemoteCallbacks, Repository, Signature};
+use log::{info, warn};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use walkdir::WalkDir;
+use which::which;
+
+use crate::http;
+
+/// Branch GitHub Pages serves the site from
+const PAGES_BRANCH: &str = "gh-pages";
+
+/// Directories static site generators write their output to, most specific first
+const SITE_DIRS: &[&str] = &["dist", "build", "out", "_site"];
+
+/// Hosts static sites are published to without any cloud infrastructure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StaticHost {
+    GithubPages,
+    Netlify,
+}
+
+impl StaticHost {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StaticHost::GithubPages => "GitHub Pages",
+            StaticHost::Netlify => "Netlify",
+        }
+    }
+}
+
+/// Everything needed to publish to a host, resolved before anything is built so a missing
+/// token or CLI fails the deploy straight away. No `Debug`: it holds tokens.
+pub enum Publisher {
+    GithubPages { owner: String, repo: String, token: String },
+    Netlify { site: String, token: String },
+}
+
+impl Publisher {
+    pub fn resolve(host: StaticHost, repository: &str, netlify_site: Option<&str>) -> Result<Self> {
+        match host {
+            StaticHost::GithubPages => {
+                let (owner, repo) = github_repo(repository)?;
+                let token = std::env::var("GITHUB_TOKEN")
+                    .or_else(|_| std::env::var("GH_TOKEN"))
+                    .map_err(|_| anyhow!("GitHub Pages deploys need a token with push access to {}/{} in GITHUB_TOKEN (or GH_TOKEN)", owner, repo))?;
+                Ok(Publisher::GithubPages { owner, repo, token })
+            }
+            StaticHost::Netlify => {
+                which("netlify").map_err(|_| anyhow!("netlify CLI not found. Install it with: npm install -g netlify-cli"))?;
+                let token = std::env::var("NETLIFY_AUTH_TOKEN")
+                    .map_err(|_| anyhow!("Netlify deploys need a personal access token in NETLIFY_AUTH_TOKEN"))?;
+                let site = netlify_site
+                    .map(str::to_string)
+                    .or_else(|| std::env::var("NETLIFY_SITE_ID").ok())
+                    .ok_or_else(|| anyhow!("Netlify deploys need a site: pass --netlify-site or set NETLIFY_SITE_ID"))?;
+                Ok(Publisher::Netlify { site, token })
+            }
+        }
+    }
+
+    /// Publish the contents of `site_dir` and return the public URL
+    pub async fn publish(&self, site_dir: &Path) -> Result<String> {
+        match self {
+            Publisher::GithubPages { owner, repo, token } => {
+                let remote = format!("https://github.com/{}/{}.git", owner, repo);
+                push_site(site_dir, &remote, Some(token))?;
+                enable_github_pages(owner, repo, token).await;
+                Ok(pages_url(owner, repo))
+            }
+            Publisher::Netlify { site, token } => {
+                let staging = tempfile::tempdir()?;
+                copy_site(site_dir, staging.path())?;
+                publish_to_netlify(staging.path(), site, token).await
+            }
+        }
+    }
+}
+
+/// Owner and name of a GitHub repository from its HTTPS or SSH clone URL
+pub fn github_repo(repository: &str) -> Result<(String, String)> {
+    let github_regex = Regex::new(r"github\.com[/:]([^/]+)/([^/]+?)(?:\.git)?/?$").unwrap();
+    github_regex
+        .captures(repository.trim())
+        .map(|caps| (caps[1].to_string(), caps[2].to_string()))
+        .ok_or_else(|| anyhow!("GitHub Pages needs a github.com repository, got {}", repository))
+}
+
+/// URL GitHub serves the Pages site of `owner/repo` at
+pub fn pages_url(owner: &str, repo: &str) -> String {
+    let owner = owner.to_lowercase();
+    if repo.to_lowercase() == format!("{}.github.io", owner) {
+        format!("https://{}.github.io/", owner)
+    } else {
+        format!("https://{}.github.io/{}/", owner, repo)
+    }
+}
+
+/// Run the app's build commands locally, with `env` set
+pub async fn build_site(app_root: &Path, build_commands: &[String], env: &HashMap<String, String>) -> Result<()> {
+    for command in build_commands {
+        info!("🔨 {}", command);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(app_root)
+            .envs(env)
+            .output()
+            .await
+            .map_err(|e| anyhow!("Failed to run `{}`: {}", command, e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let tail: Vec<&str> = stderr.lines().rev().take(20).collect();
+            return Err(anyhow!(
+                "Build step `{}` failed ({}):\n{}",
+                command,
+                output.status,
+                tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Directory holding the built site: the first build output directory with an `index.html`,
+/// then the detected static files directory, then the app root itself
+pub fn find_site_dir(app_root: &Path, static_files_dir: Option<&str>) -> Result<PathBuf> {
+    SITE_DIRS
+        .iter()
+        .copied()
+        .chain(static_files_dir)
+        .chain(["public", ""])
+        .map(|dir| app_root.join(dir))
+        .find(|dir| dir.join("index.html").is_file())
+        .ok_or_else(|| {
+            anyhow!(
+                "No index.html found in {}/ or the app root; did the build produce a static site?",
+                SITE_DIRS.join("/, ")
+            )
+        })
+}
+
+/// Commit the site as the only content of the Pages branch and force-push it to `remote`
+pub fn push_site(site_dir: &Path, remote: &str, token: Option<&str>) -> Result<()> {
+    let staging = tempfile::tempdir()?;
+    copy_site(site_dir, staging.path())?;
+    // Serve files as-is, including ones starting with an underscore
+    fs::write(staging.path().join(".nojekyll"), "")?;
+
+    let repo = Repository::init(staging.path())?;
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = Signature::now("autodeployment", "autodeployment@users.noreply.github.com")?;
+    let branch_ref = format!("refs/heads/{}", PAGES_BRANCH);
+    repo.commit(Some(&branch_ref), &signature, &signature, "Publish site", &tree, &[])?;
+
+    info!("📤 Pushing site to the {} branch...", PAGES_BRANCH);
+    let mut rejection = None;
+    {
+        let mut callbacks = RemoteCallbacks::new();
+        if let Some(token) = token {
+            callbacks.credentials(move |_, _, _| Cred::userpass_plaintext("x-access-token", token));
+        }
+        callbacks.push_update_reference(|_, status| {
+            rejection = status.map(str::to_string);
+            Ok(())
+        });
+
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        repo.remote_anonymous(remote)?
+            .push(&[format!("+{0}:{0}", branch_ref)], Some(&mut options))
+            .map_err(|e| anyhow!("Failed to push to {}: {}", PAGES_BRANCH, e.message()))?;
+    }
+
+    match rejection {
+        Some(reason) => Err(anyhow!("{} rejected the push to {}: {}", remote, PAGES_BRANCH, reason)),
+        None => Ok(()),
+    }
+}
+
+/// Copy the site for publishing, leaving out dotfiles such as `.env` and `.git`: a site
+/// served from the app root would otherwise publish them. `.well-known` is kept.
+fn copy_site(from: &Path, to: &Path) -> Result<()> {
+    let published = |entry: &walkdir::DirEntry| {
+        let name = entry.file_name().to_string_lossy();
+        entry.depth() == 0 || !name.starts_with('.') || name == ".well-known"
+    };
+    for entry in WalkDir::new(from).into_iter().filter_entry(published) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if entry.file_type().is_file() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Point Pages at the gh-pages branch. Failures only warn: Pages may already be configured
+/// by hand or the token may lack the admin scope this call needs.
+async fn enable_github_pages(owner: &str, repo: &str, token: &str) {
+    let response = http::client()
+        .post(format!("https://api.github.com/repos/{}/{}/pages", owner, repo))
+        .bearer_auth(token)
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "autodeployment")
+        .json(&serde_json::json!({"source": {"branch": PAGES_BRANCH, "path": "/"}}))
+        .send()
+        .await;
+
+    match response {
+        // 409: Pages is already enabled
+        Ok(response) if response.status().is_success() || response.status().as_u16() == 409 => {}
+        Ok(response) => warn!(
+            "⚠️ Could not enable GitHub Pages ({}); set the source to the {} branch in the repository settings",
+            response.status(),
+            PAGES_BRANCH
+        ),
+        Err(e) => warn!("⚠️ Could not enable GitHub Pages: {}", e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NetlifyDeploy {
+    /// Production URL, present for `--prod` deploys
+    url: Option<String>,
+    deploy_url: Option<String>,
+}
+
+async fn publish_to_netlify(site_dir: &Path, site: &str, token: &str) -> Result<String> {
+    info!("📤 Uploading site to Netlify...");
+    let output = Command::new("netlify")
+        .args(["deploy", "--prod", "--json", "--site", site, "--dir"])
+        .arg(site_dir)
+        .env("NETLIFY_AUTH_TOKEN", token)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "netlify deploy failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_netlify_url(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_netlify_url(stdout: &str) -> Result<String> {
+    let deploy: NetlifyDeploy = serde_json::from_str(stdout)
+        .map_err(|e| anyhow!("Failed to parse netlify deploy output: {}", e))?;
+    deploy
+        .url
+        .or(deploy.deploy_url)
+        .ok_or_else(|| anyhow!("netlify deploy did not report a URL"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_repo_and_pages_url() {
+        let (owner, repo) = github_repo("https://github.com/Arvo-AI/hello_world.git").unwrap();
+        assert_eq!((owner.as_str(), repo.as_str()), ("Arvo-AI", "hello_world"));
+        assert_eq!(github_repo("git@github.com:acme/site").unwrap(), ("acme".to_string(), "site".to_string()));
+        assert!(github_repo("https://gitlab.com/acme/site").is_err());
+
+        assert_eq!(pages_url("Arvo-AI", "hello_world"), "https://arvo-ai.github.io/hello_world/");
+        assert_eq!(pages_url("acme", "acme.github.io"), "https://acme.github.io/");
+    }
+
+    #[test]
+    fn test_find_site_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        assert!(find_site_dir(root, None).is_err());
+
+        fs::write(root.join("index.html"), "<h1>root</h1>").unwrap();
+        assert_eq!(find_site_dir(root, None).unwrap(), root.join(""));
+
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::write(root.join("build/index.html"), "<h1>built</h1>").unwrap();
+        assert_eq!(find_site_dir(root, None).unwrap(), root.join("build"));
+    }
+
+    #[test]
+    fn test_push_site_replaces_branch() {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let remote = Repository::init_bare(remote_dir.path()).unwrap();
+        let site = tempfile::tempdir().unwrap();
+        fs::create_dir_all(site.path().join("assets")).unwrap();
+        fs::write(site.path().join("index.html"), "<h1>v1</h1>").unwrap();
+        fs::write(site.path().join("assets/app.js"), "console.log(1)").unwrap();
+        fs::write(site.path().join(".env.production"), "API_SECRET=hunter2\n").unwrap();
+        fs::create_dir_all(site.path().join(".well-known")).unwrap();
+        fs::write(site.path().join(".well-known/security.txt"), "Contact: security@example.com\n").unwrap();
+
+        let remote_url = remote_dir.path().to_str().unwrap();
+        push_site(site.path(), remote_url, None).unwrap();
+        fs::write(site.path().join("index.html"), "<h1>v2</h1>").unwrap();
+        push_site(site.path(), remote_url, None).unwrap();
+
+        let commit = remote.find_reference("refs/heads/gh-pages").unwrap().peel_to_commit().unwrap();
+        assert_eq!(commit.parent_count(), 0, "each publish replaces the branch");
+        let tree = commit.tree().unwrap();
+        let index = tree.get_name("index.html").unwrap().to_object(&remote).unwrap();
+        assert_eq!(index.as_blob().unwrap().content(), b"<h1>v2</h1>");
+        assert!(tree.get_name(".nojekyll").is_some());
+        assert!(tree.get_path(Path::new("assets/app.js")).is_ok());
+        assert!(tree.get_name(".env.production").is_none(), "dotfiles are never published");
+        assert!(tree.get_path(Path::new(".well-known/security.txt")).is_ok());
+    }
+
+    #[test]
+    fn test_parse_netlify_url() {
+        let prod = r#"{"site_id": "abc", "deploy_url": "https://123--site.netlify.app", "url": "https://site.netlify.app"}"#;
+        assert_eq!(parse_netlify_url(prod).unwrap(), "https://site.netlify.app");
+        let draft = r#"{"site_id": "abc", "deploy_url": "https://123--site.netlify.app"}"#;
+        assert_eq!(parse_netlify_url(draft).unwrap(), "https://123--site.netlify.app");
+        assert!(parse_netlify_url("not json").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use log::warn;
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::infrastructure::TerraformConfig;
+
+/// What a provider accepts in the tags (labels on GCP) of a resource
+struct TagRules {
+    /// Resource type prefix of the provider
+    prefix: &'static str,
+    /// Map arguments holding the tags
+    arguments: &'static [&'static str],
+    max_key_len: usize,
+    max_value_len: usize,
+    /// Whether keys and values are lowercased
+    lowercase: bool,
+    /// Characters allowed in keys; the others become `_`
+    key_allowed: fn(char) -> bool,
+    /// Characters allowed in values; the others become `_`
+    value_allowed: fn(char) -> bool,
+    /// Characters a key may start with; other keys get `KEY_PREFIX`
+    key_start: fn(char) -> bool,
+}
+
+/// AWS tags: letters, digits, spaces and `_ . : / = + - @`; keys up to 128 characters,
+/// values up to 256
+const AWS_TAGS: TagRules = TagRules {
+    prefix: "aws_",
+    arguments: &["tags"],
+    max_key_len: 128,
+    max_value_len: 256,
+    lowercase: false,
+    key_allowed: aws_allowed,
+    value_allowed: aws_allowed,
+    key_start: |_| true,
+};
+
+/// GCP labels: lowercase letters, digits, `_` and `-`, at most 63 characters; keys start
+/// with a letter. `tags` on GCP are network tags, not labels.
+const GCP_LABELS: TagRules = TagRules {
+    prefix: "google_",
+    arguments: &["labels", "resource_labels"],
+    max_key_len: 63,
+    max_value_len: 63,
+    lowercase: true,
+    key_allowed: gcp_allowed,
+    value_allowed: gcp_allowed,
+    key_start: |c| c.is_ascii_lowercase(),
+};
+
+/// Azure tags: anything but `< > % & \ ? /` in keys and anything in values; keys up to 512
+/// characters, values up to 256
+const AZURE_TAGS: TagRules = TagRules {
+    prefix: "azurerm_",
+    arguments: &["tags"],
+    max_key_len: 512,
+    max_value_len: 256,
+    lowercase: false,
+    key_allowed: |c| !"<>%&\\?/".contains(c),
+    value_allowed: |_| true,
+    key_start: |_| true,
+};
+
+fn aws_allowed(c: char) -> bool {
+    c.is_alphanumeric() || c.is_whitespace() || "_.:/=+-@".contains(c)
+}
+
+fn gcp_allowed(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'
+}
+
+const PROVIDER_RULES: &[TagRules] = &[AWS_TAGS, GCP_LABELS, AZURE_TAGS];
+
+/// Put in front of a key that may not start with its first character
+const KEY_PREFIX: &str = "tag_";
+
+/// A tag that had to be changed to be accepted by the provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagChange {
+    /// `type.name` of the tagged resource
+    pub resource: String,
+    /// `key=value` as generated
+    pub original: String,
+    /// `key=value` as written, or `None` when the tag was dropped because its normalized key
+    /// clashed with another tag
+    pub normalized: Option<String>,
+}
+
+impl fmt::Display for TagChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.normalized {
+            Some(normalized) => write!(f, "{}: tag {} normalized to {}", self.resource, self.original, normalized),
+            None => write!(f, "{}: tag {} dropped, its normalized key is already used", self.resource, self.original),
+        }
+    }
+}
+
+impl TagRules {
+    fn normalize(&self, text: &str, allowed: fn(char) -> bool, max_len: usize) -> String {
+        let text = if self.lowercase { text.to_lowercase() } else { text.to_string() };
+        text.chars()
+            .map(|c| if allowed(c) { c } else { '_' })
+            .take(max_len)
+            .collect()
+    }
+
+    fn normalize_key(&self, key: &str) -> String {
+        let key = self.normalize(key, self.key_allowed, self.max_key_len);
+        if key.chars().next().is_some_and(self.key_start) {
+            return key;
+        }
+        format!("{}{}", KEY_PREFIX, key).chars().take(self.max_key_len).collect()
+    }
+
+    fn normalize_value(&self, value: &Value) -> Value {
+        match value {
+            // Terraform expressions are resolved at apply time; leave them to the provider
+            Value::String(s) if s.starts_with("var.") || s.contains("${") => value.clone(),
+            Value::String(s) => Value::String(self.normalize(s, self.value_allowed, self.max_value_len)),
+            other => other.clone(),
+        }
+    }
+}
+
+/// Make every tag and label in `config` valid for its provider: on GCP keys and values are
+/// lowercased, characters the provider rejects become `_`, over-long keys and values are
+/// truncated. Returns the tags that changed, which are also logged.
+pub fn normalize_tags(config: &mut TerraformConfig) -> Vec<TagChange> {
+    let mut changes = Vec::new();
+    for resource in &mut config.resources {
+        let Some(rules) = PROVIDER_RULES.iter().find(|rules| resource.resource_type.starts_with(rules.prefix)) else {
+            continue;
+        };
+        for argument in rules.arguments {
+            let Some(Value::Object(tags)) = resource.config.get_mut(*argument) else {
+                continue;
+            };
+            let mut normalized = Map::new();
+            for (key, value) in tags.iter() {
+                let (new_key, new_value) = (rules.normalize_key(key), rules.normalize_value(value));
+                if new_key == *key && new_value == *value {
+                    normalized.insert(new_key, new_value);
+                    continue;
+                }
+                let clashes = normalized.contains_key(&new_key) || (new_key != *key && tags.contains_key(&new_key));
+                changes.push(TagChange {
+                    resource: format!("{}.{}", resource.resource_type, resource.name),
+                    original: format!("{}={}", key, display_value(value)),
+                    normalized: (!clashes).then(|| format!("{}={}", new_key, display_value(&new_value))),
+                });
+                if !clashes {
+                    normalized.insert(new_key, new_value);
+                }
+            }
+            *tags = normalized;
+        }
+    }
+    for change in &changes {
+        warn!("🏷️ {}", change);
+    }
+    changes
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::TerraformResource;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_tag_valid_on_aws_is_normalized_on_gcp() {
+        let resource = |resource_type: &str, argument: &str| TerraformResource {
+            resource_type: resource_type.to_string(),
+            name: "app".to_string(),
+            config: serde_json::from_value(json!({
+                argument: {"Team": "Platform Eng/Core", "owner": "ops@example.com", "region": "var.region", "team": "web"}
+            }))
+            .unwrap(),
+        };
+        let mut config = TerraformConfig {
+            provider: "aws".to_string(),
+            resources: vec![resource("aws_instance", "tags")],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        let tags = config.resources[0].config["tags"].clone();
+        assert!(normalize_tags(&mut config).is_empty(), "valid AWS tags");
+        assert_eq!(config.resources[0].config["tags"], tags);
+
+        let mut config = TerraformConfig {
+            provider: "gcp".to_string(),
+            resources: vec![resource("google_compute_instance", "labels")],
+            variables: HashMap::new(),
+            outputs: HashMap::new(),
+        };
+        config.resources[0].config.insert("tags".to_string(), json!(["web"]));
+        config.resources[0].config.get_mut("labels").unwrap()["1st-deploy"] = json!("x".repeat(80));
+        let changes = normalize_tags(&mut config);
+        assert_eq!(
+            config.resources[0].config["labels"],
+            json!({"owner": "ops_example_com", "region": "var.region", "team": "web", "tag_1st-deploy": "x".repeat(63)})
+        );
+        assert_eq!(config.resources[0].config["tags"], json!(["web"]), "network tags are not labels");
+        assert_eq!(changes.len(), 3);
+        assert!(changes.contains(&TagChange {
+            resource: "google_compute_instance.app".to_string(),
+            original: "Team=Platform Eng/Core".to_string(),
+            normalized: None,
+        }));
+        assert_eq!(changes.iter().find(|c| c.original.starts_with("owner")).unwrap().to_string(),
+            "google_compute_instance.app: tag owner=ops@example.com normalized to owner=ops_example_com");
+    }
+}
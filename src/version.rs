@@ -0,0 +1,85 @@
+use std::process::Command;
+
+use crate::ai_nlp;
+
+/// Versions of the tool and the components it depends on, for bug reports
+#[derive(Debug)]
+pub struct VersionReport {
+    pub tool: &'static str,
+    pub terraform: Option<String>,
+    pub git2: &'static str,
+    pub libgit2: String,
+    pub ai_model: String,
+    pub os: &'static str,
+    pub arch: &'static str,
+}
+
+/// Collect version information. Works offline and never touches credentials.
+pub fn collect() -> VersionReport {
+    let git = git2::Version::get();
+    let (major, minor, patch) = git.libgit2_version();
+
+    VersionReport {
+        tool: env!("CARGO_PKG_VERSION"),
+        terraform: terraform_version(),
+        git2: git.crate_version(),
+        libgit2: format!("{}.{}.{}", major, minor, patch),
+        ai_model: ai_nlp::model().to_string(),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+    }
+}
+
+pub fn print_report(report: &VersionReport) {
+    println!("autodeployment {}", report.tool);
+    println!("  Terraform: {}", report.terraform.as_deref().unwrap_or("not found"));
+    println!("  git2: {} (libgit2 {})", report.git2, report.libgit2);
+    println!("  AI model: {}", report.ai_model);
+    println!("  Platform: {}/{}", report.os, report.arch);
+}
+
+/// Run `terraform version` and extract the version number, if terraform is installed
+fn terraform_version() -> Option<String> {
+    let output = Command::new("terraform").arg("version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_terraform_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the first line of `terraform version` output, e.g. `Terraform v1.6.2`
+fn parse_terraform_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()?
+        .trim()
+        .strip_prefix("Terraform v")
+        .map(|version| version.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_terraform_version() {
+        assert_eq!(
+            parse_terraform_version("Terraform v1.6.2\non linux_amd64\n"),
+            Some("1.6.2".to_string())
+        );
+        assert_eq!(
+            parse_terraform_version("Terraform v1.5.7\n\nYour version of Terraform is out of date!"),
+            Some("1.5.7".to_string())
+        );
+        assert_eq!(parse_terraform_version("OpenTofu v1.6.0"), None);
+        assert_eq!(parse_terraform_version(""), None);
+    }
+
+    #[test]
+    fn test_collect_reports_build_info() {
+        let report = collect();
+        assert_eq!(report.tool, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report.ai_model, "gemini-2.5-flash");
+        assert!(!report.os.is_empty());
+    }
+}